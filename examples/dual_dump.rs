@@ -0,0 +1,55 @@
+//! Dumps the flash of two ECUs at once, each on its own K-line adapter.
+//!
+//! Every connection's state lives in its `Client`, so each can be driven
+//! from its own thread:
+//!
+//! ```text
+//! cargo run --example dual_dump -- /dev/ttyUSB0 first.bin /dev/ttyUSB1 second.bin
+//! ```
+
+use std::{path::PathBuf, thread};
+
+use ecu_flasher::{
+    Error,
+    k_line::{KLine, SerialInterface},
+    kwp2000::client::Client,
+    port_builder,
+    port_lock::PortLock,
+    profile::EcuProfile,
+};
+
+/// Initializes the ECU on `port_path` and dumps its flash to `output`.
+fn dump(port_path: &str, output: PathBuf) -> Result<Client, Error> {
+    let _lock = PortLock::acquire(port_path, false)?;
+    let profile = EcuProfile::default();
+    let mut port = SerialInterface::open(port_builder(port_path))?;
+    port.init_kwp2000(profile.init_address, &profile.init_timing)?;
+    let mut client = Client::new(Box::new(port));
+    client.diagnostic_mode()?;
+    let layout = client.profile.memory_layout.clone();
+    client.dump_resumable(layout.base_address, layout.size, &output)?;
+    Ok(client)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [first_port, first_output, second_port, second_output] = &args[..] else {
+        eprintln!("usage: dual_dump <port> <output> <port> <output>");
+        std::process::exit(2);
+    };
+    let sessions =
+        [(first_port, first_output), (second_port, second_output)].map(|(port, output)| {
+            let (port, output) = (port.clone(), PathBuf::from(output));
+            thread::spawn(move || (dump(&port, output), port))
+        });
+    for session in sessions {
+        let (result, port) = session.join().expect("dump thread panicked");
+        match result.and_then(|client| {
+            let stats = client.stats.clone();
+            client.disconnect().map(|()| stats)
+        }) {
+            Ok(stats) => println!("{port}: done, {} frames received", stats.frames_received),
+            Err(e) => eprintln!("{port}: {e}"),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Switches between tune maps at runtime by writing selector variables in
+//! RAM, with the interlocks of `Client::write_live`: only values from the
+//! allowed set are written, nothing is written while a transfer holds the
+//! bus, and every write is read back.
+//!
+//! ```text
+//! cargo run --example map_switch -- /dev/ttyUSB0 maps.txt map_selector launch_enable
+//! ```
+//!
+//! The two names are looked up in the definitions file. Typing `1` or `2`
+//! toggles the first or second variable between 0 and 1, `q` quits.
+
+use std::{io::BufRead, path::Path, sync::mpsc, thread, time::Duration};
+
+use ecu_flasher::{
+    Error,
+    analysis::definitions,
+    k_line::{KLine, SerialInterface},
+    kwp2000::{
+        client::Client,
+        live::{LiveChangeHandler, LiveVariable},
+    },
+    port_builder,
+    port_lock::PortLock,
+    profile::EcuProfile,
+};
+
+fn run(port_path: &str, definitions: &Path, names: [&str; 2]) -> Result<(), Error> {
+    let definitions = definitions::load(definitions)?;
+    let variable = |name: &str| {
+        let definition = definitions.iter().find(|d| d.name == name).ok_or_else(|| {
+            Error::LiveValueRejected {
+                name: name.to_string(),
+                reason: "not in the definitions".to_string(),
+            }
+        })?;
+        LiveVariable::from_definition(definition, vec![0, 1])
+    };
+    let [first, second] = [variable(names[0])?, variable(names[1])?];
+    let mut values = [0, 0];
+
+    let _lock = PortLock::acquire(port_path, false)?;
+    let profile = EcuProfile::default();
+    let mut port = SerialInterface::open(port_builder(port_path))?;
+    port.init_kwp2000(profile.init_address, &profile.init_timing)?;
+    let mut client = Client::new(Box::new(port));
+    client.diagnostic_mode()?;
+    client.on_live_change = LiveChangeHandler(Some(Box::new(|change| {
+        println!("{} = {}", change.name, change.value)
+    })));
+
+    // stdin blocks, read it on its own thread so the session is kept alive
+    let (keys, input) = mpsc::channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if keys.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    loop {
+        let line = match input.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) => line,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                client.tester_present()?;
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let (index, variable) = match line.trim() {
+            "1" => (0, &first),
+            "2" => (1, &second),
+            "q" => break,
+            _ => {
+                println!("1 or 2 toggles a variable, q quits");
+                continue;
+            }
+        };
+        let value = values[index] ^ 1;
+        match client.write_live(variable, value) {
+            Ok(()) => values[index] = value,
+            Err(e) => eprintln!("{}: {e}", variable.name),
+        }
+    }
+    client.disconnect()
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [port, definitions, first, second] = &args[..] else {
+        eprintln!("usage: map_switch <port> <definitions> <variable> <variable>");
+        std::process::exit(2);
+    };
+    if let Err(e) = run(port, Path::new(definitions), [first, second]) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Builds every combination of the optional features.
+set -e
+
+for transport in "" transport-serialport; do
+    for flash in "" flash; do
+        for diag in "" diag; do
+            features=$(echo "$transport $flash $diag" | xargs | tr ' ' ',')
+            echo "checking features: [$features]"
+            cargo check --no-default-features --features "$features"
+        done
+    done
+done
+
+echo "checking features: [default,async]"
+cargo check --features async
+
+echo "checking features: [default,uds_compat]"
+cargo check --features uds_compat
@@ -0,0 +1,297 @@
+//! Reader for the communication logs of the NefMoto flasher, turning the
+//! traffic they recorded into frames this crate decodes.
+//!
+//! Frames are runs of two digit hex bytes on a line, optionally preceded by
+//! a `HH:MM:SS.mmm` time and a direction marker (`TX`/`Sent`,
+//! `RX`/`Received`). Lines holding only hex bytes continue an incomplete
+//! frame, the flasher wraps long ones. Other lines are the flasher's own
+//! commentary and skipped. Without a marker the direction is inferred from
+//! the service byte.
+
+use std::{path::Path, time::Duration};
+
+use crate::{
+    Error,
+    kwp2000::{
+        constants::Service,
+        raw_message::{RawMessage, expected_frame_len},
+    },
+};
+
+/// Fewest hex bytes a line needs to start a frame: a format byte, the
+/// service and the checksum.
+const MIN_FRAME_BYTES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the tester
+    ToEcu,
+    /// Sent by the ECU
+    FromEcu,
+}
+
+/// Frame of a capture.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    /// Time of day the frame was logged at, if the log has it.
+    pub at: Option<Duration>,
+    pub direction: Direction,
+    /// The log had no direction marker, `direction` was inferred from the
+    /// service.
+    pub direction_inferred: bool,
+    pub message: RawMessage,
+    /// Line of the log the frame starts on.
+    pub line: usize,
+}
+
+/// Parses `HH:MM:SS` with optional fractional seconds.
+fn parse_time(token: &str) -> Option<Duration> {
+    let mut parts = token.split(':');
+    let (h, m, s) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    let (h, m): (u64, u64) = (h.parse().ok()?, m.parse().ok()?);
+    let s: f64 = s.parse().ok()?;
+    (h < 24 && m < 60 && (0.0..61.0).contains(&s))
+        .then(|| Duration::from_secs(h * 3600 + m * 60) + Duration::from_secs_f64(s))
+}
+
+fn parse_direction(token: &str) -> Option<Direction> {
+    match token.to_ascii_lowercase().as_str() {
+        "tx" | "sent" | "send" | "sending" | "write" => Some(Direction::ToEcu),
+        "rx" | "received" | "receive" | "recv" | "read" => Some(Direction::FromEcu),
+        _ => None,
+    }
+}
+
+fn parse_hex_byte(token: &str) -> Option<u8> {
+    (token.len() == 2)
+        .then(|| u8::from_str_radix(token, 16).ok())
+        .flatten()
+}
+
+/// What was read from one line of a log.
+#[derive(Default)]
+struct Line {
+    at: Option<Duration>,
+    direction: Option<Direction>,
+    /// Longest run of hex bytes on the line
+    bytes: Vec<u8>,
+    /// The line holds nothing but hex bytes
+    only_hex: bool,
+}
+
+fn parse_line(text: &str) -> Line {
+    let mut line = Line {
+        only_hex: true,
+        ..Line::default()
+    };
+    let mut run = Vec::new();
+    for token in text.split(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']') {
+        let token = token.trim_end_matches(':');
+        if token.is_empty() {
+            continue;
+        }
+        if let Some(byte) = parse_hex_byte(token) {
+            run.push(byte);
+            continue;
+        }
+        line.only_hex = false;
+        if run.len() > line.bytes.len() {
+            line.bytes = std::mem::take(&mut run);
+        }
+        run.clear();
+        if let Some(at) = parse_time(token) {
+            line.at = line.at.or(Some(at));
+        } else if let Some(direction) = parse_direction(token) {
+            line.direction = line.direction.or(Some(direction));
+        }
+    }
+    if run.len() > line.bytes.len() {
+        line.bytes = run;
+    }
+    line
+}
+
+/// Frame being put together from one or more lines.
+struct Partial {
+    at: Option<Duration>,
+    direction: Option<Direction>,
+    line: usize,
+    bytes: Vec<u8>,
+}
+
+impl Partial {
+    /// Takes the complete frames off the front of the buffer.
+    fn take_frames(&mut self, frames: &mut Vec<CapturedFrame>) -> Result<(), Error> {
+        while let Some(len) = expected_frame_len(&self.bytes).filter(|len| *len <= self.bytes.len())
+        {
+            let message = RawMessage::read_from_bytes(&mut &self.bytes[..len]).map_err(|e| {
+                Error::CaptureParse {
+                    line: self.line,
+                    reason: e.to_string(),
+                }
+            })?;
+            let inferred = match message.service {
+                Service::Query(_) => Direction::ToEcu,
+                Service::Response(_) => Direction::FromEcu,
+            };
+            frames.push(CapturedFrame {
+                at: self.at,
+                direction: self.direction.unwrap_or(inferred),
+                direction_inferred: self.direction.is_none(),
+                message,
+                line: self.line,
+            });
+            self.bytes.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+/// Parses the frames in the NefMoto log `text`, errors give the line
+/// number.
+pub fn parse_nefmoto(text: &str) -> Result<Vec<CapturedFrame>, Error> {
+    let mut frames = Vec::new();
+    let mut partial: Option<Partial> = None;
+    for (i, text) in text.lines().enumerate() {
+        let line = parse_line(text);
+        if line.bytes.is_empty() {
+            continue;
+        }
+        match &mut partial {
+            Some(p) if line.only_hex && !p.bytes.is_empty() => {
+                p.bytes.extend_from_slice(&line.bytes)
+            }
+            _ if line.bytes.len() < MIN_FRAME_BYTES => continue,
+            _ => {
+                if let Some(p) = partial.take().filter(|p| !p.bytes.is_empty()) {
+                    return Err(Error::CaptureParse {
+                        line: p.line,
+                        reason: format!("incomplete frame {:02x?}", p.bytes),
+                    });
+                }
+                partial = Some(Partial {
+                    at: line.at,
+                    direction: line.direction,
+                    line: i + 1,
+                    bytes: line.bytes,
+                });
+            }
+        }
+        if let Some(p) = &mut partial {
+            p.take_frames(&mut frames)?;
+        }
+    }
+    if let Some(p) = partial.filter(|p| !p.bytes.is_empty()) {
+        return Err(Error::CaptureParse {
+            line: p.line,
+            reason: format!("incomplete frame {:02x?}", p.bytes),
+        });
+    }
+    Ok(frames)
+}
+
+pub fn load_nefmoto(path: &Path) -> Result<Vec<CapturedFrame>, Error> {
+    parse_nefmoto(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{
+        constants::ServiceError,
+        response::{self, ProcessError, Response},
+    };
+
+    /// Sanitized log with commentary, a wrapped frame and frames without
+    /// direction markers.
+    const SAMPLE: &str = include_str!("testdata/nefmoto.log");
+
+    #[test]
+    fn sample_is_converted() {
+        let frames = parse_nefmoto(SAMPLE).unwrap();
+        let services: Vec<u8> = frames.iter().map(|f| f.message.service.into()).collect();
+        assert_eq!(
+            services,
+            [
+                0x81, 0xC1, 0x10, 0x50, 0x1A, 0x5A, 0x23, 0x7F, 0x3E, 0x7E, 0x82, 0xC2
+            ]
+        );
+        for (i, frame) in frames.iter().enumerate() {
+            let expected = if i % 2 == 0 {
+                Direction::ToEcu
+            } else {
+                Direction::FromEcu
+            };
+            assert_eq!(frame.direction, expected, "frame {i}");
+            assert_eq!(frame.direction_inferred, matches!(i, 6 | 7), "frame {i}");
+        }
+        assert_eq!(
+            frames[0].at,
+            Some(Duration::from_millis(12 * 3_600_000 + 100))
+        );
+        assert_eq!(frames[0].line, 3);
+        // wrapped over three lines
+        assert_eq!(frames[5].line, 9);
+        assert_eq!(frames[6].line, 13);
+    }
+
+    #[test]
+    fn converted_frames_round_trip() {
+        let logged: Vec<u8> = SAMPLE
+            .lines()
+            .map(parse_line)
+            .filter(|l| l.bytes.len() >= MIN_FRAME_BYTES || l.only_hex)
+            .flat_map(|l| l.bytes)
+            .collect();
+        let converted: Vec<u8> = parse_nefmoto(SAMPLE)
+            .unwrap()
+            .into_iter()
+            .flat_map(|f| f.message.to_bytes().unwrap())
+            .collect();
+        assert_eq!(converted, logged);
+    }
+
+    #[test]
+    fn ecu_frames_decode() {
+        let frames = parse_nefmoto(SAMPLE).unwrap();
+        let responses: Vec<Response> = frames
+            .into_iter()
+            .filter(|f| f.direction == Direction::FromEcu)
+            // the key bytes answering StartCommunication belong to the init
+            .skip(1)
+            .map(|f| response::from_raw(f.message))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        match &responses[1] {
+            Response::EcuIdentification(0x9B, data) => assert!(data.starts_with(b"8D0907551M")),
+            r => panic!("{r:?}"),
+        }
+        assert!(matches!(
+            responses[2],
+            Response::Error(ProcessError {
+                error: ServiceError::RequestOutOfRange,
+                ..
+            })
+        ));
+        assert!(matches!(responses[3], Response::TesterPresent));
+    }
+
+    #[test]
+    fn broken_frames_give_the_line() {
+        // bad checksum
+        let text = "TX: 82 10 F1 3E 01 C3";
+        assert!(matches!(
+            parse_nefmoto(text),
+            Err(Error::CaptureParse { line: 1, .. })
+        ));
+        // log ends inside a frame
+        let text = "commentary\nRX: 83 F1 10 7F 23";
+        assert!(matches!(
+            parse_nefmoto(text),
+            Err(Error::CaptureParse { line: 2, .. })
+        ));
+    }
+}
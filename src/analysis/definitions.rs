@@ -0,0 +1,181 @@
+//! Reader for files naming regions of a dump, like the maps of an engine
+//! calibration.
+//!
+//! One definition per line as `name,address,size[,columns,rows]`. Numbers
+//! are decimal or hex with a `0x` prefix, `columns` and `rows` describe the
+//! axes of a map (a curve has one row). Empty lines, lines starting with
+//! `#` and a first line starting with `name,` are skipped. Names have to be
+//! unique.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::Error;
+
+/// Named region of memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    /// Columns and rows of the map, `None` for a single value or a region
+    /// without axes.
+    pub axes: Option<(u32, u32)>,
+}
+
+fn parse_number(field: &str) -> Option<u32> {
+    match field.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => field.parse().ok(),
+    }
+}
+
+fn parse_line(line: &str) -> Result<Definition, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if !(fields.len() == 3 || fields.len() == 5) {
+        return Err(format!("expected 3 or 5 fields, found {}", fields.len()));
+    }
+    let number = |i: usize, what: &str| {
+        parse_number(fields[i]).ok_or_else(|| format!("invalid {what} {:?}", fields[i]))
+    };
+    if fields[0].is_empty() {
+        return Err("empty name".to_string());
+    }
+    let address = number(1, "address")?;
+    let size = number(2, "size")?;
+    if size == 0 {
+        return Err("size is 0".to_string());
+    }
+    if address.checked_add(size - 1).is_none() {
+        return Err("region runs past the end of the address space".to_string());
+    }
+    let axes = if fields.len() == 5 {
+        let (columns, rows) = (number(3, "columns")?, number(4, "rows")?);
+        let cells = columns.checked_mul(rows).filter(|c| *c != 0);
+        if !cells.is_some_and(|c| size % c == 0) {
+            return Err(format!("{columns}x{rows} cells don't fit a size of {size}"));
+        }
+        Some((columns, rows))
+    } else {
+        None
+    };
+    Ok(Definition {
+        name: fields[0].to_string(),
+        address,
+        size,
+        axes,
+    })
+}
+
+/// Parses the definitions in `text`, errors give the line number.
+pub fn parse(text: &str) -> Result<Vec<Definition>, Error> {
+    let mut definitions = Vec::new();
+    // line each name was defined on
+    let mut names = HashMap::new();
+    let lines = text.lines().enumerate().filter(|(i, line)| {
+        let line = line.trim();
+        !(line.is_empty() || line.starts_with('#') || *i == 0 && line.starts_with("name,"))
+    });
+    for (i, line) in lines {
+        let error = |reason| Error::DefinitionParse {
+            line: i + 1,
+            reason,
+        };
+        let definition = parse_line(line).map_err(error)?;
+        if let Some(first) = names.insert(definition.name.clone(), i + 1) {
+            return Err(error(format!(
+                "{:?} is already defined on line {first}",
+                definition.name
+            )));
+        }
+        definitions.push(definition);
+    }
+    Ok(definitions)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Definition>, Error> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::annotate;
+
+    const FIXTURE: &str = "name,address,size,columns,rows
+# ME7.5 ignition and limiter, made up addresses
+KFZW, 0x810000, 0x100, 16, 16
+
+NMAX,0x810100,2
+LAMFA,0x810102,0x20,8,2
+";
+
+    #[test]
+    fn fixture_is_parsed() {
+        let definitions = parse(FIXTURE).unwrap();
+        assert_eq!(
+            definitions,
+            [
+                Definition {
+                    name: "KFZW".to_string(),
+                    address: 0x810000,
+                    size: 0x100,
+                    axes: Some((16, 16)),
+                },
+                Definition {
+                    name: "NMAX".to_string(),
+                    address: 0x810100,
+                    size: 2,
+                    axes: None,
+                },
+                Definition {
+                    name: "LAMFA".to_string(),
+                    address: 0x810102,
+                    size: 0x20,
+                    axes: Some((8, 2)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let cases = [
+            ("A,0x10,2\nB,0x1g,2", 2),
+            ("A,0x10", 1),
+            ("A,0x10,0", 1),
+            ("# comment\n\nA,0x10,3,2,2", 3),
+            ("A,0xFFFFFFFF,2", 1),
+            (",0x10,2", 1),
+        ];
+        for (text, line) in cases {
+            match parse(text) {
+                Err(Error::DefinitionParse { line: l, .. }) => assert_eq!(l, line, "{text}"),
+                r => panic!("{text}: {r:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn duplicate_name_is_rejected() {
+        let text = format!("{FIXTURE}NMAX,0x810200,2\n");
+        match parse(&text) {
+            Err(Error::DefinitionParse { line: 7, reason }) => {
+                assert!(reason.contains("line 5"), "{reason}")
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn regions_outside_the_dump_are_missing() {
+        let definitions = parse(FIXTURE).unwrap();
+        // covers KFZW and the first byte of NMAX
+        let dump: Vec<u8> = (0..=0x100).map(|i| i as u8).collect();
+        let regions = annotate(&dump, 0x810000, &definitions);
+        assert_eq!(regions[0].data, Some(&dump[..0x100]));
+        assert_eq!(regions[1].data, None);
+        assert_eq!(regions[2].data, None);
+        // starts before the dump
+        assert_eq!(annotate(&dump, 0x810001, &definitions)[0].data, None);
+    }
+}
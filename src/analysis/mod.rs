@@ -0,0 +1,36 @@
+//! Working with dumps after they were read.
+
+pub mod capture;
+pub mod definitions;
+
+use definitions::Definition;
+
+/// Region of a dump named by a definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedRegion<'a> {
+    pub definition: &'a Definition,
+    /// Bytes of the region, `None` if it isn't completely inside the dump.
+    pub data: Option<&'a [u8]>,
+}
+
+/// Extracts the region of every definition from `dump`, which was read
+/// starting at `base_address`.
+pub fn annotate<'a>(
+    dump: &'a [u8],
+    base_address: u32,
+    definitions: &'a [Definition],
+) -> Vec<AnnotatedRegion<'a>> {
+    definitions
+        .iter()
+        .map(|definition| {
+            let data = definition
+                .address
+                .checked_sub(base_address)
+                .and_then(|start| {
+                    let start = start as usize;
+                    dump.get(start..start.checked_add(definition.size as usize)?)
+                });
+            AnnotatedRegion { definition, data }
+        })
+        .collect()
+}
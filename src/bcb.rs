@@ -43,10 +43,11 @@ pub enum RepeatMode {
 /// Uses `encrypt_data` and `create_bcb_data` to create a the data section of a
 /// `TransferData` message. The data is compressed, and then encrypted.
 ///
-/// If `is_first` is specified a special header is added to the beginning of
-/// the compressed data before everything is encrypted.
+/// If `header` is given it is added to the beginning of the compressed data
+/// before everything is encrypted.
 /// This should be done for the first data packet sent after the ECU's positive
-/// response to the`RequestDownload` message.
+/// response to the`RequestDownload` message, see
+/// `EcuProfile::bcb_first_block_header`.
 ///
 /// This will compress as much of `data` as it can while maintaining an overall
 /// packet size less than `max_len`.
@@ -60,17 +61,17 @@ pub fn encrypt_and_compress(
     data: &[u8],
     key_index: &mut usize,
     key: &[u8],
-    is_first: bool,
+    header: Option<&[u8]>,
 ) -> Result<(usize, Vec<u8>), Error> {
     // make room for the special first message header
-    if is_first {
-        max_len -= 2;
+    if let Some(header) = header {
+        max_len = max_len.saturating_sub(header.len());
     }
 
     let (uncompressed_length, mut compressed) = create_bcb_data(data, max_len)?;
 
-    if is_first {
-        let mut new = vec![0x1A, 0x01];
+    if let Some(header) = header {
+        let mut new = header.to_vec();
         new.append(&mut compressed);
         compressed = new;
     }
@@ -84,6 +85,9 @@ pub fn encrypt_and_compress(
 /// Increments key_index when run, checks that key_index is within range  before using
 /// it but not after updating it and returning.
 pub fn encrypt_data(key: &[u8], data: &mut [u8], key_index: &mut usize) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::UnexpectedValue);
+    }
     for b in data.iter_mut() {
         if *key_index >= key.len() {
             *key_index = 0;
@@ -143,8 +147,13 @@ pub fn next_bcb_block<W: Write>(
     // number of bytes in a BCB data block header
     const BLOCK_HEADER_SIZE: usize = 2;
 
-    let max_data_bytes = Ord::min(max_len - BLOCK_HEADER_SIZE, data.len());
-    let max_index_norepeats = Ord::min(max_len - BLOCK_HEADER_SIZE, data.len());
+    let remaining = data.len().saturating_sub(*current_index);
+    let max_data_bytes = Ord::min(max_len.saturating_sub(BLOCK_HEADER_SIZE), remaining);
+    // the last index compared to the one after it
+    let max_index_norepeats = Ord::min(
+        *current_index + max_data_bytes,
+        data.len().saturating_sub(1),
+    );
 
     let mut repeat_start = 0;
     let mut repeat_end = 0;
@@ -184,8 +193,8 @@ pub fn next_bcb_block<W: Write>(
             let repeat_mode = RepeatMode::Repeating as u16;
             let header = repeat_mode << 14 | (0x3FFF & repeated_bytes as u16);
 
-            compressed.write(&header.to_be_bytes())?;
-            compressed.write(&[data[repeat_start]])?;
+            compressed.write_all(&header.to_be_bytes())?;
+            compressed.write_all(&[data[repeat_start]])?;
 
             repeated_bytes
         } else {
@@ -194,6 +203,9 @@ pub fn next_bcb_block<W: Write>(
     } else {
         let data_bytes = if found_repeat {
             repeat_start - *current_index
+        } else if max_data_bytes == remaining {
+            // the rest of the data fits, don't leave a single byte behind
+            max_data_bytes
         } else {
             max_data_bytes - (max_data_bytes % 2)
         };
@@ -202,8 +214,8 @@ pub fn next_bcb_block<W: Write>(
             let repeat_mode = RepeatMode::NoRepeats as u16;
             let header = repeat_mode << 14 | (0x3FFF & data_bytes as u16);
 
-            compressed.write(&header.to_be_bytes())?;
-            compressed.write(&data[*current_index..data_bytes])?;
+            compressed.write_all(&header.to_be_bytes())?;
+            compressed.write_all(&data[*current_index..*current_index + data_bytes])?;
 
             data_bytes
         } else {
@@ -211,3 +223,67 @@ pub fn next_bcb_block<W: Write>(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KEY;
+
+    /// Decrypted packet and the number of bytes compressed into it.
+    fn packet(max_len: usize, data: &[u8], header: Option<&[u8]>) -> (usize, Vec<u8>) {
+        let mut key_index = 0;
+        let (used, mut packet) =
+            encrypt_and_compress(max_len, data, &mut key_index, KEY, header).unwrap();
+        let mut key_index = 0;
+        encrypt_data(KEY, &mut packet, &mut key_index).unwrap();
+        (used, packet)
+    }
+
+    const DATA: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+
+    #[test]
+    fn without_header() {
+        let (used, packet) = packet(64, &DATA, None);
+        assert_eq!(used, DATA.len());
+        assert_eq!(packet, [&[0x00, 0x06][..], &DATA].concat());
+    }
+
+    #[test]
+    fn default_header_is_prepended() {
+        let (used, packet) = packet(64, &DATA, Some(&[0x1A, 0x01]));
+        assert_eq!(used, DATA.len());
+        assert_eq!(packet, [&[0x1A, 0x01, 0x00, 0x06][..], &DATA].concat());
+    }
+
+    #[test]
+    fn other_header_reserves_its_length() {
+        let header = [0xAB, 0xCD, 0xEF];
+        // room for the header, a block header and 4 data bytes
+        let (used, packet) = packet(header.len() + 2 + 4, &DATA, Some(&header));
+        assert_eq!(used, 4);
+        assert_eq!(
+            packet,
+            [&header[..], &[0x00, 0x04, 0x01, 0x02, 0x03, 0x04]].concat()
+        );
+    }
+
+    #[test]
+    fn empty_key_is_rejected() {
+        let mut key_index = 0;
+        assert!(matches!(
+            encrypt_data(&[], &mut [1, 2, 3], &mut key_index),
+            Err(Error::UnexpectedValue)
+        ));
+    }
+
+    #[test]
+    fn blocks_after_the_first_and_an_odd_last_byte_are_compressed() {
+        let data = [1, 2, 3, 4, 7, 7, 7, 7, 9];
+        let (used, compressed) = create_bcb_data(&data, 64).unwrap();
+        assert_eq!(used, data.len());
+        assert_eq!(
+            compressed,
+            [0x00, 0x04, 1, 2, 3, 4, 0x40, 0x04, 7, 0x00, 0x01, 9]
+        );
+    }
+}
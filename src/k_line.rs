@@ -1,45 +1,226 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::Error;
-use crate::kwp2000::{Interface, raw_message::RawMessage};
+use crate::kwp2000::{Interface, message::Message, response::Response};
+#[cfg(feature = "transport-serialport")]
+use {
+    crate::kwp2000::{INIT_BAUD_RATE, raw_message::RawMessage},
+    std::io::ErrorKind,
+};
+
+/// Timing of the 5-baud initialization. ECUs differ in how closely they
+/// follow ISO 14230-2, see the presets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitTiming {
+    /// Time the bus must be quiet for before the address is sent (W5)
+    pub idle: Duration,
+    /// Time the line is held before the address bits
+    pub start: Duration,
+    /// Longest wait for the sync byte after the address
+    pub sync_timeout: Duration,
+    /// Time between the second key byte and its complement (W4)
+    pub w4: Duration,
+    /// Time given to the ECU after the init before the first request
+    pub settle: Duration,
+}
+
+impl InitTiming {
+    /// Timing known to work with ME7, the default.
+    pub const fn me7() -> Self {
+        Self {
+            idle: Duration::from_millis(300),
+            start: Duration::from_millis(200),
+            sync_timeout: Duration::from_secs(1),
+            w4: Duration::from_millis(25),
+            settle: Duration::ZERO,
+        }
+    }
+
+    /// Instrument clusters behind a gateway, which need a longer quiet bus
+    /// and time to settle. Not measured, chosen with margin.
+    pub const fn cluster() -> Self {
+        Self {
+            idle: Duration::from_millis(1000),
+            settle: Duration::from_millis(300),
+            ..Self::me7()
+        }
+    }
+
+    /// The ISO 14230-2 limits: sync within W1max (300ms), W4 in the middle
+    /// of 25-50ms.
+    pub const fn strict_iso() -> Self {
+        Self {
+            sync_timeout: Duration::from_millis(300),
+            w4: Duration::from_millis(35),
+            ..Self::me7()
+        }
+    }
+
+    /// Presets tried in turn by `KLine::scan_addresses`, the default first.
+    pub const fn presets() -> [Self; 3] {
+        [Self::me7(), Self::cluster(), Self::strict_iso()]
+    }
+}
+
+impl Default for InitTiming {
+    fn default() -> Self {
+        Self::me7()
+    }
+}
+
+/// Number of bytes seen on a busy bus kept for `Error::BusBusy`.
+pub(crate) const BUS_BUSY_SAMPLE_LEN: usize = 16;
+
+/// Time a module that could not be told to stop communicating is given to
+/// drop its session before the next address is initialized.
+const SCAN_SESSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of initializing one address during `KLine::scan_addresses`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanResult {
+    pub address: u8,
+    /// Key bytes sent by the module, `None` if it did not respond.
+    pub key_bytes: Option<[u8; 2]>,
+}
+
+impl ScanResult {
+    pub fn is_kwp2000(&self) -> bool {
+        self.key_bytes.is_some_and(|k| k[1] == 0x8F)
+    }
+}
 
 pub trait KLine {
-    type Error;
+    type Error: From<Error>;
+
+    /// Maximum time `send_init_5baud` waits for the bus to go quiet.
+    fn bus_idle_limit(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    /// Listens until nothing was received for `idle`, fails with
+    /// `Error::BusBusy` if that doesn't happen within `limit`.
+    fn wait_for_bus_idle(&mut self, idle: Duration, limit: Duration) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let mut observed = 0;
+        let mut sample = Vec::new();
+        while let Some(byte) = self.read_byte_timeout(idle)? {
+            observed += 1;
+            if sample.len() < BUS_BUSY_SAMPLE_LEN {
+                sample.push(byte);
+            }
+            if start.elapsed() >= limit {
+                return Err(Error::BusBusy { observed, sample }.into());
+            }
+        }
+        Ok(())
+    }
 
-    fn send_init_5baud(&mut self, address: u8) -> Result<(), Self::Error> {
-        // Idle for 300ms before sending anything.
+    fn send_init_5baud(&mut self, address: u8, timing: &InitTiming) -> Result<(), Self::Error> {
+        // Wait for the bus to be idle before sending anything.
         self.set_low()?;
-        self.delay(Duration::from_millis(300));
+        let limit = self.bus_idle_limit();
+        self.wait_for_bus_idle(timing.idle, limit)?;
 
         // Send high bit to start transfer.
         self.set_high()?;
-        self.delay(Duration::from_millis(200));
+        self.delay(timing.start);
 
         // Send target address at 5 baud
         self.bitbang(5, address)?;
         Ok(())
     }
 
-    fn init_kwp2000(&mut self, address: u8) -> Result<(), Self::Error> {
-        self.send_init_5baud(address)?;
+    /// Sends `address` and waits for the sync byte, `false` if it didn't
+    /// arrive within `timing.sync_timeout`.
+    fn sync(&mut self, address: u8, timing: &InitTiming) -> Result<bool, Self::Error> {
+        self.send_init_5baud(address, timing)?;
+        while let Some(byte) = self.read_byte_timeout(timing.sync_timeout)? {
+            if byte == 0x55 {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
-        // Wait for timing byte
-        self.wait_for_byte(0x55)?;
+    fn init_kwp2000(&mut self, address: u8, timing: &InitTiming) -> Result<(), Self::Error> {
+        if !self.sync(address, timing)? {
+            return Err(Error::Io(std::io::ErrorKind::TimedOut.into()).into());
+        }
 
         // Wait for the second key byte
         self.wait_for_byte(0x8F)?;
 
         // Wait a bit before sending complement of key byte 2
-        self.delay(Duration::from_millis(25));
+        self.delay(timing.w4);
         self.write_byte(0xFF - 0x8F)?;
 
         self.wait_for_byte(0xFF - address)?;
+        self.delay(timing.settle);
 
         Ok(())
     }
 
+    /// Initializes any module at `address` and returns the key bytes it sent,
+    /// unlike `init_kwp2000` which only accepts KWP2000 modules.
+    fn init_address(&mut self, address: u8, timing: &InitTiming) -> Result<[u8; 2], Self::Error> {
+        if !self.sync(address, timing)? {
+            return Err(Error::Io(std::io::ErrorKind::TimedOut.into()).into());
+        }
+        self.finish_init(address, timing)
+    }
+
+    /// Rest of `init_address` after the sync byte.
+    fn finish_init(&mut self, address: u8, timing: &InitTiming) -> Result<[u8; 2], Self::Error> {
+        let key_bytes = [self.read_byte()?, self.read_byte()?];
+
+        self.delay(timing.w4);
+        self.write_byte(0xFF - key_bytes[1])?;
+
+        self.wait_for_byte(0xFF - address)?;
+        self.delay(timing.settle);
+
+        Ok(key_bytes)
+    }
+
+    /// Initializes each of `addresses` in turn to find the modules present on
+    /// the bus. Sessions that are opened are stopped (KWP2000) or left to time
+    /// out before moving on, failures only affect the result of that address.
+    /// Addresses that don't send the sync byte are tried again with the next
+    /// of `InitTiming::presets`, other failures end the address.
+    fn scan_addresses(&mut self, addresses: &[u8]) -> Vec<ScanResult>
+    where
+        Self: Interface + Sized,
+    {
+        addresses
+            .iter()
+            .map(|&address| {
+                let mut key_bytes = None;
+                for timing in InitTiming::presets() {
+                    match self.sync(address, &timing) {
+                        Ok(true) => {
+                            key_bytes = self.finish_init(address, &timing).ok();
+                            break;
+                        }
+                        Ok(false) => continue,
+                        Err(_) => break,
+                    }
+                }
+                let result = ScanResult { address, key_bytes };
+                if result.key_bytes.is_some() {
+                    let stopped = result.is_kwp2000()
+                        && self.send(Message::StopCommunication).is_ok()
+                        && matches!(self.next_response(), Ok(Response::CommunicationStopped));
+                    if !stopped {
+                        self.delay(SCAN_SESSION_TIMEOUT);
+                    }
+                }
+                result
+            })
+            .collect()
+    }
+
     fn bitbang(&mut self, baud: u8, byte: u8) -> Result<(), Self::Error> {
-        let delay = Duration::from_millis(1_000 / baud as u64);
+        let delay = Duration::from_millis(1_000 / baud.max(1) as u64);
 
         for state in (0..8).map(|n| ((1 << n) & byte) == 0) {
             if state {
@@ -67,14 +248,16 @@ pub trait KLine {
 
     fn write_byte(&mut self, byte: u8) -> Result<(), Self::Error>;
     fn read_byte(&mut self) -> Result<u8, Self::Error>;
+    /// Reads a byte, `None` if nothing was received within `timeout`.
+    fn read_byte_timeout(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error>;
 
     fn set_high(&mut self) -> Result<(), Self::Error>;
     fn set_low(&mut self) -> Result<(), Self::Error>;
 }
 
-#[cfg(feature = "serialport")]
+#[cfg(feature = "transport-serialport")]
 impl<A: serialport::SerialPort> KLine for A {
-    type Error = serialport::Error;
+    type Error = Error;
 
     fn read_byte(&mut self) -> Result<u8, Self::Error> {
         let mut buf = [0u8];
@@ -82,12 +265,25 @@ impl<A: serialport::SerialPort> KLine for A {
         Ok(buf[0])
     }
 
+    fn read_byte_timeout(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        let previous = self.timeout();
+        self.set_timeout(timeout)?;
+        let mut buf = [0u8];
+        let result = self.read_exact(&mut buf);
+        self.set_timeout(previous)?;
+        match result {
+            Ok(()) => Ok(Some(buf[0])),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.set_break()
+        Ok(self.set_break()?)
     }
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.clear_break()
+        Ok(self.clear_break()?)
     }
 
     fn write_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
@@ -100,10 +296,10 @@ impl<A: serialport::SerialPort> KLine for A {
     }
 }
 
-#[cfg(feature = "serialport")]
+#[cfg(feature = "transport-serialport")]
 impl<A: serialport::SerialPort + std::io::Read + std::fmt::Debug> Interface for A {
     fn send_raw(&mut self, message: RawMessage) -> Result<(), Error> {
-        self.write_all(&message.to_bytes())?;
+        self.write_all(&message.to_bytes()?)?;
         Ok(())
     }
 
@@ -116,4 +312,100 @@ impl<A: serialport::SerialPort + std::io::Read + std::fmt::Debug> Interface for
         self.set_baud_rate(baud_rate)?;
         Ok(())
     }
+
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        let available = self.bytes_to_read()? as usize;
+        let start = buffer.len();
+        buffer.resize(start + available, 0);
+        self.read_exact(&mut buffer[start..])?;
+        Ok(available)
+    }
+
+    fn reinitialize(&mut self, address: u8, timing: &InitTiming) -> Result<(), Error> {
+        self.switch_baud(INIT_BAUD_RATE)?;
+        self.init_kwp2000(address, timing)
+    }
+}
+
+/// Serial port that remembers how it was opened, so it can be closed and
+/// opened again when the adapter wedges (`Interface::reset_hard`).
+#[cfg(feature = "transport-serialport")]
+#[derive(Debug)]
+pub struct SerialInterface {
+    builder: serialport::SerialPortBuilder,
+    /// `None` while the port is closed during `reset_hard`
+    port: Option<serialport::TTYPort>,
+}
+
+#[cfg(feature = "transport-serialport")]
+impl SerialInterface {
+    pub fn open(builder: serialport::SerialPortBuilder) -> Result<Self, Error> {
+        Ok(Self {
+            port: Some(builder.clone().open_native()?),
+            builder,
+        })
+    }
+
+    fn port(&mut self) -> Result<&mut serialport::TTYPort, Error> {
+        self.port
+            .as_mut()
+            .ok_or_else(|| Error::Io(ErrorKind::NotConnected.into()))
+    }
+}
+
+#[cfg(feature = "transport-serialport")]
+impl KLine for SerialInterface {
+    type Error = Error;
+
+    fn read_byte(&mut self) -> Result<u8, Self::Error> {
+        self.port()?.read_byte()
+    }
+    fn read_byte_timeout(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        self.port()?.read_byte_timeout(timeout)
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.port()?.set_high()
+    }
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.port()?.set_low()
+    }
+    fn write_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.port()?.write_byte(byte)
+    }
+    fn delay(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+#[cfg(feature = "transport-serialport")]
+impl Interface for SerialInterface {
+    fn send_raw(&mut self, message: RawMessage) -> Result<(), Error> {
+        self.port()?.send_raw(message)
+    }
+    fn next_raw_message(&mut self) -> Result<RawMessage, Error> {
+        self.port()?.next_raw_message()
+    }
+    fn switch_baud(&mut self, baud_rate: u32) -> Result<(), Error> {
+        self.port()?.switch_baud(baud_rate)
+    }
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        self.port()?.read_available(buffer)
+    }
+    fn reinitialize(&mut self, address: u8, timing: &InitTiming) -> Result<(), Error> {
+        self.switch_baud(INIT_BAUD_RATE)?;
+        self.init_kwp2000(address, timing)
+    }
+    fn reset_hard(&mut self) -> Result<(), Error> {
+        use serialport::SerialPort;
+
+        let mut builder = self.builder.clone();
+        if let Some(port) = self.port.take() {
+            // keep the baud rate and timeout changed since opening
+            builder = builder.baud_rate(port.baud_rate()?).timeout(port.timeout());
+            // closes the device so it can be opened again
+            drop(port);
+        }
+        self.port = Some(builder.open_native()?);
+        Ok(())
+    }
 }
@@ -0,0 +1,306 @@
+//! Async variant of the 5-baud initialization for callers that can't block
+//! for the two seconds it takes, like GUIs.
+
+use std::{
+    io::ErrorKind,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::Instant};
+
+use crate::{
+    Error,
+    k_line::{BUS_BUSY_SAMPLE_LEN, InitTiming, KLine},
+};
+
+/// How long a single read blocks before the future yields.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Time the ECU has to answer each step of the initialization.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Step of `init_kwp2000_async` in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitProgress {
+    WaitingForIdleBus,
+    /// Bit `bit` of the address is being sent, least significant first.
+    SendingAddress {
+        bit: u8,
+    },
+    /// Waiting for the 0x55 synchronization byte
+    WaitingForSync,
+    /// Key bytes received, the init fails if the second isn't 0x8F (KWP2000)
+    KeyBytes([u8; 2]),
+    Done,
+}
+
+/// Puts the line back to idle when dropped, so cancelling the init between
+/// bit times doesn't leave the break condition set.
+struct IdleOnDrop<'a, K: KLine>(&'a mut K);
+
+impl<K: KLine> Drop for IdleOnDrop<'_, K> {
+    fn drop(&mut self) {
+        let _ = self.0.set_low();
+    }
+}
+
+impl<K: KLine> Deref for IdleOnDrop<'_, K> {
+    type Target = K;
+    fn deref(&self) -> &K {
+        self.0
+    }
+}
+
+impl<K: KLine> DerefMut for IdleOnDrop<'_, K> {
+    fn deref_mut(&mut self) -> &mut K {
+        self.0
+    }
+}
+
+/// Reads a byte, `None` if nothing was received within `timeout`. Only
+/// blocks for `POLL_INTERVAL` at a time.
+async fn read_byte<K: KLine>(line: &mut K, timeout: Duration) -> Result<Option<u8>, K::Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(byte) = line.read_byte_timeout(POLL_INTERVAL)? {
+            return Ok(Some(byte));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn next_byte<K: KLine>(line: &mut K) -> Result<u8, K::Error> {
+    read_byte(line, RESPONSE_TIMEOUT)
+        .await?
+        .ok_or_else(|| Error::Io(ErrorKind::TimedOut.into()).into())
+}
+
+async fn expect_byte<K: KLine>(line: &mut K, byte: u8, timeout: Duration) -> Result<(), K::Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match read_byte(line, left).await? {
+            Some(b) if b == byte => return Ok(()),
+            Some(_) => {}
+            None => return Err(Error::Io(ErrorKind::TimedOut.into()).into()),
+        }
+    }
+}
+
+/// Async version of `KLine::init_kwp2000`, reporting its steps through
+/// `progress`. Dropping the future stops the init and leaves the line idle.
+pub async fn init_kwp2000_async<K: KLine>(
+    line: &mut K,
+    address: u8,
+    timing: &InitTiming,
+    progress: &watch::Sender<InitProgress>,
+) -> Result<(), K::Error> {
+    let mut line = IdleOnDrop(line);
+
+    progress.send_replace(InitProgress::WaitingForIdleBus);
+    line.set_low()?;
+    let limit = Instant::now() + line.bus_idle_limit();
+    let mut observed = 0;
+    let mut sample = Vec::new();
+    while let Some(byte) = read_byte(&mut *line, timing.idle).await? {
+        observed += 1;
+        if sample.len() < BUS_BUSY_SAMPLE_LEN {
+            sample.push(byte);
+        }
+        if Instant::now() >= limit {
+            return Err(Error::BusBusy { observed, sample }.into());
+        }
+    }
+
+    // start bit, then the address at 5 baud
+    line.set_high()?;
+    tokio::time::sleep(timing.start).await;
+    for bit in 0..8 {
+        progress.send_replace(InitProgress::SendingAddress { bit });
+        if address & (1 << bit) == 0 {
+            line.set_high()?;
+        } else {
+            line.set_low()?;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    line.set_low()?;
+
+    progress.send_replace(InitProgress::WaitingForSync);
+    expect_byte(&mut *line, 0x55, timing.sync_timeout).await?;
+    let key_bytes = [next_byte(&mut *line).await?, next_byte(&mut *line).await?];
+    progress.send_replace(InitProgress::KeyBytes(key_bytes));
+    if key_bytes[1] != 0x8F {
+        return Err(Error::UnexpectedValue.into());
+    }
+
+    tokio::time::sleep(timing.w4).await;
+    line.write_byte(0xFF - key_bytes[1])?;
+    expect_byte(&mut *line, 0xFF - address, RESPONSE_TIMEOUT).await?;
+    tokio::time::sleep(timing.settle).await;
+
+    progress.send_replace(InitProgress::Done);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        High,
+        Low,
+        Write(u8),
+        Read(u8),
+    }
+
+    /// K-line of an ECU at address 0x01 answering the init once the address
+    /// was sent, recording what the init did, when and during which step.
+    struct ScriptedLine {
+        start: Instant,
+        /// When the sync byte and the key bytes can be read
+        answer_at: Instant,
+        received: VecDeque<u8>,
+        progress: watch::Receiver<InitProgress>,
+        events: Vec<(Duration, InitProgress, Event)>,
+    }
+
+    impl ScriptedLine {
+        fn new(timing: &InitTiming, progress: watch::Receiver<InitProgress>) -> Self {
+            let start = Instant::now();
+            Self {
+                start,
+                answer_at: start + timing.idle + timing.start + Duration::from_millis(1600),
+                received: VecDeque::from([0x55, 0x01, 0x8F]),
+                progress,
+                events: Vec::new(),
+            }
+        }
+        fn record(&mut self, event: Event) {
+            let progress = *self.progress.borrow();
+            self.events.push((self.start.elapsed(), progress, event));
+        }
+        /// Times of the level changes.
+        fn levels(&self) -> Vec<(Duration, Event)> {
+            self.events
+                .iter()
+                .filter(|(_, _, e)| matches!(e, Event::High | Event::Low))
+                .map(|(t, _, e)| (*t, *e))
+                .collect()
+        }
+    }
+
+    impl KLine for ScriptedLine {
+        type Error = Error;
+
+        fn delay(&self, _duration: Duration) {
+            panic!("the async init must not block");
+        }
+        fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+            self.record(Event::Write(byte));
+            // the ECU answers with the complement of the address
+            self.received.push_back(0xFE);
+            Ok(())
+        }
+        fn read_byte(&mut self) -> Result<u8, Error> {
+            panic!("the async init must not block");
+        }
+        fn read_byte_timeout(&mut self, _timeout: Duration) -> Result<Option<u8>, Error> {
+            if Instant::now() < self.answer_at {
+                return Ok(None);
+            }
+            let byte = self.received.pop_front();
+            if let Some(b) = byte {
+                self.record(Event::Read(b));
+            }
+            Ok(byte)
+        }
+        fn set_high(&mut self) -> Result<(), Error> {
+            self.record(Event::High);
+            Ok(())
+        }
+        fn set_low(&mut self) -> Result<(), Error> {
+            self.record(Event::Low);
+            Ok(())
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn address_is_sent_at_5_baud() {
+        let timing = InitTiming::default();
+        let (progress, receiver) = watch::channel(InitProgress::Done);
+        let mut line = ScriptedLine::new(&timing, receiver.clone());
+        init_kwp2000_async(&mut line, 0x01, &timing, &progress)
+            .await
+            .unwrap();
+        assert_eq!(*receiver.borrow(), InitProgress::Done);
+
+        // start bit after the idle bus, then the bits of 0x01 200ms apart
+        let bits_start = timing.idle + timing.start;
+        let mut expected = vec![(Duration::ZERO, Event::Low), (timing.idle, Event::High)];
+        expected.push((bits_start, Event::Low));
+        for bit in 1..8 {
+            expected.push((bits_start + ms(200 * bit), Event::High));
+        }
+        expected.push((bits_start + ms(1600), Event::Low));
+        // the guard idles the line once more when done
+        expected.push((
+            bits_start + ms(1600) + timing.w4 + timing.settle,
+            Event::Low,
+        ));
+        assert_eq!(line.levels(), expected);
+
+        let steps: Vec<_> = line.events.iter().map(|(_, p, e)| (*p, *e)).collect();
+        assert_eq!(
+            steps[steps.len() - 6..],
+            [
+                (InitProgress::WaitingForSync, Event::Read(0x55)),
+                (InitProgress::WaitingForSync, Event::Read(0x01)),
+                (InitProgress::WaitingForSync, Event::Read(0x8F)),
+                (InitProgress::KeyBytes([0x01, 0x8F]), Event::Write(0x70)),
+                (InitProgress::KeyBytes([0x01, 0x8F]), Event::Read(0xFE)),
+                (InitProgress::Done, Event::Low),
+            ][..]
+        );
+        let mut bits: Vec<_> = steps
+            .iter()
+            .filter_map(|(p, _)| match p {
+                InitProgress::SendingAddress { bit } => Some(*bit),
+                _ => None,
+            })
+            .collect();
+        // the stop bit is sent during bit 7
+        bits.dedup();
+        assert_eq!(bits, [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_init_leaves_the_line_idle() {
+        let timing = InitTiming::default();
+        let (progress, receiver) = watch::channel(InitProgress::Done);
+        let mut line = ScriptedLine::new(&timing, receiver.clone());
+        // cancelled while sending bit 2, which set the break condition
+        let cancel_at = timing.idle + timing.start + ms(500);
+        let init = init_kwp2000_async(&mut line, 0x00, &timing, &progress);
+        assert!(tokio::time::timeout(cancel_at, init).await.is_err());
+        assert_eq!(*receiver.borrow(), InitProgress::SendingAddress { bit: 2 });
+        assert_eq!(line.levels().last(), Some(&(cancel_at, Event::Low)));
+        assert!(
+            !line
+                .events
+                .iter()
+                .any(|(_, _, e)| matches!(e, Event::Write(_)))
+        );
+    }
+}
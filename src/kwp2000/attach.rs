@@ -0,0 +1,103 @@
+//! Taking over a session another process or component already initialized,
+//! redoing the 5-baud init would drop it.
+
+use crate::Error;
+
+use super::{
+    INIT_BAUD_RATE,
+    client::{Client, DebugInterface},
+    constants::DiagnosticMode,
+    raw_message::HeaderFormat,
+    timing::TimingState,
+    warning::Warning,
+};
+
+/// What is assumed about the session `Client::attach` takes over. The
+/// diagnostic mode is left unknown and security access locked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionState {
+    /// Baud rate the session is running at.
+    pub baud_rate: u32,
+    pub header_format: HeaderFormat,
+    pub timing: TimingState,
+    /// Identification option and the value it must have for the ECU to be
+    /// the expected one, not checked if `None`.
+    pub expected_identification: Option<(u8, Vec<u8>)>,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            baud_rate: INIT_BAUD_RATE,
+            header_format: HeaderFormat::default(),
+            timing: TimingState::default(),
+            expected_identification: None,
+        }
+    }
+}
+
+/// How `Client::attach` connects when the session is gone, and what
+/// `quick_connect` does after connecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectOptions {
+    pub mode: DiagnosticMode,
+    pub baud_rate: Option<u32>,
+    /// Get security access, only used by `quick_connect`.
+    pub security: bool,
+    /// Identification option to read, only used by `quick_connect`.
+    pub identification: Option<u8>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            mode: DiagnosticMode::Diagnostics,
+            baud_rate: None,
+            security: false,
+            identification: None,
+        }
+    }
+}
+
+impl Client {
+    /// Creates a client on a link whose session is still open, without
+    /// initializing the ECU. The session is checked with a `TesterPresent`,
+    /// if the ECU doesn't answer and `connect` is given it is initialized
+    /// again (see `recover_session`) and switched to `connect.mode`.
+    /// Fails with `Error::WrongEcu` if the identification in `assumed`
+    /// doesn't match, whether the session was kept or not.
+    pub fn attach(
+        interface: Box<dyn DebugInterface>,
+        assumed: SessionState,
+        connect: Option<ConnectOptions>,
+    ) -> Result<Client, Error> {
+        let mut client = Client::new(interface);
+        client.interface.switch_baud(assumed.baud_rate)?;
+        client.baud_rate = assumed.baud_rate;
+        client.header_format = assumed.header_format;
+        client.timing = assumed.timing;
+        if let Err(error) = client.tester_present() {
+            // the request is not going to be answered
+            client.reset_session_state();
+            let Some(options) = connect else {
+                return Err(error);
+            };
+            client.warn(Warning::AttachFailed {
+                baud_rate: assumed.baud_rate,
+            });
+            client.recover_session()?;
+            client.switch_mode(options.mode, options.baud_rate)?;
+        }
+        if let Some((option, expected)) = assumed.expected_identification {
+            let got = client.read_ecu_identification(option)?;
+            if got != expected {
+                return Err(Error::WrongEcu {
+                    option,
+                    expected,
+                    got,
+                });
+            }
+        }
+        Ok(client)
+    }
+}
@@ -0,0 +1,189 @@
+use crate::Error;
+
+use super::{client::Client, response::Response};
+
+/// Result of `Client::write_batch`.
+#[derive(Debug, Clone)]
+pub struct BatchReport {
+    /// Whether each entry of the batch was written, in the order given.
+    pub written: Vec<bool>,
+    /// Address and length of every write sent after coalescing.
+    pub writes: Vec<(u32, u32)>,
+}
+
+/// Contiguous range made of one or more batch entries.
+struct Range {
+    address: u64,
+    data: Vec<u8>,
+    entries: Vec<usize>,
+}
+
+/// Merges the entries into as few contiguous ranges as possible. Fails with
+/// `Error::WriteConflict` if two entries write different values to the same
+/// byte.
+fn coalesce(writes: &[(u32, Vec<u8>)]) -> Result<Vec<Range>, Error> {
+    let mut order: Vec<usize> = (0..writes.len())
+        .filter(|i| !writes[*i].1.is_empty())
+        .collect();
+    order.sort_by_key(|i| writes[*i].0);
+
+    let mut ranges: Vec<Range> = Vec::new();
+    for i in order {
+        let (address, data) = &writes[i];
+        let address = *address as u64;
+        match ranges.last_mut() {
+            Some(range) if address <= range.address + range.data.len() as u64 => {
+                let offset = (address - range.address) as usize;
+                for (j, b) in data.iter().enumerate() {
+                    match range.data.get(offset + j) {
+                        Some(existing) if existing != b => {
+                            return Err(Error::WriteConflict {
+                                address: (address + j as u64) as u32,
+                            });
+                        }
+                        Some(_) => {}
+                        None => range.data.push(*b),
+                    }
+                }
+                range.entries.push(i);
+            }
+            _ => ranges.push(Range {
+                address,
+                data: data.clone(),
+                entries: vec![i],
+            }),
+        }
+    }
+    Ok(ranges)
+}
+
+impl Client {
+    /// Writes every `(address, data)` entry using as few dynamic identifier
+    /// writes as possible by merging adjacent and overlapping entries and
+    /// splitting them into what fits in one message.
+    /// Conflicting entries are rejected before anything is sent. Entries
+    /// the ECU refuses to write are reported as not written, other errors
+    /// stop the batch.
+    pub fn write_batch(&mut self, writes: &[(u32, Vec<u8>)]) -> Result<BatchReport, Error> {
+        let ranges = coalesce(writes)?;
+        let mut report = BatchReport {
+            written: writes.iter().map(|(_, d)| d.is_empty()).collect(),
+            writes: Vec::new(),
+        };
+        let max_write = self.max_dd_write();
+        for range in ranges {
+            let mut failed = Vec::new();
+            for (n, chunk) in range.data.chunks(max_write).enumerate() {
+                let start = range.address + (n * max_write) as u64;
+                let end = start + chunk.len() as u64;
+                let address = start as u32;
+                match self.dd_write_address(address, chunk.to_vec()) {
+                    Ok(()) => report.writes.push((address, chunk.len() as u32)),
+                    Err(Error::UnexpectedResponse(Response::Error(_))) => {
+                        failed.push((start, end));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            for i in range.entries {
+                let (address, data) = &writes[i];
+                let start = *address as u64;
+                let end = start + data.len() as u64;
+                report.written[i] = !failed.iter().any(|(s, e)| start < *e && end > *s);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+    use std::sync::{Arc, Mutex};
+
+    /// ECU accepting dynamic identifier writes except for definitions
+    /// starting in `rejected`, recording every request it gets.
+    fn write_ecu(rejected: std::ops::Range<u32>) -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let client = mock::client(move |request| {
+            log.lock().unwrap().push(request.to_vec());
+            vec![Ok(match request {
+                [0x2C, 0xF0, 0x04] => vec![0x6C, 0xF0],
+                [0x2C, 0xF0, 0x03, 0x01, _, a, b, c] => {
+                    if rejected.contains(&u32::from_be_bytes([0, *a, *b, *c])) {
+                        vec![0x7F, 0x2C, 0x31]
+                    } else {
+                        vec![0x6C, 0xF0]
+                    }
+                }
+                [0x3B, 0xF0, ..] => vec![0x7B, 0xF0],
+                _ => panic!("unexpected request {request:02X?}"),
+            })]
+        });
+        (client, requests)
+    }
+
+    #[test]
+    fn batch_needs_fewer_messages_than_single_writes() {
+        let batch = vec![
+            (0x104, vec![5, 6]),
+            (0x100, vec![1, 2]),
+            (0x102, vec![3, 4]),
+            (0x103, vec![4, 5]),
+            (0x106, vec![7, 8]),
+            (0x200, vec![9]),
+            (0x300, vec![10, 11]),
+            (0x301, vec![11]),
+        ];
+        let (mut client, requests) = write_ecu(0..0);
+        for (address, data) in &batch {
+            client.dd_write_address(*address, data.clone()).unwrap();
+        }
+        let naive = requests.lock().unwrap().len();
+        assert_eq!(naive, 3 * batch.len());
+
+        let (mut client, requests) = write_ecu(0..0);
+        let report = client.write_batch(&batch).unwrap();
+        assert!(report.written.iter().all(|w| *w));
+        assert_eq!(report.writes, [(0x100, 8), (0x200, 1), (0x300, 2)]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3 * 3);
+        assert!(requests.contains(&vec![0x3B, 0xF0, 1, 2, 3, 4, 5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn long_ranges_are_split() {
+        let (mut client, requests) = write_ecu(0..0);
+        let max = client.max_dd_write();
+        let report = client
+            .write_batch(&[(0x1000, vec![0xAA; max]), (0x1000 + max as u32, vec![0xBB])])
+            .unwrap();
+        assert_eq!(
+            report.writes,
+            [(0x1000, max as u32), (0x1000 + max as u32, 1)]
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2 * 3);
+    }
+
+    #[test]
+    fn conflict_sends_nothing() {
+        let (mut client, requests) = write_ecu(0..0);
+        assert!(matches!(
+            client.write_batch(&[(0x100, vec![1, 2, 3]), (0x102, vec![4])]),
+            Err(Error::WriteConflict { address: 0x102 })
+        ));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refused_writes_are_reported() {
+        let (mut client, _) = write_ecu(0x200..0x201);
+        let report = client
+            .write_batch(&[(0x100, vec![1]), (0x200, vec![2]), (0x300, vec![])])
+            .unwrap();
+        assert_eq!(report.written, [true, false, true]);
+        assert_eq!(report.writes, [(0x100, 1)]);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::ServiceError,
+    progress::{Operation, Phase, Tracker},
+    response::{ProcessError, Response},
+    warning::Warning,
+};
+
+/// Largest piece `Client::read_best_effort` reads with one strategy, smaller
+/// if the header format doesn't allow it.
+const CHUNK_SIZE: u32 = 0x80;
+
+/// Mechanisms that can be used to read the ECU's memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadStrategy {
+    /// `Client::dd_read_address`
+    DynamicIdentifier,
+    ReadMemoryByAddress,
+    /// `Client::read_data`, usually needs the programming session
+    Upload,
+}
+
+/// Whether `Client::read_best_effort` uses uploads, which are fast but on
+/// most ECUs only allowed in the programming session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// Try the strategies in the order of `Client::read_strategies`,
+    /// uploading only in the current session.
+    #[default]
+    Configured,
+    /// Try an upload first, entering the programming session if the ECU
+    /// refuses it in the current one, and fall back to the other strategies
+    /// if it is still refused.
+    PreferFast,
+    /// Only read with uploads, entering the programming session if needed.
+    RequireFast,
+    /// Never upload.
+    CompatibilityOnly,
+}
+
+/// Result of `Client::read_best_effort`.
+#[derive(Debug, Clone)]
+pub struct BestEffortRead {
+    /// Data read, ranges that could not be read are filled with zeros.
+    pub data: Vec<u8>,
+    /// Address, length and strategy of every range that was read.
+    pub served: Vec<(u32, u32, ReadStrategy)>,
+    /// Address and length of every range that could not be read.
+    pub unreadable: Vec<(u32, u32)>,
+}
+
+/// Adds `address..address + length` to `ranges`, merging it with the last
+/// range if they are adjacent and were read the same way.
+fn push_range<T: PartialEq + Copy>(
+    ranges: &mut Vec<(u32, u32, T)>,
+    address: u32,
+    length: u32,
+    t: T,
+) {
+    match ranges.last_mut() {
+        Some((a, l, last)) if *last == t && *a + *l == address => *l += length,
+        _ => ranges.push((address, length, t)),
+    }
+}
+
+/// Returns true if `error` is the ECU refusing a service because of the
+/// session it is in.
+fn is_wrong_session(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::UnexpectedResponse(Response::Error(ProcessError {
+            error: ServiceError::ServiceNotSupportedInActiveMode,
+            ..
+        }))
+    )
+}
+
+/// Returns true if `error` is the ECU refusing a read mechanism (for this
+/// address or in this session), meaning the next strategy should be tried.
+fn is_refusal(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::UnexpectedResponse(Response::Error(ProcessError {
+            error: ServiceError::ServiceNotSupported
+                | ServiceError::ServiceNotSupportedInActiveMode
+                | ServiceError::FunctionNotSupportedOrInvalidFormat
+                | ServiceError::ConditionsNotCorrect
+                | ServiceError::SecurityAccessRequired
+                | ServiceError::RequestOutOfRange
+                | ServiceError::GeneralReject
+                | ServiceError::UploadNotAccepted
+                | ServiceError::ImproperUploadType
+                | ServiceError::CannotUploadFromAddress
+                | ServiceError::BadUploadSize,
+            ..
+        }))
+    )
+}
+
+impl Client {
+    /// Uploads `length` bytes from `address`, entering the programming
+    /// session first if the current session doesn't allow it. The session is
+    /// left again if the upload still fails, `in_programming` tracks whether
+    /// this left the ECU in the programming session.
+    fn upload_in_programming_session(
+        &mut self,
+        address: u32,
+        length: u32,
+        in_programming: &mut bool,
+    ) -> Result<Vec<u8>, Error> {
+        match self.read_with_strategy(ReadStrategy::Upload, address, length) {
+            Err(e) if !*in_programming && is_wrong_session(&e) => {
+                let result = self
+                    .programming_mode(None)
+                    .and_then(|_| self.read_with_strategy(ReadStrategy::Upload, address, length));
+                if result.is_ok() {
+                    *in_programming = true;
+                } else {
+                    // the switch may have happened even if it reported an error
+                    let _ = self.diagnostic_mode();
+                }
+                result
+            }
+            r => r,
+        }
+    }
+    fn read_with_strategy(
+        &mut self,
+        strategy: ReadStrategy,
+        address: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, Error> {
+        match strategy {
+            ReadStrategy::DynamicIdentifier => match self.dd_read_address(address, length as u8) {
+                // another strategy may read all of it
+                Err(Error::PartialRead { .. }) => Err(Error::NotEnoughData),
+                r => r,
+            },
+            ReadStrategy::ReadMemoryByAddress => self.read_memory_by_address(address, length as u8),
+            ReadStrategy::Upload => {
+                let mut data = Vec::with_capacity(length as usize);
+                match self.read_data(address, length, &mut data) {
+                    Ok(_) if data.len() == length as usize => Ok(data),
+                    Ok(_) => Err(Error::NotEnoughData),
+                    Err(Error::PartialRead { source, .. }) => {
+                        Err(Error::partial(data, address, *source))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Reads as much of `size` bytes starting at `address` as possible, trying
+    /// the strategies in `read_strategies` in order for every chunk. Which
+    /// strategy worked is remembered in `read_strategy_regions` and tried
+    /// first for later reads in the same region. `read_policy` decides
+    /// whether and when uploads are used, a programming session entered for
+    /// them is left before returning.
+    ///
+    /// Errors after part of the range was read are returned as
+    /// `Error::PartialRead` holding the data read so far.
+    pub fn read_best_effort(&mut self, address: u32, size: u32) -> Result<BestEffortRead, Error> {
+        let mut tracker = Tracker::start(self, Operation::Read, address, size as usize);
+        let result = self.read_best_effort_tracked(address, size, &mut tracker);
+        tracker.finish(self, &result);
+        result
+    }
+    /// `read_best_effort` as part of the operation tracked by `tracker`.
+    pub(crate) fn read_best_effort_tracked(
+        &mut self,
+        address: u32,
+        size: u32,
+        tracker: &mut Tracker,
+    ) -> Result<BestEffortRead, Error> {
+        let mut read = BestEffortRead {
+            data: Vec::with_capacity(size as usize),
+            served: Vec::new(),
+            unreadable: Vec::new(),
+        };
+        let mut in_programming = false;
+        let mut result =
+            self.read_best_effort_into(address, size, &mut read, &mut in_programming, tracker);
+        if in_programming {
+            let left = self.diagnostic_mode();
+            if result.is_ok() {
+                result = left;
+            }
+        }
+        match result {
+            Ok(()) => Ok(read),
+            Err(e) => Err(Error::partial(read.data, address, e)),
+        }
+    }
+    fn read_best_effort_into(
+        &mut self,
+        address: u32,
+        size: u32,
+        read: &mut BestEffortRead,
+        in_programming: &mut bool,
+        tracker: &mut Tracker,
+    ) -> Result<(), Error> {
+        let configured: Vec<ReadStrategy> = match self.read_policy {
+            ReadPolicy::Configured => self.read_strategies.clone(),
+            ReadPolicy::PreferFast => std::iter::once(ReadStrategy::Upload)
+                .chain(
+                    self.read_strategies
+                        .iter()
+                        .copied()
+                        .filter(|s| *s != ReadStrategy::Upload),
+                )
+                .collect(),
+            ReadPolicy::RequireFast => vec![ReadStrategy::Upload],
+            ReadPolicy::CompatibilityOnly => self
+                .read_strategies
+                .iter()
+                .copied()
+                .filter(|s| *s != ReadStrategy::Upload)
+                .collect(),
+        };
+        // not tried again in this read once refused
+        let mut upload_refused = false;
+        let mut offset = 0;
+        while offset < size {
+            self.check_deadline(offset as usize)?;
+            let chunk_address = address.wrapping_add(offset);
+            let chunk_size = Ord::min(CHUNK_SIZE, self.max_dd_read() as u32);
+            let length = Ord::min(chunk_size, size - offset);
+
+            let known = self
+                .read_strategy_regions
+                .iter()
+                .find(|(a, l, _)| (*a..*a + *l).contains(&chunk_address))
+                .map(|(_, _, s)| *s)
+                .filter(|s| configured.contains(s));
+            let strategies: Vec<ReadStrategy> = known
+                .into_iter()
+                .chain(configured.iter().copied().filter(|s| Some(*s) != known))
+                .collect();
+
+            let mut served = None;
+            for strategy in strategies {
+                let upload = strategy == ReadStrategy::Upload;
+                if upload && upload_refused {
+                    continue;
+                }
+                let result = if upload && self.read_policy != ReadPolicy::Configured {
+                    self.upload_in_programming_session(chunk_address, length, in_programming)
+                } else {
+                    self.read_with_strategy(strategy, chunk_address, length)
+                };
+                match result {
+                    Ok(data) if data.len() == length as usize => {
+                        read.data.extend_from_slice(&data);
+                        served = Some(strategy);
+                        break;
+                    }
+                    Ok(_) => {}
+                    // keeps what the upload got before failing
+                    Err(Error::PartialRead { data, source, .. }) => {
+                        read.data.extend_from_slice(&data);
+                        return Err(*source);
+                    }
+                    Err(e) if self.read_policy == ReadPolicy::RequireFast => return Err(e),
+                    Err(e) if is_refusal(&e) || matches!(e, Error::NotEnoughData) => {
+                        upload_refused |= upload;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            match served {
+                Some(strategy) => {
+                    if configured.first() != Some(&strategy) && known != Some(strategy) {
+                        self.warn(Warning::ReadStrategyFallback {
+                            address: chunk_address,
+                            strategy,
+                        });
+                    }
+                    push_range(&mut read.served, chunk_address, length, strategy);
+                    if known != Some(strategy) {
+                        push_range(
+                            &mut self.read_strategy_regions,
+                            chunk_address,
+                            length,
+                            strategy,
+                        );
+                    }
+                }
+                None => {
+                    read.data.resize(read.data.len() + length as usize, 0);
+                    match read.unreadable.last_mut() {
+                        Some((a, l)) if *a + *l == chunk_address => *l += length,
+                        _ => read.unreadable.push((chunk_address, length)),
+                    }
+                }
+            }
+            offset += length;
+            tracker.update(
+                self,
+                Phase::Transferring,
+                address.wrapping_add(offset),
+                offset as usize,
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{mock, progress::ProgressHandler};
+    use std::sync::{Arc, Mutex};
+
+    /// Client for an ECU refusing uploads and serving dynamic identifier
+    /// reads, and the services of the requests sent to it.
+    fn upload_refusing_client() -> (Client, Arc<Mutex<Vec<u8>>>) {
+        let services = Arc::new(Mutex::new(Vec::new()));
+        let sent = services.clone();
+        let mut dd = mock::dd_ecu(0..0);
+        let client = mock::client(move |request| {
+            sent.lock().unwrap().push(request[0]);
+            match request {
+                // ConditionsNotCorrect
+                [0x35, ..] => vec![Ok(vec![0x7F, 0x35, 0x22])],
+                _ => dd(request),
+            }
+        });
+        (client, services)
+    }
+
+    #[test]
+    fn default_policy_keeps_the_configured_order() {
+        let (mut client, services) = upload_refusing_client();
+        let read = client.read_best_effort(0x10, 0x20).unwrap();
+        assert_eq!(read.data, (0x10..0x30).collect::<Vec<u8>>());
+        assert_eq!(
+            read.served,
+            vec![(0x10, 0x20, ReadStrategy::DynamicIdentifier)]
+        );
+        assert!(!services.lock().unwrap().contains(&0x35));
+    }
+
+    #[test]
+    fn prefer_fast_tries_an_upload_first() {
+        let (mut client, services) = upload_refusing_client();
+        client.read_policy = ReadPolicy::PreferFast;
+        let read = client.read_best_effort(0x10, 0x20).unwrap();
+        assert_eq!(read.data, (0x10..0x30).collect::<Vec<u8>>());
+        assert_eq!(services.lock().unwrap().first(), Some(&0x35));
+    }
+
+    /// Phase and bytes done of every progress report of `client`, and the
+    /// JSON documents of the reports.
+    fn record_progress(client: &mut Client) -> Arc<Mutex<Vec<(Phase, usize, String)>>> {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        client.on_progress = ProgressHandler(Some(Box::new(move |progress| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((progress.phase, progress.bytes_done, progress.to_json()))
+        })));
+        reports
+    }
+
+    #[test]
+    fn progress_goes_from_started_to_finished() {
+        let (mut client, _) = upload_refusing_client();
+        let reports = record_progress(&mut client);
+        let chunk = Ord::min(CHUNK_SIZE, client.max_dd_read() as u32) as usize;
+        client.read_best_effort(0x10, 3 * chunk as u32).unwrap();
+
+        let reports = reports.lock().unwrap();
+        let phases: Vec<_> = reports.iter().map(|(p, done, _)| (*p, *done)).collect();
+        assert_eq!(
+            phases,
+            [
+                (Phase::Started, 0),
+                (Phase::Transferring, chunk),
+                (Phase::Transferring, 2 * chunk),
+                (Phase::Transferring, 3 * chunk),
+                (Phase::Finished, 3 * chunk),
+            ]
+        );
+        assert!(reports[0].2.contains(r#""phase":"Started""#));
+        assert!(reports[4].2.contains(r#""phase":"Finished""#));
+        assert!(reports[4].2.contains(r#""last_error":null"#));
+    }
+
+    #[test]
+    fn failed_read_ends_in_the_failed_phase() {
+        let mut dd = mock::dd_ecu(0..0);
+        let mut reads = 0;
+        let mut client = mock::client(move |request| match request {
+            [0x21, ..] if reads == 1 => vec![],
+            [0x21, ..] => {
+                reads += 1;
+                dd(request)
+            }
+            _ => dd(request),
+        });
+        let reports = record_progress(&mut client);
+        assert!(client.read_best_effort(0x10, 0x100).is_err());
+
+        let reports = reports.lock().unwrap();
+        let (phase, _, json) = reports.last().unwrap();
+        assert_eq!(*phase, Phase::Failed);
+        assert!(!json.contains(r#""last_error":null"#));
+        assert_eq!(reports.iter().filter(|(p, ..)| p.is_terminal()).count(), 1);
+    }
+}
@@ -0,0 +1,65 @@
+//! Exclusive use of the bus during block transfers.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::Error;
+
+use super::constants::ServiceId;
+
+/// Held by block transfers for their whole duration. Any other request
+/// sent between the blocks would make the ECU abort the transfer.
+#[derive(Debug, Default)]
+pub(crate) struct BusToken(Arc<AtomicBool>);
+
+impl BusToken {
+    /// Takes the token until the returned guard is dropped, which also
+    /// happens when unwinding from a panic.
+    pub(crate) fn acquire(&self) -> Result<BusGuard, Error> {
+        if self.0.swap(true, Ordering::AcqRel) {
+            return Err(Error::BusBusyWithTransfer);
+        }
+        Ok(BusGuard(self.0.clone()))
+    }
+
+    pub(crate) fn is_held(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+pub(crate) struct BusGuard(Arc<AtomicBool>);
+
+impl Drop for BusGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// What keeps the bus busy, checked by the keep-alive and watchdog before
+/// they send anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BusActivity {
+    #[default]
+    Idle,
+    /// A block transfer holds the bus.
+    Transfer,
+    /// The ECU answered `ResponsePending`, or `RoutineNotComplete` to the
+    /// last poll for routine results. Its responses keep the session open
+    /// and any other request would abort what it is doing (a flash erase
+    /// for example).
+    EcuBusy,
+}
+
+/// Whether a request of `service` is part of a block transfer and may be
+/// sent while the token is held.
+pub(crate) fn is_transfer_service(service: ServiceId) -> bool {
+    matches!(
+        service,
+        ServiceId::RequestDownload
+            | ServiceId::RequestUpload
+            | ServiceId::TransferData
+            | ServiceId::RequestTransferExit
+    )
+}
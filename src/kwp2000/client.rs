@@ -1,28 +1,345 @@
-use std::{fmt::Debug, io::ErrorKind};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
 use crate::{
     Error,
     kwp2000::{
-        constants::{ReadMode, ServiceError, ServiceId},
+        constants::{CommonIdentifier, LocalIdentifier, ReadMode, ServiceError, ServiceId, me7},
         response::ProcessError,
-        security_key_from_seed,
     },
+    profile::{Capabilities, EcuProfile, Heartbeat},
 };
 
 use super::{
-    Interface,
-    constants::{CompressionFormat, DiagnosticMode, EncryptionFormat, TimingParameter},
-    message::{Message, TransferType},
-    response::Response,
+    INIT_BAUD_RATE, Interface,
+    best_effort::{ReadPolicy, ReadStrategy},
+    bus::{BusActivity, BusToken, is_transfer_service},
+    constants::{DiagnosticMode, ResetMode, Service, ServiceResponse, TimingParameter},
+    diagnostics::{self, Hint},
+    live::LiveChangeHandler,
+    message::Message,
+    policy::{DangerPolicy, DangerousAction},
+    progress::ProgressHandler,
+    raw_message::{FrameAssembler, HeaderFormat, RawMessage},
+    recovery::{LinkHealth, is_timeout},
+    response::{self, Echo, RequestContext, Response},
+    security::{SecurityAccessOutcome, SecurityAccessReport, SeedHistory, seed_bytes},
+    stats::ClientStats,
+    teardown::{EcuChange, EcuChanges},
+    timing::TimingState,
+    transfer::TransferRequest,
+    warning::{MAX_WARNINGS, Warning},
+    watchdog::Watchdog,
+};
+#[cfg(feature = "flash")]
+use {
+    super::codec::{BoschCodec, DataCodec},
+    super::fingerprint::TesterFingerprint,
+    std::sync::Arc,
 };
 
-pub trait DebugInterface: Interface + Debug {}
+/// Time source of the client's rate limiter.
+pub trait Clock: Debug + Send {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// `Clock` of the system, the default.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Largest `Client::response_window` that is honoured.
+pub const MAX_RESPONSE_WINDOW: usize = 4;
+
+/// Number of responses kept for `Client::take_unsolicited`, older ones are
+/// dropped.
+const MAX_UNSOLICITED: usize = 16;
+
+/// Number of received frames kept for `Client::recent_frames`.
+const MAX_RECENT_FRAMES: usize = 16;
+
+/// Added to the response timeouts of `Client::poll`, serial adapters pass
+/// on received bytes with some delay.
+pub(crate) const POLL_LATENCY_MARGIN: Duration = Duration::from_millis(100);
+
+/// Shortest dynamic identifier definition tried after the ECU rejected
+/// longer ones.
+const MIN_DD_LENGTH: u8 = 8;
+
+/// Sub function of `SecurityAccess` requesting the seed.
+const SEED_REQUEST: u8 = 0x01;
 
-impl DebugInterface for serialport::TTYPort {}
+/// Interface the client talks through, implemented for every `Interface`
+/// that is `Debug` and `Send`.
+pub trait DebugInterface: Interface + Debug + Send + sealed::Sealed {}
+
+impl<T: Interface + Debug + Send> DebugInterface for T {}
+
+mod sealed {
+    /// Keeps `DebugInterface` implemented only by the blanket impl, so
+    /// methods can be added to it without breaking anyone.
+    pub trait Sealed {}
+
+    impl<T: super::Interface + super::Debug + Send> Sealed for T {}
+}
+
+/// Layout of the data in `TransferData` blocks sent by the ECU during an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadBlockFormat {
+    /// Blocks only contain data.
+    Plain,
+    /// Each block starts with the 3 byte address of the data it contains.
+    AddressPrefixed,
+}
+
+/// Whether `TransferData` blocks start with a block sequence counter, in
+/// both directions. The counter is 1 for the first block of a transfer and
+/// wraps around to 0 after 0xFF. During downloads the ECU answers each block
+/// with the counter of the last block it accepted, answering with the
+/// counter of the block before that asks for the last block again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockCounter {
+    #[default]
+    Off,
+    Rolling,
+    /// Detected from the first block of the next upload, a counter is
+    /// assumed if it starts with 0x01. The profile is updated with the
+    /// result.
+    Detect,
+}
+
+/// Maximum number of times the same block is sent again because the ECU
+/// asked for it.
+#[cfg(feature = "flash")]
+pub(crate) const MAX_BLOCK_REPEATS: u32 = 3;
 
 #[derive(Debug)]
 pub struct Client {
     pub interface: Box<dyn DebugInterface>,
+    /// If `None` the format is detected from the first block of each upload.
+    pub upload_block_format: Option<UploadBlockFormat>,
+    pub profile: EcuProfile,
+    pub danger_policy: DangerPolicy,
+    /// Called with the progress of reads and flash writes.
+    pub on_progress: ProgressHandler,
+    /// Called after a live variable was written.
+    pub on_live_change: LiveChangeHandler,
+    pub stats: ClientStats,
+    /// Used to space requests by the profile's rate limits and to hold seed
+    /// requests back after a security lockout.
+    pub clock: Box<dyn Clock>,
+    /// Long running operations (transfers, range reads, security access
+    /// retries) fail with `Error::DeadlineExceeded` once this has passed.
+    /// Checked between protocol steps and takes precedence over retries.
+    pub deadline: Option<Instant>,
+    /// Whether `read_best_effort` uses uploads.
+    pub read_policy: ReadPolicy,
+    /// Order in which `read_best_effort` tries the read mechanisms.
+    pub read_strategies: Vec<ReadStrategy>,
+    /// Address, length and strategy of the regions `read_best_effort` found
+    /// a working strategy for.
+    pub read_strategy_regions: Vec<(u32, u32, ReadStrategy)>,
+    /// Start flash writes even if the ECU reports a half programmed flash.
+    #[cfg(feature = "flash")]
+    pub force_flash: bool,
+    /// Written to the ECU by flash writes before the data is sent.
+    #[cfg(feature = "flash")]
+    pub flash_fingerprint: Option<TesterFingerprint>,
+    /// Allow flash writes that don't start on a sector boundary.
+    #[cfg(feature = "flash")]
+    pub partial_sectors: bool,
+    /// Leave the key out of displayed security access reports.
+    pub redact_security_key: bool,
+    /// Write an integrity sidecar next to completed dumps, see
+    /// `image::Sidecar`.
+    pub dump_sidecar: bool,
+    /// Reject responses that repeat another request parameter (mode,
+    /// security level, identifier...) than the request they answer with
+    /// `Error::ParameterEchoMismatch`, so a late response can't pass for
+    /// the one expected. On by default, see `without_echo_check`.
+    pub strict_echo: bool,
+    /// Seeds received by `get_security_access`, checked for ECUs that don't
+    /// send random ones. Restore a saved history to check across sessions.
+    pub seed_history: SeedHistory,
+    /// Number of security access attempts to keep in reserve, keys aren't
+    /// sent once the ECU reports this many or fewer left.
+    pub security_attempt_reserve: u8,
+    /// Check the flash with the profile's checksum routine every this many
+    /// bytes while writing.
+    #[cfg(feature = "flash")]
+    pub verify_interval: Option<usize>,
+    /// Framing used with the ECU, determines the payload sizes.
+    pub header_format: HeaderFormat,
+    /// Timing parameters in effect for the session.
+    pub timing: TimingState,
+    /// Baud rate of the link, updated when a session change switches it.
+    pub baud_rate: u32,
+    /// After the session ended at a baud rate other than the
+    /// initialization one, wait out the profile's `baud_fallback_window`
+    /// so the ECU can be initialized again right away.
+    pub wait_for_baud_fallback: bool,
+    /// Number of requests that may be waiting for a response at the same
+    /// time (at most `MAX_RESPONSE_WINDOW`). With more than one, responses
+    /// are matched to the oldest pending request of their service instead
+    /// of being taken in order, for ECUs that answer out of order while a
+    /// periodic transmission is active.
+    pub response_window: usize,
+    /// Checked by `poll_watchdog`, disabled if `None`.
+    pub watchdog: Option<Watchdog>,
+    /// Service and send time of the requests still waiting for their
+    /// response, oldest first.
+    pending: VecDeque<(RequestContext, Instant)>,
+    /// Responses that arrived before the response to an older pending
+    /// request.
+    early_responses: VecDeque<RawMessage>,
+    /// Responses that matched no pending request.
+    unsolicited: VecDeque<Response>,
+    /// Bytes of a frame `poll` hasn't received completely yet.
+    assembler: FrameAssembler,
+    /// Last frames received, oldest first.
+    recent_frames: VecDeque<RawMessage>,
+    /// Time the last frame was sent or received.
+    last_frame: Option<Instant>,
+    /// `last_frame` when `poll_watchdog` last warned.
+    pub(crate) watchdog_warned: Option<Instant>,
+    /// The ECU answered the last request for routine results with
+    /// `RoutineNotComplete`, it is busy until it answers the next one.
+    pub(crate) routine_not_complete: bool,
+    /// The ECU answered `ResponsePending` to the request being waited for.
+    response_pending: bool,
+    /// Whether the ECU has the `TesterPresent` sub functions inverted,
+    /// `None` until detected.
+    tester_present_inverted: Option<bool>,
+    /// The ECU refused `TesterPresent` in the active mode.
+    tester_present_refused: bool,
+    ecu_changes: EcuChanges,
+    /// Attempts left the ECU reported with the last rejected key.
+    security_attempts_left: Option<u8>,
+    /// Longest dynamic identifier definition the ECU accepted after
+    /// rejecting a longer one.
+    dd_length_limit: Option<u8>,
+    /// Diagnostic modes the ECU refused to switch to.
+    refused_modes: Vec<DiagnosticMode>,
+    /// Whether the ECU answered a timing parameter request, `None` before
+    /// the first.
+    pub(crate) timing_supported: Option<bool>,
+    /// Failures reading responses since the last valid one.
+    pub(crate) link_health: LinkHealth,
+    /// Held while a block transfer is in progress.
+    pub(crate) bus_token: BusToken,
+    /// Earliest time the next request matching each rate limit or
+    /// security lockout may be sent, by service and sub function.
+    rate_limited: HashMap<(ServiceId, Option<u8>), Instant>,
+    /// Whether the profile's security algorithm was checked against its
+    /// vectors.
+    security_vectors_checked: bool,
+    last_security_exchange: Option<SecurityAccessReport>,
+    /// Codecs used by `write_data`.
+    #[cfg(feature = "flash")]
+    pub(crate) codecs: Vec<Arc<dyn DataCodec>>,
+    warnings: Vec<Warning>,
+}
+
+// All state of a connection lives in its client, clients for different ECUs
+// can be driven from their own threads.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Client>();
+};
+
+/// Result of `Client::dd_read_range`.
+#[derive(Debug, Clone)]
+pub struct RangeRead {
+    /// Data read, chunks that could not be read are filled with zeros.
+    pub data: Vec<u8>,
+    /// Address and length of every chunk that could not be read.
+    pub unreadable: Vec<(u32, u8)>,
+}
+
+/// Local identifier used by the `dd_*` helpers for their dynamic definitions.
+const DD_IDENTIFIER: LocalIdentifier = me7::DYNAMIC_IDENTIFIER;
+
+/// Maximum number of chunks in a row that may need their definition redone
+/// before `Client::dd_read_range` gives up.
+const MAX_CONSECUTIVE_REDEFINITIONS: usize = 8;
+
+/// Returns true if `error` is the ECU rejecting the use of the `dd_*`
+/// identifier with `service` because its definition was lost (some ECUs clear
+/// it on session events). An address that can't be read is rejected when the
+/// identifier is defined instead.
+fn definition_lost(error: &Error, service: ServiceId) -> bool {
+    matches!(
+        error,
+        Error::UnexpectedResponse(Response::Error(ProcessError {
+            error: ServiceError::RequestOutOfRange,
+            service: s,
+            ..
+        })) if *s == service
+    )
+}
+
+/// Whether `error` means the ECU refused to define the dynamic identifier,
+/// which it does for addresses it can't read.
+fn definition_rejected(error: &Error) -> bool {
+    definition_lost(error, ServiceId::DynamicallyDefineLocalIdentifier)
+}
+
+/// Whether `response` is the ECU saying it is still running a routine.
+fn is_routine_not_complete(response: &Response) -> bool {
+    matches!(
+        response,
+        Response::Error(ProcessError {
+            error: ServiceError::RoutineNotComplete,
+            ..
+        })
+    )
+}
+
+/// Checks the counter `got` of an uploaded block, `last` is the counter of
+/// the previous block. Returns false for a repeat of the previous block.
+fn check_block_counter(last: Option<u8>, got: u8) -> Result<bool, Error> {
+    let expected = last.map_or(1, |c| c.wrapping_add(1));
+    if got == expected {
+        Ok(true)
+    } else if Some(got) == last {
+        Ok(false)
+    } else {
+        Err(Error::BlockSequenceGap { expected, got })
+    }
+}
+
+/// Returns the data contained in an upload block, checking the address prefix
+/// against `expected` if the block has one.
+fn strip_upload_block(
+    format: UploadBlockFormat,
+    expected: u32,
+    block: &[u8],
+) -> Result<&[u8], Error> {
+    match format {
+        UploadBlockFormat::Plain => Ok(block),
+        UploadBlockFormat::AddressPrefixed => {
+            if block.len() < 3 {
+                return Err(Error::NotEnoughData);
+            }
+            let got = u32::from_be_bytes([0, block[0], block[1], block[2]]);
+            if got != expected {
+                return Err(Error::UploadAddressMismatch { expected, got });
+            }
+            Ok(&block[3..])
+        }
+    }
 }
 
 macro_rules! message_chain {
@@ -32,9 +349,9 @@ macro_rules! message_chain {
         })*
     }} => {
         $(
-            $client.interface.send($message)?;
+            $client.send($message)?;
 
-            match $client.interface.next_response()? {
+            match $client.next_response()? {
                 $($response => $respond,)*
                 r => return Err(Error::UnexpectedResponse(r)),
             }
@@ -43,58 +360,975 @@ macro_rules! message_chain {
 }
 
 impl Client {
-    pub fn dd_write_address(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
-        assert!(data.len() <= 253);
-        message_chain! {self => {
-            Message::ClearLocalIdentifier(0xF0) => {
-                Response::LocalIdentifierDefined(0xF0) => {}
+    /// Maximum number of data bytes after the service id in a request.
+    pub fn max_request_payload(&self) -> usize {
+        self.header_format.max_payload()
+    }
+    /// Maximum number of data bytes after the service id in a response.
+    pub fn max_response_payload(&self) -> usize {
+        self.header_format.max_payload()
+    }
+    /// Reads identification `option` from the ECU. Options listed in
+    /// `EcuProfile::split_identifications` are collected from as many
+    /// responses as their length byte asks for.
+    pub fn read_ecu_identification(&mut self, option: u8) -> Result<Vec<u8>, Error> {
+        self.send(Message::ReadECUIdentification(option))?;
+        let data = match self.next_response()? {
+            Response::EcuIdentification(o, data) if o == option => data,
+            r => return Err(Error::UnexpectedResponse(r)),
+        };
+        if !self.profile.split_identifications.contains(&option) {
+            return Ok(data);
+        }
+        let (&expected, first) = data.split_first().ok_or(Error::NotEnoughData)?;
+        let expected = expected as usize;
+        let mut data = first.to_vec();
+        while data.len() < expected {
+            let source = match self.next_response() {
+                Ok(Response::EcuIdentification(o, mut part)) if o == option => {
+                    data.append(&mut part);
+                    continue;
+                }
+                Ok(r) => Error::UnexpectedResponse(r),
+                Err(e) => e,
+            };
+            return Err(Error::IncompleteIdentification {
+                option,
+                expected,
+                data,
+                source: Box::new(source),
+            });
+        }
+        data.truncate(expected);
+        Ok(data)
+    }
+    /// Reads the data of common identifier `id`.
+    pub fn read_common_identifier(&mut self, id: CommonIdentifier) -> Result<Vec<u8>, Error> {
+        self.send(Message::ReadCommonIdentifier(id))?;
+        match self.next_response()? {
+            Response::CommonIdentifierRead(i, data) if i == id => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads several common identifiers with one request. The response
+    /// doesn't say where one identifier's data ends, so each is given with
+    /// the length of its data.
+    pub fn read_common_identifiers(
+        &mut self,
+        ids: &[(CommonIdentifier, usize)],
+    ) -> Result<BTreeMap<CommonIdentifier, Vec<u8>>, Error> {
+        let Some(&(first, _)) = ids.first() else {
+            return Ok(BTreeMap::new());
+        };
+        self.send(Message::ReadCommonIdentifiers(
+            ids.iter().map(|(id, _)| *id).collect(),
+        ))?;
+        let data = match self.next_response()? {
+            Response::CommonIdentifierRead(i, data) if i == first => data,
+            r => return Err(Error::UnexpectedResponse(r)),
+        };
+        let malformed =
+            || Error::UnexpectedResponse(Response::CommonIdentifierRead(first, data.clone()));
+        let mut records = BTreeMap::new();
+        let mut rest = &data[..];
+        for (i, &(id, length)) in ids.iter().enumerate() {
+            // the first identifier was taken off by the response parser
+            if i > 0 {
+                match rest.split_first_chunk::<2>() {
+                    Some((echo, tail)) if u16::from_be_bytes(*echo) == id.0 => rest = tail,
+                    _ => return Err(malformed()),
+                }
             }
-            Message::DefineLocalIdentifierAddress(0xF0, data.len() as u8, address) => {
-                Response::LocalIdentifierDefined(0xF0) => {}
+            let (record, tail) = rest.split_at_checked(length).ok_or_else(malformed)?;
+            records.insert(id, record.to_vec());
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+        Ok(records)
+    }
+    /// Writes `data` to common identifier `id`, e.g. a coding or adaptation
+    /// value. Most ECUs only allow it after `get_security_access`, fails with
+    /// `Error::SecurityAccessRequired` if it wasn't granted.
+    pub fn write_common_identifier(
+        &mut self,
+        id: CommonIdentifier,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.confirm(DangerousAction::CodingWrite {
+            identifier: id,
+            size: data.len() as u32,
+        })?;
+        self.send(Message::WriteCommonIdentifier(id, data.to_vec()))?;
+        match self.next_response()? {
+            Response::CommonIdentifierWritten(i) if i == id => Ok(()),
+            Response::CommonIdentifierWritten(_) => Err(Error::UnexpectedValue),
+            Response::Error(ProcessError {
+                error: ServiceError::SecurityAccessRequired,
+                service,
+                ..
+            }) => Err(Error::SecurityAccessRequired(service)),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Sends `message` to the ECU. Fails with `Error::MessageTooLong` if it
+    /// doesn't fit the header format.
+    pub fn send(&mut self, message: Message) -> Result<(), Error> {
+        let change = EcuChange::of(&message);
+        let raw = message.raw()?;
+        if raw.data.len() > self.max_request_payload() {
+            return Err(Error::MessageTooLong {
+                length: raw.data.len(),
+                max: self.max_request_payload(),
+            });
+        }
+        if let Service::Query(service) = raw.service {
+            if self.bus_token.is_held() && !is_transfer_service(service) {
+                return Err(Error::BusBusyWithTransfer);
             }
-            Message::WriteLocalIdentifier(0xF0, data) => {
-                Response::LocalIdentifierWritten(0xF0) => {return Ok(())}
+            self.rate_limit(service, raw.data.first().copied());
+            let request = RequestContext {
+                service,
+                sub_function: raw.data.first().copied(),
+                memory_size: match service {
+                    ServiceId::ReadMemoryByAddress => raw.data.get(3).copied(),
+                    _ => None,
+                },
+                echo: Echo::of_request(service, &raw.data),
+            };
+            self.pending.push_back((request, Instant::now()));
+            while self.pending.len() > self.response_window.clamp(1, MAX_RESPONSE_WINDOW) {
+                self.pending.pop_front();
             }
-        }}
+        }
+        self.interface.send_raw(raw)?;
+        self.last_frame = Some(Instant::now());
+        if let Some(change) = change {
+            self.ecu_changes.apply(change);
+        }
+        Ok(())
     }
-    pub fn dd_read_address(&mut self, address: u32, length: u8) -> Result<Vec<u8>, Error> {
+    /// ECU side state changed by the requests sent so far and not undone,
+    /// see `teardown`.
+    pub fn ecu_changes(&self) -> &EcuChanges {
+        &self.ecu_changes
+    }
+    /// Rate limit of the profile that requests of `service` with
+    /// `sub_function` as first data byte fall under, and the key they are
+    /// held back by.
+    fn rate_limit_of(
+        &self,
+        service: ServiceId,
+        sub_function: Option<u8>,
+    ) -> (Option<Duration>, (ServiceId, Option<u8>)) {
+        match self.profile.rate_limits.iter().find(|l| {
+            l.service == service && (l.sub_function.is_none() || l.sub_function == sub_function)
+        }) {
+            Some(limit) => (Some(limit.interval), (service, limit.sub_function)),
+            None => (None, (service, sub_function)),
+        }
+    }
+    /// Waits until a request of `service` with `sub_function` as first data
+    /// byte is allowed by the profile's rate limits and any lockout set by
+    /// `hold_off`.
+    fn rate_limit(&mut self, service: ServiceId, sub_function: Option<u8>) {
+        let (interval, key) = self.rate_limit_of(service, sub_function);
+        self.wait_rate_limit(key);
+        match interval {
+            Some(interval) => {
+                self.rate_limited.insert(key, self.clock.now() + interval);
+            }
+            None => {
+                self.rate_limited.remove(&key);
+            }
+        }
+    }
+    /// Sleeps until requests held back by `key` may be sent, recording the
+    /// delay.
+    fn wait_rate_limit(&mut self, key: (ServiceId, Option<u8>)) {
+        let (service, _) = key;
+        if let Some(next) = self.rate_limited.get(&key) {
+            let delay = next.saturating_duration_since(self.clock.now());
+            if delay > Duration::ZERO {
+                self.stats.rate_limit_delays += 1;
+                self.stats.rate_limit_delay_total += delay;
+                self.warn(Warning::RateLimited { service, delay });
+                self.clock.sleep(delay);
+            }
+        }
+    }
+    /// Keeps requests of `service` with `sub_function` from being sent for
+    /// `duration`, on top of the profile's rate limits.
+    fn hold_off(
+        &mut self,
+        service: ServiceId,
+        sub_function: Option<u8>,
+        duration: Duration,
+    ) -> (ServiceId, Option<u8>) {
+        let (_, key) = self.rate_limit_of(service, sub_function);
+        let until = self.clock.now() + duration;
+        let next = self.rate_limited.entry(key).or_insert(until);
+        *next = Ord::max(*next, until);
+        key
+    }
+    /// Whether a block transfer holds the bus, other requests fail with
+    /// `Error::BusBusyWithTransfer` until it is done.
+    pub fn transfer_in_progress(&self) -> bool {
+        self.bus_token.is_held()
+    }
+    /// What keeps the bus busy, see `BusActivity`.
+    pub fn bus_activity(&self) -> BusActivity {
+        if self.transfer_in_progress() {
+            BusActivity::Transfer
+        } else if self.routine_not_complete || self.response_pending {
+            BusActivity::EcuBusy
+        } else {
+            BusActivity::Idle
+        }
+    }
+    /// Time the last frame was sent to or received from the ECU.
+    pub fn last_frame(&self) -> Option<Instant> {
+        self.last_frame
+    }
+    /// Convenience function when not expecting to have to wait for a
+    /// response
+    pub fn next_response(&mut self) -> Result<Response, Error> {
+        self.next_response_expect_wait(None)
+    }
+    /// See `Interface::next_response_expect_wait`, also records the latency
+    /// of the oldest pending request in the stats. The response is waited
+    /// for P2max, and for P2*max after each `ResponsePending` (see
+    /// `response_timeout`), but no longer than the interface's read timeout
+    /// allows. Fails with `Error::PendingLimitExceeded` after the profile's
+    /// `max_pending` answers, or once `deadline` passed.
+    pub fn next_response_expect_wait(
+        &mut self,
+        last_command: Option<ServiceId>,
+    ) -> Result<Response, Error> {
+        let mut waits = 0;
+        let mut pending = false;
+        let mut window_start = Instant::now();
+        loop {
+            let raw = match self.take_early_response() {
+                Some(raw) => raw,
+                None => {
+                    let raw = match self.next_raw_message() {
+                        Ok(raw) => raw,
+                        // the window may be longer than the interface's
+                        // read timeout
+                        Err(e)
+                            if is_timeout(&e)
+                                && window_start.elapsed() < self.response_timeout(pending) =>
+                        {
+                            continue;
+                        }
+                        Err(e) => {
+                            self.response_pending = false;
+                            return Err(e);
+                        }
+                    };
+                    match self.route_response(raw)? {
+                        Some(raw) => raw,
+                        None => continue,
+                    }
+                }
+            };
+            let request = self.pending.front().map(|(r, _)| *r);
+            let response = response::from_raw_for(raw, request)
+                .inspect_err(|e| self.link_health.record_failure(e))?;
+            self.link_health = LinkHealth::default();
+            match response {
+                Response::Echo(_) => {
+                    self.stats.echoes += 1;
+                    continue;
+                }
+                Response::StillProcessing(s) => {
+                    if last_command.is_some_and(|c| c != s) {
+                        self.response_pending = false;
+                        return Err(Error::UnexpectedPending);
+                    }
+                    waits += 1;
+                    if waits > self.profile.max_pending {
+                        self.response_pending = false;
+                        return Err(Error::PendingLimitExceeded {
+                            service: s,
+                            count: waits,
+                        });
+                    }
+                    if let Err(e) = self.check_deadline(0) {
+                        self.response_pending = false;
+                        return Err(e);
+                    }
+                    pending = true;
+                    window_start = Instant::now();
+                    self.response_pending = true;
+                    continue;
+                }
+                _ => {
+                    if pending {
+                        self.response_pending = false;
+                    }
+                    self.routine_not_complete = is_routine_not_complete(&response);
+                    if let Some((request, sent)) = self.pending.pop_front() {
+                        self.stats
+                            .record_latency(request.service, sent.elapsed(), waits);
+                        self.check_echo(request, &response)?;
+                    }
+                    return Ok(response);
+                }
+            }
+        }
+    }
+    /// Fails with `Error::ParameterEchoMismatch` if `strict_echo` is set
+    /// and `response` repeats another parameter value than `request` had.
+    fn check_echo(&self, request: RequestContext, response: &Response) -> Result<(), Error> {
+        match (request.echo, Echo::of_response(response)) {
+            (Some(expected), Some(got))
+                if self.strict_echo && expected.field == got.field && expected != got =>
+            {
+                Err(Error::ParameterEchoMismatch {
+                    field: expected.field,
+                    expected: expected.value,
+                    got: got.value,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Runs `call` with `strict_echo` off, for ECUs that repeat parameters
+    /// wrongly in some responses.
+    pub fn without_echo_check<T>(
+        &mut self,
+        call: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let strict = std::mem::replace(&mut self.strict_echo, false);
+        let result = call(self);
+        self.strict_echo = strict;
+        result
+    }
+    /// Advances the exchange of the oldest pending request by at most one
+    /// frame without blocking, for event loops: `send` a request, then
+    /// call this until it returns the response. `None` while the response
+    /// is still on its way or after a `ResponsePending`. Fails with a
+    /// timeout once nothing arrived for P2max, or P2*max after a
+    /// `ResponsePending`. Responses are taken in order, `response_window`
+    /// doesn't apply.
+    pub fn poll(&mut self) -> Result<Option<Response>, Error> {
+        if let Some(raw) = self.poll_frame()? {
+            let request = self.pending.front().map(|(r, _)| *r);
+            let response = response::from_raw_for(raw, request)
+                .inspect_err(|e| self.link_health.record_failure(e))?;
+            self.link_health = LinkHealth::default();
+            return Ok(match response {
+                Response::Echo(_) => {
+                    self.stats.echoes += 1;
+                    None
+                }
+                Response::StillProcessing(_) => {
+                    self.response_pending = true;
+                    None
+                }
+                response => {
+                    self.response_pending = false;
+                    self.routine_not_complete = is_routine_not_complete(&response);
+                    if let Some((request, sent)) = self.pending.pop_front() {
+                        self.stats
+                            .record_latency(request.service, sent.elapsed(), 0);
+                        self.check_echo(request, &response)?;
+                    }
+                    Some(response)
+                }
+            });
+        }
+        let limit = self.response_timeout(self.response_pending);
+        if !self.pending.is_empty()
+            && self
+                .last_frame
+                .is_some_and(|at| at.elapsed() > limit.saturating_add(POLL_LATENCY_MARGIN))
+        {
+            self.pending.pop_front();
+            self.response_pending = false;
+            self.assembler.clear();
+            let error = Error::Io(std::io::ErrorKind::TimedOut.into());
+            self.record_read_failure(&error);
+            return Err(error);
+        }
+        Ok(None)
+    }
+    /// Next complete frame received, without waiting for one.
+    pub(crate) fn poll_frame(&mut self) -> Result<Option<RawMessage>, Error> {
+        let mut bytes = Vec::new();
+        if self.interface.read_available(&mut bytes)? > 0 {
+            self.last_frame = Some(Instant::now());
+            self.assembler.push(&bytes);
+        }
+        let Some(frame) = self.assembler.next_frame() else {
+            return Ok(None);
+        };
+        let raw = frame.inspect_err(|e| self.record_read_failure(e))?;
+        self.record_frame(&raw);
+        Ok(Some(raw))
+    }
+    /// Sends `raw` without expecting a response to it, responses are left to
+    /// the caller.
+    pub(crate) fn send_untracked(&mut self, raw: RawMessage) -> Result<(), Error> {
+        if self.bus_token.is_held() {
+            return Err(Error::BusBusyWithTransfer);
+        }
+        self.interface.send_raw(raw)?;
+        self.last_frame = Some(Instant::now());
+        Ok(())
+    }
+    fn next_raw_message(&mut self) -> Result<RawMessage, Error> {
+        let raw = self
+            .interface
+            .next_raw_message()
+            .inspect_err(|e| self.record_read_failure(e))?;
+        self.last_frame = Some(Instant::now());
+        self.record_frame(&raw);
+        Ok(raw)
+    }
+    fn record_read_failure(&mut self, error: &Error) {
+        self.link_health.record_failure(error);
+        if let Error::InvalidChecksum { .. } = error {
+            self.stats.invalid_checksums += 1;
+        }
+    }
+    fn record_frame(&mut self, raw: &RawMessage) {
+        self.stats.frames_received += 1;
+        if self.recent_frames.len() >= MAX_RECENT_FRAMES {
+            self.recent_frames.pop_front();
+        }
+        self.recent_frames.push_back(raw.clone());
+    }
+    /// Takes a response that arrived early for the oldest pending request.
+    fn take_early_response(&mut self) -> Option<RawMessage> {
+        let service = self.pending.front()?.0.service;
+        let i = self
+            .early_responses
+            .iter()
+            .position(|r| responded_service(r) == Some(service))?;
+        self.early_responses.remove(i)
+    }
+    /// Returns `raw` if it is for the oldest pending request, otherwise keeps
+    /// it for the pending request it answers or as unsolicited. Everything
+    /// is for the oldest request with a `response_window` of one.
+    fn route_response(&mut self, raw: RawMessage) -> Result<Option<RawMessage>, Error> {
+        if self.response_window <= 1 {
+            return Ok(Some(raw));
+        }
+        let (Some(service), Some((oldest, _))) = (responded_service(&raw), self.pending.front())
+        else {
+            return Ok(Some(raw));
+        };
+        if service == oldest.service {
+            Ok(Some(raw))
+        } else if self.pending.iter().any(|(r, _)| r.service == service) {
+            if self.early_responses.len() >= MAX_RESPONSE_WINDOW {
+                self.early_responses.pop_front();
+            }
+            self.early_responses.push_back(raw);
+            Ok(None)
+        } else {
+            self.push_unsolicited(response::from_raw(raw)?);
+            Ok(None)
+        }
+    }
+    pub(crate) fn push_unsolicited(&mut self, response: Response) {
+        if self.unsolicited.len() >= MAX_UNSOLICITED {
+            self.unsolicited.pop_front();
+        }
+        self.unsolicited.push_back(response);
+    }
+    /// Takes the oldest response that matched no pending request, only
+    /// collected with a `response_window` above one.
+    pub fn take_unsolicited(&mut self) -> Option<Response> {
+        self.unsolicited.pop_front()
+    }
+    /// Last frames received (at most 16), oldest first.
+    pub fn recent_frames(&self) -> Vec<RawMessage> {
+        self.recent_frames.iter().cloned().collect()
+    }
+    /// Last frame received.
+    pub(crate) fn last_received_frame(&self) -> Option<&RawMessage> {
+        self.recent_frames.back()
+    }
+    /// Troubleshooting hints for `error`, see `diagnostics::explain`.
+    pub fn explain(&self, error: &Error) -> Vec<Hint> {
+        diagnostics::explain(error, &self.stats, &self.recent_frames())
+    }
+    /// Forgets the state of the session, for when the ECU dropped it.
+    pub(crate) fn reset_session_state(&mut self) {
+        self.pending.clear();
+        self.early_responses.clear();
+        self.last_frame = None;
+        self.link_health = LinkHealth::default();
+    }
+    /// Workarounds applied by the client, oldest first. Only the last
+    /// `MAX_WARNINGS` are kept.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+    pub(crate) fn warn(&mut self, warning: Warning) {
+        if self.warnings.len() >= MAX_WARNINGS {
+            self.warnings.remove(0);
+        }
+        self.warnings.push(warning);
+    }
+    /// Returns `Error::DeniedByPolicy` if the danger policy does not allow
+    /// `action`.
+    pub(crate) fn confirm(&self, action: DangerousAction) -> Result<(), Error> {
+        if self.danger_policy.confirm(&action) {
+            Ok(())
+        } else {
+            Err(Error::DeniedByPolicy(action))
+        }
+    }
+    /// Defines the identifier for `length` bytes at `address`. If the ECU
+    /// rejects the length but accepts a shorter definition at the same
+    /// address (halving down to `MIN_DD_LENGTH`), the `dd_*` accesses are
+    /// limited to that length and `Error::DdLengthRejected` returned, so the
+    /// caller can try again in shorter pieces. If every length is rejected
+    /// the address can't be read and the limit is left as it was.
+    fn dd_define_address(&mut self, address: u32, length: u8) -> Result<(), Error> {
+        let error = match self.dd_define(address, length) {
+            Err(e) if length > MIN_DD_LENGTH && definition_rejected(&e) => e,
+            r => return r,
+        };
+        let mut shorter = length;
+        while shorter > MIN_DD_LENGTH {
+            shorter = Ord::max(shorter / 2, MIN_DD_LENGTH);
+            match self.dd_define(address, shorter) {
+                Ok(()) => {
+                    self.dd_length_limit = Some(shorter);
+                    self.warn(Warning::DdLengthLimited { length: shorter });
+                    return Err(Error::DdLengthRejected { length });
+                }
+                Err(e) if definition_rejected(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(error)
+    }
+    fn dd_define(&mut self, address: u32, length: u8) -> Result<(), Error> {
         message_chain! {self => {
-            Message::ClearLocalIdentifier(0xF0) => {
-                Response::LocalIdentifierDefined(0xF0) => {}
+            Message::ClearLocalIdentifier(DD_IDENTIFIER) => {
+                Response::LocalIdentifierDefined(DD_IDENTIFIER) => {}
             }
-            Message::DefineLocalIdentifierAddress(0xF0, length, address) => {
-                Response::LocalIdentifierDefined(0xF0) => {}
+        }}
+        self.send(Message::DefineLocalIdentifierAddress(
+            DD_IDENTIFIER,
+            length,
+            address,
+        ))?;
+        match self.next_response()? {
+            Response::LocalIdentifierDefined(DD_IDENTIFIER) => Ok(()),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    fn dd_write_defined(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        message_chain! {self => {
+            Message::WriteLocalIdentifier(DD_IDENTIFIER, data) => {
+                Response::LocalIdentifierWritten(DD_IDENTIFIER) => {return Ok(())}
             }
-            Message::ReadLocalIdentifier(0xF0, ReadMode::Single, 1) => {
+        }}
+    }
+    fn dd_read_defined(&mut self) -> Result<Vec<u8>, Error> {
+        message_chain! {self => {
+            Message::ReadLocalIdentifier(DD_IDENTIFIER, ReadMode::Single, 1) => {
                 Response::LocalIdentifierRead(_, data) => {return Ok(data)}
             }
         }}
     }
+    /// Fails if the deadline has passed, `completed` is the number of bytes
+    /// the current operation had finished.
+    pub(crate) fn check_deadline(&self, completed: usize) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                Err(Error::DeadlineExceeded { completed })
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Longest dynamic identifier definition the ECU accepts, from the
+    /// profile or learned from a rejected definition. `None` if no limit is
+    /// known besides the framing.
+    pub fn dd_length_limit(&self) -> Option<u8> {
+        match (self.profile.dd_length_limit, self.dd_length_limit) {
+            (Some(a), Some(b)) => Some(Ord::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+    fn dd_limited(&self, max: usize) -> usize {
+        self.dd_length_limit()
+            .map_or(max, |limit| Ord::min(max, limit as usize))
+    }
+    /// Maximum number of bytes `dd_write_address` writes with one
+    /// definition.
+    pub fn max_dd_write(&self) -> usize {
+        // identifier byte
+        self.dd_limited(self.max_request_payload() - 1)
+    }
+    /// Maximum number of bytes `dd_read_address` reads with one definition.
+    pub fn max_dd_read(&self) -> usize {
+        // identifier byte
+        self.dd_limited(Ord::min(self.max_response_payload() - 1, u8::MAX as usize))
+    }
+    /// Writes `data` to `address` through a dynamically defined identifier,
+    /// in pieces of `max_dd_write` bytes. If the ECU lost the definition
+    /// before the write it is defined once more.
+    pub fn dd_write_address(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
+        let max = self.max_request_payload() - 1;
+        if data.len() > max {
+            return Err(Error::MessageTooLong {
+                length: data.len(),
+                max,
+            });
+        }
+        self.confirm(DangerousAction::MemoryWrite {
+            address,
+            size: data.len() as u32,
+        })?;
+        let mut offset = 0;
+        while offset < data.len() {
+            let end = Ord::min(offset + self.max_dd_write(), data.len());
+            match self.dd_write_piece(
+                address.wrapping_add(offset as u32),
+                data[offset..end].to_vec(),
+            ) {
+                Ok(()) => offset = end,
+                // the limit was lowered, try again with a shorter piece
+                Err(Error::DdLengthRejected { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+    /// Reads `size` bytes at `address` with `ReadMemoryByAddress`. Fails
+    /// with `Error::UnexpectedValue` if the ECU echoes another address.
+    pub fn read_memory_by_address(&mut self, address: u32, size: u8) -> Result<Vec<u8>, Error> {
+        self.send(Message::ReadMemoryByAddress {
+            address,
+            size,
+            mode: None,
+            max_response_count: None,
+        })?;
+        match self.next_response()? {
+            Response::MemoryAddressRead(Some(a), _) if a != address & 0xFF_FFFF => {
+                Err(Error::UnexpectedValue)
+            }
+            Response::MemoryAddressRead(_, data) if data.len() == size as usize => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Maximum number of bytes `write_memory_by_address` writes, what fits
+    /// one request after the address and length bytes.
+    pub fn max_memory_write(&self) -> usize {
+        Ord::min(self.max_request_payload() - 4, u8::MAX as usize)
+    }
+    /// Writes `data` to `address` with `WriteMemoryByAddress`. Fails with
+    /// `Error::MessageTooLong` if it is longer than `max_memory_write`.
+    pub fn write_memory_by_address(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
+        let max = self.max_memory_write();
+        if data.len() > max {
+            return Err(Error::MessageTooLong {
+                length: data.len(),
+                max,
+            });
+        }
+        self.confirm(DangerousAction::MemoryWrite {
+            address,
+            size: data.len() as u32,
+        })?;
+        self.send(Message::WriteMemoryByAddress { address, data })?;
+        match self.next_response()? {
+            Response::MemoryAddressWritten(a) if a == address & 0xFF_FFFF => Ok(()),
+            Response::MemoryAddressWritten(_) => Err(Error::UnexpectedValue),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    fn dd_write_piece(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
+        self.dd_define_address(address, data.len() as u8)?;
+        match self.dd_write_defined(data.clone()) {
+            Err(e) if definition_lost(&e, ServiceId::WriteDataByLocalIdentifier) => {
+                self.stats.dd_redefinitions += 1;
+                self.warn(Warning::IdentifierRedefined { address });
+                self.dd_define_address(address, data.len() as u8)?;
+                self.dd_write_defined(data)
+            }
+            r => r,
+        }
+    }
+    /// Writes `data` to `address` like `dd_write_address` and reads it back
+    /// through the same definition, failing with `Error::VerifyMismatch` if
+    /// the ECU returns something else. Locations that change on their own
+    /// should use `dd_write_address` instead.
+    pub fn dd_write_verified(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
+        self.dd_write_address(address, data.clone())?;
+        // the definition only covers the last piece of a split write
+        let got = if data.len() > self.max_dd_write() {
+            self.dd_read_address(address, data.len() as u8)?
+        } else {
+            match self.dd_read_defined() {
+                Err(e) if definition_lost(&e, ServiceId::ReadDataByLocalIdentifier) => {
+                    self.stats.dd_redefinitions += 1;
+                    self.warn(Warning::IdentifierRedefined { address });
+                    self.dd_define_address(address, data.len() as u8)?;
+                    self.dd_read_defined()?
+                }
+                r => r?,
+            }
+        };
+        if got == data {
+            Ok(())
+        } else {
+            Err(Error::VerifyMismatch {
+                address,
+                expected: data,
+                got,
+            })
+        }
+    }
+    /// Reads `length` bytes from `address` through a dynamically defined
+    /// identifier, in pieces of `max_dd_read` bytes. If the ECU lost the
+    /// definition before the read it is defined once more.
+    ///
+    /// Fails with `Error::NotEnoughData` if the ECU returns fewer bytes than
+    /// defined, wrapped in `Error::PartialRead` with the bytes before them
+    /// if there are any.
+    pub fn dd_read_address(&mut self, address: u32, length: u8) -> Result<Vec<u8>, Error> {
+        let max = Ord::min(self.max_response_payload() - 1, u8::MAX as usize);
+        if length as usize > max {
+            return Err(Error::MessageTooLong {
+                length: length as usize,
+                max,
+            });
+        }
+        let mut data = Vec::with_capacity(length as usize);
+        while data.len() < length as usize {
+            let piece = Ord::min(length as usize - data.len(), self.max_dd_read()) as u8;
+            match self.dd_read_piece(address.wrapping_add(data.len() as u32), piece) {
+                Ok(d) => {
+                    let short = d.len() < piece as usize;
+                    data.extend_from_slice(&d);
+                    if short {
+                        return Err(Error::partial(data, address, Error::NotEnoughData));
+                    }
+                }
+                // the limit was lowered, try again with a shorter piece
+                Err(Error::DdLengthRejected { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(data)
+    }
+    fn dd_read_piece(&mut self, address: u32, length: u8) -> Result<Vec<u8>, Error> {
+        self.dd_define_address(address, length)?;
+        match self.dd_read_defined() {
+            Err(e) if definition_lost(&e, ServiceId::ReadDataByLocalIdentifier) => {
+                self.stats.dd_redefinitions += 1;
+                self.warn(Warning::IdentifierRedefined { address });
+                self.dd_define_address(address, length)?;
+                self.dd_read_defined()
+            }
+            r => r,
+        }
+    }
+    /// Reads `size` bytes starting at `address` in chunks of `chunk_size`
+    /// using `dd_read_address`. Chunks the identifier can't be defined for,
+    /// or that still fail after redefining it, are skipped and reported in
+    /// the result, as is the end of a chunk the ECU returned too few bytes
+    /// for. `chunk_size` is capped to what fits in a response.
+    ///
+    /// Errors after part of the range was read are returned as
+    /// `Error::PartialRead` holding the data read so far.
+    pub fn dd_read_range(
+        &mut self,
+        address: u32,
+        size: u32,
+        chunk_size: u8,
+    ) -> Result<RangeRead, Error> {
+        if chunk_size == 0 {
+            return Err(Error::UnexpectedValue);
+        }
+        let mut read = RangeRead {
+            data: Vec::with_capacity(size as usize),
+            unreadable: Vec::new(),
+        };
+        match self.dd_read_range_into(address, size, chunk_size, &mut read) {
+            Ok(()) => Ok(read),
+            Err(e) => Err(Error::partial(read.data, address, e)),
+        }
+    }
+    fn dd_read_range_into(
+        &mut self,
+        address: u32,
+        size: u32,
+        chunk_size: u8,
+        read: &mut RangeRead,
+    ) -> Result<(), Error> {
+        let mut consecutive_redefinitions = 0;
+        let mut offset = 0;
+        while offset < size {
+            self.check_deadline(offset as usize)?;
+            let chunk_address = address.wrapping_add(offset);
+            let length = Ord::min(
+                Ord::min(chunk_size as usize, self.max_dd_read()) as u32,
+                size - offset,
+            ) as u8;
+            let redefinitions = self.stats.dd_redefinitions;
+
+            match self.dd_read_address(chunk_address, length) {
+                Ok(data) => read.data.extend_from_slice(&data),
+                // the ECU returned less than defined, the rest of the chunk
+                // is skipped
+                Err(Error::PartialRead {
+                    data,
+                    at_address,
+                    source,
+                }) if matches!(*source, Error::NotEnoughData) => {
+                    read.data.extend_from_slice(&data);
+                    read.data
+                        .resize(read.data.len() + length as usize - data.len(), 0);
+                    read.unreadable
+                        .push((at_address, length - data.len() as u8));
+                }
+                Err(e)
+                    if definition_lost(&e, ServiceId::ReadDataByLocalIdentifier)
+                        || definition_rejected(&e)
+                        || matches!(e, Error::NotEnoughData) =>
+                {
+                    read.data.resize(read.data.len() + length as usize, 0);
+                    read.unreadable.push((chunk_address, length));
+                }
+                Err(e) => return Err(e),
+            }
+
+            if self.stats.dd_redefinitions != redefinitions {
+                consecutive_redefinitions += 1;
+                if consecutive_redefinitions > MAX_CONSECUTIVE_REDEFINITIONS {
+                    return Err(Error::DefinitionUnstable);
+                }
+            } else {
+                consecutive_redefinitions = 0;
+            }
+            offset += length as u32;
+        }
+        Ok(())
+    }
     pub fn clear_security_wait(&mut self) -> Result<(), Error> {
         self.dd_write_address(0x380da8, vec![0, 0])
     }
+    /// Uploads `size` bytes starting at `address` to `destination`, returns
+    /// the number of bytes written.
+    ///
+    /// Errors after part of the data was written are returned as
+    /// `Error::PartialRead` holding the address to continue from, its data
+    /// is empty as it is in `destination` already.
     pub fn read_data<W: std::io::Write>(
         &mut self,
         address: u32,
         size: u32,
         destination: &mut W,
     ) -> Result<usize, Error> {
-        self.interface.send(Message::RequestDataTransfer {
-            address,
-            size,
-            compression: CompressionFormat::Uncompressed,
-            encryption: EncryptionFormat::Unencrypted,
-            transfer_type: TransferType::Upload,
-        })?;
         let mut written = 0;
-        while let Ok(m) = self.interface.next_response() {
-            if let Response::UploadConfirmation(_) = m {
-                self.interface.send(Message::RequestData)?;
+        match self.read_data_into(address, size, destination, &mut written) {
+            Ok(()) => Ok(written),
+            Err(e) if written > 0 => Err(Error::PartialRead {
+                data: Vec::new(),
+                at_address: address.wrapping_add(written as u32),
+                source: Box::new(e),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+    fn read_data_into<W: std::io::Write>(
+        &mut self,
+        address: u32,
+        size: u32,
+        destination: &mut W,
+        written: &mut usize,
+    ) -> Result<(), Error> {
+        let request = TransferRequest::upload(address, size).build(self)?;
+        let _token = self.bus_token.acquire()?;
+        self.send(request)?;
+        let mut format = self.upload_block_format;
+        let mut last_counter = None;
+        // blocks longer than fit in a message come in several, which are
+        // put back together before the block is stripped
+        let mut block_len = 0;
+        let mut assembled = Vec::new();
+        loop {
+            let m = self.next_response()?;
+            self.check_deadline(*written)?;
+            if let Response::UploadConfirmation(max) = m {
+                block_len = max as usize;
+                self.send(Message::RequestData)?;
             } else if let Response::DataTransfer(d) = m {
                 if !d.is_empty() {
-                    written += d.len();
-                    destination.write(&d)?;
-                    self.interface.send(Message::RequestData)?;
+                    let full_message = d.len() >= self.max_response_payload();
+                    if self.profile.block_counter == BlockCounter::Detect {
+                        let detected = if d[0] == 0x01 {
+                            BlockCounter::Rolling
+                        } else {
+                            BlockCounter::Off
+                        };
+                        self.warn(Warning::BlockCounterDetected {
+                            address,
+                            counter: detected,
+                        });
+                        self.profile.block_counter = detected;
+                    }
+                    let d = if self.profile.block_counter == BlockCounter::Rolling {
+                        if !check_block_counter(last_counter, d[0])? {
+                            self.warn(Warning::BlockRepeated { counter: d[0] });
+                            self.send(Message::RequestData)?;
+                            continue;
+                        }
+                        last_counter = Some(d[0]);
+                        &d[1..]
+                    } else {
+                        &d[..]
+                    };
+                    if block_len > self.max_response_payload() {
+                        assembled.extend_from_slice(d);
+                        if assembled.len() < block_len && full_message {
+                            continue;
+                        }
+                    }
+                    let d = if assembled.is_empty() {
+                        d
+                    } else {
+                        &assembled[..]
+                    };
+                    let expected = address.wrapping_add(*written as u32) & 0xFFFFFF;
+                    let format = match format {
+                        Some(format) => format,
+                        None => {
+                            let detected = if d.len() > 3 && d[0..3] == expected.to_be_bytes()[1..4]
+                            {
+                                UploadBlockFormat::AddressPrefixed
+                            } else {
+                                UploadBlockFormat::Plain
+                            };
+                            self.warn(Warning::UploadFormatDetected {
+                                address,
+                                format: detected,
+                            });
+                            *format.insert(detected)
+                        }
+                    };
+                    let d = strip_upload_block(format, expected, d)?;
+                    *written += d.len();
+                    destination.write_all(d)?;
+                    assembled.clear();
+                    // a request past the end would only be answered with
+                    // an error or not at all
+                    if *written >= size as usize {
+                        break;
+                    }
+                    self.send(Message::RequestData)?;
                 } else {
                     break;
                 }
@@ -102,103 +1336,173 @@ impl Client {
                 return Err(Error::UnexpectedResponse(m));
             }
         }
-        return Ok(written);
+        Ok(())
     }
-    pub fn write_data_bosch(&mut self, address: u32, data: &[u8], key: &[u8]) -> Result<(), Error> {
-        self.interface.send(Message::RequestDataTransfer {
-            address,
-            size: data.len() as u32,
-            compression: CompressionFormat::Bosch,
-            encryption: EncryptionFormat::Bosch,
-            transfer_type: TransferType::Download,
+    pub fn use_fastest_timing(&mut self) -> Result<(), Error> {
+        let limits = self.read_timing(Message::GetTimingLimits, TimingParameter::Limits)?;
+        let [p2min, p2max, p3min, p3max, p4min] = limits.to_bytes();
+        self.send(Message::ChangeTimingParameters {
+            p2min,
+            p2max,
+            p3min,
+            p3max,
+            p4min,
         })?;
-        let mut enc_index = 0;
-        let mut max_len = 0;
-
-        // uncompressed bytes sent so far
-        let mut sent_bytes = 0;
-
-        let mut response = self.interface.next_response();
-        while let Ok(m) = response {
-            response = self.interface.next_response();
-            let send = if let Response::DownloadConfirmation(max) = m {
-                max_len = max as usize;
-                Some(true)
-            } else if let Response::ReadyForMoreData = m {
-                Some(false)
-            } else if let Response::Error(ProcessError {
-                error: ServiceError::RoutineNotComplete,
-                service: ServiceId::RequestDownload,
-            }) = m
-            {
-                continue;
-            } else {
-                return Err(Error::UnexpectedResponse(m));
-            };
-
-            if let Some(first) = send {
-                if sent_bytes >= data.len() {
-                    break;
-                }
-                let (sent, transfer_block) = crate::bcb::encrypt_and_compress(
-                    max_len,
-                    &data[sent_bytes..],
-                    &mut enc_index,
-                    key,
-                    first,
-                )?;
-
-                self.interface.send(Message::SendData(transfer_block))?;
-
-                sent_bytes += sent;
+        match self.next_response()? {
+            Response::TimingSet => {
+                self.timing = limits;
+                Ok(())
             }
+            r => Err(Error::UnexpectedResponse(r)),
         }
-
-        if let Err(e) = response {
-            match e {
-                Error::Io(error) if error.kind() == ErrorKind::TimedOut => {}
-                Error::SerialPort(error)
-                    if error.kind() == serialport::ErrorKind::Io(ErrorKind::TimedOut) => {}
-                _ => return Err(e),
+    }
+    pub fn new(interface: Box<dyn DebugInterface>) -> Client {
+        Client {
+            interface,
+            upload_block_format: None,
+            profile: EcuProfile::default(),
+            danger_policy: DangerPolicy::default(),
+            on_progress: ProgressHandler::default(),
+            on_live_change: LiveChangeHandler::default(),
+            stats: ClientStats::default(),
+            clock: Box::new(SystemClock),
+            deadline: None,
+            read_policy: ReadPolicy::default(),
+            read_strategies: vec![
+                ReadStrategy::DynamicIdentifier,
+                ReadStrategy::ReadMemoryByAddress,
+                ReadStrategy::Upload,
+            ],
+            read_strategy_regions: Vec::new(),
+            #[cfg(feature = "flash")]
+            force_flash: false,
+            #[cfg(feature = "flash")]
+            flash_fingerprint: None,
+            #[cfg(feature = "flash")]
+            partial_sectors: false,
+            redact_security_key: false,
+            dump_sidecar: false,
+            strict_echo: true,
+            seed_history: SeedHistory::default(),
+            security_attempt_reserve: 1,
+            #[cfg(feature = "flash")]
+            verify_interval: None,
+            header_format: HeaderFormat::default(),
+            timing: TimingState::default(),
+            baud_rate: INIT_BAUD_RATE,
+            wait_for_baud_fallback: true,
+            response_window: 1,
+            watchdog: None,
+            pending: VecDeque::new(),
+            early_responses: VecDeque::new(),
+            unsolicited: VecDeque::new(),
+            assembler: FrameAssembler::default(),
+            recent_frames: VecDeque::new(),
+            last_frame: None,
+            watchdog_warned: None,
+            routine_not_complete: false,
+            response_pending: false,
+            tester_present_inverted: None,
+            tester_present_refused: false,
+            ecu_changes: EcuChanges::default(),
+            security_attempts_left: None,
+            dd_length_limit: None,
+            refused_modes: Vec::new(),
+            timing_supported: None,
+            link_health: LinkHealth::default(),
+            bus_token: BusToken::default(),
+            rate_limited: HashMap::new(),
+            security_vectors_checked: false,
+            last_security_exchange: None,
+            #[cfg(feature = "flash")]
+            codecs: vec![Arc::new(BoschCodec {
+                key: crate::KEY.to_vec(),
+            })],
+            warnings: Vec::new(),
+        }
+    }
+    /// Tells the ECU the tester is still there so it keeps the session open.
+    /// Fails with `Error::KeepAliveSkipped` without sending anything while
+    /// the bus is busy (see `bus_activity`), the ongoing traffic keeps it
+    /// open. Some modes, like the ME7 bootloader,
+    /// refuse `TesterPresent` with `ServiceNotSupportedInActiveMode`: the
+    /// answer shows the session is alive, and the profile's `heartbeat` is
+    /// sent instead until the mode changes.
+    pub fn tester_present(&mut self) -> Result<(), Error> {
+        let activity = self.bus_activity();
+        if activity != BusActivity::Idle {
+            return Err(Error::KeepAliveSkipped(activity));
+        }
+        if self.tester_present_refused {
+            return self.heartbeat();
+        }
+        self.send(self.tester_present_message(true))?;
+        match self.next_response()? {
+            Response::TesterPresent => Ok(()),
+            Response::Error(ProcessError {
+                error: ServiceError::ServiceNotSupportedInActiveMode,
+                service: ServiceId::TesterPresent,
+                ..
+            }) => {
+                self.tester_present_refused = true;
+                self.warn(Warning::TesterPresentUnsupported);
+                Ok(())
             }
+            r => Err(Error::UnexpectedResponse(r)),
         }
-        Ok(())
     }
-    pub fn use_fastest_timing(&mut self) -> Result<(), Error> {
-        self.interface.send(Message::GetTimingLimits)?;
-        let response = self.interface.next_response()?;
-        Err(Error::UnexpectedResponse(
-            if let Response::TimingParameters {
-                kind: TimingParameter::Limits,
-                p2min,
-                p2max,
-                p3min,
-                p3max,
-                p4min,
-            } = response
-            {
-                self.interface.send(Message::ChangeTimingParameters {
-                    p2min,
-                    p2max,
-                    p3min,
-                    p3max,
-                    p4min,
-                })?;
-                let response = self.interface.next_response()?;
-                if let Response::TimingSet = response {
-                    return Ok(());
-                } else {
-                    response
-                }
-            } else {
-                response
-            },
-        ))
+    /// Whether `tester_present` sends `TesterPresent`, false once the ECU
+    /// refused it in the active mode.
+    pub fn tester_present_supported(&self) -> bool {
+        !self.tester_present_refused
     }
-    pub fn new(interface: Box<dyn DebugInterface>) -> Client {
-        Client { interface }
+    fn heartbeat(&mut self) -> Result<(), Error> {
+        match self.profile.heartbeat {
+            Heartbeat::Identification(option) => self.read_ecu_identification(option).map(|_| ()),
+            Heartbeat::TimingRead => self
+                .read_timing(Message::GetCurrentTiming, TimingParameter::Read)
+                .map(|_| ()),
+        }
+    }
+    /// Whether the ECU has the `TesterPresent` sub functions the wrong way
+    /// around, detected or from the profile.
+    pub fn tester_present_inverted(&self) -> bool {
+        self.tester_present_inverted
+            .unwrap_or(self.profile.tester_present_inverted)
+    }
+    /// `TesterPresent` asking for a response or not, as this ECU
+    /// understands it.
+    fn tester_present_message(&self, respond: bool) -> Message {
+        Message::TesterPresent(respond != self.tester_present_inverted())
+    }
+    /// Sends a `TesterPresent` asking for no response and waits P2max for
+    /// one anyway. If one arrives the ECU has the sub functions inverted,
+    /// which is remembered for the session and recorded as a warning.
+    /// Needs an interface that supports `read_available`. Returns whether
+    /// they are inverted.
+    pub fn detect_tester_present_inversion(&mut self) -> Result<bool, Error> {
+        self.send(self.tester_present_message(false))?;
+        let inverted = loop {
+            match self.poll() {
+                Ok(Some(Response::TesterPresent)) => break !self.tester_present_inverted(),
+                Ok(Some(r)) => return Err(Error::UnexpectedResponse(r)),
+                Ok(None) => std::thread::sleep(Duration::from_millis(1)),
+                Err(e) if is_timeout(&e) => break self.tester_present_inverted(),
+                Err(e) => return Err(e),
+            }
+        };
+        if inverted != self.profile.tester_present_inverted {
+            self.warn(Warning::TesterPresentInverted { inverted });
+        }
+        self.tester_present_inverted = Some(inverted);
+        Ok(inverted)
     }
+    /// Ends the session after undoing its changes to the ECU (see
+    /// `teardown`). If it ran at a baud rate other than the
+    /// initialization one the interface is switched back, see
+    /// `wait_for_baud_fallback`.
     pub fn disconnect(mut self) -> Result<(), Error> {
+        self.teardown();
         message_chain! {self => {
             Message::StopDiagnosticSession => {
                 Response::DiagnosticSessionStopped => {}
@@ -209,10 +1513,64 @@ impl Client {
             }
         }}
 
+        self.return_to_init_baud()
+    }
+    /// Resets the ECU with `mode`. The ECU drops off the bus afterwards, so
+    /// the session state kept by the client is reset and `recover_session`
+    /// has to be called before talking to it again. No response before the
+    /// read times out is taken as the ECU having reset before it could
+    /// answer (`Warning::ResetUnconfirmed`).
+    pub fn ecu_reset(&mut self, mode: ResetMode) -> Result<(), Error> {
+        self.confirm(DangerousAction::EcuReset(mode))?;
+        self.send(Message::ECUReset(mode))?;
+        match self.next_response() {
+            Ok(Response::EcuReset) => {}
+            Err(e) if is_timeout(&e) => self.warn(Warning::ResetUnconfirmed { mode }),
+            Ok(r) => return Err(Error::UnexpectedResponse(r)),
+            Err(e) => return Err(e),
+        }
+        self.timing = TimingState::default();
+        self.ecu_changes = EcuChanges::default();
+        self.reset_session_state();
         Ok(())
     }
 
-    fn switch_mode(
+    /// Capabilities of the profile, less what the ECU refused on this
+    /// session so far.
+    pub fn capabilities(&self) -> Capabilities {
+        let profile = self.profile.capabilities();
+        let programming_session = profile.programming_session
+            && !self.refused_modes.contains(&DiagnosticMode::Programming);
+        Capabilities {
+            programming_session,
+            flash: profile.flash && programming_session,
+            timing_parameters: profile.timing_parameters && self.timing_supported != Some(false),
+            dd_length_limit: self.dd_length_limit(),
+            ..profile
+        }
+    }
+    /// Switches to `new_mode`, remembering whether the ECU refused it.
+    pub(crate) fn switch_mode(
+        &mut self,
+        new_mode: DiagnosticMode,
+        baud_rate: Option<u32>,
+    ) -> Result<(), Error> {
+        let result = self.request_mode(new_mode, baud_rate);
+        match &result {
+            Ok(()) => {
+                self.refused_modes.retain(|m| *m != new_mode);
+                self.tester_present_refused = false;
+            }
+            Err(Error::UnexpectedResponse(Response::Error(_))) => {
+                if !self.refused_modes.contains(&new_mode) {
+                    self.refused_modes.push(new_mode);
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+    fn request_mode(
         &mut self,
         new_mode: DiagnosticMode,
         baud_rate: Option<u32>,
@@ -221,7 +1579,7 @@ impl Client {
             Message::StartDiagnosticSession(new_mode, baud_rate) => {
                 Response::StartedDiagnosticMode(mode, new_baud) => {
                     if let Some(baud) = new_baud {
-                        self.interface.switch_baud(baud)?;
+                        self.switch_baud_checked(baud)?;
                     }
                     if mode == new_mode {
                         Ok(())
@@ -232,6 +1590,47 @@ impl Client {
             }
         }}
     }
+    /// Switches the local baud rate to `baud` after the ECU agreed to it and
+    /// checks that the ECU answers there. If it doesn't but still answers
+    /// at the old rate the local rate is switched back and
+    /// `Error::BaudSwitchRejected` returned. If it answers at neither the
+    /// new rate is kept and the probe's error returned.
+    fn switch_baud_checked(&mut self, baud: u32) -> Result<(), Error> {
+        let previous = self.baud_rate;
+        self.interface.switch_baud(baud)?;
+        self.baud_rate = baud;
+        let Err(error) = self.probe_link() else {
+            return Ok(());
+        };
+        self.interface.switch_baud(previous)?;
+        self.baud_rate = previous;
+        if self.probe_link().is_ok() {
+            return Err(Error::BaudSwitchRejected {
+                requested: baud,
+                current: previous,
+            });
+        }
+        self.interface.switch_baud(baud)?;
+        self.baud_rate = baud;
+        Err(error)
+    }
+    /// Checks that the ECU answers at the current baud rate.
+    fn probe_link(&mut self) -> Result<(), Error> {
+        let result = self
+            .send(self.tester_present_message(true))
+            .and_then(|()| self.next_response());
+        match result {
+            Ok(Response::TesterPresent) => Ok(()),
+            result => {
+                self.warn(Warning::BaudProbeFailed {
+                    baud_rate: self.baud_rate,
+                });
+                // the request is not going to be answered
+                self.pending.clear();
+                Err(result.map_or_else(|e| e, Error::UnexpectedResponse))
+            }
+        }
+    }
     pub fn programming_mode(&mut self, baud_rate: Option<u32>) -> Result<(), Error> {
         self.switch_mode(DiagnosticMode::Programming, baud_rate)
     }
@@ -243,28 +1642,733 @@ impl Client {
     pub fn diagnostic_mode(&mut self) -> Result<(), Error> {
         self.switch_mode(DiagnosticMode::Diagnostics, None)
     }
-    pub fn get_security_access(&mut self) -> Result<(), Error> {
-        let seed_arr;
-        message_chain! {self => {
-            Message::RequestSecuritySeed => {
-                Response::SecurityAccessSeed(_, seed) => {
-                    seed_arr = seed.to_vec().try_into().unwrap();
-                }
-                Response::SecurityAccessGranted(_) => {
-                    return Ok(());
+    /// Computes the key for `seed` with the profile's algorithm without
+    /// sending anything.
+    pub fn dry_run_security(&self, seed: [u8; 4]) -> u32 {
+        (self.profile.security_algorithm)(seed)
+    }
+    fn check_security_vectors(&mut self) -> Result<(), Error> {
+        if !self.security_vectors_checked {
+            for (seed, expected) in &self.profile.security_vectors {
+                let got = self.dry_run_security(*seed);
+                if got != *expected {
+                    return Err(Error::SecurityAlgorithmMismatch {
+                        seed: *seed,
+                        expected: *expected,
+                        got,
+                    });
                 }
             }
-            Message::SendSecurityKey(security_key_from_seed(seed_arr)) => {
+            self.security_vectors_checked = true;
+        }
+        Ok(())
+    }
+    /// Seed and key of the last key sent by `get_security_access`.
+    pub fn last_security_exchange(&self) -> Option<&SecurityAccessReport> {
+        self.last_security_exchange.as_ref()
+    }
+    /// Holds seed requests back for the profile's `security_lockout`, but
+    /// not past `deadline`, and waits for it through the rate limiter.
+    fn wait_security_lockout(&mut self) -> Result<(), Error> {
+        let mut lockout = self.profile.security_lockout;
+        if let Some(deadline) = self.deadline {
+            lockout = lockout.min(deadline.saturating_duration_since(Instant::now()));
+        }
+        let key = self.hold_off(ServiceId::SecurityAccess, Some(SEED_REQUEST), lockout);
+        self.wait_rate_limit(key);
+        self.check_deadline(0)
+    }
+    /// Security access attempts the ECU said are left, `None` if it didn't
+    /// or access was granted since.
+    pub fn security_attempts_left(&self) -> Option<u8> {
+        self.security_attempts_left
+    }
+    /// Fails with `Error::SecurityAttemptsLow` instead of sending a key if
+    /// the ECU reported no more than `security_attempt_reserve` attempts
+    /// left. If the ECU refuses access for too many attempts or requests
+    /// coming too fast, it is tried again after the profile's
+    /// `security_lockout`, at most `security_retries` times.
+    pub fn get_security_access(&mut self) -> Result<(), Error> {
+        self.check_security_vectors()?;
+        let mut attempts: u8 = 0;
+        loop {
+            if let Some(remaining) = self.security_attempts_left
+                && remaining <= self.security_attempt_reserve
+            {
+                return Err(Error::SecurityAttemptsLow { remaining });
+            }
+            attempts = attempts.saturating_add(1);
+            self.send(Message::RequestSecuritySeed)?;
+            let (level, seed) = match self.next_response()? {
+                Response::SecurityAccessSeed(level, seed) => (level, seed),
+                Response::SecurityAccessGranted(_) => return Ok(()),
+                r => return Err(Error::UnexpectedResponse(r)),
+            };
+            let seed_bytes = seed_bytes(&seed, self.profile.seed_status_byte)?;
+            let seed_defect = self.seed_history.record(seed_bytes);
+            if let Some(defect) = seed_defect {
+                self.warn(Warning::DegenerateSeed {
+                    seed: seed.clone(),
+                    defect,
+                });
+            }
+            let key = self.dry_run_security(seed_bytes);
+            let mut report = SecurityAccessReport {
+                level,
+                seed,
+                key_sent: key.to_be_bytes().to_vec(),
+                attempts,
+                seed_defect,
+                outcome: SecurityAccessOutcome::Granted,
+                redact_key: self.redact_security_key,
+            };
+
+            self.send(Message::SendSecurityKey(key))?;
+            match self.next_response()? {
                 Response::SecurityAccessGranted(_) => {
+                    self.last_security_exchange = Some(report);
+                    self.security_attempts_left = None;
                     return Ok(());
                 }
-                Response::Error(ProcessError {
-                    error: ServiceError::TooManyAttempts | ServiceError::RequestingTooFast,
-                    service: ServiceId::SecurityAccess,
-                }) => {
-                    return self.get_security_access();
+                Response::Error(
+                    e @ ProcessError {
+                        service: ServiceId::SecurityAccess,
+                        ..
+                    },
+                ) => {
+                    let error = e.error;
+                    if self.profile.reports_remaining_attempts
+                        && let Some(remaining) = e.remaining_attempts()
+                    {
+                        self.security_attempts_left = Some(remaining);
+                    }
+                    report.outcome = SecurityAccessOutcome::Rejected(error);
+                    self.last_security_exchange = Some(report.clone());
+                    if !matches!(
+                        error,
+                        ServiceError::TooManyAttempts | ServiceError::RequestingTooFast
+                    ) || attempts > self.profile.security_retries
+                    {
+                        return Err(Error::SecurityAccessDenied(Box::new(report)));
+                    }
+                    self.wait_security_lockout()?;
                 }
+                r => return Err(Error::UnexpectedResponse(r)),
             }
-        }}
+        }
+    }
+}
+
+/// Service of the request `raw` answers, `None` for messages that aren't
+/// final responses.
+fn responded_service(raw: &RawMessage) -> Option<ServiceId> {
+    match raw.service {
+        Service::Response(ServiceResponse::NegativeResponse) => {
+            if raw.data.get(1) == Some(&(ServiceError::ResponsePending as u8)) {
+                None
+            } else {
+                ServiceId::from_repr(*raw.data.first()?)
+            }
+        }
+        Service::Response(response) => response.request(),
+        Service::Query(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn plain_upload_block_is_returned_as_is() {
+        let block = [0x12, 0x34, 0x56, 0x78];
+        assert_eq!(
+            strip_upload_block(UploadBlockFormat::Plain, 0x380000, &block).unwrap(),
+            &block
+        );
+    }
+
+    #[test]
+    fn address_prefix_is_stripped() {
+        let block = [0x38, 0x00, 0x10, 0xAA, 0xBB];
+        assert_eq!(
+            strip_upload_block(UploadBlockFormat::AddressPrefixed, 0x380010, &block).unwrap(),
+            &[0xAA, 0xBB]
+        );
+        // a prefix without data is an empty block
+        assert_eq!(
+            strip_upload_block(UploadBlockFormat::AddressPrefixed, 0x380010, &block[..3]).unwrap(),
+            &[] as &[u8]
+        );
+    }
+
+    #[test]
+    fn corrupted_address_prefix_is_rejected() {
+        let block = [0x38, 0x00, 0x20, 0xAA, 0xBB];
+        assert!(matches!(
+            strip_upload_block(UploadBlockFormat::AddressPrefixed, 0x380010, &block),
+            Err(Error::UploadAddressMismatch {
+                expected: 0x380010,
+                got: 0x380020
+            })
+        ));
+        assert!(matches!(
+            strip_upload_block(UploadBlockFormat::AddressPrefixed, 0x380010, &block[..2]),
+            Err(Error::NotEnoughData)
+        ));
+    }
+
+    #[test]
+    fn rejected_definition_is_skipped() {
+        let mut client = mock::client(mock::dd_ecu(16..24));
+        let read = client.dd_read_range(0, 48, 16).unwrap();
+        let mut expected: Vec<u8> = (0..48).collect();
+        expected[16..32].fill(0);
+        assert_eq!(read.data, expected);
+        assert_eq!(read.unreadable, vec![(16, 16)]);
+    }
+
+    #[test]
+    fn oversized_dd_write_is_rejected() {
+        let mut client = mock::client(|request| panic!("sent {request:02X?}"));
+        assert!(matches!(
+            client.dd_write_address(0x380000, vec![0; 300]),
+            Err(Error::MessageTooLong { .. })
+        ));
+    }
+
+    fn broken_pipe() -> Error {
+        Error::Io(std::io::ErrorKind::BrokenPipe.into())
+    }
+
+    #[test]
+    fn failed_upload_reports_where_it_stopped() {
+        let mut blocks = 0;
+        let mut client = mock::client(move |request| match request {
+            [0x35, ..] => vec![Ok(vec![0x75, 0x10])],
+            [0x36] if blocks < 2 => {
+                blocks += 1;
+                let mut block = vec![0x76];
+                block.extend([blocks; 0x10]);
+                vec![Ok(block)]
+            }
+            [0x36] => vec![Err(broken_pipe())],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        let mut data = Vec::new();
+        match client.read_data(0x380000, 0x40, &mut data) {
+            Err(Error::PartialRead {
+                data: kept,
+                at_address,
+                source,
+            }) => {
+                assert!(kept.is_empty());
+                assert_eq!(at_address, 0x380020);
+                assert!(matches!(*source, Error::Io(_)));
+            }
+            r => panic!("{r:?}"),
+        }
+        assert_eq!(data, [[1; 0x10], [2; 0x10]].concat());
+    }
+
+    #[test]
+    fn failed_range_read_keeps_the_data_read() {
+        let mut dd = mock::dd_ecu(0..0);
+        let mut reads = 0;
+        let mut client = mock::client(move |request| match request {
+            [0x21, ..] if reads == 2 => vec![Err(broken_pipe())],
+            [0x21, ..] => {
+                reads += 1;
+                dd(request)
+            }
+            _ => dd(request),
+        });
+        match client.dd_read_range(0x10, 0x20, 8) {
+            Err(Error::PartialRead {
+                data, at_address, ..
+            }) => {
+                assert_eq!(data, (0x10..0x20).collect::<Vec<u8>>());
+                assert_eq!(at_address, 0x20);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn block_counters_follow_each_other() {
+        assert!(check_block_counter(None, 1).unwrap());
+        assert!(check_block_counter(Some(1), 2).unwrap());
+        assert!(check_block_counter(Some(0xFF), 0x00).unwrap());
+    }
+
+    #[test]
+    fn duplicated_block_is_reported_as_repeat() {
+        assert!(!check_block_counter(Some(3), 3).unwrap());
+    }
+
+    #[test]
+    fn dropped_block_is_a_gap() {
+        assert!(matches!(
+            check_block_counter(Some(3), 5),
+            Err(Error::BlockSequenceGap {
+                expected: 4,
+                got: 5
+            })
+        ));
+        assert!(matches!(
+            check_block_counter(None, 2),
+            Err(Error::BlockSequenceGap {
+                expected: 1,
+                got: 2
+            })
+        ));
+    }
+
+    /// Client uploading blocks with the counters in `counters`, block `n`
+    /// holding 4 bytes of `n`.
+    fn counted_upload(counters: &'static [u8]) -> Client {
+        let mut next = counters.iter();
+        let mut client = mock::client(move |request| match request {
+            [0x35, ..] => vec![Ok(vec![0x75, 0x05])],
+            [0x36] => {
+                let counter = *next.next().expect("no block left");
+                vec![Ok(vec![0x76, counter, counter, counter, counter, counter])]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.profile.block_counter = BlockCounter::Rolling;
+        client
+    }
+
+    #[test]
+    fn duplicated_upload_block_is_skipped() {
+        let mut client = counted_upload(&[1, 2, 2, 3]);
+        let mut data = Vec::new();
+        assert_eq!(client.read_data(0x380000, 12, &mut data).unwrap(), 12);
+        assert_eq!(data, [[1; 4], [2; 4], [3; 4]].concat());
+    }
+
+    #[test]
+    fn dropped_upload_block_fails_the_read() {
+        let mut client = counted_upload(&[1, 2, 4]);
+        let mut data = Vec::new();
+        match client.read_data(0x380000, 12, &mut data) {
+            Err(Error::PartialRead {
+                at_address, source, ..
+            }) => {
+                assert_eq!(at_address, 0x380008);
+                assert!(matches!(
+                    *source,
+                    Error::BlockSequenceGap {
+                        expected: 3,
+                        got: 4
+                    }
+                ));
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    /// Client for an ECU sending a seed and answering every key with
+    /// `refusal`, and the number of keys sent to it.
+    fn refusing_security(refusal: ServiceError) -> (Client, Arc<Mutex<u8>>) {
+        let keys = Arc::new(Mutex::new(0));
+        let sent = keys.clone();
+        let mut client = mock::client(move |request| match request {
+            [0x27, 0x01] => vec![Ok(vec![0x67, 0x01, 0x12, 0x34, 0x56, 0x78])],
+            [0x27, 0x02, ..] => {
+                *sent.lock().unwrap() += 1;
+                vec![Ok(vec![0x7F, 0x27, refusal as u8])]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.profile.security_lockout = Duration::ZERO;
+        client.profile.rate_limits.clear();
+        (client, keys)
+    }
+
+    #[test]
+    fn lockout_is_retried_a_limited_number_of_times() {
+        let (mut client, keys) = refusing_security(ServiceError::TooManyAttempts);
+        client.profile.security_retries = 2;
+        assert!(matches!(
+            client.get_security_access(),
+            Err(Error::SecurityAccessDenied(_))
+        ));
+        assert_eq!(*keys.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn wrong_key_is_not_retried() {
+        let (mut client, keys) = refusing_security(ServiceError::InvalidKey);
+        assert!(matches!(
+            client.get_security_access(),
+            Err(Error::SecurityAccessDenied(_))
+        ));
+        assert_eq!(*keys.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn no_key_is_sent_within_the_reserve() {
+        let (mut client, keys) = refusing_security(ServiceError::TooManyAttempts);
+        client.security_attempts_left = Some(1);
+        assert!(matches!(
+            client.get_security_access(),
+            Err(Error::SecurityAttemptsLow { remaining: 1 })
+        ));
+        assert_eq!(*keys.lock().unwrap(), 0);
+    }
+
+    /// ECU of `mock::dd_ecu` answering reads of the identifier defined at
+    /// `short` with only 4 bytes.
+    fn short_dd_ecu(short: u32) -> impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send {
+        let mut dd = mock::dd_ecu(0..0);
+        let mut defined = 0;
+        move |request| {
+            if let [0x2C, 0xF0, 0x03, 0x01, _, a, b, c] = request {
+                defined = u32::from_be_bytes([0, *a, *b, *c]);
+            }
+            let mut responses = dd(request);
+            if request[0] == 0x21
+                && defined == short
+                && let Some(Ok(response)) = responses.first_mut()
+            {
+                response.truncate(2 + 4);
+            }
+            responses
+        }
+    }
+
+    #[test]
+    fn short_dd_read_is_an_error() {
+        let mut client = mock::client(short_dd_ecu(0x18));
+        match client.dd_read_address(0x18, 8) {
+            Err(Error::PartialRead {
+                data,
+                at_address,
+                source,
+            }) => {
+                assert_eq!(data, [0x18, 0x19, 0x1A, 0x1B]);
+                assert_eq!(at_address, 0x1C);
+                assert!(matches!(*source, Error::NotEnoughData));
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn short_chunk_keeps_the_range_aligned() {
+        let mut client = mock::client(short_dd_ecu(0x18));
+        let read = client.dd_read_range(0x10, 0x20, 8).unwrap();
+        let mut expected: Vec<u8> = (0x10..0x30).collect();
+        expected[0x0C..0x10].fill(0);
+        assert_eq!(read.data, expected);
+        assert_eq!(read.unreadable, vec![(0x1C, 4)]);
+    }
+
+    /// Client for `ecu` and the lengths of the definitions sent to it.
+    fn recording_defines(
+        mut ecu: impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send + 'static,
+    ) -> (Client, Arc<Mutex<Vec<u8>>>) {
+        let lengths = Arc::new(Mutex::new(Vec::new()));
+        let sent = lengths.clone();
+        let client = mock::client(move |request| {
+            if let [0x2C, 0xF0, 0x03, 0x01, size, ..] = request {
+                sent.lock().unwrap().push(*size);
+            }
+            ecu(request)
+        });
+        (client, lengths)
+    }
+
+    #[test]
+    fn length_limit_is_learned_from_a_shorter_definition() {
+        let (mut client, lengths) = recording_defines(mock::capped_dd_ecu(0..0, 0x40));
+        let read = client.dd_read_range(0x1000, 0x100, 0x80).unwrap();
+        assert_eq!(read.data, (0..=0xFF).collect::<Vec<u8>>());
+        assert!(read.unreadable.is_empty());
+        assert_eq!(client.dd_length_limit(), Some(0x40));
+        // rejected, accepted shorter, then the rest in pieces of the limit
+        assert_eq!(
+            *lengths.lock().unwrap(),
+            [0x80, 0x40, 0x40, 0x40, 0x40, 0x40]
+        );
+    }
+
+    #[test]
+    fn unreadable_region_keeps_the_length_limit() {
+        let (mut client, lengths) = recording_defines(mock::dd_ecu(0x1000..0x1100));
+        let read = client.dd_read_range(0x1000, 0x200, 0x80).unwrap();
+        assert_eq!(read.unreadable, vec![(0x1000, 0x80), (0x1080, 0x80)]);
+        assert_eq!(read.data[0x100..], (0..=0xFF).collect::<Vec<u8>>());
+        assert_eq!(client.dd_length_limit(), None);
+        let lengths = lengths.lock().unwrap();
+        // every length is tried for the unreadable chunks, then full chunks
+        let probes = [0x80, 0x40, 0x20, 0x10, 0x08];
+        assert_eq!(lengths[..10], [probes, probes].concat());
+        assert_eq!(lengths[10..], [0x80, 0x80]);
+    }
+
+    /// Client for an ECU in a mode that refuses `TesterPresent`, and the
+    /// requests sent to it.
+    fn refusing_tester_present() -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sent = requests.clone();
+        let client = mock::client(move |request| {
+            sent.lock().unwrap().push(request.to_vec());
+            match request {
+                // ServiceNotSupportedInActiveMode
+                [0x3E, ..] => vec![Ok(vec![0x7F, 0x3E, 0x80])],
+                [0x1A, 0x9B] => vec![Ok(vec![0x5A, 0x9B, 0x01])],
+                [0x83, 0x02] => vec![Ok(vec![0xC3, 0x02, 0x00, 0x32, 0x00, 0xFF, 0x00])],
+                _ => panic!("unexpected request {request:02X?}"),
+            }
+        });
+        (client, requests)
+    }
+
+    #[test]
+    fn refused_tester_present_switches_to_the_heartbeat() {
+        let (mut client, requests) = refusing_tester_present();
+        client.tester_present().unwrap();
+        assert!(!client.tester_present_supported());
+        assert!(matches!(
+            client.warnings(),
+            [Warning::TesterPresentUnsupported]
+        ));
+        client.tester_present().unwrap();
+        client.watchdog = Some(Watchdog {
+            threshold: 0.0,
+            keep_alive: true,
+        });
+        assert!(client.poll_watchdog().unwrap());
+        // no new session or reconnect, only the heartbeat
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [vec![0x3E, 0x01], vec![0x1A, 0x9B], vec![0x1A, 0x9B]]
+        );
+    }
+
+    #[test]
+    fn timing_read_heartbeat() {
+        let (mut client, requests) = refusing_tester_present();
+        client.profile.heartbeat = Heartbeat::TimingRead;
+        client.tester_present().unwrap();
+        client.tester_present().unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [vec![0x3E, 0x01], vec![0x83, 0x02]]
+        );
+    }
+
+    #[test]
+    fn clients_on_separate_threads_keep_their_own_state() {
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let session = |marker: u8, reads: u32| {
+            let barrier = barrier.clone();
+            let mut client = mock::client(move |request| match request {
+                [0x23, _, _, _, size] => {
+                    let mut response = vec![0x63];
+                    response.resize(*size as usize + 1, marker);
+                    vec![Ok(response)]
+                }
+                _ => panic!("unexpected request {request:02X?}"),
+            });
+            std::thread::spawn(move || {
+                barrier.wait();
+                for i in 0..reads {
+                    let data = client.read_memory_by_address(0x380000 + i, 4).unwrap();
+                    assert_eq!(data, [marker; 4]);
+                }
+                client
+            })
+        };
+        let first = session(0xAA, 50);
+        let second = session(0x55, 30);
+        let first = first.join().unwrap();
+        let second = second.join().unwrap();
+        assert_eq!(first.stats.frames_received, 50);
+        assert_eq!(second.stats.frames_received, 30);
+        assert!(first.warnings().is_empty() && second.warnings().is_empty());
+    }
+
+    /// ECU with RAM behind its dynamic identifier, ignoring writes if
+    /// `stuck`, recording every request it gets.
+    fn ram_ecu(stuck: bool) -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let mut ram = vec![0u8; 0x100];
+        let mut defined = (0, 0);
+        let client = mock::client(move |request| {
+            log.lock().unwrap().push(request.to_vec());
+            vec![Ok(match request {
+                [0x2C, 0xF0, 0x04] => vec![0x6C, 0xF0],
+                [0x2C, 0xF0, 0x03, 0x01, size, _, _, c] => {
+                    defined = (*c as usize, *size as usize);
+                    vec![0x6C, 0xF0]
+                }
+                [0x3B, 0xF0, data @ ..] => {
+                    if !stuck {
+                        ram[defined.0..defined.0 + data.len()].copy_from_slice(data);
+                    }
+                    vec![0x7B, 0xF0]
+                }
+                [0x21, 0xF0, ..] => {
+                    let mut response = vec![0x61, 0xF0];
+                    response.extend_from_slice(&ram[defined.0..defined.0 + defined.1]);
+                    response
+                }
+                _ => panic!("unexpected request {request:02X?}"),
+            })]
+        });
+        (client, requests)
+    }
+
+    fn defines(requests: &Mutex<Vec<Vec<u8>>>) -> usize {
+        let requests = requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|r| r.starts_with(&[0x2C, 0xF0, 0x03]))
+            .count()
+    }
+
+    #[test]
+    fn verified_write_reuses_the_definition() {
+        let (mut client, requests) = ram_ecu(false);
+        client.dd_write_verified(0x10, vec![1, 2, 3]).unwrap();
+        assert_eq!(defines(&requests), 1);
+        assert_eq!(
+            requests.lock().unwrap().last().unwrap(),
+            &vec![0x21, 0xF0, 0x01, 0x01]
+        );
+        assert_eq!(client.stats.dd_redefinitions, 0);
+    }
+
+    #[test]
+    fn verified_write_reports_a_mismatch() {
+        let (mut client, requests) = ram_ecu(true);
+        assert!(matches!(
+            client.dd_write_verified(0x10, vec![1, 2, 3]),
+            Err(Error::VerifyMismatch { address: 0x10, expected, got })
+                if expected == [1, 2, 3] && got == [0, 0, 0]
+        ));
+        assert_eq!(defines(&requests), 1);
+        // a plain write doesn't read anything back
+        requests.lock().unwrap().clear();
+        client.dd_write_address(0x10, vec![1, 2, 3]).unwrap();
+        assert!(!requests.lock().unwrap().iter().any(|r| r[0] == 0x21));
+    }
+
+    /// Clock that only moves when slept on or advanced, and the sleeps.
+    #[derive(Debug, Clone)]
+    struct TestClock {
+        now: Arc<Mutex<Instant>>,
+        sleeps: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl TestClock {
+        fn install(client: &mut Client) -> Self {
+            let clock = TestClock {
+                now: Arc::new(Mutex::new(Instant::now())),
+                sleeps: Arc::new(Mutex::new(Vec::new())),
+            };
+            client.clock = Box::new(clock.clone());
+            clock
+        }
+        fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+        fn sleeps(&self) -> Vec<Duration> {
+            self.sleeps.lock().unwrap().clone()
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+            self.advance(duration);
+        }
+    }
+
+    /// Client for an ECU granting security access and answering
+    /// `TesterPresent` and memory reads.
+    fn granting_security() -> Client {
+        mock::client(|request| {
+            vec![Ok(match request {
+                [0x27, 0x01] => vec![0x67, 0x01, 0x12, 0x34, 0x56, 0x78],
+                [0x27, 0x02, ..] => vec![0x67, 0x02],
+                [0x3E, 0x01] => vec![0x7E],
+                [0x23, _, _, _, size] => [vec![0x63], vec![0; *size as usize]].concat(),
+                _ => panic!("unexpected request {request:02X?}"),
+            })]
+        })
+    }
+
+    #[test]
+    fn seed_requests_are_spaced() {
+        let mut client = granting_security();
+        let clock = TestClock::install(&mut client);
+        let interval = client.profile.rate_limits[0].interval;
+        client.get_security_access().unwrap();
+        // unrelated requests in between don't wait and don't reset the limit
+        client.tester_present().unwrap();
+        client.read_memory_by_address(0x380000, 4).unwrap();
+        clock.advance(Duration::from_secs(3));
+        client.tester_present().unwrap();
+        assert!(clock.sleeps().is_empty());
+
+        client.get_security_access().unwrap();
+        let delay = interval - Duration::from_secs(3);
+        assert_eq!(clock.sleeps(), [delay]);
+        assert_eq!(client.stats.rate_limit_delays, 1);
+        assert_eq!(client.stats.rate_limit_delay_total, delay);
+        assert!(client.warnings().iter().any(|w| matches!(
+            w,
+            Warning::RateLimited {
+                service: ServiceId::SecurityAccess,
+                delay: d
+            } if *d == delay
+        )));
+
+        // once the interval has passed nothing waits
+        clock.advance(interval);
+        client.get_security_access().unwrap();
+        assert_eq!(clock.sleeps().len(), 1);
+    }
+
+    #[test]
+    fn lockout_waits_through_the_rate_limiter() {
+        let (mut client, keys) = refusing_security(ServiceError::RequestingTooFast);
+        let clock = TestClock::install(&mut client);
+        let lockout = Duration::from_secs(30);
+        client.profile.security_lockout = lockout;
+        client.profile.rate_limits = EcuProfile::default().rate_limits;
+        client.profile.security_retries = 2;
+        assert!(matches!(
+            client.get_security_access(),
+            Err(Error::SecurityAccessDenied(_))
+        ));
+        assert_eq!(*keys.lock().unwrap(), 3);
+        // the lockout is longer than the seed interval, nothing else waits
+        assert_eq!(clock.sleeps(), [lockout, lockout]);
+        assert_eq!(client.stats.rate_limit_delays, 2);
+    }
+
+    #[test]
+    fn seed_interval_outlasts_a_short_lockout() {
+        let (mut client, _) = refusing_security(ServiceError::TooManyAttempts);
+        let clock = TestClock::install(&mut client);
+        client.profile.rate_limits = EcuProfile::default().rate_limits;
+        let interval = client.profile.rate_limits[0].interval;
+        client.profile.security_lockout = interval / 2;
+        client.profile.security_retries = 1;
+        assert!(client.get_security_access().is_err());
+        // the seed interval outlasts the shorter lockout
+        assert_eq!(clock.sleeps(), [interval]);
     }
 }
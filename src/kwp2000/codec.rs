@@ -0,0 +1,52 @@
+use std::fmt::Debug;
+
+use crate::{Error, bcb};
+
+use super::constants::{CompressionFormat, EncryptionFormat};
+
+/// Compression and encryption of the blocks sent in a download with a
+/// specific data format. Implementations for formats the crate doesn't
+/// support can be added with `Client::register_codec`.
+pub trait DataCodec: Debug + Send + Sync {
+    fn compression(&self) -> CompressionFormat;
+    fn encryption(&self) -> EncryptionFormat;
+    /// Encodes as much of `data` as fits in a block of at most `max_len`
+    /// bytes. `header` is put in front of the data of the first block.
+    /// `state` starts at zero for every download and is kept between blocks
+    /// (the key index for the Bosch format).
+    ///
+    /// Returns the number of bytes of `data` encoded and the block.
+    fn encode_block(
+        &self,
+        max_len: usize,
+        data: &[u8],
+        state: &mut usize,
+        header: Option<&[u8]>,
+    ) -> Result<(usize, Vec<u8>), Error>;
+}
+
+/// Bosch BCB compression with XOR encryption, see `crate::bcb`.
+#[derive(Debug, Clone)]
+pub struct BoschCodec {
+    pub key: Vec<u8>,
+}
+
+impl DataCodec for BoschCodec {
+    fn compression(&self) -> CompressionFormat {
+        CompressionFormat::Bosch
+    }
+
+    fn encryption(&self) -> EncryptionFormat {
+        EncryptionFormat::Bosch
+    }
+
+    fn encode_block(
+        &self,
+        max_len: usize,
+        data: &[u8],
+        state: &mut usize,
+        header: Option<&[u8]>,
+    ) -> Result<(usize, Vec<u8>), Error> {
+        bcb::encrypt_and_compress(max_len, data, state, &self.key, header)
+    }
+}
@@ -1,6 +1,56 @@
+use std::fmt::Display;
+
 use crate::Error;
 use strum::FromRepr;
 
+macro_rules! Identifier {
+    {$($(#[$attr:meta])* $name:ident($inner:ty) => $width:literal),*} => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub struct $name(pub $inner);
+
+            impl From<$inner> for $name {
+                fn from(value: $inner) -> Self {
+                    Self(value)
+                }
+            }
+
+            impl From<$name> for $inner {
+                fn from(value: $name) -> Self {
+                    value.0
+                }
+            }
+
+            impl Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, concat!("0x{:0", $width, "x}"), self.0)
+                }
+            }
+        )*
+    };
+}
+
+Identifier! {
+    /// Identifier used by the `*ByLocalIdentifier` services
+    LocalIdentifier(u8) => 2,
+    /// Identifier used by the `*ByCommonIdentifier` services
+    CommonIdentifier(u16) => 4,
+    /// Identifier used by the `*RoutineByLocalIdentifier` services
+    RoutineIdentifier(u8) => 2
+}
+
+/// Identifiers with a known meaning on Bosch ME7
+pub mod me7 {
+    use super::{LocalIdentifier, RoutineIdentifier};
+
+    /// Dynamically defined local identifier, the `dd_*` helpers of the
+    /// client define their reads and writes on it
+    pub const DYNAMIC_IDENTIFIER: LocalIdentifier = LocalIdentifier(0xF0);
+    /// Routine checking the flash over a range against an expected checksum
+    pub const CHECKSUM_ROUTINE: RoutineIdentifier = RoutineIdentifier(0x02);
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DynamicDefinitionMode {
@@ -20,7 +70,7 @@ pub enum TimingParameter {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
 pub enum CompressionFormat {
     Uncompressed = 0x00,
     Bosch = 0x10,
@@ -30,7 +80,7 @@ pub enum CompressionFormat {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
 pub enum EncryptionFormat {
     Unencrypted = 0x00,
     Bosch = 0x01,
@@ -43,6 +93,16 @@ pub const fn data_format_byte(compression: CompressionFormat, encryption: Encryp
     compression as u8 | encryption as u8
 }
 
+/// Kind of reset requested with `ECUReset`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum ResetMode {
+    /// Same as switching the ECU off and on again
+    PowerOn = 0x01,
+    /// Also resets the nonvolatile memory, clearing adaptation values
+    NonvolatileMemory = 0x82,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReadMode {
@@ -53,6 +113,28 @@ pub enum ReadMode {
     Stop = 0x05,
 }
 
+/// What an `InputOutputControlByLocalIdentifier` request does with the
+/// output.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum IoControlParameter {
+    /// Give the output back to the ECU
+    ReturnControlToEcu = 0x00,
+    ReportCurrentState = 0x01,
+    ReportIoConditions = 0x02,
+    ReportIoScaling = 0x03,
+    /// Reset the long term adjustment to its default
+    ResetToDefault = 0x04,
+    /// Hold the output at its current state
+    FreezeCurrentState = 0x05,
+    ExecuteControlOption = 0x06,
+    /// Set the output to the given state until control is returned
+    ShortTermAdjustment = 0x07,
+    /// Store the given state as the output's adjustment
+    LongTermAdjustment = 0x08,
+    ReportIoCalibrationParameters = 0x09,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressMode {
@@ -88,6 +170,7 @@ pub enum SecurityLevel {
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+#[non_exhaustive]
 pub enum ServiceError {
     /// Service not supported by control unit
     ServiceNotSupported = 0x11,
@@ -138,7 +221,7 @@ pub enum ServiceError {
 macro_rules! ServiceEnums {
     {$($(#[$attr:meta])? $name:ident = $id:expr => $response:expr),*} => {
         #[repr(u8)]
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromRepr)]
         pub enum ServiceId {
             $($(#[$attr])* $name = $id,)*
         }
@@ -148,6 +231,16 @@ macro_rules! ServiceEnums {
             NegativeResponse = 0x7F,
             $($(#[$attr])* $name = $response,)*
         }
+        impl ServiceResponse {
+            /// Service of the request this is a positive response to,
+            /// `None` for negative responses.
+            pub fn request(&self) -> Option<ServiceId> {
+                match self {
+                    Self::NegativeResponse => None,
+                    $(Self::$name => Some(ServiceId::$name),)*
+                }
+            }
+        }
     };
 }
 
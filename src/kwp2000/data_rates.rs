@@ -0,0 +1,129 @@
+//! Rates of the periodic transmissions started with `ReadMode::Slow`,
+//! `Medium` and `Fast`.
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{ReadMode, ServiceError},
+    message::Message,
+    response::{ProcessError, Response},
+};
+
+/// Period of each periodic transmission rate, as the raw byte the ECU
+/// uses. The unit is ECU specific.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRates {
+    pub slow: u8,
+    pub medium: u8,
+    pub fast: u8,
+}
+
+impl DataRates {
+    /// Rate of periodic transmissions with `mode`, `None` for the modes
+    /// that aren't periodic.
+    pub fn rate(&self, mode: ReadMode) -> Option<u8> {
+        match mode {
+            ReadMode::Slow => Some(self.slow),
+            ReadMode::Medium => Some(self.medium),
+            ReadMode::Fast => Some(self.fast),
+            ReadMode::Single | ReadMode::Stop => None,
+        }
+    }
+}
+
+/// Error for a `SetDataRates` answer, `Error::DataRatesNotSupported` if
+/// the ECU has no configurable rates.
+fn data_rates_error(response: Response) -> Error {
+    match response {
+        Response::Error(ProcessError {
+            error: ServiceError::ScalingNotSupported,
+            ..
+        }) => Error::DataRatesNotSupported,
+        r => Error::UnexpectedResponse(r),
+    }
+}
+
+impl Client {
+    /// Sets the rates of the periodic transmissions.
+    pub fn set_data_rates(&mut self, rates: DataRates) -> Result<(), Error> {
+        self.send(Message::SetDataRates {
+            slow: rates.slow,
+            medium: rates.medium,
+            fast: rates.fast,
+        })?;
+        match self.next_response()? {
+            Response::DataRatesSet => Ok(()),
+            // some ECUs echo the rates
+            Response::DataRates(r) if r == rates => Ok(()),
+            r => Err(data_rates_error(r)),
+        }
+    }
+    /// Reads the rates of the periodic transmissions.
+    pub fn data_rates(&mut self) -> Result<DataRates, Error> {
+        self.send(Message::ReadDataRates)?;
+        match self.next_response()? {
+            Response::DataRates(rates) => Ok(rates),
+            r => Err(data_rates_error(r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+
+    const RATES: DataRates = DataRates {
+        slow: 0x40,
+        medium: 0x10,
+        fast: 0x02,
+    };
+
+    #[test]
+    fn rates_are_set_and_read() {
+        let mut client = mock::client(|request| match request {
+            [0x26, 0x40, 0x10, 0x02] => vec![Ok(vec![0x66])],
+            [0x26] => vec![Ok(vec![0x66, 0x40, 0x10, 0x02])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.set_data_rates(RATES).unwrap();
+        assert_eq!(client.data_rates().unwrap(), RATES);
+        assert_eq!(RATES.rate(ReadMode::Medium), Some(0x10));
+        assert_eq!(RATES.rate(ReadMode::Single), None);
+    }
+
+    #[test]
+    fn echoed_rates_must_match() {
+        let mut client = mock::client(|request| match request {
+            [0x26, 0x40, ..] => vec![Ok(vec![0x66, 0x40, 0x10, 0x02])],
+            [0x26, ..] => vec![Ok(vec![0x66, 0x40, 0x10, 0x02])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.set_data_rates(RATES).unwrap();
+        let other = DataRates {
+            fast: 0x01,
+            ..RATES
+        };
+        assert!(matches!(
+            client.set_data_rates(other),
+            Err(Error::UnexpectedResponse(Response::DataRates(r))) if r == RATES
+        ));
+    }
+
+    #[test]
+    fn scaling_not_supported_means_no_rates() {
+        let mut client = mock::client(|request| match request {
+            [0x26, ..] => vec![Ok(vec![0x7F, 0x26, 0x91])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        assert!(matches!(
+            client.set_data_rates(RATES),
+            Err(Error::DataRatesNotSupported)
+        ));
+        assert!(matches!(
+            client.data_rates(),
+            Err(Error::DataRatesNotSupported)
+        ));
+    }
+}
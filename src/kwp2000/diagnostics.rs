@@ -0,0 +1,275 @@
+//! Troubleshooting hints for common failures, matched on the error, the
+//! client's counters and the frames received before it.
+
+use std::fmt::Display;
+
+use crate::Error;
+
+use super::{
+    constants::{Service, ServiceError, ServiceResponse},
+    raw_message::RawMessage,
+    recovery::is_timeout,
+    response::{ProcessError, Response},
+    stats::ClientStats,
+};
+
+/// Number of bad checksums after which they are taken as a pattern rather
+/// than line noise.
+const CHECKSUM_STORM: u32 = 3;
+
+/// Likely cause of a failure and what to try about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hint {
+    /// Stable name of the failure signature.
+    pub id: &'static str,
+    pub cause: &'static str,
+    pub remedy: &'static str,
+}
+
+impl Display for Hint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.cause, self.remedy)
+    }
+}
+
+/// Whether a failure matches a known signature.
+type Signature = fn(&Failure) -> bool;
+
+/// What is known about a failure when explaining it.
+struct Failure<'a> {
+    error: &'a Error,
+    stats: &'a ClientStats,
+    traffic: &'a [RawMessage],
+}
+
+impl Failure<'_> {
+    fn is_echo(frame: &RawMessage) -> bool {
+        matches!(frame.service, Service::Query(_))
+    }
+}
+
+fn no_init_response(f: &Failure) -> bool {
+    is_timeout(f.error) && f.stats.frames_received == 0 && f.traffic.is_empty()
+}
+
+fn bus_busy(f: &Failure) -> bool {
+    matches!(f.error, Error::BusBusy { .. })
+}
+
+fn only_echoes(f: &Failure) -> bool {
+    is_timeout(f.error) && !f.traffic.is_empty() && f.traffic.iter().all(Failure::is_echo)
+}
+
+fn security_lockout(f: &Failure) -> bool {
+    matches!(
+        f.error,
+        Error::SecurityTimout
+            | Error::UnexpectedResponse(Response::Error(ProcessError {
+                error: ServiceError::TooManyAttempts | ServiceError::RequestingTooFast,
+                ..
+            }))
+    )
+}
+
+fn checksum_storm(f: &Failure) -> bool {
+    (matches!(f.error, Error::InvalidChecksum { .. }) || is_timeout(f.error))
+        && f.stats.invalid_checksums >= CHECKSUM_STORM
+        && f.stats.invalid_checksums > f.stats.frames_received
+}
+
+fn baud_switch_death(f: &Failure) -> bool {
+    let last_response = f.traffic.iter().rev().find(|m| !Failure::is_echo(m));
+    is_timeout(f.error)
+        && last_response.is_some_and(|m| {
+            m.service == Service::Response(ServiceResponse::StartDiagnosticSession)
+                && m.data.len() > 1
+        })
+}
+
+/// Failure signatures and the hint for each, in the order they are reported.
+const HINTS: &[(Signature, Hint)] = &[
+    (
+        no_init_response,
+        Hint {
+            id: "no-init-response",
+            cause: "nothing was received after the 5-baud init",
+            remedy: "check that the ignition is on, the adapter is connected to the \
+                     K-line (pin 7) and the profile's init address is right for this ECU",
+        },
+    ),
+    (
+        bus_busy,
+        Hint {
+            id: "bus-busy",
+            cause: "another device kept talking on the K-line",
+            remedy: "disconnect other diagnostic tools and cycle the ignition before \
+                     trying again",
+        },
+    ),
+    (
+        only_echoes,
+        Hint {
+            id: "only-echoes",
+            cause: "only the echoes of our own requests were received, the ECU is \
+                    not answering",
+            remedy: "check the pin 7 wiring and that the ECU was initialized at the \
+                     right address",
+        },
+    ),
+    (
+        security_lockout,
+        Hint {
+            id: "security-lockout",
+            cause: "the ECU locked security access after failed or too fast attempts",
+            remedy: "leave the ignition on for 10 minutes without sending seed \
+                     requests, then check the profile's security algorithm",
+        },
+    ),
+    (
+        checksum_storm,
+        Hint {
+            id: "checksum-storm",
+            cause: "most frames arrived with bad checksums, consistent InvalidChecksum \
+                    usually means the adapter's RX is inverted or it isn't a K-line \
+                    adapter",
+            remedy: "try another adapter or disable the adapter's echo and inversion \
+                     settings",
+        },
+    ),
+    (
+        baud_switch_death,
+        Hint {
+            id: "baud-switch",
+            cause: "the ECU stopped answering after switching baud rates",
+            remedy: "the adapter may not support the rate the ECU asked for, request \
+                     the session without a baud rate change",
+        },
+    ),
+];
+
+/// Hints for `error`, given the client's `stats` and the frames received
+/// before it (`Client::recent_frames`), most relevant first. Empty if the
+/// failure matches no known signature.
+pub fn explain(error: &Error, stats: &ClientStats, recent_traffic: &[RawMessage]) -> Vec<Hint> {
+    let error = match error {
+        Error::PartialRead { source, .. } => source,
+        e => e,
+    };
+    let failure = Failure {
+        error,
+        stats,
+        traffic: recent_traffic,
+    };
+    HINTS
+        .iter()
+        .filter(|(matches, _)| matches(&failure))
+        .map(|(_, hint)| *hint)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{constants::ServiceId, mock::frame};
+    use std::io::ErrorKind;
+
+    fn timeout() -> Error {
+        Error::Io(ErrorKind::TimedOut.into())
+    }
+
+    fn stats(frames_received: u32, invalid_checksums: u32) -> ClientStats {
+        let mut stats = ClientStats::default();
+        stats.frames_received = frames_received;
+        stats.invalid_checksums = invalid_checksums;
+        stats
+    }
+
+    fn ids(error: &Error, stats: &ClientStats, traffic: &[RawMessage]) -> Vec<&'static str> {
+        explain(error, stats, traffic)
+            .iter()
+            .map(|h| h.id)
+            .collect()
+    }
+
+    #[test]
+    fn silence_after_init() {
+        assert_eq!(
+            ids(&timeout(), &ClientStats::default(), &[]),
+            ["no-init-response"]
+        );
+    }
+
+    #[test]
+    fn bus_kept_busy() {
+        let error = Error::BusBusy {
+            observed: 12,
+            sample: vec![0x55],
+        };
+        assert_eq!(ids(&error, &ClientStats::default(), &[]), ["bus-busy"]);
+    }
+
+    #[test]
+    fn echoes_without_answers() {
+        let stats = stats(2, 0);
+        let traffic = [frame(&[0x21, 0xF0]), frame(&[0x3E, 0x01])];
+        assert_eq!(ids(&timeout(), &stats, &traffic), ["only-echoes"]);
+    }
+
+    #[test]
+    fn security_locked_out() {
+        let refused = Error::UnexpectedResponse(Response::Error(ProcessError {
+            error: ServiceError::TooManyAttempts,
+            service: ServiceId::SecurityAccess,
+            extra: vec![],
+        }));
+        for error in [Error::SecurityTimout, refused] {
+            assert_eq!(
+                ids(&error, &ClientStats::default(), &[]),
+                ["security-lockout"]
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_storm_needs_a_pattern() {
+        let error = Error::InvalidChecksum {
+            received: 0x12,
+            computed: 0x34,
+        };
+        let storm = stats(1, 5);
+        assert_eq!(ids(&error, &storm, &[]), ["checksum-storm"]);
+        // a few bad frames among many good ones are line noise
+        let noise = stats(100, 5);
+        assert!(ids(&error, &noise, &[]).is_empty());
+    }
+
+    #[test]
+    fn silence_after_baud_switch() {
+        let stats = stats(3, 0);
+        let traffic = [
+            frame(&[0x10, 0x89, 0x14]),
+            frame(&[0x50, 0x89, 0x14]),
+            frame(&[0x3E, 0x01]),
+        ];
+        assert_eq!(ids(&timeout(), &stats, &traffic), ["baud-switch"]);
+        // the session started without a baud rate change
+        assert!(ids(&timeout(), &stats, &[frame(&[0x50, 0x89])]).is_empty());
+    }
+
+    #[test]
+    fn partial_reads_are_explained_by_their_cause() {
+        let error = Error::partial(vec![1, 2], 0x380000, timeout());
+        assert!(matches!(error, Error::PartialRead { .. }));
+        assert_eq!(
+            ids(&error, &ClientStats::default(), &[]),
+            ["no-init-response"]
+        );
+    }
+
+    #[test]
+    fn unknown_failures_have_no_hints() {
+        assert!(ids(&Error::NotEnoughData, &ClientStats::default(), &[]).is_empty());
+        let hint = HINTS[0].1;
+        assert_eq!(hint.to_string(), format!("{}: {}", hint.cause, hint.remedy));
+    }
+}
@@ -0,0 +1,307 @@
+//! Diagnostic trouble codes stored by the ECU.
+
+use std::fmt::Display;
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{ServiceError, ServiceId},
+    message::Message,
+    policy::DangerousAction,
+    response::{ProcessError, Response},
+};
+
+/// DTC group selecting every stored code.
+pub const ALL_GROUPS: u16 = 0xFF00;
+
+/// Freeze frame record access methods of `Message::ReadFreezeFrame`.
+pub const FREEZE_FRAME_ALL_DATA: u8 = 0x00;
+pub const FREEZE_FRAME_BY_LOCAL_IDENTIFIER: u8 = 0x01;
+pub const FREEZE_FRAME_BY_COMMON_IDENTIFIER: u8 = 0x02;
+/// The identifier is the code that made the ECU store the frame.
+pub const FREEZE_FRAME_BY_DTC: u8 = 0x04;
+
+/// Stored diagnostic trouble code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    /// Code as sent by the ECU
+    pub code: u16,
+    pub status: u8,
+}
+
+/// Decoded DTC status byte, bit layout of ISO 14230-3. Manufacturers may
+/// use the bits differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DtcState {
+    /// The fault was present when the status was read.
+    pub test_failed: bool,
+    /// The fault was seen but isn't stored as confirmed yet (maturing).
+    pub pending: bool,
+    /// The fault is stored.
+    pub confirmed: bool,
+    /// The warning lamp (MIL) is requested.
+    pub mil_on: bool,
+    /// The test for the code hasn't run to completion.
+    pub test_incomplete: bool,
+}
+
+impl DtcState {
+    pub fn from_status(status: u8) -> Self {
+        // bits 6-5: not stored, stored but not present, maturing, present
+        let storage = (status >> 5) & 0b11;
+        Self {
+            test_failed: storage == 0b11,
+            pending: storage == 0b10,
+            confirmed: storage == 0b01 || storage == 0b11,
+            mil_on: status & 0x80 != 0,
+            test_incomplete: status & 0x10 != 0,
+        }
+    }
+}
+
+impl Dtc {
+    pub fn state(&self) -> DtcState {
+        DtcState::from_status(self.status)
+    }
+
+    /// Parses the three byte entries (code, status) of a response.
+    pub fn parse_list(data: &[u8]) -> Result<Vec<Self>, Error> {
+        let entries = data.chunks_exact(3);
+        if !entries.remainder().is_empty() {
+            return Err(Error::NotEnoughData);
+        }
+        Ok(entries
+            .map(|e| Self {
+                code: u16::from_be_bytes([e[0], e[1]]),
+                status: e[2],
+            })
+            .collect())
+    }
+}
+
+impl Display for Dtc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04X} (status 0x{:02x})", self.code, self.status)
+    }
+}
+
+impl Client {
+    /// Reads the codes stored for `group`, `ALL_GROUPS` for every code.
+    /// Codes that don't fit one response are collected from the ones
+    /// following it.
+    pub fn read_dtcs(&mut self, group: u16) -> Result<Vec<Dtc>, Error> {
+        self.collect_dtcs(Message::ReadDiagnosticTroubleCodes { group })
+    }
+    /// Reads the status of `code`, `Error::UnknownDtc` if the ECU doesn't
+    /// know it.
+    pub fn read_dtc_status(&mut self, code: u16) -> Result<Dtc, Error> {
+        self.send(Message::ReadStatusOfDtc(code))?;
+        match self.next_response()? {
+            Response::DtcStatus { code: c, status } if c == code => Ok(Dtc { code, status }),
+            Response::Error(ProcessError {
+                error: ServiceError::RequestOutOfRange,
+                ..
+            }) => Err(Error::UnknownDtc(code)),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads the record of freeze frame `frame_number` selected by `mode`
+    /// and `identifier`, see `Message::ReadFreezeFrame`. `None` if the ECU
+    /// has no such frame stored.
+    pub fn read_freeze_frame(
+        &mut self,
+        frame_number: u8,
+        mode: u8,
+        identifier: u16,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.send(Message::ReadFreezeFrame {
+            frame_number,
+            mode,
+            identifier,
+        })?;
+        match self.next_response()? {
+            Response::FreezeFrame(n, data) if n == frame_number => Ok(Some(data)),
+            Response::Error(ProcessError {
+                error: ServiceError::RequestOutOfRange,
+                ..
+            }) => Ok(None),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads the freeze frame stored when `dtc` was set, `None` if there is
+    /// none.
+    pub fn read_dtc_freeze_frame(&mut self, dtc: &Dtc) -> Result<Option<Vec<u8>>, Error> {
+        self.read_freeze_frame(0x00, FREEZE_FRAME_BY_DTC, dtc.code)
+    }
+    /// Reads every stored code, see `read_dtcs`.
+    pub fn read_all_dtcs(&mut self) -> Result<Vec<Dtc>, Error> {
+        self.read_dtcs(ALL_GROUPS)
+    }
+    /// Clears the codes stored for `group`, `ALL_GROUPS` for every code.
+    /// ECUs often take a second or two, answering `ResponsePending` until
+    /// done.
+    pub fn clear_dtcs(&mut self, group: u16) -> Result<(), Error> {
+        self.confirm(DangerousAction::DtcClear { group })?;
+        self.send(Message::ClearDiagnosticInformation { group })?;
+        match self.next_response_expect_wait(Some(ServiceId::ClearDiagnosticInformation))? {
+            Response::DiagnosticInformationCleared => Ok(()),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Clears every stored code, see `clear_dtcs`.
+    pub fn clear_all_dtcs(&mut self) -> Result<(), Error> {
+        self.clear_dtcs(ALL_GROUPS)
+    }
+    /// Reads the codes of `group` with any of the status bits in `mask`
+    /// set, the service most VAG tools use. Codes that don't fit one
+    /// response are collected from the ones following it.
+    pub fn read_dtcs_by_status(&mut self, mask: u8, group: u16) -> Result<Vec<Dtc>, Error> {
+        self.collect_dtcs(Message::ReadDtcByStatus {
+            status_mask: mask,
+            group,
+        })
+    }
+    /// Sends `request` and reads responses until they held as many codes
+    /// as their count byte says.
+    fn collect_dtcs(&mut self, request: Message) -> Result<Vec<Dtc>, Error> {
+        self.send(request)?;
+        let mut dtcs = Vec::new();
+        loop {
+            match self.next_response()? {
+                Response::DiagnosticTroubleCodes(count, mut part)
+                | Response::DtcsByStatus(count, mut part) => {
+                    dtcs.append(&mut part);
+                    if dtcs.len() >= count as usize {
+                        return Ok(dtcs);
+                    }
+                }
+                r => return Err(Error::UnexpectedResponse(r)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{constants::Service, mock, response};
+
+    #[test]
+    fn dtc_status_request_is_encoded() {
+        let raw = Message::ReadStatusOfDtc(0x1234).raw().unwrap();
+        assert_eq!(raw.service, Service::Query(ServiceId::ReadStatusOfDTC));
+        assert_eq!(raw.data, [0x12, 0x34]);
+    }
+
+    #[test]
+    fn dtc_status_response_is_parsed() {
+        match response::from_raw(mock::frame(&[0x57, 0x01, 0x12, 0x34, 0xE0])).unwrap() {
+            Response::DtcStatus { code, status } => {
+                assert_eq!(code, 0x1234);
+                assert_eq!(status, 0xE0);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn dtc_status_byte_is_decoded() {
+        assert_eq!(
+            DtcState::from_status(0xF0),
+            DtcState {
+                test_failed: true,
+                pending: false,
+                confirmed: true,
+                mil_on: true,
+                test_incomplete: true,
+            }
+        );
+        let maturing = DtcState::from_status(0x40);
+        assert!(maturing.pending && !maturing.confirmed && !maturing.mil_on);
+        let stored = DtcState::from_status(0x20);
+        assert!(stored.confirmed && !stored.test_failed);
+    }
+
+    #[test]
+    fn client_reads_dtc_status() {
+        let mut client = mock::client(|request| match request {
+            [0x17, 0x12, 0x34] => vec![Ok(vec![0x57, 0x01, 0x12, 0x34, 0x60])],
+            // no codes
+            [0x17, 0x9A, 0xBC] => vec![Ok(vec![0x57, 0x00])],
+            // RequestOutOfRange
+            [0x17, ..] => vec![Ok(vec![0x7F, 0x17, 0x31])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        assert_eq!(
+            client.read_dtc_status(0x1234).unwrap(),
+            Dtc {
+                code: 0x1234,
+                status: 0x60
+            }
+        );
+        assert!(matches!(
+            client.read_dtc_status(0x5678),
+            Err(Error::UnknownDtc(0x5678))
+        ));
+        assert!(matches!(
+            client.read_dtc_status(0x9ABC),
+            Err(Error::UnknownDtc(0x9ABC))
+        ));
+    }
+
+    #[test]
+    fn freeze_frame_requests_are_encoded() {
+        let encode = |mode, identifier| {
+            Message::ReadFreezeFrame {
+                frame_number: 0x02,
+                mode,
+                identifier,
+            }
+            .raw()
+            .unwrap()
+        };
+        let raw = encode(FREEZE_FRAME_ALL_DATA, 0x1234);
+        assert_eq!(raw.service, Service::Query(ServiceId::ReadFreezeFrameData));
+        assert_eq!(raw.data, [0x02, 0x00]);
+        assert_eq!(
+            encode(FREEZE_FRAME_BY_LOCAL_IDENTIFIER, 0x1234).data,
+            [0x02, 0x01, 0x34]
+        );
+        assert_eq!(
+            encode(FREEZE_FRAME_BY_COMMON_IDENTIFIER, 0x1234).data,
+            [0x02, 0x02, 0x12, 0x34]
+        );
+        assert_eq!(
+            encode(FREEZE_FRAME_BY_DTC, 0x1234).data,
+            [0x02, 0x04, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn freeze_frame_response_is_parsed() {
+        match response::from_raw(mock::frame(&[0x52, 0x02, 0xAA, 0xBB])).unwrap() {
+            Response::FreezeFrame(frame, data) => {
+                assert_eq!(frame, 0x02);
+                assert_eq!(data, [0xAA, 0xBB]);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn client_reads_freeze_frames() {
+        let mut client = mock::client(|request| match request {
+            [0x12, 0x00, 0x04, 0x12, 0x34] => vec![Ok(vec![0x52, 0x00, 0xAA, 0xBB])],
+            // RequestOutOfRange
+            [0x12, ..] => vec![Ok(vec![0x7F, 0x12, 0x31])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        let dtc = |code| Dtc { code, status: 0x60 };
+        assert_eq!(
+            client.read_dtc_freeze_frame(&dtc(0x1234)).unwrap(),
+            Some(vec![0xAA, 0xBB])
+        );
+        assert_eq!(client.read_dtc_freeze_frame(&dtc(0x5678)).unwrap(), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Dumps that can be stopped and continued by a later run.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, MemoryLayout};
+
+use super::{
+    client::Client,
+    image::SidecarWriter,
+    progress::{Operation, Tracker},
+};
+
+/// Number of bytes read between updates of the manifest.
+const DUMP_CHUNK: u32 = 0x1000;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Continues a 64-bit FNV-1a hash `hash` over `data`. Used instead of the
+/// standard hasher because the result is stored and has to stay the same
+/// across builds.
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn layout_hash(layout: &MemoryLayout) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, &layout.base_address.to_be_bytes());
+    hash = fnv1a(hash, &layout.size.to_be_bytes());
+    for sector in &layout.sectors {
+        hash = fnv1a(hash, &sector.to_be_bytes());
+    }
+    hash
+}
+
+/// Progress of a dump, stored next to the output file so a later run can
+/// continue it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpManifest {
+    pub address: u32,
+    pub size: u32,
+    /// Address up to which the output file holds the dumped data
+    pub watermark: u32,
+    /// Hash of the profile's memory layout the dump was started with
+    pub layout_hash: u64,
+    /// Hash of the output file up to the watermark
+    pub output_hash: u64,
+}
+
+impl DumpManifest {
+    /// Path of the manifest belonging to the output file `output`.
+    pub fn path(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".resume");
+        PathBuf::from(path)
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "address=0x{:08x}\nsize=0x{:08x}\nwatermark=0x{:08x}\nlayout=0x{:016x}\noutput=0x{:016x}\n",
+            self.address, self.size, self.watermark, self.layout_hash, self.output_hash
+        )
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let field = |name: &'static str| {
+            text.lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix('='))
+                .and_then(|v| u64::from_str_radix(v.trim().trim_start_matches("0x"), 16).ok())
+                .ok_or(Error::ResumeRejected(format!(
+                    "manifest has no valid {name}"
+                )))
+        };
+        let address = |name| {
+            field(name)?
+                .try_into()
+                .map_err(|_| Error::ResumeRejected(format!("manifest {name} is too large")))
+        };
+        Ok(Self {
+            address: address("address")?,
+            size: address("size")?,
+            watermark: address("watermark")?,
+            layout_hash: field("layout")?,
+            output_hash: field("output")?,
+        })
+    }
+
+    fn save(&self, output: &Path) -> Result<(), Error> {
+        let path = Self::path(output);
+        let tmp = path.with_extension("resume.tmp");
+        std::fs::write(&tmp, self.to_text())?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Checks that the dump can be continued with `layout` into `output`,
+    /// which has to hold exactly the data dumped so far.
+    pub fn validate(&self, layout: &MemoryLayout, output: &mut File) -> Result<(), Error> {
+        if self.layout_hash != layout_hash(layout) {
+            return Err(Error::ResumeRejected(
+                "the profile's memory layout changed since the dump was started".to_string(),
+            ));
+        }
+        let dumped = self.watermark.wrapping_sub(self.address);
+        if dumped > self.size {
+            return Err(Error::ResumeRejected(
+                "watermark is outside the dumped range".to_string(),
+            ));
+        }
+        let mut data = Vec::new();
+        output.read_to_end(&mut data)?;
+        if data.len() != dumped as usize {
+            return Err(Error::ResumeRejected(format!(
+                "output holds {} bytes, the manifest expects {dumped}",
+                data.len()
+            )));
+        }
+        if fnv1a(FNV_OFFSET, &data) != self.output_hash {
+            return Err(Error::ResumeRejected(
+                "output file was changed since the dump was stopped".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Dumps `size` bytes from `address` into the file at `output` with
+    /// `read_best_effort`, keeping a manifest next to it (see
+    /// `DumpManifest::path`) so the dump can be continued with
+    /// `resume_dump` if it is stopped. The manifest is removed once the dump
+    /// is complete, and replaced by a sidecar if `dump_sidecar` is set.
+    pub fn dump_resumable(&mut self, address: u32, size: u32, output: &Path) -> Result<(), Error> {
+        let manifest = DumpManifest {
+            address,
+            size,
+            watermark: address,
+            layout_hash: layout_hash(&self.profile.memory_layout),
+            output_hash: FNV_OFFSET,
+        };
+        let mut file = File::create(output)?;
+        manifest.save(output)?;
+        self.continue_dump(manifest, &mut file, output)
+    }
+    /// Continues the dump into `output` described by the manifest next to
+    /// it, appending to the file. Fails with `Error::ResumeRejected` if the
+    /// file or the profile's memory layout don't match the manifest. The
+    /// session has to be set up again (security access, mode) before.
+    pub fn resume_dump(&mut self, output: &Path) -> Result<(), Error> {
+        let text = std::fs::read_to_string(DumpManifest::path(output))?;
+        let manifest = DumpManifest::parse(&text)?;
+        let mut file = OpenOptions::new().read(true).append(true).open(output)?;
+        manifest.validate(&self.profile.memory_layout, &mut file)?;
+        self.continue_dump(manifest, &mut file, output)
+    }
+    fn continue_dump(
+        &mut self,
+        mut manifest: DumpManifest,
+        file: &mut File,
+        output: &Path,
+    ) -> Result<(), Error> {
+        let mut tracker = Tracker::start(
+            self,
+            Operation::Read,
+            manifest.address,
+            manifest.size as usize,
+        );
+        // hashed as it is written, a resumed dump starts from what is on
+        // disk, which `resume_dump` checked against the manifest
+        let mut sidecar = None;
+        if self.dump_sidecar {
+            let mut writer = SidecarWriter::new(manifest.address, DUMP_CHUNK);
+            writer.update(&std::fs::read(output)?);
+            sidecar = Some(writer);
+        }
+        let result = self.dump_chunks(&mut manifest, file, output, &mut tracker, &mut sidecar);
+        tracker.finish(self, &result);
+        result?;
+        if let Some(sidecar) = sidecar {
+            sidecar.finish().save(output)?;
+        }
+        std::fs::remove_file(DumpManifest::path(output))?;
+        Ok(())
+    }
+    fn dump_chunks(
+        &mut self,
+        manifest: &mut DumpManifest,
+        file: &mut File,
+        output: &Path,
+        tracker: &mut Tracker,
+        sidecar: &mut Option<SidecarWriter>,
+    ) -> Result<(), Error> {
+        let end = manifest.address as u64 + manifest.size as u64;
+        while (manifest.watermark as u64) < end {
+            let length = Ord::min(DUMP_CHUNK as u64, end - manifest.watermark as u64) as u32;
+            tracker.done_before = manifest.watermark.wrapping_sub(manifest.address) as usize;
+            let read = self.read_best_effort_tracked(manifest.watermark, length, tracker);
+            let (data, error) = match read {
+                Ok(read) => (read.data, None),
+                // partial reads end on a chunk boundary of read_best_effort
+                Err(Error::PartialRead { data, source, .. }) => (data, Some(*source)),
+                Err(e) => (Vec::new(), Some(e)),
+            };
+            file.write_all(&data)?;
+            file.flush()?;
+            manifest.watermark = manifest.watermark.wrapping_add(data.len() as u32);
+            manifest.output_hash = fnv1a(manifest.output_hash, &data);
+            if let Some(sidecar) = sidecar {
+                sidecar.update(&data);
+            }
+            manifest.save(output)?;
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
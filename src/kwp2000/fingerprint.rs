@@ -0,0 +1,108 @@
+//! Identification of the tester that last programmed the ECU.
+
+use crate::Error;
+
+use super::{
+    client::Client, message::Message, policy::DangerousAction, response::Response, warning::Warning,
+};
+
+/// Tester identification written when programming, read back by workshops
+/// to see who flashed the ECU.
+///
+/// Encoded as ten bytes: the programming date as BCD year (since 2000),
+/// month and day, the importer number (2 bytes), the workshop code (3 bytes)
+/// and the tester serial number (2 bytes), numbers big endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TesterFingerprint {
+    pub year: u8,
+    pub month: u8,
+    pub day: u8,
+    pub importer: u16,
+    /// Workshop code, at most 24 bits
+    pub workshop_code: u32,
+    pub tester_serial: u16,
+}
+
+impl TesterFingerprint {
+    pub const LEN: usize = 10;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let data: &[u8; Self::LEN] = data
+            .get(..Self::LEN)
+            .and_then(|d| d.try_into().ok())
+            .ok_or(Error::NotEnoughData)?;
+        let bcd = |b: u8| {
+            let (high, low) = (b >> 4, b & 0x0F);
+            if high > 9 || low > 9 {
+                Err(Error::UnexpectedValue)
+            } else {
+                Ok(high * 10 + low)
+            }
+        };
+        Ok(Self {
+            year: bcd(data[0])?,
+            month: bcd(data[1])?,
+            day: bcd(data[2])?,
+            importer: u16::from_be_bytes([data[3], data[4]]),
+            workshop_code: u32::from_be_bytes([0, data[5], data[6], data[7]]),
+            tester_serial: u16::from_be_bytes([data[8], data[9]]),
+        })
+    }
+
+    /// Fails with `Error::UnexpectedValue` if a date field doesn't fit two
+    /// BCD digits or the workshop code doesn't fit 24 bits.
+    pub fn to_bytes(&self) -> Result<[u8; Self::LEN], Error> {
+        let bcd = |n: u8| {
+            if n > 99 {
+                Err(Error::UnexpectedValue)
+            } else {
+                Ok(((n / 10) << 4) | (n % 10))
+            }
+        };
+        if self.workshop_code > 0xFF_FFFF {
+            return Err(Error::UnexpectedValue);
+        }
+        let importer = self.importer.to_be_bytes();
+        let workshop = self.workshop_code.to_be_bytes();
+        let serial = self.tester_serial.to_be_bytes();
+        Ok([
+            bcd(self.year)?,
+            bcd(self.month)?,
+            bcd(self.day)?,
+            importer[0],
+            importer[1],
+            workshop[1],
+            workshop[2],
+            workshop[3],
+            serial[0],
+            serial[1],
+        ])
+    }
+}
+
+impl Client {
+    /// Reads the fingerprint of the tester that last programmed the ECU,
+    /// `None` if the profile doesn't know where the ECU keeps it.
+    pub fn read_fingerprint(&mut self) -> Result<Option<TesterFingerprint>, Error> {
+        let Some((option, _)) = self.profile.fingerprint else {
+            return Ok(None);
+        };
+        let data = self.read_ecu_identification(option)?;
+        TesterFingerprint::from_bytes(&data).map(Some)
+    }
+    /// Writes `fingerprint` to the ECU. Profiles that don't know where the
+    /// ECU keeps it skip the write with a `Warning::FingerprintSkipped`.
+    pub fn write_fingerprint(&mut self, fingerprint: &TesterFingerprint) -> Result<(), Error> {
+        let Some((_, identifier)) = self.profile.fingerprint else {
+            self.warn(Warning::FingerprintSkipped);
+            return Ok(());
+        };
+        let data = fingerprint.to_bytes()?;
+        self.confirm(DangerousAction::FingerprintWrite)?;
+        self.send(Message::WriteLocalIdentifier(identifier, data.to_vec()))?;
+        match self.next_response()? {
+            Response::LocalIdentifierWritten(id) if id == identifier => Ok(()),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+}
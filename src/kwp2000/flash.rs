@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::Error;
+
+use super::{
+    client::{BlockCounter, Client, MAX_BLOCK_REPEATS},
+    codec::{BoschCodec, DataCodec},
+    constants::{CompressionFormat, EncryptionFormat, ServiceError, ServiceId},
+    message::Message,
+    policy::DangerousAction,
+    programming::{FlashReport, MAX_VERIFY_RETRIES},
+    progress::{Operation, Phase, Tracker},
+    recovery::is_timeout,
+    response::{ProcessError, Response},
+    transfer::TransferRequest,
+    warning::Warning,
+};
+
+impl Client {
+    /// Adds `codec` to the codecs used by `write_data`, replacing the one
+    /// registered for the same formats.
+    pub fn register_codec(&mut self, codec: Arc<dyn DataCodec>) {
+        self.codecs.retain(|c| {
+            c.compression() != codec.compression() || c.encryption() != codec.encryption()
+        });
+        self.codecs.push(codec);
+    }
+    /// Codec registered for the given formats.
+    pub fn codec(
+        &self,
+        compression: CompressionFormat,
+        encryption: EncryptionFormat,
+    ) -> Option<Arc<dyn DataCodec>> {
+        self.codecs
+            .iter()
+            .find(|c| c.compression() == compression && c.encryption() == encryption)
+            .cloned()
+    }
+    /// Writes `data` to the flash at `address` using the registered codec
+    /// for the given formats. Fails with `Error::UnsupportedDataFormat`
+    /// before sending anything if there is none.
+    pub fn write_data(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        compression: CompressionFormat,
+        encryption: EncryptionFormat,
+    ) -> Result<FlashReport, Error> {
+        let codec = self
+            .codec(compression, encryption)
+            .ok_or(Error::UnsupportedDataFormat {
+                compression,
+                encryption,
+            })?;
+        self.write_data_with(address, data, codec.as_ref())
+    }
+    /// Writes `data` to the flash at `address` using the Bosch format with
+    /// `key`.
+    pub fn write_data_bosch(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        key: &[u8],
+    ) -> Result<FlashReport, Error> {
+        self.write_data_with(address, data, &BoschCodec { key: key.to_vec() })
+    }
+    /// Fails if the range doesn't fit the profile's memory layout (see
+    /// `check_flash_range`) or the ECU reports that a previous attempt left
+    /// the flash half programmed, unless `force_flash` is set. With `verify_interval` set
+    /// the data is sent in groups that are each checked with the profile's
+    /// checksum routine, a group that fails is sent again.
+    fn write_data_with(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        codec: &dyn DataCodec,
+    ) -> Result<FlashReport, Error> {
+        self.confirm(DangerousAction::FlashWrite {
+            address,
+            size: data.len() as u32,
+            sectors: self
+                .profile
+                .memory_layout
+                .sectors_in_range(address, data.len() as u32),
+        })?;
+        self.check_flash_range(address, data.len() as u32)?;
+        if self.verify_interval.is_some() && self.profile.checksum_routine.is_none() {
+            return Err(Error::NotImplemented);
+        }
+        let status = self.read_programming_status()?;
+        if status.is_half_programmed() {
+            self.warn(Warning::HalfProgrammed {
+                status: status.clone(),
+            });
+            if !self.force_flash {
+                return Err(Error::HalfProgrammed(status));
+            }
+        }
+
+        if let Some(fingerprint) = self.flash_fingerprint {
+            self.write_fingerprint(&fingerprint)?;
+        }
+
+        let mut tracker = Tracker::start(self, Operation::FlashWrite, address, data.len());
+        let result = self.write_groups(address, data, codec, &mut tracker);
+        tracker.finish(self, &result);
+        let (sent, verified) = result?;
+
+        Ok(FlashReport {
+            sent,
+            verified,
+            programming_status: self.read_programming_status()?,
+        })
+    }
+    /// Sends `data`, in groups checked with the checksum routine if
+    /// `verify_interval` is set. Returns the number of bytes sent and
+    /// verified.
+    fn write_groups(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        codec: &dyn DataCodec,
+        tracker: &mut Tracker,
+    ) -> Result<(usize, usize), Error> {
+        let mut sent = 0;
+        let mut verified = 0;
+        match self.verify_interval {
+            Some(interval) => {
+                let mut retries = 0;
+                while verified < data.len() {
+                    let end = Ord::min(verified.saturating_add(interval.max(1)), data.len());
+                    let group = &data[verified..end];
+                    let group_address = address.wrapping_add(verified as u32);
+                    sent += self.download(group_address, group, codec, tracker, verified)?;
+                    tracker.update(self, Phase::Verifying, group_address, end);
+                    if self.verify_checksum(group_address, group)? {
+                        verified = end;
+                        retries = 0;
+                    } else if retries < MAX_VERIFY_RETRIES {
+                        retries += 1;
+                        self.warn(Warning::VerifyRetry {
+                            address: group_address,
+                        });
+                    } else {
+                        return Err(Error::VerifyFailed {
+                            address: group_address,
+                            verified,
+                        });
+                    }
+                }
+            }
+            None => sent = self.download(address, data, codec, tracker, 0)?,
+        }
+        Ok((sent, verified))
+    }
+    /// Sends `data` to `address` in one download, returns the number of
+    /// bytes sent. `done` is the number of bytes of the operation tracked by
+    /// `tracker` that were sent before.
+    fn download(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        codec: &dyn DataCodec,
+        tracker: &mut Tracker,
+        done: usize,
+    ) -> Result<usize, Error> {
+        let request = TransferRequest::download(address, data.len() as u32)
+            .encoded_with(codec)
+            .build(self)?;
+        let _token = self.bus_token.acquire()?;
+        self.send(request)?;
+        let mut codec_state = 0;
+        // block length from the confirmation, blocks longer than fit in a
+        // message are sent in several
+        let mut block_len = 0;
+        let message_len = self.max_request_payload();
+        let mut queued: VecDeque<Vec<u8>> = VecDeque::new();
+        let counted = self.profile.block_counter == BlockCounter::Rolling;
+        // counter and contents of the last block sent
+        let mut counter = 0u8;
+        let mut last_block = Vec::new();
+        let mut repeats = 0;
+
+        // uncompressed bytes sent so far
+        let mut sent_bytes = 0;
+
+        let mut response = self.next_response();
+        loop {
+            let m = match response {
+                Ok(m) => m,
+                // some ECUs don't acknowledge the last block
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e),
+            };
+            self.check_deadline(sent_bytes)?;
+            let first = if let Response::DownloadConfirmation(max) = m {
+                block_len = max as usize;
+                true
+            } else if let Response::ReadyForMoreData = m {
+                false
+            } else if let Response::DataTransfer(ack) = &m
+                && counted
+                && ack.len() == 1
+            {
+                if ack[0] == counter {
+                    false
+                } else if ack[0] == counter.wrapping_sub(1) && repeats < MAX_BLOCK_REPEATS {
+                    repeats += 1;
+                    self.warn(Warning::BlockRepeated { counter });
+                    self.send(Message::SendData(last_block.clone()))?;
+                    response = self.next_response();
+                    continue;
+                } else {
+                    return Err(Error::BlockSequenceGap {
+                        expected: counter,
+                        got: ack[0],
+                    });
+                }
+            } else if let Response::Error(ProcessError {
+                error: ServiceError::RoutineNotComplete,
+                service: ServiceId::RequestDownload,
+                ..
+            }) = m
+            {
+                response = self.next_response();
+                continue;
+            } else {
+                return Err(Error::UnexpectedResponse(m));
+            };
+
+            if let Some(part) = queued.pop_front() {
+                self.send(Message::SendData(part))?;
+                response = self.next_response();
+                continue;
+            }
+            if sent_bytes >= data.len() {
+                break;
+            }
+            let header = &self.profile.bcb_first_block_header;
+            let (sent, mut transfer_block) = codec.encode_block(
+                if counted {
+                    // the counter is per message
+                    Ord::min(block_len, message_len).saturating_sub(1)
+                } else {
+                    block_len
+                },
+                &data[sent_bytes..],
+                &mut codec_state,
+                (first && !header.is_empty()).then_some(header.as_slice()),
+            )?;
+            if counted {
+                counter = counter.wrapping_add(1);
+                transfer_block.insert(0, counter);
+                last_block.clone_from(&transfer_block);
+                repeats = 0;
+            } else if transfer_block.len() > message_len {
+                queued.extend(
+                    transfer_block[message_len..]
+                        .chunks(message_len)
+                        .map(<[u8]>::to_vec),
+                );
+                transfer_block.truncate(message_len);
+            }
+
+            self.send(Message::SendData(transfer_block))?;
+
+            sent_bytes += sent;
+            tracker.update(
+                self,
+                Phase::Transferring,
+                address.wrapping_add(sent_bytes as u32),
+                done + sent_bytes,
+            );
+            response = self.next_response();
+        }
+        Ok(sent_bytes)
+    }
+}
@@ -0,0 +1,252 @@
+use std::fmt::Display;
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::ServiceError,
+    response::{ProcessError, Response},
+};
+
+/// Removes the padding ECUs put after identification strings.
+fn trim_padding(data: &[u8]) -> &[u8] {
+    let end = data
+        .iter()
+        .rposition(|b| !matches!(b, 0x00 | b' ' | 0xFF))
+        .map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Value of a VIN character in the check digit calculation.
+fn vin_value(c: u8) -> Option<u32> {
+    Some(match c {
+        b'0'..=b'9' => (c - b'0') as u32,
+        b'A' | b'J' => 1,
+        b'B' | b'K' | b'S' => 2,
+        b'C' | b'L' | b'T' => 3,
+        b'D' | b'M' | b'U' => 4,
+        b'E' | b'N' | b'V' => 5,
+        b'F' | b'W' => 6,
+        b'G' | b'P' | b'X' => 7,
+        b'H' | b'Y' => 8,
+        b'R' | b'Z' => 9,
+        _ => return None,
+    })
+}
+
+/// Vehicle identification number, 17 characters. The check digit at
+/// position 9 is only verified when it is a digit or `X`, European VINs
+/// (VAG ones among them) often have a filler like `Z` there instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vin(String);
+
+impl Vin {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        const WEIGHTS: [u32; 17] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+        let malformed = || Error::MalformedIdentification {
+            what: "VIN",
+            data: data.to_vec(),
+        };
+        let vin = trim_padding(data);
+        if vin.len() != WEIGHTS.len() {
+            return Err(malformed());
+        }
+        let mut sum = 0;
+        for (c, weight) in vin.iter().zip(WEIGHTS) {
+            sum += vin_value(*c).ok_or_else(malformed)? * weight;
+        }
+        let check = match sum % 11 {
+            10 => b'X',
+            n => b'0' + n as u8,
+        };
+        if (vin[8].is_ascii_digit() || vin[8] == b'X') && vin[8] != check {
+            return Err(malformed());
+        }
+        Ok(Self(String::from_utf8_lossy(vin).into_owned()))
+    }
+}
+
+impl Display for Vin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Immobilizer identifier, 14 alphanumeric characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImmobilizerId(String);
+
+impl ImmobilizerId {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let id = trim_padding(data);
+        if id.len() != 14 || !id.iter().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::MalformedIdentification {
+                what: "immobilizer id",
+                data: data.to_vec(),
+            });
+        }
+        Ok(Self(String::from_utf8_lossy(id).into_owned()))
+    }
+}
+
+impl Display for ImmobilizerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Identification option with the part number, software version and engine
+/// of VW/Bosch ECUs.
+pub const VAG_IDENTIFICATION: u8 = 0x9B;
+
+/// Identification the VW/Bosch ECUs return for option 0x9B: the 12
+/// character VAG part number, the 4 character software version and a
+/// description of the engine, sometimes followed by binary data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcuIdentification {
+    /// VAG part number, e.g. `8D0907551M`
+    pub part_number: String,
+    pub software_version: String,
+    /// Engine the software is for, e.g. `2.7l V6/5VT`
+    pub engine_code: String,
+    /// The identification as read, for fields this doesn't decode.
+    pub raw: Vec<u8>,
+}
+
+impl EcuIdentification {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let malformed = || Error::MalformedIdentification {
+            what: "ECU identification",
+            data: data.to_vec(),
+        };
+        let text = |bytes: &[u8]| {
+            let bytes = trim_padding(bytes);
+            bytes
+                .iter()
+                .all(|c| c.is_ascii_graphic() || *c == b' ')
+                .then(|| String::from_utf8_lossy(bytes).into_owned())
+        };
+        if data.len() < 16 {
+            return Err(malformed());
+        }
+        let part_number = text(&data[..12]).ok_or_else(malformed)?;
+        let software_version = text(&data[12..16]).ok_or_else(malformed)?;
+        let description = &data[16..];
+        let end = description
+            .iter()
+            .position(|c| !(c.is_ascii_graphic() || *c == b' '))
+            .unwrap_or(description.len());
+        Ok(Self {
+            part_number,
+            software_version,
+            engine_code: text(&description[..end]).unwrap_or_default(),
+            raw: data.to_vec(),
+        })
+    }
+}
+
+impl Display for EcuIdentification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.part_number, self.software_version, self.engine_code
+        )
+    }
+}
+
+impl Client {
+    /// Reads the part number, software version and engine from
+    /// identification option 0x9B, see `read_ecu_identification` for the
+    /// bytes of other options.
+    pub fn read_vag_identification(&mut self) -> Result<EcuIdentification, Error> {
+        EcuIdentification::parse(&self.read_ecu_identification(VAG_IDENTIFICATION)?)
+    }
+    /// Reads identification `option` if the profile has one, failing with
+    /// `Error::NotExposed` if it doesn't or the ECU refuses the option.
+    fn read_profile_identification(
+        &mut self,
+        option: Option<u8>,
+        what: &'static str,
+    ) -> Result<Vec<u8>, Error> {
+        let option = option.ok_or(Error::NotExposed(what))?;
+        match self.read_ecu_identification(option) {
+            Err(Error::UnexpectedResponse(Response::Error(ProcessError {
+                error: ServiceError::RequestOutOfRange | ServiceError::ServiceNotSupported,
+                ..
+            }))) => Err(Error::NotExposed(what)),
+            r => r,
+        }
+    }
+    /// Reads the VIN stored in the ECU.
+    pub fn read_vin_kwp(&mut self) -> Result<Vin, Error> {
+        let data = self.read_profile_identification(self.profile.vin_identification, "VIN")?;
+        Vin::parse(&data)
+    }
+    /// Reads the identifier of the immobilizer the ECU is paired with.
+    pub fn read_immobilizer_id(&mut self) -> Result<ImmobilizerId, Error> {
+        let data = self.read_profile_identification(
+            self.profile.immobilizer_identification,
+            "immobilizer id",
+        )?;
+        ImmobilizerId::parse(&data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+
+    #[test]
+    fn vin_check_digit() {
+        let valid = Vin::parse(b"1M8GDM9AXKP042788\0\0").unwrap();
+        assert_eq!(valid.to_string(), "1M8GDM9AXKP042788");
+        assert!(matches!(
+            Vin::parse(b"1M8GDM9A1KP042788"),
+            Err(Error::MalformedIdentification { what: "VIN", .. })
+        ));
+        // filler instead of a check digit
+        assert!(Vin::parse(b"WVWZZZ1JZ3W386752").is_ok());
+        // too short, and a letter VINs don't use
+        assert!(Vin::parse(b"WVWZZZ1JZ3W38675").is_err());
+        assert!(Vin::parse(b"WVWZZZ1JZ3W38675O").is_err());
+    }
+
+    #[test]
+    fn refused_vin_is_not_exposed() {
+        let mut client = mock::client(|request| match request {
+            // RequestOutOfRange
+            [0x1A, 0x90] => vec![Ok(vec![0x7F, 0x1A, 0x31])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.profile.vin_identification = Some(0x90);
+        assert!(matches!(
+            client.read_vin_kwp(),
+            Err(Error::NotExposed("VIN"))
+        ));
+        client.profile.vin_identification = None;
+        assert!(matches!(
+            client.read_vin_kwp(),
+            Err(Error::NotExposed("VIN"))
+        ));
+    }
+
+    #[test]
+    fn client_reads_vin() {
+        let mut client = mock::client(|request| match request {
+            [0x1A, 0x90] => {
+                let mut response = vec![0x5A, 0x90];
+                response.extend_from_slice(b"WVWZZZ1JZ3W386752   ");
+                vec![Ok(response)]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        client.profile.vin_identification = Some(0x90);
+        assert_eq!(
+            client.read_vin_kwp().unwrap().to_string(),
+            "WVWZZZ1JZ3W386752"
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! Integrity files written next to dumps, so a dump damaged on its way to
+//! the disk is caught before it is used.
+//!
+//! The sidecar holds the CRC-32 of every chunk of the dump and the SHA-256
+//! of the whole file, with the chunk size and the address the dump starts
+//! at. Both are implemented here as the results are stored and have to stay
+//! the same across builds and tools.
+
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// Continues a CRC-32 (IEEE, as used by zip and `crc32` tools) `crc` over
+/// `data`. Start with 0.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Streaming SHA-256.
+#[derive(Debug, Clone)]
+struct Sha256 {
+    state: [u32; 8],
+    block: Vec<u8>,
+    length: u64,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (out, s) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&s.to_be_bytes());
+        }
+        digest
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Integrity information of a dump, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    /// Address the first byte of the dump was read from
+    pub base_address: u32,
+    /// Bytes covered by each CRC, the last chunk may be shorter.
+    pub chunk_size: u32,
+    /// Length of the dump
+    pub size: u64,
+    pub chunk_crcs: Vec<u32>,
+    pub sha256: [u8; 32],
+}
+
+impl Sidecar {
+    /// Path of the sidecar belonging to the dump `dump`.
+    pub fn path(dump: &Path) -> PathBuf {
+        let mut path = dump.as_os_str().to_owned();
+        path.push(".sidecar");
+        PathBuf::from(path)
+    }
+
+    /// Computes the sidecar of `data`.
+    pub fn of(base_address: u32, chunk_size: u32, data: &[u8]) -> Self {
+        let mut writer = SidecarWriter::new(base_address, chunk_size);
+        writer.update(data);
+        writer.finish()
+    }
+
+    pub fn to_text(&self) -> String {
+        let crcs: Vec<String> = self.chunk_crcs.iter().map(|c| format!("{c:08x}")).collect();
+        format!(
+            "base_address=0x{:08x}\nchunk_size=0x{:08x}\nsize=0x{:x}\nsha256={}\ncrc32={}\n",
+            self.base_address,
+            self.chunk_size,
+            self.size,
+            hex(&self.sha256),
+            crcs.join(",")
+        )
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let malformed = |reason: String| Error::DumpCorrupted(format!("sidecar {reason}"));
+        let field = |name: &'static str| {
+            text.lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix('='))
+                .map(str::trim)
+                .ok_or_else(|| malformed(format!("has no {name}")))
+        };
+        let number = |name: &'static str| {
+            u64::from_str_radix(field(name)?.trim_start_matches("0x"), 16)
+                .map_err(|_| malformed(format!("has an invalid {name}")))
+        };
+        let address = |name: &'static str| {
+            number(name)?
+                .try_into()
+                .map_err(|_| malformed(format!("{name} is too large")))
+        };
+        let sha = field("sha256")?;
+        let mut sha256 = [0; 32];
+        if sha.len() != 64 {
+            return Err(malformed("has an invalid sha256".to_string()));
+        }
+        for (i, byte) in sha256.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&sha[i * 2..i * 2 + 2], 16)
+                .map_err(|_| malformed("has an invalid sha256".to_string()))?;
+        }
+        let crcs = field("crc32")?;
+        let chunk_crcs = if crcs.is_empty() {
+            Vec::new()
+        } else {
+            crcs.split(',')
+                .map(|c| u32::from_str_radix(c, 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| malformed("has an invalid crc32".to_string()))?
+        };
+        let chunk_size = address("chunk_size")?;
+        if chunk_size == 0 {
+            return Err(malformed("has a zero chunk_size".to_string()));
+        }
+        Ok(Self {
+            base_address: address("base_address")?,
+            chunk_size,
+            size: number("size")?,
+            chunk_crcs,
+            sha256,
+        })
+    }
+
+    pub fn save(&self, dump: &Path) -> Result<(), Error> {
+        let path = Self::path(dump);
+        let tmp = path.with_extension("sidecar.tmp");
+        std::fs::write(&tmp, self.to_text())?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Checks `data` against the sidecar, `Error::DumpCorrupted` naming the
+    /// first bad chunk if it doesn't match.
+    pub fn verify(&self, data: &[u8]) -> Result<(), Error> {
+        if data.len() as u64 != self.size {
+            return Err(Error::DumpCorrupted(format!(
+                "dump is {} bytes, the sidecar expects {}",
+                data.len(),
+                self.size
+            )));
+        }
+        let chunks = data.chunks(self.chunk_size as usize);
+        if chunks.len() != self.chunk_crcs.len() {
+            return Err(Error::DumpCorrupted(format!(
+                "sidecar has {} chunk CRCs, the dump has {} chunks",
+                self.chunk_crcs.len(),
+                chunks.len()
+            )));
+        }
+        for (i, (chunk, expected)) in chunks.zip(&self.chunk_crcs).enumerate() {
+            if crc32(0, chunk) != *expected {
+                let offset = i as u64 * self.chunk_size as u64;
+                return Err(Error::DumpCorrupted(format!(
+                    "chunk at 0x{:06x} does not match its CRC",
+                    self.base_address as u64 + offset
+                )));
+            }
+        }
+        let mut sha = Sha256::new();
+        sha.update(data);
+        if sha.finish() != self.sha256 {
+            return Err(Error::DumpCorrupted(
+                "SHA-256 does not match the sidecar".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `Sidecar` from a dump as it is written.
+#[derive(Debug, Clone)]
+pub struct SidecarWriter {
+    base_address: u32,
+    chunk_size: u32,
+    chunk_crcs: Vec<u32>,
+    /// CRC of the current chunk and the bytes in it
+    chunk: (u32, u32),
+    sha: Sha256,
+}
+
+impl SidecarWriter {
+    pub fn new(base_address: u32, chunk_size: u32) -> Self {
+        Self {
+            base_address,
+            chunk_size: chunk_size.max(1),
+            chunk_crcs: Vec::new(),
+            chunk: (0, 0),
+            sha: Sha256::new(),
+        }
+    }
+
+    /// Adds `data`, the next bytes of the dump.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.sha.update(data);
+        while !data.is_empty() {
+            let (crc, len) = self.chunk;
+            let take = ((self.chunk_size - len) as usize).min(data.len());
+            self.chunk = (crc32(crc, &data[..take]), len + take as u32);
+            data = &data[take..];
+            if self.chunk.1 == self.chunk_size {
+                self.chunk_crcs.push(self.chunk.0);
+                self.chunk = (0, 0);
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Sidecar {
+        if self.chunk.1 > 0 {
+            self.chunk_crcs.push(self.chunk.0);
+        }
+        Sidecar {
+            base_address: self.base_address,
+            chunk_size: self.chunk_size,
+            size: self.sha.length,
+            chunk_crcs: self.chunk_crcs,
+            sha256: self.sha.finish(),
+        }
+    }
+}
+
+/// Checks the dump at `path` against the sidecar next to it.
+pub fn verify_sidecar(path: &Path) -> Result<Sidecar, Error> {
+    let sidecar = Sidecar::parse(&std::fs::read_to_string(Sidecar::path(path))?)?;
+    sidecar.verify(&std::fs::read(path)?)?;
+    Ok(sidecar)
+}
+
+/// Reads the dump at `path`, checking it against its sidecar if it has
+/// one. A dump that fails the check is only returned with `force`.
+pub fn read_dump(path: &Path, force: bool) -> Result<Vec<u8>, Error> {
+    let data = std::fs::read(path)?;
+    let sidecar = match std::fs::read_to_string(Sidecar::path(path)) {
+        Ok(text) => Sidecar::parse(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(data),
+        Err(e) => return Err(e.into()),
+    };
+    match sidecar.and_then(|s| s.verify(&data)) {
+        Err(e) if !force => Err(e),
+        _ => Ok(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut sha = Sha256::new();
+        sha.update(data);
+        hex(&sha.finish())
+    }
+
+    /// Data that differs in every byte of a few chunks.
+    fn dump_data() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF43926);
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // longer than one block
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn writer_matches_the_whole_dump() {
+        let data = dump_data();
+        let mut writer = SidecarWriter::new(0x800000, 0x1000);
+        for piece in data.chunks(333) {
+            writer.update(piece);
+        }
+        let sidecar = writer.finish();
+        assert_eq!(sidecar, Sidecar::of(0x800000, 0x1000, &data));
+        assert_eq!(sidecar.chunk_crcs.len(), 3);
+        assert_eq!(sidecar.size, data.len() as u64);
+        assert_eq!(Sidecar::parse(&sidecar.to_text()).unwrap(), sidecar);
+        sidecar.verify(&data).unwrap();
+    }
+
+    #[test]
+    fn damaged_chunk_is_named() {
+        let mut data = dump_data();
+        let sidecar = Sidecar::of(0x800000, 0x1000, &data);
+        data[0x1234] ^= 0x01;
+        assert!(matches!(
+            sidecar.verify(&data),
+            Err(Error::DumpCorrupted(reason)) if reason.contains("0x801000")
+        ));
+        data.pop();
+        assert!(matches!(
+            sidecar.verify(&data),
+            Err(Error::DumpCorrupted(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_sidecar_is_rejected() {
+        let text = Sidecar::of(0, 0x1000, &dump_data()).to_text();
+        for broken in [
+            text.replace("chunk_size=0x00001000", "chunk_size=0x0"),
+            text.replace("sha256=", "sha256=0"),
+            text.replace("crc32=", "crc32=xyz,"),
+            text.replace("base_address=0x00000000", "base_address=0x100000000"),
+            text.lines().skip(1).collect::<Vec<_>>().join("\n"),
+        ] {
+            assert!(
+                matches!(Sidecar::parse(&broken), Err(Error::DumpCorrupted(_))),
+                "{broken}"
+            );
+        }
+    }
+
+    #[test]
+    fn damaged_dump_is_only_read_with_force() {
+        let dir = std::env::temp_dir().join(format!("ecu_flasher_image_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.bin");
+        let mut data = dump_data();
+        std::fs::write(&path, &data).unwrap();
+        // no sidecar yet
+        assert_eq!(read_dump(&path, false).unwrap(), data);
+        Sidecar::of(0, 0x1000, &data).save(&path).unwrap();
+        verify_sidecar(&path).unwrap();
+        data[0] ^= 0xFF;
+        std::fs::write(&path, &data).unwrap();
+        assert!(verify_sidecar(&path).is_err());
+        assert!(read_dump(&path, false).is_err());
+        assert_eq!(read_dump(&path, true).unwrap(), data);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! Taking over ECU outputs for actuator tests, such as driving the fuel
+//! pump relay or a valve.
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{CommonIdentifier, IoControlParameter, LocalIdentifier, ServiceId},
+    message::Message,
+    policy::DangerousAction,
+    response::Response,
+};
+
+/// Output an `InputOutputControl` request addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOutput {
+    Local(LocalIdentifier),
+    Common(CommonIdentifier),
+}
+
+impl Client {
+    /// Applies `control` with `state` to the output at local `identifier`,
+    /// returns the state the ECU reports for it. Outputs left under the
+    /// tester's control are returned to the ECU by `teardown`.
+    pub fn io_control_local(
+        &mut self,
+        identifier: LocalIdentifier,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.io_control(IoOutput::Local(identifier), control, state)
+    }
+    /// Applies `control` with `state` to the output at common
+    /// `identifier`, see `io_control_local`.
+    pub fn io_control_common(
+        &mut self,
+        identifier: CommonIdentifier,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.io_control(IoOutput::Common(identifier), control, state)
+    }
+    /// Gives the output at local `identifier` back to the ECU.
+    pub fn release_io_control(&mut self, identifier: LocalIdentifier) -> Result<(), Error> {
+        self.release_output(IoOutput::Local(identifier))
+    }
+    /// Gives `output` back to the ECU. Allowed whatever the danger policy,
+    /// it undoes an `io_control_*`.
+    pub fn release_output(&mut self, output: IoOutput) -> Result<(), Error> {
+        self.send_io_control(output, IoControlParameter::ReturnControlToEcu, Vec::new())
+            .map(|_| ())
+    }
+    fn io_control(
+        &mut self,
+        output: IoOutput,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.confirm(DangerousAction::IoControl { output, control })?;
+        self.send_io_control(output, control, state)
+    }
+    fn send_io_control(
+        &mut self,
+        output: IoOutput,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        match output {
+            IoOutput::Local(identifier) => {
+                self.send(Message::IoControlByLocalIdentifier {
+                    identifier,
+                    control,
+                    state,
+                })?;
+                match self.next_response_expect_wait(Some(
+                    ServiceId::InputOutputControlByLocalIdentifier,
+                ))? {
+                    Response::IoControlled(i, state) if i == identifier => Ok(state),
+                    r => Err(Error::UnexpectedResponse(r)),
+                }
+            }
+            IoOutput::Common(identifier) => {
+                self.send(Message::IoControlByCommonIdentifier {
+                    identifier,
+                    control,
+                    state,
+                })?;
+                match self.next_response_expect_wait(Some(
+                    ServiceId::InputOutputControlByCommonIdentifier,
+                ))? {
+                    Response::IoControlledCommon(i, state) if i == identifier => Ok(state),
+                    r => Err(Error::UnexpectedResponse(r)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{constants::Service, mock, response};
+
+    #[test]
+    fn local_io_control_is_encoded() {
+        let raw = Message::IoControlByLocalIdentifier {
+            identifier: LocalIdentifier(0x12),
+            control: IoControlParameter::ShortTermAdjustment,
+            state: vec![0x01, 0x02],
+        }
+        .raw()
+        .unwrap();
+        assert_eq!(
+            raw.service,
+            Service::Query(ServiceId::InputOutputControlByLocalIdentifier)
+        );
+        assert_eq!(
+            raw.data,
+            [
+                0x12,
+                IoControlParameter::ShortTermAdjustment as u8,
+                0x01,
+                0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn local_io_control_response_is_parsed() {
+        match response::from_raw(mock::frame(&[0x70, 0x12, 0x07, 0xAA])).unwrap() {
+            Response::IoControlled(identifier, state) => {
+                assert_eq!(identifier, LocalIdentifier(0x12));
+                assert_eq!(state, [0xAA]);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn local_output_is_controlled_and_released() {
+        let mut client = mock::client(|request| match request {
+            [0x30, identifier, control, ..] => {
+                vec![Ok(vec![0x70, *identifier, *control, 0xAA])]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        let output = LocalIdentifier(0x12);
+        let state = client
+            .io_control_local(output, IoControlParameter::ShortTermAdjustment, vec![0x01])
+            .unwrap();
+        assert_eq!(state, [0xAA]);
+        assert_eq!(
+            client.ecu_changes().io_controlled,
+            [IoOutput::Local(output)]
+        );
+        client.release_io_control(output).unwrap();
+        assert!(client.ecu_changes().io_controlled.is_empty());
+    }
+
+    #[test]
+    fn common_io_control_is_encoded() {
+        let raw = Message::IoControlByCommonIdentifier {
+            identifier: CommonIdentifier(0x1234),
+            control: IoControlParameter::FreezeCurrentState,
+            state: vec![],
+        }
+        .raw()
+        .unwrap();
+        assert_eq!(
+            raw.service,
+            Service::Query(ServiceId::InputOutputControlByCommonIdentifier)
+        );
+        assert_eq!(
+            raw.data,
+            [0x12, 0x34, IoControlParameter::FreezeCurrentState as u8]
+        );
+    }
+
+    #[test]
+    fn common_io_control_response_is_parsed() {
+        match response::from_raw(mock::frame(&[0x6F, 0x12, 0x34, 0x05, 0xAA])).unwrap() {
+            Response::IoControlledCommon(identifier, state) => {
+                assert_eq!(identifier, CommonIdentifier(0x1234));
+                assert_eq!(state, [0xAA]);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn common_output_is_controlled_and_released() {
+        let mut client = mock::client(|request| match request {
+            [0x2F, high, low, control, ..] => {
+                vec![Ok(vec![0x6F, *high, *low, *control])]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        let output = IoOutput::Common(CommonIdentifier(0x1234));
+        let state = client
+            .io_control_common(
+                CommonIdentifier(0x1234),
+                IoControlParameter::FreezeCurrentState,
+                vec![],
+            )
+            .unwrap();
+        assert!(state.is_empty());
+        assert_eq!(client.ecu_changes().io_controlled, [output]);
+        client.release_output(output).unwrap();
+        assert!(client.ecu_changes().io_controlled.is_empty());
+    }
+}
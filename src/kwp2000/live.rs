@@ -0,0 +1,230 @@
+//! Writing variables in RAM that the ECU reads at runtime, like a map
+//! selector.
+
+use std::fmt::Debug;
+
+use crate::{Error, analysis::definitions::Definition};
+
+use super::client::Client;
+
+/// Variable in the ECU's RAM and the values it may be set to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveVariable {
+    pub name: String,
+    pub address: u32,
+    /// Size in bytes, 1 to 4. Values are stored little endian like the
+    /// C167 does.
+    pub size: u8,
+    /// Values `Client::write_live` accepts.
+    pub allowed: Vec<u32>,
+}
+
+impl LiveVariable {
+    /// Variable at the region of `definition`, which has to be 1 to 4
+    /// bytes long.
+    pub fn from_definition(definition: &Definition, allowed: Vec<u32>) -> Result<Self, Error> {
+        if !(1..=4).contains(&definition.size) {
+            return Err(Error::LiveValueRejected {
+                name: definition.name.clone(),
+                reason: format!("size {} is not 1 to 4 bytes", definition.size),
+            });
+        }
+        Ok(Self {
+            name: definition.name.clone(),
+            address: definition.address,
+            size: definition.size as u8,
+            allowed,
+        })
+    }
+
+    /// Bytes to write for `value`, fails if it isn't allowed or doesn't fit.
+    fn encode(&self, value: u32) -> Result<Vec<u8>, Error> {
+        let reject = |reason: String| Error::LiveValueRejected {
+            name: self.name.clone(),
+            reason,
+        };
+        if !self.allowed.contains(&value) {
+            return Err(reject(format!("{value} is not an allowed value")));
+        }
+        let size = self.size as usize;
+        if !(1..=4).contains(&size) || (size < 4 && value >> (size * 8) != 0) {
+            return Err(reject(format!("{value} does not fit {size} bytes")));
+        }
+        Ok(value.to_le_bytes()[..size].to_vec())
+    }
+}
+
+/// A `LiveVariable` that was written and read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveChange {
+    pub name: String,
+    pub address: u32,
+    pub value: u32,
+}
+
+pub type LiveChangeCallback = Box<dyn FnMut(&LiveChange) + Send>;
+
+/// Called after every successful `Client::write_live`.
+#[derive(Default)]
+pub struct LiveChangeHandler(pub Option<LiveChangeCallback>);
+
+impl Debug for LiveChangeHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "LiveChangeHandler"),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+impl Client {
+    /// Sets `variable` to `value` with `dd_write_verified`. Fails without
+    /// sending anything if the value isn't in the variable's allowed set or
+    /// a block transfer holds the bus. Reports the change to `on_live_change`.
+    pub fn write_live(&mut self, variable: &LiveVariable, value: u32) -> Result<(), Error> {
+        let data = variable.encode(value)?;
+        if self.transfer_in_progress() {
+            return Err(Error::BusBusyWithTransfer);
+        }
+        self.dd_write_verified(variable.address, data)?;
+        let change = LiveChange {
+            name: variable.name.clone(),
+            address: variable.address,
+            value,
+        };
+        if let Some(handler) = &mut self.on_live_change.0 {
+            handler(&change);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::kwp2000::mock;
+
+    fn selector() -> LiveVariable {
+        LiveVariable {
+            name: "map_selector".to_string(),
+            address: 0x380100,
+            size: 1,
+            allowed: vec![0, 1],
+        }
+    }
+
+    /// Client for an ECU whose RAM is written and read through the dynamic
+    /// identifier, with the requests sent to it. A `stuck` ECU keeps the
+    /// old RAM contents.
+    fn ram_ecu(stuck: bool) -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sent = requests.clone();
+        let mut ram = HashMap::new();
+        let mut defined = (0, 0);
+        let client = mock::client(move |request| {
+            sent.lock().unwrap().push(request.to_vec());
+            vec![Ok(match request {
+                [0x2C, 0xF0, 0x04] => vec![0x6C, 0xF0],
+                [0x2C, 0xF0, 0x03, 0x01, size, a, b, c] => {
+                    defined = (u32::from_be_bytes([0, *a, *b, *c]), *size as u32);
+                    vec![0x6C, 0xF0]
+                }
+                [0x3B, 0xF0, data @ ..] => {
+                    if !stuck {
+                        for (address, byte) in (defined.0..).zip(data) {
+                            ram.insert(address, *byte);
+                        }
+                    }
+                    vec![0x7B, 0xF0]
+                }
+                [0x21, 0xF0, ..] => {
+                    let mut response = vec![0x61, 0xF0];
+                    let (address, size) = defined;
+                    response
+                        .extend((address..address + size).map(|a| *ram.get(&a).unwrap_or(&0xFF)));
+                    response
+                }
+                _ => panic!("unexpected request {request:02X?}"),
+            })]
+        });
+        (client, requests)
+    }
+
+    #[test]
+    fn allowed_value_is_written_and_reported() {
+        let (mut client, requests) = ram_ecu(false);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        client.on_live_change = LiveChangeHandler(Some(Box::new(move |change| {
+            seen.lock().unwrap().push(change.clone())
+        })));
+        client.write_live(&selector(), 1).unwrap();
+        assert!(requests.lock().unwrap().contains(&vec![0x3B, 0xF0, 0x01]));
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [LiveChange {
+                name: "map_selector".to_string(),
+                address: 0x380100,
+                value: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn rejected_values_send_nothing() {
+        let (mut client, requests) = ram_ecu(false);
+        assert!(matches!(
+            client.write_live(&selector(), 2),
+            Err(Error::LiveValueRejected { .. })
+        ));
+        // allowed, but too large for one byte
+        let overflowing = LiveVariable {
+            allowed: vec![0x100],
+            ..selector()
+        };
+        assert!(matches!(
+            client.write_live(&overflowing, 0x100),
+            Err(Error::LiveValueRejected { .. })
+        ));
+        let definition = Definition {
+            name: "map".to_string(),
+            address: 0x380200,
+            size: 5,
+            axes: None,
+        };
+        assert!(LiveVariable::from_definition(&definition, vec![0]).is_err());
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_waits_for_the_transfer() {
+        let (mut client, requests) = ram_ecu(false);
+        let token = client.bus_token.acquire().unwrap();
+        assert!(matches!(
+            client.write_live(&selector(), 1),
+            Err(Error::BusBusyWithTransfer)
+        ));
+        assert!(requests.lock().unwrap().is_empty());
+        drop(token);
+        client.write_live(&selector(), 1).unwrap();
+    }
+
+    #[test]
+    fn unchanged_read_back_is_a_mismatch() {
+        let (mut client, _) = ram_ecu(true);
+        client.on_live_change =
+            LiveChangeHandler(Some(Box::new(|change| panic!("reported {change:?}"))));
+        assert!(matches!(
+            client.write_live(&selector(), 1),
+            Err(Error::VerifyMismatch {
+                address: 0x380100,
+                ..
+            })
+        ));
+    }
+}
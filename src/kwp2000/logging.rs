@@ -0,0 +1,360 @@
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{LocalIdentifier, ReadMode},
+    message::Message,
+    raw_message::FrameInfo,
+    recovery::is_timeout,
+    response::Response,
+    timing::transmit_time,
+};
+
+/// How long before P3max runs out the keep-alive frame is sent, leaves room
+/// for scheduling delays and the transmission itself.
+const KEEP_ALIVE_MARGIN: Duration = Duration::from_millis(500);
+
+/// Timing of a periodic log.
+#[derive(Debug, Clone, Copy)]
+pub struct LogSchedule {
+    /// Time between samples
+    pub period: Duration,
+    /// A sample that is due less than this after a keep-alive frame would be
+    /// needed is taken early instead of sending a `TesterPresent`.
+    pub early_window: Duration,
+    /// Initialize the ECU again and carry on logging if it drops the session
+    /// by resetting itself, see `Client::session_lost`.
+    pub resume_after_reset: bool,
+}
+
+/// When a log sample was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleTime {
+    /// When the first byte of the response was read
+    pub received: Instant,
+    /// Estimated time the ECU sent the response: when it was read
+    /// completely less the time transmitting it takes at the link's baud
+    /// rate, at most `received`. Never earlier than the estimate of the
+    /// sample before it.
+    pub estimated: Instant,
+    /// `estimated` was moved forward to the previous sample's because
+    /// frames arrived bunched up, for example after a stall.
+    pub adjusted: bool,
+}
+
+/// Stamps the samples of a log, keeping the estimates from going back.
+#[derive(Debug, Default)]
+struct SampleClock {
+    last: Option<Instant>,
+}
+
+impl SampleClock {
+    /// Time of the sample received in `frame`, the time it is called if
+    /// the frame wasn't timed.
+    fn stamp(&mut self, frame: Option<FrameInfo>, baud_rate: u32) -> SampleTime {
+        let (received, estimated) = match frame {
+            Some(frame) => {
+                let sent = frame
+                    .completed_at
+                    .checked_sub(transmit_time(frame.total_len, baud_rate))
+                    .unwrap_or(frame.completed_at);
+                (frame.first_byte_at, Ord::min(sent, frame.first_byte_at))
+            }
+            None => (Instant::now(), Instant::now()),
+        };
+        let adjusted = self.last.is_some_and(|last| estimated < last);
+        let estimated = match self.last {
+            Some(last) if adjusted => last,
+            _ => estimated,
+        };
+        self.last = Some(estimated);
+        SampleTime {
+            received,
+            estimated,
+            adjusted,
+        }
+    }
+}
+
+/// Entry of a periodic log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogEntry {
+    /// Data read at `at`.
+    Sample { at: SampleTime, data: Vec<u8> },
+    /// The ECU dropped the session after the sample at `from`, logging
+    /// resumed at `to`.
+    Gap { from: Instant, to: Instant },
+}
+
+/// Next frame a log has to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NextFrame {
+    Sample(Instant),
+    KeepAlive(Instant),
+}
+
+/// Decides what to send next so that a frame goes out within
+/// `keep_alive_interval` of `last_frame`, preferring to pull a sample
+/// forward over a keep-alive.
+fn next_frame(
+    last_frame: Instant,
+    next_sample: Instant,
+    keep_alive_interval: Duration,
+    early_window: Duration,
+) -> NextFrame {
+    let deadline = last_frame + keep_alive_interval;
+    if next_sample <= deadline {
+        NextFrame::Sample(next_sample)
+    } else if next_sample - deadline <= early_window {
+        NextFrame::Sample(deadline)
+    } else {
+        NextFrame::KeepAlive(deadline)
+    }
+}
+
+fn sleep_until(at: Instant) {
+    if let Some(d) = at.checked_duration_since(Instant::now()) {
+        std::thread::sleep(d);
+    }
+}
+
+impl Client {
+    /// Reads `identifier` every `schedule.period` and passes each sample to
+    /// `on_entry` until it returns false. Gaps longer than the session's
+    /// P3max are bridged by taking a sample early or sending a
+    /// `TesterPresent`. If the ECU resets itself and
+    /// `schedule.resume_after_reset` is set, a `LogEntry::Gap` is passed
+    /// once the session is recovered and logging continues. A single
+    /// timeout is then taken as a slow answer and logging goes on.
+    pub fn log_local_identifier<F: FnMut(LogEntry) -> bool>(
+        &mut self,
+        identifier: LocalIdentifier,
+        schedule: LogSchedule,
+        mut on_entry: F,
+    ) -> Result<(), Error> {
+        let mut samples = 0;
+        let mut next_sample = Instant::now();
+        let mut last_sample = Instant::now();
+        let mut clock = SampleClock::default();
+        loop {
+            self.check_deadline(samples)?;
+            let last_frame = self.last_frame().unwrap_or_else(Instant::now);
+            let result = match next_frame(
+                last_frame,
+                next_sample,
+                self.timing.keep_alive_interval(KEEP_ALIVE_MARGIN),
+                schedule.early_window,
+            ) {
+                NextFrame::Sample(at) => {
+                    sleep_until(at);
+                    self.read_log_sample(identifier).map(Some)
+                }
+                NextFrame::KeepAlive(at) => {
+                    sleep_until(at);
+                    self.stats.keep_alives += 1;
+                    self.tester_present().map(|()| None)
+                }
+            };
+            match result {
+                Ok(Some(data)) => {
+                    samples += 1;
+                    last_sample = Instant::now();
+                    let frame = self.last_received_frame().and_then(|f| f.frame);
+                    let entry = LogEntry::Sample {
+                        at: clock.stamp(frame, self.baud_rate),
+                        data,
+                    };
+                    if !on_entry(entry) {
+                        return Ok(());
+                    }
+                    next_sample += self.timing.request_period(schedule.period);
+                }
+                Ok(None) => {}
+                // a single timeout may be a slow answer, the next frame
+                // tells whether the ECU dropped the session
+                Err(e) if schedule.resume_after_reset && is_timeout(&e) && !self.session_lost() => {
+                }
+                Err(_) if schedule.resume_after_reset && self.session_lost() => {
+                    self.recover_session()?;
+                    next_sample = Instant::now();
+                    let entry = LogEntry::Gap {
+                        from: last_sample,
+                        to: next_sample,
+                    };
+                    if !on_entry(entry) {
+                        return Ok(());
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    fn read_log_sample(&mut self, identifier: LocalIdentifier) -> Result<Vec<u8>, Error> {
+        self.send(Message::ReadLocalIdentifier(
+            identifier,
+            ReadMode::Single,
+            1,
+        ))?;
+        match self.next_response()? {
+            Response::LocalIdentifierRead(id, data) if id == identifier => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{
+        mock::{self, Fault},
+        warning::Warning,
+    };
+
+    const BAUD_RATE: u32 = 10400;
+
+    /// A 10 byte frame whose first byte was read at `first` and the rest
+    /// `read_in` later.
+    fn frame(first: Instant, read_in: Duration) -> Option<FrameInfo> {
+        Some(FrameInfo {
+            checksum: 0,
+            had_length_byte: false,
+            header_len: 3,
+            total_len: 10,
+            first_byte_at: first,
+            completed_at: first + read_in,
+        })
+    }
+
+    #[test]
+    fn stamps_do_not_go_back_after_a_stall() {
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+        let on_time = transmit_time(10, BAUD_RATE);
+        let mut clock = SampleClock::default();
+
+        let steady: Vec<_> = (0..3)
+            .map(|n| clock.stamp(frame(t0 + ms(100 * n), on_time), BAUD_RATE))
+            .collect();
+        for (n, stamp) in steady.iter().enumerate() {
+            assert_eq!(stamp.estimated, t0 + ms(100 * n as u64));
+            assert!(!stamp.adjusted);
+        }
+
+        // Nothing for 500 ms, then the buffered frames are read one
+        // right after the other.
+        let stalled = clock.stamp(frame(t0 + ms(700), on_time), BAUD_RATE);
+        assert_eq!(stalled.estimated, t0 + ms(700));
+        assert!(!stalled.adjusted);
+        let burst: Vec<_> = (1..4)
+            .map(|n| clock.stamp(frame(t0 + ms(700 + n), ms(1)), BAUD_RATE))
+            .collect();
+        for stamp in &burst {
+            assert!(stamp.adjusted);
+            assert_eq!(stamp.estimated, t0 + ms(700));
+            assert!(stamp.estimated <= stamp.received);
+        }
+
+        let after = clock.stamp(frame(t0 + ms(800), on_time), BAUD_RATE);
+        assert!(!after.adjusted);
+
+        let all: Vec<_> = steady
+            .iter()
+            .chain([&stalled])
+            .chain(&burst)
+            .chain([&after])
+            .collect();
+        assert!(all.windows(2).all(|w| w[0].estimated <= w[1].estimated));
+    }
+
+    #[test]
+    fn untimed_frames_are_stamped_now() {
+        let before = Instant::now();
+        let stamp = SampleClock::default().stamp(None, BAUD_RATE);
+        assert!(stamp.received >= before && stamp.estimated >= before);
+        assert!(!stamp.adjusted);
+    }
+
+    /// Logs local identifier 0x10 of an ECU counting its reads until
+    /// `entries` were taken, injecting `faults` into the reads after the
+    /// second sample.
+    fn log_with_faults(
+        resume_after_reset: bool,
+        faults: Vec<Fault>,
+        entries: usize,
+    ) -> (Client, Result<Vec<LogEntry>, Error>) {
+        let mut reads = 0u8;
+        let (mut client, injected) = mock::faulty_client(move |request| match request {
+            [0x21, 0x10, ..] => {
+                reads += 1;
+                vec![Ok(vec![0x61, 0x10, reads])]
+            }
+            [0x3E, 0x01] => vec![Ok(vec![0x7E])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        let schedule = LogSchedule {
+            period: Duration::from_millis(5),
+            early_window: Duration::ZERO,
+            resume_after_reset,
+        };
+        let mut log = Vec::new();
+        let result = client.log_local_identifier(LocalIdentifier(0x10), schedule, |entry| {
+            log.push(entry);
+            if log.len() == 2 {
+                for fault in &faults {
+                    injected.inject(0x21, fault.clone());
+                }
+            }
+            log.len() < entries
+        });
+        (client, result.map(|()| log))
+    }
+
+    fn data(entries: &[LogEntry]) -> Vec<Option<u8>> {
+        entries
+            .iter()
+            .map(|e| match e {
+                LogEntry::Sample { data, .. } => Some(data[0]),
+                LogEntry::Gap { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn log_resumes_after_the_ecu_reboots() {
+        let (client, log) =
+            log_with_faults(true, vec![Fault::DropResponse, Fault::DropResponse], 5);
+        // the ECU read the dropped samples too
+        assert_eq!(
+            data(&log.unwrap()),
+            [Some(1), Some(2), None, Some(5), Some(6)]
+        );
+        assert_eq!(client.stats.session_recoveries, 1);
+        assert!(
+            client
+                .warnings()
+                .iter()
+                .any(|w| matches!(w, Warning::SessionInterrupted { .. }))
+        );
+    }
+
+    #[test]
+    fn timeout_then_garbage_is_a_reboot() {
+        let (_, log) = log_with_faults(true, vec![Fault::DropResponse, Fault::CorruptChecksum], 4);
+        assert_eq!(data(&log.unwrap()), [Some(1), Some(2), None, Some(5)]);
+    }
+
+    #[test]
+    fn slow_answer_is_not_a_reboot() {
+        let (client, log) = log_with_faults(true, vec![Fault::DropResponse], 4);
+        assert_eq!(data(&log.unwrap()), [Some(1), Some(2), Some(4), Some(5)]);
+        assert_eq!(client.stats.session_recoveries, 0);
+    }
+
+    #[test]
+    fn timeout_ends_the_log_without_resuming() {
+        let (_, log) = log_with_faults(false, vec![Fault::DropResponse], 4);
+        assert!(is_timeout(&log.unwrap_err()));
+    }
+}
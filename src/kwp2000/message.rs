@@ -1,5 +1,7 @@
+use super::dtc::{FREEZE_FRAME_ALL_DATA, FREEZE_FRAME_BY_LOCAL_IDENTIFIER};
 use super::raw_message::RawMessage;
 use super::{baud_rate_to_byte, constants::*};
+use crate::Error;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransferType {
@@ -12,15 +14,46 @@ pub enum Message {
     /// optional baudrate
     StartDiagnosticSession(DiagnosticMode, Option<u32>),
     StopCommunication,
+    ECUReset(ResetMode),
+    /// DTC group, `dtc::ALL_GROUPS` for every stored code
+    ReadDiagnosticTroubleCodes {
+        group: u16,
+    },
+    /// code
+    ReadStatusOfDtc(u16),
+    /// DTC group, `dtc::ALL_GROUPS` to clear every stored code
+    ClearDiagnosticInformation {
+        group: u16,
+    },
+    /// Codes of `group` with any of the status bits in `status_mask` set
+    ReadDtcByStatus {
+        status_mask: u8,
+        group: u16,
+    },
+    /// Record of freeze frame `frame_number` selected by `mode` (one of
+    /// `dtc::FREEZE_FRAME_*`) and `identifier`, which is not sent for
+    /// `FREEZE_FRAME_ALL_DATA` and only its low byte for
+    /// `FREEZE_FRAME_BY_LOCAL_IDENTIFIER`.
+    ReadFreezeFrame {
+        frame_number: u8,
+        mode: u8,
+        identifier: u16,
+    },
     RequestSecuritySeed,
-    ClearLocalIdentifier(u8),
+    ClearLocalIdentifier(LocalIdentifier),
     /// identifier, mode, maximum response count
-    ReadLocalIdentifier(u8, ReadMode, u8),
-    WriteLocalIdentifier(u8, Vec<u8>),
+    ReadLocalIdentifier(LocalIdentifier, ReadMode, u8),
+    WriteLocalIdentifier(LocalIdentifier, Vec<u8>),
+    ReadCommonIdentifier(CommonIdentifier),
+    /// Several identifiers in one request, answered by one response
+    /// holding each identifier followed by its data
+    ReadCommonIdentifiers(Vec<CommonIdentifier>),
+    WriteCommonIdentifier(CommonIdentifier, Vec<u8>),
     /// identifier, length, address
-    DefineLocalIdentifierAddress(u8, u8, u32),
+    DefineLocalIdentifierAddress(LocalIdentifier, u8, u32),
     SendSecurityKey(u32),
-    /// whether or not server should respond
+    /// whether or not server should respond (sub function 0x01 or 0x02,
+    /// some ECUs have them inverted, see `Client::tester_present_inverted`)
     TesterPresent(bool),
     StopDiagnosticSession,
     ReadMemoryByAddress {
@@ -30,6 +63,11 @@ pub enum Message {
         /// requires that `mode` be set
         max_response_count: Option<u8>,
     },
+    /// `data` is at most 255 bytes, the length is sent in one byte
+    WriteMemoryByAddress {
+        address: u32,
+        data: Vec<u8>,
+    },
     RequestDataTransfer {
         transfer_type: TransferType,
         address: u32,
@@ -38,6 +76,7 @@ pub enum Message {
         compression: CompressionFormat,
     },
     RequestData,
+    RequestTransferExit,
     GetCurrentTiming,
     GetDefaultTiming,
     GetTimingLimits,
@@ -59,10 +98,51 @@ pub enum Message {
         p4min: u8,
     },
     SendData(Vec<u8>),
+    /// identification option
+    ReadECUIdentification(u8),
+    /// routine, parameters
+    StartRoutine(RoutineIdentifier, Vec<u8>),
+    /// routine, parameters
+    StopRoutine(RoutineIdentifier, Vec<u8>),
+    RequestRoutineResults(RoutineIdentifier),
+    /// Routine at `address`, for code downloaded to RAM
+    StartRoutineByAddress {
+        address: u32,
+        parameters: Vec<u8>,
+    },
+    StopRoutineByAddress {
+        address: u32,
+        parameters: Vec<u8>,
+    },
+    RequestRoutineResultsByAddress {
+        address: u32,
+    },
+    /// Output at local `identifier`, `state` is the control state, empty
+    /// for the report and return control parameters
+    IoControlByLocalIdentifier {
+        identifier: LocalIdentifier,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    },
+    /// Same as `IoControlByLocalIdentifier` for an output at common
+    /// `identifier`
+    IoControlByCommonIdentifier {
+        identifier: CommonIdentifier,
+        control: IoControlParameter,
+        state: Vec<u8>,
+    },
+    /// Rates of the periodic transmissions, see `DataRates`
+    SetDataRates {
+        slow: u8,
+        medium: u8,
+        fast: u8,
+    },
+    /// `SetDataRates` without rates, answered with the current ones
+    ReadDataRates,
 }
 
 impl Message {
-    pub fn raw(self) -> RawMessage {
+    pub fn raw(self) -> Result<RawMessage, Error> {
         let service;
         let mut data: Vec<u8> = vec![];
         match self {
@@ -98,6 +178,7 @@ impl Message {
             Message::RequestData => {
                 service = ServiceId::TransferData;
             }
+            Message::RequestTransferExit => service = ServiceId::RequestTransferExit,
             Message::RequestDataTransfer {
                 transfer_type,
                 address,
@@ -137,13 +218,28 @@ impl Message {
                 mode.map(|mode| data.push(mode as u8));
                 max_response_count.map(|m| data.push(m));
             }
+            Message::WriteMemoryByAddress {
+                address,
+                data: mut items,
+            } => {
+                service = ServiceId::WriteMemoryByAddress;
+                data.extend_from_slice(&address.to_be_bytes()[1..4]);
+                let length = u8::try_from(items.len()).map_err(|_| Error::MessageTooLong {
+                    length: items.len(),
+                    max: u8::MAX as usize,
+                })?;
+                data.push(length);
+                data.append(&mut items);
+            }
             Message::StopDiagnosticSession => {
                 service = ServiceId::StopDiagnosticSession;
             }
             Message::StartDiagnosticSession(diagnostic_mode, baud) => {
                 service = ServiceId::StartDiagnosticSession;
                 data.push(diagnostic_mode as u8);
-                baud.map(|b| data.push(baud_rate_to_byte(b)));
+                if let Some(b) = baud {
+                    data.push(baud_rate_to_byte(b)?);
+                }
             }
             Message::RequestSecuritySeed => {
                 service = ServiceId::SecurityAccess;
@@ -152,23 +248,38 @@ impl Message {
             }
             Message::ClearLocalIdentifier(id) => {
                 service = ServiceId::DynamicallyDefineLocalIdentifier;
-                data.push(id);
+                data.push(id.0);
                 data.push(DynamicDefinitionMode::ClearDynamicallyDefinedLocalIdentifier as u8);
             }
             Message::ReadLocalIdentifier(id, mode, count) => {
                 service = ServiceId::ReadDataByLocalIdentifier;
-                data.push(id);
+                data.push(id.0);
                 data.push(mode as u8);
                 data.push(count);
             }
             Message::WriteLocalIdentifier(id, mut items) => {
                 service = ServiceId::WriteDataByLocalIdentifier;
-                data.push(id);
+                data.push(id.0);
+                data.append(&mut items);
+            }
+            Message::ReadCommonIdentifier(id) => {
+                service = ServiceId::ReadDataByCommonIdentifier;
+                data.extend_from_slice(&id.0.to_be_bytes());
+            }
+            Message::ReadCommonIdentifiers(ids) => {
+                service = ServiceId::ReadDataByCommonIdentifier;
+                for id in ids {
+                    data.extend_from_slice(&id.0.to_be_bytes());
+                }
+            }
+            Message::WriteCommonIdentifier(id, mut items) => {
+                service = ServiceId::WriteDataByCommonIdentifier;
+                data.extend_from_slice(&id.0.to_be_bytes());
                 data.append(&mut items);
             }
             Message::DefineLocalIdentifierAddress(id, size, address) => {
                 service = ServiceId::DynamicallyDefineLocalIdentifier;
-                data.push(id);
+                data.push(id.0);
                 data.push(DynamicDefinitionMode::DefineByMemoryAddress as u8);
                 // TODO: allow different positions in definition
                 data.push(0x01);
@@ -189,7 +300,139 @@ impl Message {
                 data.push(if respond { 0x01 } else { 0x02 });
             }
             Message::StopCommunication => service = ServiceId::StopCommunication,
+            Message::ReadDiagnosticTroubleCodes { group } => {
+                service = ServiceId::ReadDiagnosticTroubleCodes;
+                data.extend_from_slice(&group.to_be_bytes());
+            }
+            Message::ClearDiagnosticInformation { group } => {
+                service = ServiceId::ClearDiagnosticInformation;
+                data.extend_from_slice(&group.to_be_bytes());
+            }
+            Message::ReadStatusOfDtc(code) => {
+                service = ServiceId::ReadStatusOfDTC;
+                data.extend_from_slice(&code.to_be_bytes());
+            }
+            Message::ReadDtcByStatus { status_mask, group } => {
+                service = ServiceId::ReadDTCByStatus;
+                data.push(status_mask);
+                data.extend_from_slice(&group.to_be_bytes());
+            }
+            Message::ReadFreezeFrame {
+                frame_number,
+                mode,
+                identifier,
+            } => {
+                service = ServiceId::ReadFreezeFrameData;
+                data.push(frame_number);
+                data.push(mode);
+                match mode {
+                    FREEZE_FRAME_ALL_DATA => {}
+                    FREEZE_FRAME_BY_LOCAL_IDENTIFIER => data.push(identifier as u8),
+                    _ => data.extend_from_slice(&identifier.to_be_bytes()),
+                }
+            }
+            Message::ECUReset(mode) => {
+                service = ServiceId::ECUReset;
+                data.push(mode as u8);
+            }
+            Message::ReadECUIdentification(option) => {
+                service = ServiceId::ReadECUIdentification;
+                data.push(option);
+            }
+            Message::StartRoutine(routine, mut parameters) => {
+                service = ServiceId::StartRoutineByLocalIdentifier;
+                data.push(routine.0);
+                data.append(&mut parameters);
+            }
+            Message::StopRoutine(routine, mut parameters) => {
+                service = ServiceId::StopRoutineByLocalIdentifier;
+                data.push(routine.0);
+                data.append(&mut parameters);
+            }
+            Message::RequestRoutineResults(routine) => {
+                service = ServiceId::RequestRoutineResultsByLocalIdentifier;
+                data.push(routine.0);
+            }
+            Message::StartRoutineByAddress {
+                address,
+                mut parameters,
+            } => {
+                service = ServiceId::StartRoutineByAddress;
+                data.extend_from_slice(&address.to_be_bytes()[1..4]);
+                data.append(&mut parameters);
+            }
+            Message::StopRoutineByAddress {
+                address,
+                mut parameters,
+            } => {
+                service = ServiceId::StopRoutineByAddress;
+                data.extend_from_slice(&address.to_be_bytes()[1..4]);
+                data.append(&mut parameters);
+            }
+            Message::SetDataRates { slow, medium, fast } => {
+                service = ServiceId::SetDataRates;
+                data.extend_from_slice(&[slow, medium, fast]);
+            }
+            Message::ReadDataRates => {
+                service = ServiceId::SetDataRates;
+            }
+            Message::IoControlByLocalIdentifier {
+                identifier,
+                control,
+                mut state,
+            } => {
+                service = ServiceId::InputOutputControlByLocalIdentifier;
+                data.push(identifier.0);
+                data.push(control as u8);
+                data.append(&mut state);
+            }
+            Message::IoControlByCommonIdentifier {
+                identifier,
+                control,
+                mut state,
+            } => {
+                service = ServiceId::InputOutputControlByCommonIdentifier;
+                data.extend_from_slice(&identifier.0.to_be_bytes());
+                data.push(control as u8);
+                data.append(&mut state);
+            }
+            Message::RequestRoutineResultsByAddress { address } => {
+                service = ServiceId::ResquestRoutineResultsByAddress;
+                data.extend_from_slice(&address.to_be_bytes()[1..4]);
+            }
         }
         RawMessage::new_query(service, data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_write_is_encoded() {
+        let raw = Message::WriteMemoryByAddress {
+            address: 0x380DA8,
+            data: vec![0xAA, 0xBB],
+        }
+        .raw()
+        .unwrap();
+        assert_eq!(raw.service, Service::Query(ServiceId::WriteMemoryByAddress));
+        assert_eq!(raw.data, [0x38, 0x0D, 0xA8, 0x02, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn memory_write_longer_than_its_length_byte_is_rejected() {
+        let message = Message::WriteMemoryByAddress {
+            address: 0x380000,
+            data: vec![0; 256],
+        };
+        assert!(matches!(
+            message.raw(),
+            Err(Error::MessageTooLong {
+                length: 256,
+                max: 255
+            })
+        ));
+    }
+}
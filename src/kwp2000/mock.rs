@@ -0,0 +1,235 @@
+//! Scripted `Interface` for the tests of code built on `Client`, with
+//! faults that can be injected into its answers.
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{Error, k_line::InitTiming};
+
+use super::{
+    Interface,
+    client::Client,
+    constants::{AddressMode, Service},
+    raw_message::RawMessage,
+};
+
+/// Answers a request, given as service id followed by the data, with the
+/// frames to receive next, each given as service byte followed by the data.
+type Responder = Box<dyn FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send>;
+
+/// How long reading waits before timing out after a dropped response,
+/// longer than the default P2max so each drop is one timeout like on a
+/// serial port.
+const READ_TIMEOUT: Duration = Duration::from_millis(60);
+
+/// Misbehaviour of the ECU when answering a request.
+#[derive(Debug, Clone)]
+pub(crate) enum Fault {
+    /// Answer with this negative response code without processing the
+    /// request
+    Negative(u8),
+    /// Process the request but don't answer, reads time out until the next
+    /// request
+    DropResponse,
+    /// The first frame of the answer fails its checksum
+    CorruptChecksum,
+    /// The first frame of the answer loses its last byte
+    Truncate,
+    /// This frame arrives before the answer
+    Unsolicited(Vec<u8>),
+}
+
+/// Faults to inject into the answers of a `MockInterface`, shared with the
+/// test driving it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Faults(Arc<Mutex<VecDeque<(u8, Fault)>>>);
+
+impl Faults {
+    /// Injects `fault` into the answer to the next request of `service`.
+    /// Faults for the same service apply in the order they were injected.
+    pub(crate) fn inject(&self, service: u8, fault: Fault) {
+        self.0.lock().unwrap().push_back((service, fault));
+    }
+    /// Number of faults not injected yet.
+    pub(crate) fn pending(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+    fn take(&self, service: u8) -> Option<Fault> {
+        let mut faults = self.0.lock().unwrap();
+        let i = faults.iter().position(|(s, _)| *s == service)?;
+        faults.remove(i).map(|(_, fault)| fault)
+    }
+}
+
+/// Interface answering every request with what its responder returns,
+/// changed by the injected faults. Reading with nothing left to receive
+/// fails with a broken pipe, or times out after a dropped response.
+/// Reading without blocking returns one frame at a time.
+pub(crate) struct MockInterface {
+    responder: Responder,
+    received: VecDeque<Result<Vec<u8>, Error>>,
+    faults: Faults,
+    /// The answer to the last request was dropped.
+    silent: bool,
+}
+
+impl Debug for MockInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockInterface")
+            .field("received", &self.received.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MockInterface {
+    pub(crate) fn new(
+        responder: impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send + 'static,
+    ) -> Self {
+        MockInterface {
+            responder: Box::new(responder),
+            received: VecDeque::new(),
+            faults: Faults::default(),
+            silent: false,
+        }
+    }
+    /// Handle for injecting faults into the answers.
+    pub(crate) fn faults(&self) -> Faults {
+        self.faults.clone()
+    }
+}
+
+impl Interface for MockInterface {
+    fn switch_baud(&mut self, _baud_rate: u32) -> Result<(), Error> {
+        Ok(())
+    }
+    fn send_raw(&mut self, message: RawMessage) -> Result<(), Error> {
+        let mut request = vec![message.service.into()];
+        request.extend_from_slice(&message.data);
+        self.silent = false;
+        let fault = self.faults.take(request[0]);
+        let mut answer = match fault {
+            Some(Fault::Negative(code)) => vec![Ok(vec![0x7F, request[0], code])],
+            _ => (self.responder)(&request),
+        };
+        match fault {
+            Some(Fault::DropResponse) => {
+                answer.clear();
+                self.silent = true;
+            }
+            Some(Fault::CorruptChecksum) => {
+                if let Some(first) = answer.first_mut() {
+                    *first = Err(Error::InvalidChecksum {
+                        received: 0x00,
+                        computed: 0xFF,
+                    });
+                }
+            }
+            Some(Fault::Truncate) => {
+                if let Some(Ok(first)) = answer.first_mut()
+                    && first.len() > 1
+                {
+                    first.pop();
+                }
+            }
+            Some(Fault::Unsolicited(frame)) => answer.insert(0, Ok(frame)),
+            Some(Fault::Negative(_)) | None => {}
+        }
+        self.received.extend(answer);
+        Ok(())
+    }
+    fn next_raw_message(&mut self) -> Result<RawMessage, Error> {
+        let bytes = match self.received.pop_front() {
+            Some(bytes) => bytes?,
+            None if self.silent => {
+                std::thread::sleep(READ_TIMEOUT);
+                return Err(Error::Io(ErrorKind::TimedOut.into()));
+            }
+            None => return Err(Error::Io(ErrorKind::BrokenPipe.into())),
+        };
+        Ok(frame(&bytes))
+    }
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        let Some(bytes) = self.received.pop_front() else {
+            return Ok(0);
+        };
+        let bytes = frame(&bytes?).to_bytes()?;
+        buffer.extend_from_slice(&bytes);
+        Ok(bytes.len())
+    }
+    fn reinitialize(&mut self, _address: u8, _timing: &InitTiming) -> Result<(), Error> {
+        self.received.clear();
+        self.silent = false;
+        Ok(())
+    }
+}
+
+/// Message as decoded from `bytes`, the service byte followed by the data.
+pub(crate) fn frame(bytes: &[u8]) -> RawMessage {
+    RawMessage {
+        mode: AddressMode::None,
+        target: None,
+        source: None,
+        service: Service::try_from(bytes[0]).unwrap(),
+        data: bytes[1..].to_vec(),
+        frame: None,
+    }
+}
+
+/// Client talking to a `MockInterface` with `responder`.
+pub(crate) fn client(
+    responder: impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send + 'static,
+) -> Client {
+    Client::new(Box::new(MockInterface::new(responder)))
+}
+
+/// Client talking to a `MockInterface` with `responder`, and the handle for
+/// injecting faults into its answers.
+pub(crate) fn faulty_client(
+    responder: impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send + 'static,
+) -> (Client, Faults) {
+    let interface = MockInterface::new(responder);
+    let faults = interface.faults();
+    (Client::new(Box::new(interface)), faults)
+}
+
+/// ECU answering dynamic identifier reads with the low byte of each
+/// address, refusing to define the identifier for `rejected`.
+pub(crate) fn dd_ecu(
+    rejected: std::ops::Range<u32>,
+) -> impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send {
+    capped_dd_ecu(rejected, u8::MAX)
+}
+
+/// ECU of `dd_ecu` also refusing definitions longer than `max_length`.
+pub(crate) fn capped_dd_ecu(
+    rejected: std::ops::Range<u32>,
+    max_length: u8,
+) -> impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send {
+    let mut defined = (0, 0);
+    move |request| {
+        vec![Ok(match request {
+            [0x2C, 0xF0, 0x04] => vec![0x6C, 0xF0],
+            [0x2C, 0xF0, 0x03, 0x01, size, a, b, c] => {
+                let address = u32::from_be_bytes([0, *a, *b, *c]);
+                if rejected.contains(&address) || *size > max_length {
+                    vec![0x7F, 0x2C, 0x31]
+                } else {
+                    defined = (address, *size as u32);
+                    vec![0x6C, 0xF0]
+                }
+            }
+            [0x21, 0xF0, ..] => {
+                let (address, size) = defined;
+                let mut response = vec![0x61, 0xF0];
+                response.extend((address..address + size).map(|a| a as u8));
+                response
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        })]
+    }
+}
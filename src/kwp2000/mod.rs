@@ -1,22 +1,104 @@
-use crate::Error;
+use crate::{Error, k_line::InitTiming};
 use constants::ServiceId;
 use message::Message;
-use raw_message::RawMessage;
+use raw_message::{FrameAssembler, RawMessage};
 use response::Response;
 
+pub mod attach;
+pub mod batch;
+pub mod best_effort;
+pub(crate) mod bus;
 pub mod client;
+#[cfg(feature = "flash")]
+pub mod codec;
 pub mod constants;
+pub mod data_rates;
+pub mod diagnostics;
+pub mod dtc;
+pub mod dump;
+#[cfg(feature = "flash")]
+pub mod fingerprint;
+#[cfg(feature = "flash")]
+pub mod flash;
+#[cfg(feature = "diag")]
+pub mod identification;
+pub mod image;
+pub mod io_control;
+pub mod live;
+pub mod logging;
 pub mod message;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod obd;
+pub mod policy;
+#[cfg(feature = "flash")]
+pub mod programming;
+pub mod progress;
+#[cfg(feature = "transport-serialport")]
+pub mod quick_connect;
 pub mod raw_message;
+pub mod read_only;
+pub mod recovery;
 pub mod response;
+pub mod routine;
+pub mod security;
+pub mod stats;
+pub mod teardown;
+pub mod timing;
+pub mod transfer;
+pub mod typed;
+#[cfg(feature = "uds_compat")]
+pub mod uds_compat;
+pub mod warning;
+pub mod watchdog;
+
+#[cfg(feature = "transport-serialport")]
+pub use quick_connect::quick_connect;
 
 pub trait Interface {
     fn switch_baud(&mut self, baud_rate: u32) -> Result<(), Error>;
     fn send_raw(&mut self, message: RawMessage) -> Result<(), Error>;
     fn send(&mut self, message: Message) -> Result<(), Error> {
-        self.send_raw(message.raw())
+        self.send_raw(message.raw()?)
     }
     fn next_raw_message(&mut self) -> Result<RawMessage, Error>;
+    /// Appends the bytes that were already received to `buffer` without
+    /// waiting for more, returns how many there were.
+    fn read_available(&mut self, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        let _ = buffer;
+        Err(Error::NotImplemented)
+    }
+    /// Returns the next response if it was received completely, without
+    /// waiting. Bytes of an incomplete frame are kept in `assembler` for
+    /// the next call. Skips echoes, `ResponsePending` is returned as
+    /// `Response::StillProcessing`.
+    fn next_response_nonblocking(
+        &mut self,
+        assembler: &mut FrameAssembler,
+    ) -> Result<Option<Response>, Error> {
+        let mut bytes = Vec::new();
+        self.read_available(&mut bytes)?;
+        assembler.push(&bytes);
+        while let Some(frame) = assembler.next_frame() {
+            match response::from_raw(frame?)? {
+                Response::Echo(_) => continue,
+                response => return Ok(Some(response)),
+            }
+        }
+        Ok(None)
+    }
+    /// Switches back to the initialization baud rate and initializes the
+    /// ECU at `address` again, used after the ECU dropped the session.
+    fn reinitialize(&mut self, address: u8, timing: &InitTiming) -> Result<(), Error> {
+        let _ = (address, timing);
+        Err(Error::NotImplemented)
+    }
+    /// Closes the device and opens it again with the same settings, for
+    /// adapters that stop working until they are. `Error::NotImplemented`
+    /// if the interface can't.
+    fn reset_hard(&mut self) -> Result<(), Error> {
+        Err(Error::NotImplemented)
+    }
 
     /// Convenience function when not expecting to have to wait for a
     /// response
@@ -72,9 +154,12 @@ pub fn security_key_from_seed(seed: [u8; 4]) -> u32 {
     key
 }
 
+/// Baud rate of the link after the 5-baud initialization.
+pub const INIT_BAUD_RATE: u32 = 10400;
+
 /// https://github.com/NefMoto/NefMotoOpenSource/blob/9dfa4f32d9d68e0c9d32fed69a62a224c2f39d9f/Communication/KWP2000Actions.cs#L560
-pub fn baud_rate_to_byte(baud_rate: u32) -> u8 {
-    let base = baud_rate * 32 / 6400;
+pub fn baud_rate_to_byte(baud_rate: u32) -> Result<u8, Error> {
+    let base = (baud_rate as u64 * 32 / 6400).min(u32::MAX as u64) as u32;
     let mut best_scalar_distance = 1.0;
     let mut best_exp = 0;
     let mut best_exp_result = 1;
@@ -93,9 +178,14 @@ pub fn baud_rate_to_byte(baud_rate: u32) -> u8 {
         }
     }
 
+    // baud rates outside of what the byte can represent
+    if !(32..64).contains(&(base / best_exp_result)) {
+        return Err(Error::UnexpectedValue);
+    }
+
     let z = (base / best_exp_result) - 32;
 
-    (((best_exp & 0x7) << 5) | (z & 0x1F)) as u8
+    Ok((((best_exp & 0x7) << 5) | (z & 0x1F)) as u8)
 }
 
 /// https://github.com/NefMoto/NefMotoOpenSource/blob/9dfa4f32d9d68e0c9d32fed69a62a224c2f39d9f/Communication/KWP2000Actions.cs#L535
@@ -107,3 +197,18 @@ pub fn baud_rate_from_byte(byte: u8) -> u32 {
 
     (pow * (lower + 32) * 6400) / 32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrepresentable_baud_rates_are_rejected() {
+        for baud_rate in [0, 1, u32::MAX] {
+            assert!(matches!(
+                baud_rate_to_byte(baud_rate),
+                Err(Error::UnexpectedValue)
+            ));
+        }
+    }
+}
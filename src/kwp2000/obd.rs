@@ -0,0 +1,71 @@
+//! OBD requests to the functional address, which every emissions related
+//! ECU answers, so one request can get several responses.
+
+use std::time::Instant;
+
+use crate::Error;
+
+use super::{
+    client::{Client, POLL_LATENCY_MARGIN},
+    constants::{AddressMode, ServiceId},
+    raw_message::RawMessage,
+    response::{self, ProcessError, Response},
+    timing::TimingState,
+};
+
+/// Functional address of the emissions related ECUs.
+pub const OBD_FUNCTIONAL_ADDRESS: u8 = 0x33;
+/// Address of the tester in OBD requests.
+pub const TESTER_ADDRESS: u8 = 0xF1;
+
+impl Client {
+    /// Sends the OBD request `service` with `data` to the functional address
+    /// and returns the responses with the address of the ECU that sent
+    /// them, in the order they arrived (e.g. engine, then transmission).
+    ///
+    /// Collecting ends once nothing arrived for P2max since the request or
+    /// the last response, P2*max if an ECU sent `ResponsePending`. Responses
+    /// to other services arriving meanwhile go to `take_unsolicited`. The
+    /// request isn't pending, so other requests keep their single response.
+    pub fn obd_request(
+        &mut self,
+        service: ServiceId,
+        data: Vec<u8>,
+    ) -> Result<Vec<(u8, Response)>, Error> {
+        self.send_untracked(RawMessage::new_query_addressed(
+            AddressMode::Functional,
+            Some(OBD_FUNCTIONAL_ADDRESS),
+            Some(TESTER_ADDRESS),
+            service,
+            data,
+        )?)?;
+        let mut responses = Vec::new();
+        let mut last = Instant::now();
+        let mut window = self.response_timeout(false);
+        while last.elapsed() <= window.saturating_add(POLL_LATENCY_MARGIN) {
+            let Some(raw) = self.poll_frame()? else {
+                std::thread::sleep(TimingState::MIN_RESOLUTION);
+                continue;
+            };
+            let source = raw.source;
+            match response::from_raw(raw)? {
+                Response::Echo(_) => self.stats.echoes += 1,
+                Response::StillProcessing(s) if s == service => {
+                    last = Instant::now();
+                    window = self.response_timeout(true);
+                }
+                response @ (Response::ObdData(s, _)
+                | Response::Error(ProcessError { service: s, .. }))
+                    if s == service =>
+                {
+                    last = Instant::now();
+                    window = self.response_timeout(false);
+                    // without an address header only one ECU can answer
+                    responses.push((source.unwrap_or(self.profile.init_address), response));
+                }
+                response => self.push_unsolicited(response),
+            }
+        }
+        Ok(responses)
+    }
+}
@@ -0,0 +1,214 @@
+//! Policies controlling what the client is allowed to do to the ECU.
+
+use std::fmt::Debug;
+
+use super::{
+    constants::{CommonIdentifier, IoControlParameter, ResetMode, RoutineIdentifier},
+    io_control::IoOutput,
+};
+
+/// Operations that change the state of the ECU in a way that can't easily be
+/// undone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DangerousAction {
+    /// Programming of the flash memory.
+    FlashWrite {
+        address: u32,
+        size: u32,
+        /// Number of flash sectors touched by the write
+        sectors: usize,
+    },
+    /// Write to RAM or other directly addressed memory.
+    MemoryWrite { address: u32, size: u32 },
+    /// Write of the tester fingerprint kept by the ECU.
+    #[cfg(feature = "flash")]
+    FingerprintWrite,
+    /// Reset of the ECU, which ends the session.
+    EcuReset(ResetMode),
+    /// Write of coding or adaptation data to a common identifier.
+    CodingWrite {
+        identifier: CommonIdentifier,
+        size: u32,
+    },
+    /// Taking over an ECU output, such as a relay or a valve.
+    IoControl {
+        output: IoOutput,
+        control: IoControlParameter,
+    },
+    /// Start of an ECU routine, such as a flash erase.
+    RoutineStart(RoutineIdentifier),
+    /// Start of the routine at an address, code downloaded to RAM.
+    RoutineStartByAddress(u32),
+    /// Clearing of the trouble codes stored for `group`.
+    DtcClear { group: u16 },
+}
+
+/// Consulted by the client before every `DangerousAction`. Denied actions
+/// return `Error::DeniedByPolicy` without sending anything to the ECU.
+#[derive(Default)]
+pub enum DangerPolicy {
+    /// Allow everything (default).
+    #[default]
+    Allow,
+    /// Ask the callback before every dangerous action.
+    RequireConfirmation(Box<dyn Fn(&DangerousAction) -> bool + Send>),
+    /// Deny every dangerous action, for read-only use.
+    DenyAll,
+}
+
+impl DangerPolicy {
+    pub fn confirm(&self, action: &DangerousAction) -> bool {
+        match self {
+            DangerPolicy::Allow => true,
+            DangerPolicy::RequireConfirmation(confirm) => confirm(action),
+            DangerPolicy::DenyAll => false,
+        }
+    }
+}
+
+impl Debug for DangerPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DangerPolicy::Allow => write!(f, "Allow"),
+            DangerPolicy::RequireConfirmation(_) => write!(f, "RequireConfirmation"),
+            DangerPolicy::DenyAll => write!(f, "DenyAll"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Error,
+        kwp2000::{
+            client::Client,
+            constants::{CommonIdentifier, IoControlParameter, LocalIdentifier, RoutineIdentifier},
+            live::LiveVariable,
+            mock,
+        },
+    };
+
+    type Operation = fn(&mut Client) -> Result<(), Error>;
+
+    /// Every operation that changes the ECU, each has to ask the policy
+    /// before sending anything.
+    fn destructive_operations() -> Vec<(&'static str, Operation)> {
+        let operations: Vec<(&'static str, Operation)> = vec![
+            ("dd_write_address", |c| {
+                c.dd_write_address(0x380000, vec![1])
+            }),
+            ("dd_write_verified", |c| {
+                c.dd_write_verified(0x380000, vec![1])
+            }),
+            ("write_batch", |c| {
+                c.write_batch(&[(0x380000, vec![1])]).map(|_| ())
+            }),
+            ("write_live", |c| {
+                let variable = LiveVariable {
+                    name: "map_select".to_string(),
+                    address: 0x380000,
+                    size: 1,
+                    allowed: vec![1],
+                };
+                c.write_live(&variable, 1)
+            }),
+            ("clear_security_wait", |c| c.clear_security_wait()),
+            ("write_memory_by_address", |c| {
+                c.write_memory_by_address(0x380000, vec![1])
+            }),
+            ("write_common_identifier", |c| {
+                c.write_common_identifier(CommonIdentifier(0x1234), &[1])
+            }),
+            ("io_control_local", |c| {
+                c.io_control_local(
+                    LocalIdentifier(0x01),
+                    IoControlParameter::ShortTermAdjustment,
+                    vec![1],
+                )
+                .map(|_| ())
+            }),
+            ("io_control_common", |c| {
+                c.io_control_common(
+                    CommonIdentifier(0x1234),
+                    IoControlParameter::ShortTermAdjustment,
+                    vec![1],
+                )
+                .map(|_| ())
+            }),
+            ("start_routine", |c| {
+                c.start_routine(RoutineIdentifier(0x01), Vec::new())
+                    .map(|_| ())
+            }),
+            ("start_routine_by_address", |c| {
+                c.start_routine_by_address(0x380000, Vec::new()).map(|_| ())
+            }),
+            ("clear_dtcs", |c| c.clear_dtcs(0x0000)),
+            ("clear_all_dtcs", |c| c.clear_all_dtcs()),
+            ("ecu_reset", |c| c.ecu_reset(ResetMode::PowerOn)),
+        ];
+        #[cfg(feature = "flash")]
+        let operations = [operations, flash_operations()].concat();
+        operations
+    }
+
+    #[cfg(feature = "flash")]
+    fn flash_operations() -> Vec<(&'static str, Operation)> {
+        vec![
+            ("write_data_bosch", |c| {
+                c.write_data_bosch(crate::memory_layout::BASE_ADDRESS, &[0; 16], b"GEHEIM")
+                    .map(|_| ())
+            }),
+            ("write_data", |c| {
+                use crate::kwp2000::constants::{CompressionFormat, EncryptionFormat};
+                c.write_data(
+                    crate::memory_layout::BASE_ADDRESS,
+                    &[0; 16],
+                    CompressionFormat::Bosch,
+                    EncryptionFormat::Bosch,
+                )
+                .map(|_| ())
+            }),
+            ("write_fingerprint", |c| {
+                c.profile.fingerprint = Some((0x9C, LocalIdentifier(0x9C)));
+                let fingerprint = crate::kwp2000::fingerprint::TesterFingerprint {
+                    year: 24,
+                    month: 1,
+                    day: 1,
+                    importer: 0,
+                    workshop_code: 0,
+                    tester_serial: 0,
+                };
+                c.write_fingerprint(&fingerprint)
+            }),
+        ]
+    }
+
+    #[test]
+    fn deny_all_stops_every_destructive_operation() {
+        for (name, operation) in destructive_operations() {
+            let mut client = mock::client(move |request| panic!("{name} sent {request:02X?}"));
+            client.danger_policy = DangerPolicy::DenyAll;
+            match operation(&mut client) {
+                Err(Error::DeniedByPolicy(_)) => {}
+                r => panic!("{name}: {r:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn confirmation_sees_the_action() {
+        let mut client = mock::client(|request| panic!("sent {request:02X?}"));
+        client.danger_policy = DangerPolicy::RequireConfirmation(Box::new(|action| {
+            assert_eq!(
+                action,
+                &DangerousAction::RoutineStart(RoutineIdentifier(0x01))
+            );
+            false
+        }));
+        assert!(matches!(
+            client.start_routine(RoutineIdentifier(0x01), Vec::new()),
+            Err(Error::DeniedByPolicy(DangerousAction::RoutineStart(_)))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{Error, LayoutViolation};
+
+use super::{
+    client::Client,
+    constants::{RoutineIdentifier, ServiceError},
+    response::{ProcessError, Response},
+};
+
+/// Number of times a group that failed verification is sent again before
+/// the write fails.
+pub const MAX_VERIFY_RETRIES: u32 = 2;
+
+/// Checksum the ME7 checksum routine computes over a range, the wrapping
+/// sum of its bytes.
+pub fn additive_checksum(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |sum, b| sum.wrapping_add(*b as u16))
+}
+
+/// Result of the last programming attempt as recorded by the ECU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgrammingStatus {
+    Success,
+    /// Programming was started but did not finish, the flash is only
+    /// partially written.
+    Interrupted,
+    /// Programming finished but the written data failed the ECU's checksum.
+    ChecksumFailed,
+    /// Programming was refused before anything was written.
+    PreconditionFailed,
+    /// Status the profile's decoder does not know, or no status is available
+    /// (empty) if the profile has no identification option for it.
+    Unknown(Vec<u8>),
+}
+
+impl ProgrammingStatus {
+    /// Whether the flash may be left in an unusable state.
+    pub fn is_half_programmed(&self) -> bool {
+        matches!(self, Self::Interrupted | Self::ChecksumFailed)
+    }
+}
+
+/// Decodes the identification data holding the programming status.
+pub type ProgrammingStatusDecoder = fn(&[u8]) -> ProgrammingStatus;
+
+/// Result of `Client::write_data`.
+#[derive(Debug, Clone)]
+pub struct FlashReport {
+    /// Number of uncompressed bytes sent, including groups sent again after
+    /// failing verification
+    pub sent: usize,
+    /// Number of bytes checked with the checksum routine, zero if
+    /// `Client::verify_interval` is not set
+    pub verified: usize,
+    /// Programming status read after the transfer.
+    pub programming_status: ProgrammingStatus,
+}
+
+impl Client {
+    /// Starts `routine` with `parameters` and waits for its results. The
+    /// ECU counts as busy while it answers `RoutineNotComplete`, see
+    /// `BusActivity::EcuBusy`.
+    fn run_routine(
+        &mut self,
+        routine: RoutineIdentifier,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let result = self.poll_routine(routine, parameters);
+        // don't leave it busy if the polling gave up
+        self.routine_not_complete = false;
+        result
+    }
+    fn poll_routine(
+        &mut self,
+        routine: RoutineIdentifier,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.start_routine_unconfirmed(routine, parameters)?;
+        let mut polls = 0;
+        loop {
+            match self.routine_results(routine) {
+                Err(Error::UnexpectedResponse(Response::Error(ProcessError {
+                    error: ServiceError::RoutineNotComplete,
+                    service,
+                    ..
+                }))) => {
+                    polls += 1;
+                    if polls > self.profile.max_pending {
+                        return Err(Error::PendingLimitExceeded {
+                            service,
+                            count: polls,
+                        });
+                    }
+                    self.check_deadline(0)?;
+                }
+                result => return result,
+            }
+        }
+    }
+    /// Checks the flash at `address` against `data` with the profile's
+    /// checksum routine. The routine is given the first and last address
+    /// and the expected checksum, a zero first result byte means it matched.
+    pub fn verify_checksum(&mut self, address: u32, data: &[u8]) -> Result<bool, Error> {
+        let routine = self.profile.checksum_routine.ok_or(Error::NotImplemented)?;
+        let last = address.wrapping_add(data.len().saturating_sub(1) as u32);
+        let mut parameters = Vec::with_capacity(8);
+        parameters.extend_from_slice(&address.to_be_bytes()[1..4]);
+        parameters.extend_from_slice(&last.to_be_bytes()[1..4]);
+        parameters.extend_from_slice(&additive_checksum(data).to_be_bytes());
+
+        let results = self.run_routine(routine, parameters)?;
+        Ok(results.first() == Some(&0))
+    }
+    /// Checks that a flash write of `size` bytes at `address` fits the
+    /// profile's memory layout and, if the profile can identify it, that
+    /// the ECU has the flash device the layout is for.
+    pub fn check_flash_range(&mut self, address: u32, size: u32) -> Result<(), Error> {
+        self.profile
+            .memory_layout
+            .check_range(address, size, self.partial_sectors)
+            .map_err(Error::FlashRangeRejected)?;
+        if let Some((routine, expected)) = self.profile.flash_device.clone() {
+            let got = self.run_routine(routine, Vec::new())?;
+            if got != expected {
+                return Err(Error::FlashRangeRejected(
+                    LayoutViolation::FlashDeviceMismatch { expected, got },
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Reads the result of the last programming attempt, using the
+    /// identification option and decoder from the profile.
+    pub fn read_programming_status(&mut self) -> Result<ProgrammingStatus, Error> {
+        match self.profile.programming_status {
+            Some((option, decode)) => Ok(decode(&self.read_ecu_identification(option)?)),
+            None => Ok(ProgrammingStatus::Unknown(Vec::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+    use std::sync::{Arc, Mutex};
+
+    /// Routine reading the flash device id.
+    const DEVICE_ID: RoutineIdentifier = RoutineIdentifier(0x05);
+
+    /// Client for an ECU with flash device `device`, and the requests sent
+    /// to it.
+    fn ecu(device: &'static [u8]) -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sent = requests.clone();
+        let mut client = mock::client(move |request| {
+            sent.lock().unwrap().push(request.to_vec());
+            vec![Ok(match request {
+                [0x31, 0x05] => vec![0x71, 0x05],
+                [0x33, 0x05] => [&[0x73, 0x05], device].concat(),
+                _ => panic!("unexpected request {request:02X?}"),
+            })]
+        });
+        client.profile.flash_device = Some((DEVICE_ID, vec![0x01, 0xDA]));
+        (client, requests)
+    }
+
+    #[test]
+    fn flash_device_is_checked() {
+        let (mut client, _) = ecu(&[0x01, 0xDA]);
+        let base = client.profile.memory_layout.base_address;
+        client.check_flash_range(base, 0x4000).unwrap();
+
+        let (mut client, _) = ecu(&[0x20, 0xE3]);
+        assert!(matches!(
+            client.check_flash_range(base, 0x4000),
+            Err(Error::FlashRangeRejected(LayoutViolation::FlashDeviceMismatch { expected, got }))
+                if expected == [0x01, 0xDA] && got == [0x20, 0xE3]
+        ));
+    }
+
+    #[test]
+    fn misplaced_write_sends_nothing() {
+        let (mut client, requests) = ecu(&[0x01, 0xDA]);
+        let base = client.profile.memory_layout.base_address;
+        for address in [base - 1, base + 1, base + client.profile.memory_layout.size] {
+            assert!(matches!(
+                client.write_data_bosch(address, &[0xFF; 16], crate::KEY),
+                Err(Error::FlashRangeRejected(_))
+            ));
+        }
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
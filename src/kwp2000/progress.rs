@@ -0,0 +1,262 @@
+//! Progress reporting of long running operations.
+
+use std::{
+    fmt::{Debug, Write},
+    time::{Duration, Instant},
+};
+
+use crate::profile::Capabilities;
+
+use super::client::Client;
+
+/// Long running operation a `Progress` is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// `Client::read_best_effort`
+    Read,
+    /// `Client::write_data`
+    #[cfg(feature = "flash")]
+    FlashWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Started,
+    Transferring,
+    /// Checking written data with the checksum routine
+    #[cfg(feature = "flash")]
+    Verifying,
+    Finished,
+    Failed,
+}
+
+impl Phase {
+    /// Whether the operation is over.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Finished | Self::Failed)
+    }
+}
+
+/// State of a long running operation, passed to the client's
+/// `ProgressHandler` whenever it changes.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub operation: Operation,
+    pub phase: Phase,
+    /// Address the operation is at
+    pub address: u32,
+    /// Index of the flash sector `address` is in, `None` outside the flash
+    pub sector: Option<usize>,
+    pub bytes_done: usize,
+    pub bytes_total: usize,
+    pub started: Instant,
+    /// Number of warnings the client has recorded
+    pub warnings: usize,
+    /// Error that ended the operation, set in the `Failed` phase
+    pub last_error: Option<String>,
+    /// `EcuProfile::id` of the client's profile
+    pub profile: &'static str,
+    /// `Client::capabilities` when the operation started
+    pub capabilities: Capabilities,
+}
+
+impl Progress {
+    /// Estimated time left at the rate so far, `None` before any data was
+    /// transferred.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.bytes_done == 0 {
+            return None;
+        }
+        let left = self.bytes_total.saturating_sub(self.bytes_done);
+        let secs = self.started.elapsed().as_secs_f64() * left as f64 / self.bytes_done as f64;
+        Some(Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX))
+    }
+
+    /// Encodes the progress as a JSON object.
+    pub fn to_json(&self) -> String {
+        let optional = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+        format!(
+            concat!(
+                "{{\"operation\":\"{:?}\",\"phase\":\"{:?}\",\"address\":{},\"sector\":{},",
+                "\"bytes_done\":{},\"bytes_total\":{},\"elapsed_secs\":{:.1},\"eta_secs\":{},",
+                "\"warnings\":{},\"last_error\":{},\"profile\":{},\"capabilities\":{}}}"
+            ),
+            self.operation,
+            self.phase,
+            self.address,
+            optional(self.sector.map(|s| s.to_string())),
+            self.bytes_done,
+            self.bytes_total,
+            self.started.elapsed().as_secs_f64(),
+            optional(self.eta().map(|d| format!("{:.1}", d.as_secs_f64()))),
+            self.warnings,
+            optional(self.last_error.as_deref().map(json_string)),
+            json_string(self.profile),
+            self.capabilities.to_json(),
+        )
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+pub type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+/// Called with the progress of long running operations.
+#[derive(Default)]
+pub struct ProgressHandler(pub Option<ProgressCallback>);
+
+impl Debug for ProgressHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "ProgressHandler"),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// Tracks one operation and reports its progress to the client's handler.
+pub(crate) struct Tracker {
+    progress: Progress,
+    /// Bytes of the operation done before the part being reported on
+    pub(crate) done_before: usize,
+}
+
+impl Tracker {
+    pub(crate) fn start(
+        client: &mut Client,
+        operation: Operation,
+        address: u32,
+        bytes_total: usize,
+    ) -> Self {
+        let mut tracker = Self {
+            progress: Progress {
+                operation,
+                phase: Phase::Started,
+                address,
+                sector: None,
+                bytes_done: 0,
+                bytes_total,
+                started: Instant::now(),
+                warnings: 0,
+                last_error: None,
+                profile: client.profile.id,
+                capabilities: client.capabilities(),
+            },
+            done_before: 0,
+        };
+        tracker.report(client);
+        tracker
+    }
+
+    pub(crate) fn update(
+        &mut self,
+        client: &mut Client,
+        phase: Phase,
+        address: u32,
+        bytes_done: usize,
+    ) {
+        self.progress.phase = phase;
+        self.progress.address = address;
+        self.progress.bytes_done = self.done_before + bytes_done;
+        self.report(client);
+    }
+
+    /// Reports the end of the operation with the outcome of `result`.
+    pub(crate) fn finish<T>(&mut self, client: &mut Client, result: &Result<T, crate::Error>) {
+        match result {
+            Ok(_) => {
+                self.progress.phase = Phase::Finished;
+                self.progress.bytes_done = self.progress.bytes_total;
+            }
+            Err(e) => {
+                self.progress.phase = Phase::Failed;
+                self.progress.last_error = Some(e.to_string());
+            }
+        }
+        self.report(client);
+    }
+
+    fn report(&mut self, client: &mut Client) {
+        if client.on_progress.0.is_none() {
+            return;
+        }
+        self.progress.sector = client
+            .profile
+            .memory_layout
+            .sector_index(self.progress.address);
+        self.progress.warnings = client.warnings().len();
+        if let Some(handler) = &mut client.on_progress.0 {
+            handler(&self.progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress() -> Progress {
+        Progress {
+            operation: Operation::Read,
+            phase: Phase::Transferring,
+            address: 0x380100,
+            sector: None,
+            bytes_done: 0,
+            bytes_total: 0x200,
+            started: Instant::now(),
+            warnings: 2,
+            last_error: None,
+            profile: "me7",
+            capabilities: Capabilities::default(),
+        }
+    }
+
+    #[test]
+    fn json_fields() {
+        let json = progress().to_json();
+        assert!(json.starts_with(concat!(
+            r#"{"operation":"Read","phase":"Transferring","address":3670272,"sector":null,"#,
+            r#""bytes_done":0,"bytes_total":512,"elapsed_secs":0.0,"eta_secs":null,"#,
+            r#""warnings":2,"last_error":null,"profile":"me7","capabilities":{"#
+        )));
+        assert!(json.ends_with("}}"));
+
+        let mut halfway = progress();
+        halfway.sector = Some(3);
+        halfway.bytes_done = 0x100;
+        let json = halfway.to_json();
+        assert!(json.contains(r#""sector":3,"bytes_done":256,"#));
+        assert!(!json.contains(r#""eta_secs":null"#));
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let mut failed = progress();
+        failed.phase = Phase::Failed;
+        failed.last_error = Some("bad \"frame\"\nat C:\\dump\t\u{1}".to_string());
+        assert!(failed.to_json().contains(r#""phase":"Failed","#));
+        assert!(
+            failed
+                .to_json()
+                .contains(r#""last_error":"bad \"frame\"\nat C:\\dump\u0009\u0001","#)
+        );
+        assert_eq!(json_string("ünïcode"), "\"ünïcode\"");
+    }
+}
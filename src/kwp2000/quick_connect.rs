@@ -0,0 +1,334 @@
+//! Whole connect sequence as one call with one overall timeout, for
+//! supervisor scripts that must never hang whatever the hardware does.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Error,
+    k_line::{KLine, SerialInterface},
+    profile::EcuProfile,
+};
+
+use super::{
+    Interface, attach::ConnectOptions, client::Client, constants::DiagnosticMode,
+    timing::TimingState,
+};
+
+/// Step of `quick_connect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectPhase {
+    OpenPort,
+    /// Waiting for the bus to go quiet before the init
+    LineCheck,
+    Init,
+    Session,
+    Security,
+    Identification,
+    Disconnect,
+}
+
+/// Outcome of a `quick_connect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectSummary {
+    /// Value of `ConnectOptions::identification`, `None` if none was read
+    pub identification: Option<Vec<u8>>,
+    pub mode: DiagnosticMode,
+    pub baud_rate: u32,
+    pub timing: TimingState,
+    /// Security access was granted.
+    pub security: bool,
+    /// Time every phase took, in order.
+    pub phases: Vec<(ConnectPhase, Duration)>,
+}
+
+/// What the worker of `quick_connect` reports.
+enum Progress {
+    Started(ConnectPhase),
+    Done(Result<ConnectSummary, Error>),
+}
+
+/// Worker side of `quick_connect`: runs the phases in order, stops before
+/// the next one once `abandoned` is set.
+struct Worker {
+    progress: mpsc::Sender<Progress>,
+    abandoned: Arc<AtomicBool>,
+    phases: Vec<(ConnectPhase, Duration)>,
+}
+
+impl Worker {
+    fn phase<T>(
+        &mut self,
+        phase: ConnectPhase,
+        run: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.abandoned.load(Ordering::Relaxed) {
+            return Err(Error::ConnectTimedOut { phase });
+        }
+        // the caller is gone if this fails, `abandoned` stops the next phase
+        let _ = self.progress.send(Progress::Started(phase));
+        let start = Instant::now();
+        let result = run();
+        self.phases.push((phase, start.elapsed()));
+        result
+    }
+
+    fn run<P>(
+        &mut self,
+        open: impl FnOnce() -> Result<P, Error>,
+        options: ConnectOptions,
+        deadline: Instant,
+    ) -> Result<ConnectSummary, Error>
+    where
+        P: KLine<Error = Error> + Interface + Debug + Send + 'static,
+    {
+        let profile = EcuProfile::default();
+        let mut port = self.phase(ConnectPhase::OpenPort, open)?;
+        self.phase(ConnectPhase::LineCheck, || {
+            let limit = port.bus_idle_limit();
+            port.wait_for_bus_idle(profile.init_timing.idle, limit)
+        })?;
+        self.phase(ConnectPhase::Init, || {
+            port.init_kwp2000(profile.init_address, &profile.init_timing)
+        })?;
+        let mut client = Client::new(Box::new(port));
+        // phases with loops stop on their own once it passed
+        client.deadline = Some(deadline);
+        self.phase(ConnectPhase::Session, || {
+            client.switch_mode(options.mode, options.baud_rate)
+        })?;
+        if options.security {
+            self.phase(ConnectPhase::Security, || client.get_security_access())?;
+        }
+        let identification = match options.identification {
+            Some(option) => Some(self.phase(ConnectPhase::Identification, || {
+                client.read_ecu_identification(option)
+            })?),
+            None => None,
+        };
+        let summary = ConnectSummary {
+            identification,
+            mode: options.mode,
+            baud_rate: client.baud_rate,
+            timing: client.timing,
+            security: options.security,
+            phases: Vec::new(),
+        };
+        self.phase(ConnectPhase::Disconnect, || client.disconnect())?;
+        Ok(ConnectSummary {
+            phases: std::mem::take(&mut self.phases),
+            ..summary
+        })
+    }
+}
+
+/// Opens the port at `port_path`, checks the line is quiet, initializes
+/// the ECU, switches to `options.mode`, gets security access and reads the
+/// identification if `options` asks for them, then disconnects. Returns
+/// once `timeout` passed whatever the hardware does, with
+/// `Error::ConnectTimedOut` naming the phase that was running.
+///
+/// The phases run on a worker thread so one stuck in a blocking call
+/// can't hold the caller. A worker that missed the deadline is abandoned:
+/// it stops before its next phase and closes the port once its blocking
+/// call returns. Until then the port may still be open.
+pub fn quick_connect(
+    port_path: &str,
+    options: ConnectOptions,
+    timeout: Duration,
+) -> Result<ConnectSummary, Error> {
+    let port_path = port_path.to_string();
+    connect_with(
+        move || SerialInterface::open(crate::port_builder(&port_path)),
+        options,
+        timeout,
+    )
+}
+
+/// `quick_connect` on the port `open` returns.
+fn connect_with<P>(
+    open: impl FnOnce() -> Result<P, Error> + Send + 'static,
+    options: ConnectOptions,
+    timeout: Duration,
+) -> Result<ConnectSummary, Error>
+where
+    P: KLine<Error = Error> + Interface + Debug + Send + 'static,
+{
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let (sender, progress) = mpsc::channel();
+    let mut worker = Worker {
+        progress: sender,
+        abandoned: abandoned.clone(),
+        phases: Vec::new(),
+    };
+    thread::Builder::new()
+        .name("quick-connect".to_string())
+        .spawn(move || {
+            let result = worker.run(open, options, deadline);
+            let _ = worker.progress.send(Progress::Done(result));
+        })?;
+    let mut phase = ConnectPhase::OpenPort;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match progress.recv_timeout(left) {
+            Ok(Progress::Started(p)) => phase = p,
+            Ok(Progress::Done(result)) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                abandoned.store(true, Ordering::Relaxed);
+                return Err(Error::ConnectTimedOut { phase });
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(Error::Io(std::io::ErrorKind::BrokenPipe.into()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        k_line::InitTiming,
+        kwp2000::{mock::MockInterface, raw_message::RawMessage},
+    };
+
+    /// K-line whose init takes `init`, answering requests like a
+    /// cooperative ECU that takes `identification` to answer option 0x9B.
+    #[derive(Debug)]
+    struct ScriptedLine {
+        init: Duration,
+        ecu: MockInterface,
+    }
+
+    impl ScriptedLine {
+        fn new(init: Duration, identification: Duration) -> Self {
+            Self {
+                init,
+                ecu: MockInterface::new(move |request| {
+                    vec![Ok(match request {
+                        [0x10, mode] => vec![0x50, *mode],
+                        [0x1A, 0x9B] => {
+                            thread::sleep(identification);
+                            vec![0x5A, 0x9B, b'0', b'3']
+                        }
+                        [0x20] => vec![0x60],
+                        [0x82] => vec![0xC2],
+                        _ => panic!("unexpected request {request:02X?}"),
+                    })]
+                }),
+            }
+        }
+    }
+
+    impl KLine for ScriptedLine {
+        type Error = Error;
+
+        fn wait_for_bus_idle(&mut self, _idle: Duration, _limit: Duration) -> Result<(), Error> {
+            Ok(())
+        }
+        fn init_kwp2000(&mut self, _address: u8, _timing: &InitTiming) -> Result<(), Error> {
+            thread::sleep(self.init);
+            Ok(())
+        }
+        fn delay(&self, duration: Duration) {
+            thread::sleep(duration);
+        }
+        fn write_byte(&mut self, _byte: u8) -> Result<(), Error> {
+            Ok(())
+        }
+        fn read_byte(&mut self) -> Result<u8, Error> {
+            Err(Error::NotImplemented)
+        }
+        fn read_byte_timeout(&mut self, _timeout: Duration) -> Result<Option<u8>, Error> {
+            Ok(None)
+        }
+        fn set_high(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+        fn set_low(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Interface for ScriptedLine {
+        fn switch_baud(&mut self, baud_rate: u32) -> Result<(), Error> {
+            self.ecu.switch_baud(baud_rate)
+        }
+        fn send_raw(&mut self, message: RawMessage) -> Result<(), Error> {
+            self.ecu.send_raw(message)
+        }
+        fn next_raw_message(&mut self) -> Result<RawMessage, Error> {
+            self.ecu.next_raw_message()
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+    /// Longer than any test waits, a phase that takes it is stuck.
+    const HANG: Duration = Duration::from_secs(10);
+    /// Allowed beyond the timeout for the threads to be scheduled.
+    const GRACE: Duration = Duration::from_millis(150);
+
+    fn connect(line: ScriptedLine) -> (Result<ConnectSummary, Error>, Duration) {
+        let options = ConnectOptions {
+            identification: Some(0x9B),
+            ..ConnectOptions::default()
+        };
+        let start = Instant::now();
+        let result = connect_with(move || Ok(line), options, TIMEOUT);
+        (result, start.elapsed())
+    }
+
+    #[test]
+    fn connects_and_identifies() {
+        let (result, _) = connect(ScriptedLine::new(Duration::ZERO, Duration::ZERO));
+        let summary = result.unwrap();
+        assert_eq!(summary.identification.as_deref(), Some(&b"03"[..]));
+        assert_eq!(summary.mode, DiagnosticMode::Diagnostics);
+        let phases: Vec<_> = summary.phases.iter().map(|(p, _)| *p).collect();
+        assert_eq!(
+            phases,
+            [
+                ConnectPhase::OpenPort,
+                ConnectPhase::LineCheck,
+                ConnectPhase::Init,
+                ConnectPhase::Session,
+                ConnectPhase::Identification,
+                ConnectPhase::Disconnect,
+            ]
+        );
+    }
+
+    #[test]
+    fn hang_in_init_times_out() {
+        let (result, elapsed) = connect(ScriptedLine::new(HANG, Duration::ZERO));
+        assert!(matches!(
+            result,
+            Err(Error::ConnectTimedOut {
+                phase: ConnectPhase::Init
+            })
+        ));
+        assert!(elapsed < TIMEOUT + GRACE, "{elapsed:?}");
+    }
+
+    #[test]
+    fn hang_in_identification_times_out() {
+        let (result, elapsed) = connect(ScriptedLine::new(Duration::ZERO, HANG));
+        assert!(matches!(
+            result,
+            Err(Error::ConnectTimedOut {
+                phase: ConnectPhase::Identification
+            })
+        ));
+        assert!(elapsed < TIMEOUT + GRACE, "{elapsed:?}");
+    }
+}
@@ -1,5 +1,6 @@
 use std::io::Read;
 use std::num::Wrapping;
+use std::time::Instant;
 
 use super::constants::*;
 use crate::Error;
@@ -9,6 +10,47 @@ pub const MAX_DATA_LENGTH: usize = u8::MAX as usize;
 /// Maximum number of data bytes in message before the length byte is needed
 pub const SHORT_DATA_LENGTH: usize = 0b00111111;
 
+/// Framing of the messages exchanged with the ECU, limits how much data fits
+/// in one message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderFormat {
+    /// Target and source address bytes are sent.
+    pub addressed: bool,
+    /// A length byte is sent for messages too long for the format byte.
+    pub length_byte: bool,
+}
+
+impl HeaderFormat {
+    /// Maximum number of data bytes after the service id in one message.
+    /// Without a length byte the format byte limits it, with one the ECU's
+    /// frame buffer does and the address bytes take up two bytes of it.
+    pub fn max_payload(&self) -> usize {
+        if self.length_byte {
+            MAX_DATA_LENGTH - 1 - if self.addressed { 2 } else { 0 }
+        } else {
+            SHORT_DATA_LENGTH - 1
+        }
+    }
+
+    /// Number of bytes on the wire for a message with `payload` data bytes
+    /// after the service id.
+    pub fn frame_len(&self, payload: usize) -> usize {
+        let length = payload + 1;
+        let address = if self.addressed { 2 } else { 0 };
+        let length_byte = if length > SHORT_DATA_LENGTH { 1 } else { 0 };
+        1 + address + length_byte + length + 1
+    }
+}
+
+impl Default for HeaderFormat {
+    fn default() -> Self {
+        Self {
+            addressed: false,
+            length_byte: true,
+        }
+    }
+}
+
 /// Decodes a message format byte into an address mode and a length
 /// If length is None the message header will contain a length byte
 pub fn decode_format(byte: u8) -> (AddressMode, Option<u8>) {
@@ -18,13 +60,71 @@ pub fn decode_format(byte: u8) -> (AddressMode, Option<u8>) {
             0b00 => AddressMode::None,
             0b01 => AddressMode::Carb,
             0b10 => AddressMode::Physical,
-            0b11 => AddressMode::Functional,
-            _ => panic!("impossible value"),
+            _ => AddressMode::Functional,
         },
         if length == 0 { None } else { Some(length) },
     )
 }
 
+/// Number of bytes of the frame starting with `bytes`, `None` until enough
+/// of its header is there to know.
+pub fn expected_frame_len(bytes: &[u8]) -> Option<usize> {
+    let (mode, length) = decode_format(*bytes.first()?);
+    let address = if mode == AddressMode::None { 0 } else { 2 };
+    let (header, length) = match length {
+        Some(length) => (1 + address, length),
+        None => (2 + address, *bytes.get(1 + address)?),
+    };
+    // length counts the service id and data
+    Some(header + length as usize + 1)
+}
+
+/// Collects bytes as they arrive and splits them into frames, for reading
+/// without blocking.
+#[derive(Debug, Default)]
+pub struct FrameAssembler {
+    buffer: Vec<u8>,
+}
+
+impl FrameAssembler {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next frame, `None` until it was received completely. A
+    /// frame that doesn't decode is dropped and its error returned.
+    pub fn next_frame(&mut self) -> Option<Result<RawMessage, Error>> {
+        let len = expected_frame_len(&self.buffer).filter(|len| *len <= self.buffer.len())?;
+        let frame: Vec<u8> = self.buffer.drain(..len).collect();
+        Some(RawMessage::read_from_bytes(&mut frame.as_slice()))
+    }
+
+    /// Number of bytes of incomplete frames held.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// Details of how a received message was framed on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Checksum byte as received
+    pub checksum: u8,
+    pub had_length_byte: bool,
+    /// Number of bytes before the service id
+    pub header_len: u8,
+    /// Length of the whole frame, including header and checksum
+    pub total_len: usize,
+    /// When the first byte of the frame was read
+    pub first_byte_at: Instant,
+    /// When the checksum byte was read
+    pub completed_at: Instant,
+}
+
 #[derive(Debug, Clone)]
 pub struct RawMessage {
     pub mode: AddressMode,
@@ -32,11 +132,14 @@ pub struct RawMessage {
     pub source: Option<u8>,
     pub service: Service,
     pub data: Vec<u8>,
+    /// Only set on messages that were decoded from bytes, not used when
+    /// encoding.
+    pub frame: Option<FrameInfo>,
 }
 
 impl RawMessage {
     /// Creates a message using the one byte header mode
-    pub fn new_query(service: ServiceId, data: Vec<u8>) -> Self {
+    pub fn new_query(service: ServiceId, data: Vec<u8>) -> Result<Self, Error> {
         Self::new_query_addressed(AddressMode::None, None, None, service, data)
     }
     pub fn addressed(mut self, target: u8, source: u8) -> Self {
@@ -51,44 +154,59 @@ impl RawMessage {
         source: Option<u8>,
         service: ServiceId,
         data: Vec<u8>,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         // leave one byte for the service id
-        assert!(data.len() < MAX_DATA_LENGTH);
-        match mode {
-            AddressMode::None => {
-                assert!(target.is_none() && source.is_none());
-            }
-            _ => {
-                assert!(target.is_some() && source.is_some());
-            }
+        if data.len() >= MAX_DATA_LENGTH {
+            return Err(Error::MessageTooLong {
+                length: data.len(),
+                max: MAX_DATA_LENGTH - 1,
+            });
         }
-        Self {
+        let addressed = target.is_some() && source.is_some();
+        let unaddressed = target.is_none() && source.is_none();
+        if !(mode == AddressMode::None && unaddressed || mode != AddressMode::None && addressed) {
+            return Err(Error::UnexpectedValue);
+        }
+        Ok(Self {
             mode,
             target,
             source,
             service: Service::Query(service),
             data,
-        }
+            frame: None,
+        })
     }
 
-    pub fn to_bytes(mut self) -> Vec<u8> {
+    pub fn to_bytes(mut self) -> Result<Vec<u8>, Error> {
         let mut bytes = Vec::new();
 
         // Include service id in length
         let length = 1 + self.data.len();
 
+        if length > MAX_DATA_LENGTH {
+            return Err(Error::MessageTooLong {
+                length: self.data.len(),
+                max: MAX_DATA_LENGTH - 1,
+            });
+        }
+
         let length_byte;
 
         if length <= SHORT_DATA_LENGTH {
             bytes.push(self.mode as u8 + length as u8);
             length_byte = None;
         } else {
+            // a length of 0 in the format byte means a length byte follows
+            bytes.push(self.mode as u8);
             length_byte = Some(length as u8);
         }
 
         if self.mode != AddressMode::None {
-            bytes.push(self.target.unwrap());
-            bytes.push(self.source.unwrap());
+            let (Some(target), Some(source)) = (self.target, self.source) else {
+                return Err(Error::UnexpectedValue);
+            };
+            bytes.push(target);
+            bytes.push(source);
         }
 
         if let Some(l) = length_byte {
@@ -103,7 +221,7 @@ impl RawMessage {
 
         bytes.push(crc.0);
 
-        bytes
+        Ok(bytes)
     }
 
     pub fn read_from_bytes<R: Read>(source: &mut R) -> Result<Self, Error> {
@@ -121,6 +239,7 @@ impl RawMessage {
         let mut buf = [0; MAX_DATA_LENGTH + 5];
 
         source.read_exact(&mut buf[0..1])?;
+        let first_byte_at = Instant::now();
 
         let format = buf[0];
 
@@ -178,6 +297,7 @@ impl RawMessage {
         };
 
         source.read_exact(&mut buf[0..1])?;
+        let completed_at = Instant::now();
 
         let crc_calc: Wrapping<u8> = (&[format])
             .iter()
@@ -193,15 +313,87 @@ impl RawMessage {
             .map(|x| Wrapping(*x))
             .sum();
         if buf[0] != crc_calc.0 {
-            return Err(Error::InvalidChecksum);
+            return Err(Error::InvalidChecksum {
+                received: buf[0],
+                computed: crc_calc.0,
+            });
         }
 
+        let header_len =
+            1 + if mode == AddressMode::None { 0 } else { 2 } + hlength.is_none() as u8;
+
         Ok(Self {
             mode,
             target: target_addr,
             source: source_addr,
             service,
+            frame: Some(FrameInfo {
+                checksum: buf[0],
+                had_length_byte: hlength.is_none(),
+                header_len,
+                // header, service id, data and checksum
+                total_len: header_len as usize + 1 + data.len() + 1,
+                first_byte_at,
+                completed_at,
+            }),
             data,
         })
     }
 }
+
+impl std::fmt::Display for RawMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.service {
+            Service::Query(s) => write!(f, "{s:?}")?,
+            Service::Response(s) => write!(f, "{s:?}")?,
+        }
+        if let (Some(target), Some(source)) = (self.target, self.source) {
+            write!(f, " 0x{source:02x} -> 0x{target:02x}")?;
+        }
+        writeln!(f)?;
+        crate::util::hexdump(0, &self.data, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_query_is_rejected() {
+        assert!(matches!(
+            RawMessage::new_query(ServiceId::WriteMemoryByAddress, vec![0; MAX_DATA_LENGTH]),
+            Err(Error::MessageTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn inconsistent_addressing_is_rejected() {
+        for (mode, target, source) in [
+            (AddressMode::None, Some(0x01), Some(0xF1)),
+            (AddressMode::Physical, None, None),
+            (AddressMode::Physical, Some(0x01), None),
+        ] {
+            assert!(matches!(
+                RawMessage::new_query_addressed(
+                    mode,
+                    target,
+                    source,
+                    ServiceId::TesterPresent,
+                    vec![]
+                ),
+                Err(Error::UnexpectedValue)
+            ));
+        }
+        let mut message = RawMessage::new_query(ServiceId::TesterPresent, vec![]).unwrap();
+        message.mode = AddressMode::Physical;
+        assert!(matches!(message.to_bytes(), Err(Error::UnexpectedValue)));
+    }
+
+    #[test]
+    fn every_format_byte_decodes() {
+        for byte in 0..=u8::MAX {
+            decode_format(byte);
+        }
+    }
+}
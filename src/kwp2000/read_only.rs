@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use crate::Error;
+
+#[cfg(feature = "diag")]
+use super::identification::{EcuIdentification, ImmobilizerId, Vin};
+use super::{
+    client::{Client, RangeRead},
+    constants::CommonIdentifier,
+    dtc::Dtc,
+    policy::DangerPolicy,
+    timing::TimingReport,
+    warning::Warning,
+};
+
+/// Wrapper around a `Client` that only exposes operations that don't modify
+/// the ECU's memory. The wrapped client (and so its interface) is not
+/// reachable, so no other message can be sent through it.
+///
+/// Writes don't exist on it:
+///
+/// ```compile_fail
+/// # use ecu_flasher::kwp2000::read_only::ReadOnlyClient;
+/// fn write(client: &mut ReadOnlyClient) {
+///     client.write_memory_by_address(0x380000, &[0x00]);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ReadOnlyClient {
+    client: Client,
+}
+
+impl Client {
+    /// Restricts this client to reading. The danger policy is also set to
+    /// `DangerPolicy::DenyAll`.
+    pub fn into_read_only(mut self) -> ReadOnlyClient {
+        self.danger_policy = DangerPolicy::DenyAll;
+        ReadOnlyClient { client: self }
+    }
+}
+
+impl ReadOnlyClient {
+    pub fn diagnostic_mode(&mut self) -> Result<(), Error> {
+        self.client.diagnostic_mode()
+    }
+    pub fn dd_read_address(&mut self, address: u32, length: u8) -> Result<Vec<u8>, Error> {
+        self.client.dd_read_address(address, length)
+    }
+    pub fn dd_read_range(
+        &mut self,
+        address: u32,
+        size: u32,
+        chunk_size: u8,
+    ) -> Result<RangeRead, Error> {
+        self.client.dd_read_range(address, size, chunk_size)
+    }
+    pub fn read_data<W: std::io::Write>(
+        &mut self,
+        address: u32,
+        size: u32,
+        destination: &mut W,
+    ) -> Result<usize, Error> {
+        self.client.read_data(address, size, destination)
+    }
+    pub fn read_memory_by_address(&mut self, address: u32, size: u8) -> Result<Vec<u8>, Error> {
+        self.client.read_memory_by_address(address, size)
+    }
+    pub fn read_ecu_identification(&mut self, option: u8) -> Result<Vec<u8>, Error> {
+        self.client.read_ecu_identification(option)
+    }
+    #[cfg(feature = "diag")]
+    pub fn read_vag_identification(&mut self) -> Result<EcuIdentification, Error> {
+        self.client.read_vag_identification()
+    }
+    #[cfg(feature = "diag")]
+    pub fn read_vin_kwp(&mut self) -> Result<Vin, Error> {
+        self.client.read_vin_kwp()
+    }
+    #[cfg(feature = "diag")]
+    pub fn read_immobilizer_id(&mut self) -> Result<ImmobilizerId, Error> {
+        self.client.read_immobilizer_id()
+    }
+    pub fn read_common_identifier(&mut self, id: CommonIdentifier) -> Result<Vec<u8>, Error> {
+        self.client.read_common_identifier(id)
+    }
+    pub fn read_common_identifiers(
+        &mut self,
+        ids: &[(CommonIdentifier, usize)],
+    ) -> Result<BTreeMap<CommonIdentifier, Vec<u8>>, Error> {
+        self.client.read_common_identifiers(ids)
+    }
+    pub fn read_dtcs(&mut self, group: u16) -> Result<Vec<Dtc>, Error> {
+        self.client.read_dtcs(group)
+    }
+    pub fn read_all_dtcs(&mut self) -> Result<Vec<Dtc>, Error> {
+        self.client.read_all_dtcs()
+    }
+    pub fn read_dtcs_by_status(&mut self, mask: u8, group: u16) -> Result<Vec<Dtc>, Error> {
+        self.client.read_dtcs_by_status(mask, group)
+    }
+    pub fn read_dtc_status(&mut self, code: u16) -> Result<Dtc, Error> {
+        self.client.read_dtc_status(code)
+    }
+    pub fn read_freeze_frame(
+        &mut self,
+        frame_number: u8,
+        mode: u8,
+        identifier: u16,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.client
+            .read_freeze_frame(frame_number, mode, identifier)
+    }
+    pub fn read_dtc_freeze_frame(&mut self, dtc: &Dtc) -> Result<Option<Vec<u8>>, Error> {
+        self.client.read_dtc_freeze_frame(dtc)
+    }
+    pub fn timing_report(&mut self) -> Result<TimingReport, Error> {
+        self.client.timing_report()
+    }
+    pub fn tester_present(&mut self) -> Result<(), Error> {
+        self.client.tester_present()
+    }
+    pub fn warnings(&self) -> &[Warning] {
+        self.client.warnings()
+    }
+    pub fn disconnect(self) -> Result<(), Error> {
+        self.client.disconnect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::kwp2000::mock;
+
+    /// Read-only client over `ecu`, with the service id of every request
+    /// it sends.
+    fn recording(
+        mut ecu: impl FnMut(&[u8]) -> Vec<Result<Vec<u8>, Error>> + Send + 'static,
+    ) -> (ReadOnlyClient, Arc<Mutex<Vec<u8>>>) {
+        let services = Arc::new(Mutex::new(Vec::new()));
+        let sent = services.clone();
+        let client = mock::client(move |request| {
+            sent.lock().unwrap().push(request[0]);
+            ecu(request)
+        });
+        (client.into_read_only(), services)
+    }
+
+    #[test]
+    fn reads_are_forwarded() {
+        let (mut client, services) = recording(|request| match request {
+            [0x23, 0x38, 0x00, 0x00, 0x02] => vec![Ok(vec![0x63, 0xAA, 0xBB])],
+            [0x1A, 0x9B] => vec![Ok(vec![0x5A, 0x9B, 0x01, 0x02])],
+            [0x22, 0x12, 0x34] => vec![Ok(vec![0x62, 0x12, 0x34, 0xCC])],
+            [0x13, ..] => vec![Ok(vec![0x53, 0x01, 0x12, 0x34, 0xE0])],
+            [0x18, ..] => vec![Ok(vec![0x58, 0x01, 0x12, 0x34, 0xE0])],
+            [0x17, 0x12, 0x34] => vec![Ok(vec![0x57, 0x01, 0x12, 0x34, 0x60])],
+            // RequestOutOfRange
+            [0x12, ..] => vec![Ok(vec![0x7F, 0x12, 0x31])],
+            _ => panic!("unexpected request {request:02X?}"),
+        });
+        assert_eq!(
+            client.read_memory_by_address(0x380000, 2).unwrap(),
+            [0xAA, 0xBB]
+        );
+        assert_eq!(client.read_ecu_identification(0x9B).unwrap(), [0x01, 0x02]);
+        assert_eq!(
+            client
+                .read_common_identifier(CommonIdentifier(0x1234))
+                .unwrap(),
+            [0xCC]
+        );
+        let dtc = Dtc {
+            code: 0x1234,
+            status: 0xE0,
+        };
+        assert_eq!(client.read_all_dtcs().unwrap(), [dtc]);
+        assert_eq!(client.read_dtcs_by_status(0xFF, 0xFF00).unwrap(), [dtc]);
+        assert_eq!(client.read_dtc_status(0x1234).unwrap().status, 0x60);
+        assert_eq!(client.read_freeze_frame(0, 0, 0x1234).unwrap(), None);
+        assert_eq!(
+            *services.lock().unwrap(),
+            [0x23, 0x1A, 0x22, 0x13, 0x18, 0x17, 0x12]
+        );
+    }
+
+    #[test]
+    fn danger_policy_is_deny_all() {
+        let (client, _) = recording(|request| panic!("unexpected request {request:02X?}"));
+        assert!(matches!(client.client.danger_policy, DangerPolicy::DenyAll));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{io::ErrorKind, time::Instant};
+
+use crate::Error;
+
+use super::{INIT_BAUD_RATE, client::Client, timing::TimingState, warning::Warning};
+
+/// Number of consecutive response timeouts after which the ECU is assumed
+/// to have dropped the session.
+const LOST_AFTER_TIMEOUTS: u32 = 2;
+
+/// Whether `error` is a read that timed out.
+pub(crate) fn is_timeout(error: &Error) -> bool {
+    match error {
+        Error::Io(error) => error.kind() == ErrorKind::TimedOut,
+        #[cfg(feature = "transport-serialport")]
+        Error::SerialPort(error) => error.kind() == serialport::ErrorKind::Io(ErrorKind::TimedOut),
+        _ => false,
+    }
+}
+
+/// Whether `error` means bytes were received that aren't a valid response.
+fn is_garbage(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::InvalidChecksum { .. }
+            | Error::InvalidService
+            | Error::InvalidServiceError
+            | Error::NotEnoughData
+            | Error::UnexpectedValue
+    )
+}
+
+/// Failures reading responses since the last valid one, used to tell an ECU
+/// that reset itself from one that is just slow.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LinkHealth {
+    timeouts: u32,
+    garbage_after_timeout: bool,
+}
+
+impl LinkHealth {
+    pub(crate) fn record_failure(&mut self, error: &Error) {
+        if is_timeout(error) {
+            self.timeouts += 1;
+        } else if is_garbage(error) && self.timeouts > 0 {
+            // a rebooting ECU drives the line while starting up
+            self.garbage_after_timeout = true;
+        }
+    }
+
+    fn lost(&self) -> bool {
+        self.timeouts >= LOST_AFTER_TIMEOUTS || self.garbage_after_timeout
+    }
+}
+
+impl Client {
+    /// Whether the failures reading the last responses look like the ECU
+    /// reset itself (watchdog, undervoltage) and dropped the session:
+    /// repeated timeouts, or a timeout followed by unparseable bytes.
+    pub fn session_lost(&self) -> bool {
+        self.link_health.lost()
+    }
+    /// Initializes the ECU again at the initialization baud rate after it
+    /// dropped the session, resetting the session state kept by the client.
+    /// The ECU is back in its default session afterwards, security access
+    /// and timing changes have to be redone. If initializing fails the
+    /// interface is closed and opened again (`Interface::reset_hard`) and
+    /// initializing retried once, interfaces that can't return the error.
+    pub fn recover_session(&mut self) -> Result<(), Error> {
+        let start = Instant::now();
+        self.return_to_init_baud()?;
+        let timing = self.profile.init_timing;
+        if let Err(e) = self
+            .interface
+            .reinitialize(self.profile.init_address, &timing)
+        {
+            match self.interface.reset_hard() {
+                Err(Error::NotImplemented) => return Err(e),
+                result => result?,
+            }
+            self.stats.hard_resets += 1;
+            self.warn(Warning::InterfaceReset {
+                error: e.to_string(),
+            });
+            self.interface
+                .reinitialize(self.profile.init_address, &timing)?;
+        }
+        self.baud_rate = INIT_BAUD_RATE;
+        self.timing = TimingState::default();
+        self.reset_session_state();
+        self.stats.session_recoveries += 1;
+        self.warn(Warning::SessionInterrupted {
+            recovery: start.elapsed(),
+        });
+        Ok(())
+    }
+    /// Switches the interface back to the initialization baud rate after
+    /// the session ended at another rate. With `wait_for_baud_fallback` set
+    /// this also waits out the profile's `baud_fallback_window`, until then
+    /// the ECU keeps listening at the old rate and ignores an init.
+    pub(crate) fn return_to_init_baud(&mut self) -> Result<(), Error> {
+        if self.baud_rate == INIT_BAUD_RATE {
+            return Ok(());
+        }
+        self.interface.switch_baud(INIT_BAUD_RATE)?;
+        self.baud_rate = INIT_BAUD_RATE;
+        if self.wait_for_baud_fallback {
+            std::thread::sleep(self.profile.baud_fallback_window);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::{
+        constants::{ServiceError, ServiceId},
+        mock::{self, Fault},
+        response::{ProcessError, Response},
+    };
+
+    /// ECU answering memory reads with the low byte of each address.
+    fn memory_ecu(request: &[u8]) -> Vec<Result<Vec<u8>, Error>> {
+        match request {
+            [0x23, _, _, c, size] => {
+                let mut response = vec![0x63];
+                response.extend((0..*size).map(|i| c.wrapping_add(i)));
+                vec![Ok(response)]
+            }
+            _ => panic!("unexpected request {request:02X?}"),
+        }
+    }
+
+    #[test]
+    fn faults_give_typed_errors() {
+        let (mut client, faults) = mock::faulty_client(memory_ecu);
+        faults.inject(0x23, Fault::Negative(0x22));
+        assert!(matches!(
+            client.read_memory_by_address(0x380010, 4),
+            Err(Error::UnexpectedResponse(Response::Error(ProcessError {
+                error: ServiceError::ConditionsNotCorrect,
+                service: ServiceId::ReadMemoryByAddress,
+                ..
+            })))
+        ));
+        faults.inject(0x23, Fault::DropResponse);
+        assert!(is_timeout(
+            &client.read_memory_by_address(0x380010, 4).unwrap_err()
+        ));
+        faults.inject(0x23, Fault::CorruptChecksum);
+        assert!(matches!(
+            client.read_memory_by_address(0x380010, 4),
+            Err(Error::InvalidChecksum { .. })
+        ));
+        assert_eq!(client.stats.invalid_checksums, 1);
+        faults.inject(0x23, Fault::Truncate);
+        assert!(matches!(
+            client.read_memory_by_address(0x380010, 4),
+            Err(Error::UnexpectedResponse(Response::MemoryAddressRead(_, data))) if data.len() == 3
+        ));
+        assert_eq!(faults.pending(), 0);
+        assert_eq!(
+            client.read_memory_by_address(0x380010, 4).unwrap(),
+            [0x10, 0x11, 0x12, 0x13]
+        );
+    }
+
+    #[test]
+    fn range_read_gets_through_faults() {
+        let (mut client, faults) = mock::faulty_client(mock::dd_ecu(0..0));
+        // collects the spurious frame instead of taking it as the answer
+        client.response_window = 2;
+        for fault in [
+            // first chunk: definition lost, the read after defining it once
+            // more misses its last byte
+            Fault::Negative(0x31),
+            Fault::Truncate,
+            Fault::Unsolicited(vec![0x7E]),
+            // third chunk: still refused after defining it once more
+            Fault::Negative(0x31),
+            Fault::Negative(0x31),
+        ] {
+            faults.inject(0x21, fault);
+        }
+        let read = client.dd_read_range(0, 0x80, 0x10).unwrap();
+        assert_eq!(faults.pending(), 0);
+        assert_eq!(read.unreadable, [(0x0F, 1), (0x20, 0x10)]);
+        let expected: Vec<u8> = (0..0x80u8)
+            .map(|a| {
+                if a == 0x0F || (0x20..0x30).contains(&a) {
+                    0
+                } else {
+                    a
+                }
+            })
+            .collect();
+        assert_eq!(read.data, expected);
+        assert_eq!(client.stats.dd_redefinitions, 2);
+        assert!(matches!(
+            client.take_unsolicited(),
+            Some(Response::TesterPresent)
+        ));
+    }
+}
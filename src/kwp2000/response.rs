@@ -1,14 +1,228 @@
 use super::constants::*;
+use super::data_rates::DataRates;
+use super::dtc::Dtc;
 use super::raw_message::RawMessage;
 use crate::Error;
 use crate::kwp2000::baud_rate_from_byte;
 
-pub fn from_raw(mut message: RawMessage) -> Result<Response, Error> {
+/// Returns byte `index` of `data`, `Error::NotEnoughData` if it is too short.
+fn byte(data: &[u8], index: usize) -> Result<u8, Error> {
+    data.get(index).copied().ok_or(Error::NotEnoughData)
+}
+
+/// Three byte big endian address at the start of `data`.
+fn address(data: &[u8]) -> Result<u32, Error> {
+    Ok(u32::from_be_bytes([
+        0,
+        byte(data, 0)?,
+        byte(data, 1)?,
+        byte(data, 2)?,
+    ]))
+}
+
+/// Maximum block length of an upload or download confirmation, one byte or
+/// two big endian bytes for blocks longer than 255.
+fn block_length(data: &[u8]) -> Result<u16, Error> {
+    match data {
+        [high, low, ..] => Ok(u16::from_be_bytes([*high, *low])),
+        _ => Ok(byte(data, 0)?.into()),
+    }
+}
+
+/// Fewest data bytes after the service id a `service` response can have,
+/// checked before it is parsed. Zero for responses that may be empty or
+/// that aren't parsed.
+pub const fn min_payload(service: ServiceResponse) -> usize {
+    match service {
+        // service, error code
+        ServiceResponse::NegativeResponse => 2,
+        // common identifier, then the control parameter and state
+        ServiceResponse::InputOutputControlByCommonIdentifier => 2,
+        // address
+        ServiceResponse::WriteMemoryByAddress
+        | ServiceResponse::StartRoutineByAddress
+        | ServiceResponse::StopRoutineByAddress
+        | ServiceResponse::ResquestRoutineResultsByAddress => 3,
+        // common identifier
+        ServiceResponse::ReadDataByCommonIdentifier
+        | ServiceResponse::WriteDataByCommonIdentifier => 2,
+        // mode
+        ServiceResponse::StartDiagnosticSession
+        // level, followed by the seed or a status
+        | ServiceResponse::SecurityAccess
+        // number of codes, zero if the ECU doesn't know the code
+        | ServiceResponse::ReadStatusOfDTC
+        // local identifier, option or routine
+        | ServiceResponse::ReadDataByLocalIdentifier
+        | ServiceResponse::WriteDataByLocalIdentifier
+        | ServiceResponse::DynamicallyDefineLocalIdentifier
+        | ServiceResponse::InputOutputControlByLocalIdentifier
+        | ServiceResponse::StartRoutineByLocalIdentifier
+        | ServiceResponse::StopRoutineByLocalIdentifier
+        | ServiceResponse::RequestRoutineResultsByLocalIdentifier
+        | ServiceResponse::ReadECUIdentification
+        // number of codes or frame number
+        | ServiceResponse::ReadDiagnosticTroubleCodes
+        | ServiceResponse::ReadDTCByStatus
+        | ServiceResponse::ReadFreezeFrameData
+        // maximum block length
+        | ServiceResponse::RequestUpload
+        | ServiceResponse::RequestDownload
+        // data, the address echo is optional
+        | ServiceResponse::ReadMemoryByAddress => 1,
+        // empty for a set request on some ECUs
+        ServiceResponse::AccessTimingParameter
+        // empty after a set, the rates when read
+        | ServiceResponse::SetDataRates
+        // empty when the ECU is ready for more data
+        | ServiceResponse::TransferData
+        | ServiceResponse::TesterPresent
+        | ServiceResponse::StopCommunication
+        | ServiceResponse::ECUReset
+        | ServiceResponse::ClearDiagnosticInformation
+        | ServiceResponse::StopDiagnosticSession
+        | ServiceResponse::RequestTransferExit
+        | ServiceResponse::RequestCurrentPowertrainDiagnosticData
+        | ServiceResponse::RequestPowertrainFreezeFrameData
+        | ServiceResponse::RequestEmissionRelatedDiagnosticInformation
+        | ServiceResponse::ClearResetEmissionRelatedDiagnosticInformation
+        | ServiceResponse::RequestOxygenSensorMonitoringTestResults
+        | ServiceResponse::RequestOnBoardMonitoringTestResultsForNoneContMonitoringSystem
+        | ServiceResponse::RequestOnBoardMonitoringTestResultsForContMonitoringSystem
+        | ServiceResponse::RequestControlOfOnBoardSystem
+        | ServiceResponse::RequestVehicleInformation
+        | ServiceResponse::Reserved
+        | ServiceResponse::ESCCode
+        | ServiceResponse::StartCommunication => 0,
+    }
+}
+
+/// Request parameter a response repeats, compared by the client when
+/// `Client::strict_echo` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Echo {
+    pub field: &'static str,
+    pub value: u32,
+}
+
+impl Echo {
+    /// Parameter the response to a `service` request with `data` repeats,
+    /// `None` for services whose responses repeat nothing.
+    pub fn of_request(service: ServiceId, data: &[u8]) -> Option<Self> {
+        let (field, length) = match service {
+            ServiceId::StartDiagnosticSession => ("diagnostic mode", 1),
+            ServiceId::SecurityAccess => ("security level", 1),
+            ServiceId::AccessTimingParameter => ("timing kind", 1),
+            ServiceId::StartRoutineByLocalIdentifier
+            | ServiceId::StopRoutineByLocalIdentifier
+            | ServiceId::RequestRoutineResultsByLocalIdentifier => ("routine", 1),
+            ServiceId::ReadDataByLocalIdentifier
+            | ServiceId::WriteDataByLocalIdentifier
+            | ServiceId::DynamicallyDefineLocalIdentifier
+            | ServiceId::InputOutputControlByLocalIdentifier => ("local identifier", 1),
+            ServiceId::ReadDataByCommonIdentifier
+            | ServiceId::WriteDataByCommonIdentifier
+            | ServiceId::InputOutputControlByCommonIdentifier => ("common identifier", 2),
+            ServiceId::ReadECUIdentification => ("identification option", 1),
+            ServiceId::ReadFreezeFrameData => ("frame number", 1),
+            ServiceId::ReadStatusOfDTC => ("code", 2),
+            ServiceId::StartRoutineByAddress
+            | ServiceId::StopRoutineByAddress
+            | ServiceId::ResquestRoutineResultsByAddress
+            | ServiceId::ReadMemoryByAddress
+            | ServiceId::WriteMemoryByAddress => ("address", 3),
+            _ => return None,
+        };
+        let value = data
+            .get(..length)?
+            .iter()
+            .fold(0, |value, b| value << 8 | *b as u32);
+        Some(Self { field, value })
+    }
+
+    /// Request parameter `response` repeats, see `of_request`.
+    pub fn of_response(response: &Response) -> Option<Self> {
+        let (field, value) = match response {
+            Response::StartedDiagnosticMode(mode, _) => ("diagnostic mode", *mode as u32),
+            Response::SecurityAccessSeed(level, _) | Response::SecurityAccessGranted(level) => {
+                ("security level", *level as u32)
+            }
+            Response::TimingParameters { kind, .. } => ("timing kind", *kind as u32),
+            Response::TimingRestoredToDefault => ("timing kind", TimingParameter::Defaults as u32),
+            Response::TimingSet => ("timing kind", TimingParameter::Set as u32),
+            Response::RoutineStarted(r, _)
+            | Response::RoutineStopped(r, _)
+            | Response::RoutineResults(r, _) => ("routine", r.0 as u32),
+            Response::LocalIdentifierRead(id, _)
+            | Response::LocalIdentifierWritten(id)
+            | Response::LocalIdentifierDefined(id)
+            | Response::IoControlled(id, _) => ("local identifier", id.0 as u32),
+            Response::CommonIdentifierRead(id, _)
+            | Response::CommonIdentifierWritten(id)
+            | Response::IoControlledCommon(id, _) => ("common identifier", id.0 as u32),
+            Response::EcuIdentification(option, _) => ("identification option", *option as u32),
+            Response::FreezeFrame(frame, _) => ("frame number", *frame as u32),
+            Response::DtcStatus { code, .. } => ("code", *code as u32),
+            Response::RoutineByAddressStarted(address, _)
+            | Response::RoutineByAddressStopped(address, _)
+            | Response::RoutineByAddressResults(address, _)
+            | Response::MemoryAddressRead(Some(address), _)
+            | Response::MemoryAddressWritten(address) => ("address", *address),
+            _ => return None,
+        };
+        Some(Self { field, value })
+    }
+}
+
+/// Request a response answers, lets responses that leave out the bytes
+/// echoing it be interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext {
+    pub service: ServiceId,
+    /// First data byte of the request
+    pub sub_function: Option<u8>,
+    /// Number of bytes a `ReadMemoryByAddress` request asked for, tells
+    /// whether the response echoes the address.
+    pub memory_size: Option<u8>,
+    /// Parameter the response should repeat
+    pub echo: Option<Echo>,
+}
+
+pub fn from_raw(message: RawMessage) -> Result<Response, Error> {
+    from_raw_for(message, None)
+}
+
+/// Parses `message` as the response to `request`.
+pub fn from_raw_for(
+    mut message: RawMessage,
+    request: Option<RequestContext>,
+) -> Result<Response, Error> {
+    if let Service::Response(service) = message.service {
+        let need = min_payload(service);
+        if message.data.len() < need {
+            return Err(Error::ResponseTooShort {
+                service,
+                got: message.data.len(),
+                need,
+            });
+        }
+    }
     Ok(match &message.service {
         Service::Query(_) => Response::Echo(message),
         Service::Response(service_response) => match service_response {
+            // some ECUs answer a set request without the parameter kind byte
+            ServiceResponse::AccessTimingParameter
+                if message.data.is_empty()
+                    && request.is_some_and(|r| {
+                        r.service == ServiceId::AccessTimingParameter
+                            && r.sub_function == Some(TimingParameter::Set as u8)
+                    }) =>
+            {
+                Response::TimingSet
+            }
             ServiceResponse::AccessTimingParameter => {
-                let kind = TimingParameter::from_repr(message.data[0]).unwrap();
+                let kind = TimingParameter::from_repr(byte(&message.data, 0)?)
+                    .ok_or(Error::UnexpectedValue)?;
                 if kind == TimingParameter::Defaults {
                     Response::TimingRestoredToDefault
                 } else if kind == TimingParameter::Set {
@@ -16,20 +230,39 @@ pub fn from_raw(mut message: RawMessage) -> Result<Response, Error> {
                 } else {
                     Response::TimingParameters {
                         kind,
-                        p2min: message.data[1],
-                        p2max: message.data[2],
-                        p3min: message.data[3],
-                        p3max: message.data[4],
-                        p4min: message.data[5],
+                        p2min: byte(&message.data, 1)?,
+                        p2max: byte(&message.data, 2)?,
+                        p3min: byte(&message.data, 3)?,
+                        p3max: byte(&message.data, 4)?,
+                        p4min: byte(&message.data, 5)?,
                     }
                 }
             }
+            // the address follows the data, but many ECUs leave it out.
+            // Without the requested size it is assumed to be there.
             ServiceResponse::ReadMemoryByAddress => {
-                let mut bytes = [0u8; 4];
-                for i in 3..0 {
-                    bytes[i] = message.data.pop().ok_or(Error::NotEnoughData)?;
-                }
-                Response::MemoryAddressRead(u32::from_be_bytes(bytes), message.data)
+                let echoed = match request.and_then(|r| r.memory_size) {
+                    Some(size) => message.data.len() == size as usize + 3,
+                    None => message.data.len() >= 3,
+                };
+                let echo = if echoed {
+                    let at = message.data.len() - 3;
+                    let echo = address(&message.data[at..])?;
+                    message.data.truncate(at);
+                    Some(echo)
+                } else {
+                    None
+                };
+                Response::MemoryAddressRead(echo, message.data)
+            }
+            ServiceResponse::SetDataRates if message.data.is_empty() => Response::DataRatesSet,
+            ServiceResponse::SetDataRates => Response::DataRates(DataRates {
+                slow: byte(&message.data, 0)?,
+                medium: byte(&message.data, 1)?,
+                fast: byte(&message.data, 2)?,
+            }),
+            ServiceResponse::WriteMemoryByAddress => {
+                Response::MemoryAddressWritten(address(&message.data)?)
             }
             ServiceResponse::NegativeResponse => {
                 let error = ProcessError::from_bytes(&message.data)?;
@@ -40,37 +273,129 @@ pub fn from_raw(mut message: RawMessage) -> Result<Response, Error> {
                 }
             }
             ServiceResponse::StartDiagnosticSession => Response::StartedDiagnosticMode(
-                DiagnosticMode::from_repr(message.data[0]).ok_or(Error::UnexpectedValue)?,
+                DiagnosticMode::from_repr(byte(&message.data, 0)?).ok_or(Error::UnexpectedValue)?,
                 message.data.get(1).map(|x| baud_rate_from_byte(*x)),
             ),
-            ServiceResponse::ReadDataByLocalIdentifier => {
-                Response::LocalIdentifierRead(message.data[0], message.data.split_off(1))
+            ServiceResponse::ReadDataByLocalIdentifier => Response::LocalIdentifierRead(
+                LocalIdentifier(byte(&message.data, 0)?),
+                message.data.split_off(1),
+            ),
+            ServiceResponse::ReadDataByCommonIdentifier => {
+                let id = u16::from_be_bytes([byte(&message.data, 0)?, byte(&message.data, 1)?]);
+                Response::CommonIdentifierRead(CommonIdentifier(id), message.data.split_off(2))
+            }
+            ServiceResponse::WriteDataByCommonIdentifier => {
+                Response::CommonIdentifierWritten(CommonIdentifier(u16::from_be_bytes([
+                    byte(&message.data, 0)?,
+                    byte(&message.data, 1)?,
+                ])))
             }
             ServiceResponse::TesterPresent => Response::TesterPresent,
+            ServiceResponse::StartRoutineByLocalIdentifier => Response::RoutineStarted(
+                RoutineIdentifier(byte(&message.data, 0)?),
+                message.data.split_off(1),
+            ),
+            ServiceResponse::StopRoutineByLocalIdentifier => Response::RoutineStopped(
+                RoutineIdentifier(byte(&message.data, 0)?),
+                message.data.split_off(1),
+            ),
+            ServiceResponse::RequestRoutineResultsByLocalIdentifier => Response::RoutineResults(
+                RoutineIdentifier(byte(&message.data, 0)?),
+                message.data.split_off(1),
+            ),
+            // identifier, the control parameter, then the state
+            ServiceResponse::InputOutputControlByLocalIdentifier => Response::IoControlled(
+                LocalIdentifier(byte(&message.data, 0)?),
+                message.data.split_off(2.min(message.data.len())),
+            ),
+            // identifier, the control parameter, then the state
+            ServiceResponse::InputOutputControlByCommonIdentifier => Response::IoControlledCommon(
+                CommonIdentifier(u16::from_be_bytes([
+                    byte(&message.data, 0)?,
+                    byte(&message.data, 1)?,
+                ])),
+                message.data.split_off(3.min(message.data.len())),
+            ),
+            ServiceResponse::StartRoutineByAddress => Response::RoutineByAddressStarted(
+                address(&message.data)?,
+                message.data.split_off(3),
+            ),
+            ServiceResponse::StopRoutineByAddress => Response::RoutineByAddressStopped(
+                address(&message.data)?,
+                message.data.split_off(3),
+            ),
+            ServiceResponse::ResquestRoutineResultsByAddress => Response::RoutineByAddressResults(
+                address(&message.data)?,
+                message.data.split_off(3),
+            ),
+            ServiceResponse::ReadECUIdentification => {
+                Response::EcuIdentification(byte(&message.data, 0)?, message.data.split_off(1))
+            }
             ServiceResponse::SecurityAccess => {
-                if message.data.len() == 2
+                let level = SecurityLevel::from_repr(byte(&message.data, 0)?)
+                    .ok_or(Error::UnexpectedValue)?;
+                // the level alone, or followed by a status or a zero seed
+                if message.data.len() <= 2
                     || message.data[1..].iter().max().map_or(false, |m| m == &0)
                 {
-                    Response::SecurityAccessGranted(
-                        SecurityLevel::from_repr(message.data[0]).ok_or(Error::UnexpectedValue)?,
-                    )
+                    Response::SecurityAccessGranted(level)
                 } else {
-                    let seed_level =
-                        SecurityLevel::from_repr(message.data[0]).ok_or(Error::UnexpectedValue)?;
-
-                    Response::SecurityAccessSeed(seed_level, message.data.split_off(1))
+                    Response::SecurityAccessSeed(level, message.data.split_off(1))
                 }
             }
             ServiceResponse::DynamicallyDefineLocalIdentifier => {
-                Response::LocalIdentifierDefined(message.data[0])
+                Response::LocalIdentifierDefined(LocalIdentifier(byte(&message.data, 0)?))
             }
             ServiceResponse::WriteDataByLocalIdentifier => {
-                Response::LocalIdentifierWritten(message.data[0])
+                Response::LocalIdentifierWritten(LocalIdentifier(byte(&message.data, 0)?))
             }
             ServiceResponse::StopCommunication => Response::CommunicationStopped,
+            ServiceResponse::ECUReset => Response::EcuReset,
+            ServiceResponse::ReadDiagnosticTroubleCodes => Response::DiagnosticTroubleCodes(
+                byte(&message.data, 0)?,
+                Dtc::parse_list(&message.data[1..])?,
+            ),
+            ServiceResponse::ClearDiagnosticInformation => Response::DiagnosticInformationCleared,
+            // the code is only in the request
+            ServiceResponse::ReadStatusOfDTC if message.data[0] == 0 => {
+                let code = request
+                    .and_then(|r| r.echo)
+                    .filter(|e| e.field == "code")
+                    .map_or(0, |e| e.value as u16);
+                return Err(Error::UnknownDtc(code));
+            }
+            // number of codes, then the code asked for
+            ServiceResponse::ReadStatusOfDTC => Response::DtcStatus {
+                code: u16::from_be_bytes([byte(&message.data, 1)?, byte(&message.data, 2)?]),
+                status: byte(&message.data, 3)?,
+            },
+            ServiceResponse::ReadDTCByStatus => Response::DtcsByStatus(
+                byte(&message.data, 0)?,
+                Dtc::parse_list(&message.data[1..])?,
+            ),
+            ServiceResponse::ReadFreezeFrameData => {
+                Response::FreezeFrame(byte(&message.data, 0)?, message.data.split_off(1))
+            }
+            ServiceResponse::RequestCurrentPowertrainDiagnosticData
+            | ServiceResponse::RequestPowertrainFreezeFrameData
+            | ServiceResponse::RequestEmissionRelatedDiagnosticInformation
+            | ServiceResponse::ClearResetEmissionRelatedDiagnosticInformation
+            | ServiceResponse::RequestOxygenSensorMonitoringTestResults
+            | ServiceResponse::RequestOnBoardMonitoringTestResultsForNoneContMonitoringSystem
+            | ServiceResponse::RequestOnBoardMonitoringTestResultsForContMonitoringSystem
+            | ServiceResponse::RequestControlOfOnBoardSystem
+            | ServiceResponse::RequestVehicleInformation => Response::ObdData(
+                service_response.request().ok_or(Error::InvalidService)?,
+                message.data,
+            ),
             ServiceResponse::StopDiagnosticSession => Response::DiagnosticSessionStopped,
-            ServiceResponse::RequestUpload => Response::UploadConfirmation(message.data[0]),
-            ServiceResponse::RequestDownload => Response::DownloadConfirmation(message.data[0]),
+            ServiceResponse::RequestUpload => {
+                Response::UploadConfirmation(block_length(&message.data)?)
+            }
+            ServiceResponse::RequestDownload => {
+                Response::DownloadConfirmation(block_length(&message.data)?)
+            }
+            ServiceResponse::RequestTransferExit => Response::TransferExited,
             ServiceResponse::TransferData => {
                 if message.data.is_empty() {
                     Response::ReadyForMoreData
@@ -78,39 +403,80 @@ pub fn from_raw(mut message: RawMessage) -> Result<Response, Error> {
                     Response::DataTransfer(message.data)
                 }
             }
-            _ => {
-                dbg!(message);
-                return Err(Error::NotImplemented);
-            }
+            // Listed explicitly instead of using a wildcard so that adding a
+            // service to `ServiceEnums` fails to compile until it is either
+            // parsed above or added here.
+            ServiceResponse::Reserved
+            | ServiceResponse::ESCCode
+            | ServiceResponse::StartCommunication => return Err(Error::NotImplemented),
         },
     })
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ProcessError {
     pub error: ServiceError,
     pub service: ServiceId,
+    /// Bytes after the service and error code, some ECUs put details there.
+    pub extra: Vec<u8>,
 }
 
 impl ProcessError {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
-        let service = ServiceId::from_repr(bytes[0]).ok_or(Error::InvalidService)?;
-        let error = ServiceError::from_repr(bytes[1]).ok_or(Error::InvalidServiceError)?;
-        Ok(Self { error, service })
+        let service = ServiceId::from_repr(byte(bytes, 0)?).ok_or(Error::InvalidService)?;
+        let error = ServiceError::from_repr(byte(bytes, 1)?).ok_or(Error::InvalidServiceError)?;
+        Ok(Self {
+            error,
+            service,
+            extra: bytes[2..].to_vec(),
+        })
+    }
+
+    /// Security access attempts left before the ECU locks it, for ECUs
+    /// that send them after an `InvalidKey` error (see
+    /// `EcuProfile::reports_remaining_attempts`).
+    pub fn remaining_attempts(&self) -> Option<u8> {
+        match self.error {
+            ServiceError::InvalidKey => self.extra.first().copied(),
+            _ => None,
+        }
     }
 }
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Response {
-    MemoryAddressRead(u32, Vec<u8>),
+    /// Address echoed by the ECU, if it did, and the data
+    MemoryAddressRead(Option<u32>, Vec<u8>),
+    MemoryAddressWritten(u32),
+    DataRates(DataRates),
+    DataRatesSet,
     DiagnosticSessionStopped,
     CommunicationStopped,
+    EcuReset,
+    /// Number of stored codes, the codes in this response. Long lists are
+    /// split over several responses.
+    DiagnosticTroubleCodes(u8, Vec<Dtc>),
+    DiagnosticInformationCleared,
+    /// Status of a single code, see `dtc::DtcState`
+    DtcStatus {
+        code: u16,
+        status: u8,
+    },
+    /// Same as `DiagnosticTroubleCodes`, for `ReadDtcByStatus`
+    DtcsByStatus(u8, Vec<Dtc>),
+    /// freeze frame number, record data
+    FreezeFrame(u8, Vec<u8>),
+    /// OBD service responded to, data after the service byte
+    ObdData(ServiceId, Vec<u8>),
     /// Query type messages from the server are all considered echoes
     Echo(RawMessage),
     Error(ProcessError),
-    LocalIdentifierDefined(u8),
-    LocalIdentifierRead(u8, Vec<u8>),
-    LocalIdentifierWritten(u8),
+    LocalIdentifierDefined(LocalIdentifier),
+    LocalIdentifierRead(LocalIdentifier, Vec<u8>),
+    LocalIdentifierWritten(LocalIdentifier),
+    CommonIdentifierRead(CommonIdentifier, Vec<u8>),
+    CommonIdentifierWritten(CommonIdentifier),
     /// If the returned SecurityKeyLevel is greater than 1, there are higher
     /// levels of access available.
     SecurityAccessGranted(SecurityLevel),
@@ -126,10 +492,11 @@ pub enum Response {
     /// Data response for request upload
     DataTransfer(Vec<u8>),
     ReadyForMoreData,
+    TransferExited,
     /// Maximum block length returned
-    UploadConfirmation(u8),
+    UploadConfirmation(u16),
     /// Maximum block length to send
-    DownloadConfirmation(u8),
+    DownloadConfirmation(u16),
     /// See the Message enum for details
     TimingParameters {
         kind: TimingParameter,
@@ -141,4 +508,306 @@ pub enum Response {
     },
     TimingRestoredToDefault,
     TimingSet,
+    /// identification option, data
+    EcuIdentification(u8, Vec<u8>),
+    /// routine, routine specific data
+    RoutineStarted(RoutineIdentifier, Vec<u8>),
+    /// routine, routine specific data
+    RoutineStopped(RoutineIdentifier, Vec<u8>),
+    /// routine, results
+    RoutineResults(RoutineIdentifier, Vec<u8>),
+    /// output identifier, state reported after the control parameter
+    IoControlled(LocalIdentifier, Vec<u8>),
+    /// Same as `IoControlled` for a common identifier
+    IoControlledCommon(CommonIdentifier, Vec<u8>),
+    /// routine address, results of the routine entry
+    RoutineByAddressStarted(u32, Vec<u8>),
+    /// routine address, routine specific data
+    RoutineByAddressStopped(u32, Vec<u8>),
+    /// routine address, results
+    RoutineByAddressResults(u32, Vec<u8>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+
+    /// Services `from_raw` doesn't parse yet.
+    const NOT_PARSED: [ServiceResponse; 3] = [
+        ServiceResponse::Reserved,
+        ServiceResponse::ESCCode,
+        ServiceResponse::StartCommunication,
+    ];
+
+    /// Smallest valid data of a `service` response, parsed without knowing
+    /// the request.
+    fn fixture(service: ServiceResponse) -> Vec<u8> {
+        match service {
+            // StartDiagnosticSession, GeneralReject
+            ServiceResponse::NegativeResponse => vec![0x10, 0x10],
+            ServiceResponse::AccessTimingParameter => vec![TimingParameter::Defaults as u8],
+            ServiceResponse::StartDiagnosticSession => vec![DiagnosticMode::Diagnostics as u8],
+            ServiceResponse::SecurityAccess => vec![SecurityLevel::Key1 as u8],
+            ServiceResponse::ReadStatusOfDTC => vec![0x01, 0x12, 0x34, 0x00],
+            ServiceResponse::ReadDataByCommonIdentifier
+            | ServiceResponse::WriteDataByCommonIdentifier
+            | ServiceResponse::InputOutputControlByCommonIdentifier => vec![0x12, 0x34],
+            ServiceResponse::WriteMemoryByAddress
+            | ServiceResponse::StartRoutineByAddress
+            | ServiceResponse::StopRoutineByAddress
+            | ServiceResponse::ResquestRoutineResultsByAddress => vec![0x38, 0x00, 0x00],
+            ServiceResponse::ReadDataByLocalIdentifier
+            | ServiceResponse::WriteDataByLocalIdentifier
+            | ServiceResponse::DynamicallyDefineLocalIdentifier
+            | ServiceResponse::InputOutputControlByLocalIdentifier
+            | ServiceResponse::StartRoutineByLocalIdentifier
+            | ServiceResponse::StopRoutineByLocalIdentifier
+            | ServiceResponse::RequestRoutineResultsByLocalIdentifier
+            | ServiceResponse::ReadECUIdentification
+            | ServiceResponse::ReadDiagnosticTroubleCodes
+            | ServiceResponse::ReadDTCByStatus
+            | ServiceResponse::ReadFreezeFrameData
+            | ServiceResponse::RequestUpload
+            | ServiceResponse::RequestDownload
+            | ServiceResponse::ReadMemoryByAddress => vec![0x01],
+            ServiceResponse::SetDataRates
+            | ServiceResponse::TransferData
+            | ServiceResponse::TesterPresent
+            | ServiceResponse::StopCommunication
+            | ServiceResponse::ECUReset
+            | ServiceResponse::ClearDiagnosticInformation
+            | ServiceResponse::StopDiagnosticSession
+            | ServiceResponse::RequestTransferExit
+            | ServiceResponse::RequestCurrentPowertrainDiagnosticData
+            | ServiceResponse::RequestPowertrainFreezeFrameData
+            | ServiceResponse::RequestEmissionRelatedDiagnosticInformation
+            | ServiceResponse::ClearResetEmissionRelatedDiagnosticInformation
+            | ServiceResponse::RequestOxygenSensorMonitoringTestResults
+            | ServiceResponse::RequestOnBoardMonitoringTestResultsForNoneContMonitoringSystem
+            | ServiceResponse::RequestOnBoardMonitoringTestResultsForContMonitoringSystem
+            | ServiceResponse::RequestControlOfOnBoardSystem
+            | ServiceResponse::RequestVehicleInformation
+            | ServiceResponse::Reserved
+            | ServiceResponse::ESCCode
+            | ServiceResponse::StartCommunication => vec![],
+        }
+    }
+
+    fn parse(service: ServiceResponse, data: &[u8]) -> Result<Response, Error> {
+        let mut frame = vec![service as u8];
+        frame.extend_from_slice(data);
+        from_raw(mock::frame(&frame))
+    }
+
+    fn services() -> impl Iterator<Item = ServiceResponse> {
+        (0..=u8::MAX).filter_map(ServiceResponse::from_repr)
+    }
+
+    #[test]
+    fn every_service_is_parsed() {
+        for service in services() {
+            match parse(service, &fixture(service)) {
+                Ok(Response::Echo(_)) => panic!("{service:?} parsed as an echo"),
+                Ok(_) => assert!(!NOT_PARSED.contains(&service), "{service:?} is parsed"),
+                Err(Error::NotImplemented) => {
+                    assert!(NOT_PARSED.contains(&service), "{service:?} is not parsed")
+                }
+                Err(e) => panic!("{service:?}: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_and_garbage_responses_are_errors_not_panics() {
+        for service in services() {
+            let valid = fixture(service);
+            for length in 0..valid.len() {
+                let _ = parse(service, &valid[..length]);
+            }
+            for length in 0..8 {
+                let _ = parse(service, &vec![0xFF; length]);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        assert!(matches!(
+            parse(ServiceResponse::AccessTimingParameter, &[0x09]),
+            Err(Error::UnexpectedValue)
+        ));
+        assert!(matches!(
+            parse(ServiceResponse::SecurityAccess, &[0x00, 0x12, 0x34]),
+            Err(Error::UnexpectedValue)
+        ));
+        assert!(matches!(
+            parse(ServiceResponse::StartDiagnosticSession, &[0x00]),
+            Err(Error::UnexpectedValue)
+        ));
+        assert!(matches!(
+            ProcessError::from_bytes(&[0xFF, 0x10]),
+            Err(Error::InvalidService)
+        ));
+        assert!(matches!(
+            ProcessError::from_bytes(&[0x10]),
+            Err(Error::NotEnoughData)
+        ));
+    }
+
+    /// Context the client keeps of a `service` request with `data`.
+    fn context(service: ServiceId, data: &[u8]) -> RequestContext {
+        RequestContext {
+            service,
+            sub_function: data.first().copied(),
+            memory_size: match service {
+                ServiceId::ReadMemoryByAddress => data.get(3).copied(),
+                _ => None,
+            },
+            echo: Echo::of_request(service, data),
+        }
+    }
+
+    /// Context of a `ReadMemoryByAddress` request for `size` bytes at
+    /// 0x380000.
+    fn memory_read(size: u8) -> RequestContext {
+        context(ServiceId::ReadMemoryByAddress, &[0x38, 0x00, 0x00, size])
+    }
+
+    fn captured(mut frame: &[u8]) -> RawMessage {
+        RawMessage::read_from_bytes(&mut frame).unwrap()
+    }
+
+    #[test]
+    fn memory_read_with_address_echo() {
+        // ECU 0x10 to tester 0xF1, 4 data bytes and the address
+        let frame = captured(&[
+            0x88, 0xF1, 0x10, 0x63, 0x12, 0x34, 0x56, 0x78, 0x38, 0x00, 0x00, 0x38,
+        ]);
+        match from_raw_for(frame, Some(memory_read(4))).unwrap() {
+            Response::MemoryAddressRead(address, data) => {
+                assert_eq!(address, Some(0x380000));
+                assert_eq!(data, [0x12, 0x34, 0x56, 0x78]);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn memory_read_without_address_echo() {
+        let frame = captured(&[0x85, 0xF1, 0x10, 0x63, 0x12, 0x34, 0x56, 0x78, 0xFD]);
+        match from_raw_for(frame, Some(memory_read(4))).unwrap() {
+            Response::MemoryAddressRead(address, data) => {
+                assert_eq!(address, None);
+                assert_eq!(data, [0x12, 0x34, 0x56, 0x78]);
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn responses_shorter_than_their_minimum_are_rejected() {
+        for service in services().filter(|s| !NOT_PARSED.contains(s)) {
+            let need = min_payload(service);
+            let mut valid = fixture(service);
+            assert!(valid.len() >= need, "{service:?}");
+            assert!(parse(service, &valid).is_ok(), "{service:?}");
+            valid.truncate(need);
+            assert!(
+                !matches!(parse(service, &valid), Err(Error::ResponseTooShort { .. })),
+                "{service:?} with {need} bytes"
+            );
+            if need > 0 {
+                assert!(
+                    matches!(
+                        parse(service, &valid[..need - 1]),
+                        Err(Error::ResponseTooShort { got, need: n, .. }) if got == need - 1 && n == need
+                    ),
+                    "{service:?} with {} bytes",
+                    need - 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn security_access_granted_or_seed() {
+        for data in [&[0x02][..], &[0x02, 0x34], &[0x02, 0x00, 0x00]] {
+            assert!(matches!(
+                parse(ServiceResponse::SecurityAccess, data),
+                Ok(Response::SecurityAccessGranted(SecurityLevel::Key1))
+            ));
+        }
+        match parse(ServiceResponse::SecurityAccess, &[0x01, 0x12, 0x34]) {
+            Ok(Response::SecurityAccessSeed(SecurityLevel::Seed1, seed)) => {
+                assert_eq!(seed, [0x12, 0x34])
+            }
+            r => panic!("{r:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_dtc_status() {
+        let request = context(ServiceId::ReadStatusOfDTC, &[0x12, 0x34]);
+        assert!(matches!(
+            from_raw_for(mock::frame(&[0x57, 0x00]), Some(request)),
+            Err(Error::UnknownDtc(0x1234))
+        ));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Repeated {
+        Same,
+        Different,
+        Missing,
+    }
+
+    #[test]
+    fn echo_of_request_and_response() {
+        use Repeated::*;
+        // request and response, service byte first
+        let cases: [(&[u8], &[u8], Repeated); 13] = [
+            (&[0x10, 0x89], &[0x50, 0x89], Same),
+            (&[0x10, 0x89], &[0x50, 0x85], Different),
+            (&[0x27, 0x01], &[0x67, 0x01, 0x12, 0x34], Same),
+            (&[0x27, 0x01], &[0x67, 0x03, 0x12, 0x34], Different),
+            (&[0x21, 0x01], &[0x61, 0x01, 0xAA], Same),
+            (&[0x21, 0x01], &[0x61, 0x02, 0xAA], Different),
+            (&[0x22, 0x12, 0x34], &[0x62, 0x12, 0x34, 0xAA], Same),
+            (
+                &[0x17, 0x12, 0x34],
+                &[0x57, 0x01, 0x56, 0x78, 0xE0],
+                Different,
+            ),
+            (
+                &[0x23, 0x38, 0x00, 0x00, 0x01],
+                &[0x63, 0xAA, 0x38, 0x00, 0x00],
+                Same,
+            ),
+            (
+                &[0x23, 0x38, 0x00, 0x00, 0x01],
+                &[0x63, 0xAA, 0x38, 0x00, 0x01],
+                Different,
+            ),
+            // the address echo is optional
+            (&[0x23, 0x38, 0x00, 0x00, 0x01], &[0x63, 0xAA], Missing),
+            // nothing to repeat
+            (&[0x3E], &[0x7E], Missing),
+            // request too short to have the parameter
+            (&[0x21], &[0x61, 0x01, 0xAA], Missing),
+        ];
+        for (request, response, repeated) in cases {
+            let service = ServiceId::from_repr(request[0]).unwrap();
+            let request = context(service, &request[1..]);
+            let response = from_raw_for(mock::frame(response), Some(request)).unwrap();
+            let got =
+                match (request.echo, Echo::of_response(&response)) {
+                    (Some(expected), Some(got)) if expected.field == got.field => {
+                        if expected == got { Same } else { Different }
+                    }
+                    _ => Missing,
+                };
+            assert_eq!(got, repeated, "{service:?} {response:?}");
+        }
+    }
 }
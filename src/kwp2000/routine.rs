@@ -0,0 +1,245 @@
+//! Routines: ECU side procedures such as erasing or checksumming the
+//! flash, which often take seconds to answer. Selected by local identifier,
+//! or by address for code downloaded to RAM.
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{RoutineIdentifier, ServiceId},
+    message::Message,
+    policy::DangerousAction,
+    response::Response,
+};
+
+impl Client {
+    /// Starts `routine` with `parameters`, returns the data of the
+    /// response. `ResponsePending` answers are waited out.
+    pub fn start_routine(
+        &mut self,
+        routine: RoutineIdentifier,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.confirm(DangerousAction::RoutineStart(routine))?;
+        self.start_routine_unconfirmed(routine, parameters)
+    }
+    /// `start_routine` without asking the danger policy, for the profile's
+    /// routines that only read (checksum, flash device id).
+    pub(crate) fn start_routine_unconfirmed(
+        &mut self,
+        routine: RoutineIdentifier,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.send(Message::StartRoutine(routine, parameters))?;
+        match self.next_response_expect_wait(Some(ServiceId::StartRoutineByLocalIdentifier))? {
+            Response::RoutineStarted(r, data) if r == routine => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Stops `routine` with `parameters`, returns the data of the
+    /// response. `ResponsePending` answers are waited out.
+    pub fn stop_routine(
+        &mut self,
+        routine: RoutineIdentifier,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.send(Message::StopRoutine(routine, parameters))?;
+        match self.next_response_expect_wait(Some(ServiceId::StopRoutineByLocalIdentifier))? {
+            Response::RoutineStopped(r, data) if r == routine => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads the results of `routine`. ECUs that are still running it
+    /// answer `ResponsePending`, which is waited out, or a
+    /// `RoutineNotComplete` error, which is returned.
+    pub fn routine_results(&mut self, routine: RoutineIdentifier) -> Result<Vec<u8>, Error> {
+        self.send(Message::RequestRoutineResults(routine))?;
+        match self
+            .next_response_expect_wait(Some(ServiceId::RequestRoutineResultsByLocalIdentifier))?
+        {
+            Response::RoutineResults(r, results) if r == routine => Ok(results),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Starts the routine at `address` with `parameters`, returns the
+    /// results of its entry. `ResponsePending` answers are waited out.
+    pub fn start_routine_by_address(
+        &mut self,
+        address: u32,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.confirm(DangerousAction::RoutineStartByAddress(address))?;
+        self.send(Message::StartRoutineByAddress {
+            address,
+            parameters,
+        })?;
+        match self.next_response_expect_wait(Some(ServiceId::StartRoutineByAddress))? {
+            Response::RoutineByAddressStarted(a, data) if a == address & 0xFF_FFFF => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Stops the routine at `address`, see `stop_routine`.
+    pub fn stop_routine_by_address(
+        &mut self,
+        address: u32,
+        parameters: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        self.send(Message::StopRoutineByAddress {
+            address,
+            parameters,
+        })?;
+        match self.next_response_expect_wait(Some(ServiceId::StopRoutineByAddress))? {
+            Response::RoutineByAddressStopped(a, data) if a == address & 0xFF_FFFF => Ok(data),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads the results of the routine at `address`, see
+    /// `routine_results`.
+    pub fn routine_results_by_address(&mut self, address: u32) -> Result<Vec<u8>, Error> {
+        self.send(Message::RequestRoutineResultsByAddress { address })?;
+        match self.next_response_expect_wait(Some(ServiceId::ResquestRoutineResultsByAddress))? {
+            Response::RoutineByAddressResults(a, results) if a == address & 0xFF_FFFF => {
+                Ok(results)
+            }
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::kwp2000::{bus::BusActivity, mock, watchdog::Watchdog};
+
+    /// Routine standing in for a flash erase.
+    const ERASE: RoutineIdentifier = RoutineIdentifier(0x01);
+
+    /// Client for an ECU answering the start of `ERASE` with `pending`
+    /// `ResponsePending` frames and the first `not_complete` requests for
+    /// its results with `RoutineNotComplete`, and the requests sent to it.
+    fn erasing_ecu(pending: usize, mut not_complete: usize) -> (Client, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let sent = requests.clone();
+        let mut client = mock::client(move |request| {
+            sent.lock().unwrap().push(request.to_vec());
+            match request {
+                [0x31, 0x01] => {
+                    let mut frames: Vec<_> =
+                        (0..pending).map(|_| Ok(vec![0x7F, 0x31, 0x78])).collect();
+                    frames.push(Ok(vec![0x71, 0x01]));
+                    frames
+                }
+                [0x33, 0x01] if not_complete > 0 => {
+                    not_complete -= 1;
+                    vec![Ok(vec![0x7F, 0x33, 0x23])]
+                }
+                [0x33, 0x01] => vec![Ok(vec![0x73, 0x01, 0x00])],
+                [0x3E, 0x01] => vec![Ok(vec![0x7E])],
+                _ => panic!("unexpected request {request:02X?}"),
+            }
+        });
+        // a keep-alive is due as soon as the bus is idle
+        client.watchdog = Some(Watchdog {
+            threshold: 0.0,
+            keep_alive: true,
+        });
+        (client, requests)
+    }
+
+    #[test]
+    fn erase_is_not_interrupted_by_keep_alives() {
+        let (mut client, requests) = erasing_ecu(30, 10);
+        client
+            .send(Message::StartRoutine(ERASE, Vec::new()))
+            .unwrap();
+        let started = loop {
+            if let Some(response) = client.poll().unwrap() {
+                break response;
+            }
+            assert_eq!(client.bus_activity(), BusActivity::EcuBusy);
+            client.poll_watchdog().unwrap();
+            assert!(matches!(
+                client.tester_present(),
+                Err(Error::KeepAliveSkipped(BusActivity::EcuBusy))
+            ));
+        };
+        assert!(matches!(started, Response::RoutineStarted(ERASE, _)));
+        let results = loop {
+            match client.routine_results(ERASE) {
+                Err(Error::UnexpectedResponse(_)) => {
+                    assert_eq!(client.bus_activity(), BusActivity::EcuBusy);
+                    client.poll_watchdog().unwrap();
+                }
+                r => break r.unwrap(),
+            }
+        };
+        assert_eq!(results, [0x00]);
+        // the start and every poll for the results, no keep-alive
+        let mut expected = vec![vec![0x31, 0x01]];
+        expected.extend(std::iter::repeat_n(vec![0x33, 0x01], 11));
+        assert_eq!(*requests.lock().unwrap(), expected);
+        assert_eq!(client.bus_activity(), BusActivity::Idle);
+        client.tester_present().unwrap();
+    }
+
+    #[test]
+    fn started_routine_leaves_the_bus_idle() {
+        let (mut client, requests) = erasing_ecu(0, 0);
+        client.start_routine(ERASE, Vec::new()).unwrap();
+        assert_eq!(client.bus_activity(), BusActivity::Idle);
+        assert!(client.poll_watchdog().unwrap());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [vec![0x31, 0x01], vec![0x3E, 0x01]]
+        );
+    }
+
+    /// Client for an ECU answering the routines by address with the
+    /// address in the request plus `offset`.
+    fn routine_ecu(offset: u32) -> Client {
+        mock::client(move |request| {
+            let address = u32::from_be_bytes([0, request[1], request[2], request[3]]) + offset;
+            let mut response = vec![request[0] + 0x40];
+            response.extend_from_slice(&address.to_be_bytes()[1..4]);
+            response.push(0x00);
+            vec![Ok(response)]
+        })
+    }
+
+    #[test]
+    fn routine_addresses_are_compared_as_sent() {
+        let mut client = routine_ecu(0);
+        // only the low 3 bytes of the address are sent and echoed
+        let address = 0xFF38_0000;
+        assert_eq!(
+            client.start_routine_by_address(address, vec![]).unwrap(),
+            [0x00]
+        );
+        assert_eq!(
+            client.stop_routine_by_address(address, vec![]).unwrap(),
+            [0x00]
+        );
+        assert_eq!(client.routine_results_by_address(address).unwrap(), [0x00]);
+
+        let mut client = routine_ecu(0x10);
+        assert!(matches!(
+            client.start_routine_by_address(0x380000, vec![]),
+            Err(Error::ParameterEchoMismatch {
+                field: "address",
+                ..
+            })
+        ));
+        // without the echo check the guards still refuse it
+        client.strict_echo = false;
+        assert!(matches!(
+            client.start_routine_by_address(0x380000, vec![]),
+            Err(Error::UnexpectedResponse(_))
+        ));
+        assert!(matches!(
+            client.routine_results_by_address(0x380000),
+            Err(Error::UnexpectedResponse(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::Error;
+
+use super::constants::{SecurityLevel, ServiceError};
+
+/// The 4 bytes the key is computed from, dropping a trailing status byte
+/// if `status_byte` is set. Fails with `Error::InvalidSeedLength` holding
+/// the seed as received if the length doesn't fit.
+pub(crate) fn seed_bytes(seed: &[u8], status_byte: bool) -> Result<[u8; 4], Error> {
+    let trimmed = match seed {
+        [rest @ .., _] if status_byte => rest,
+        _ => seed,
+    };
+    trimmed.try_into().map_err(|_| Error::InvalidSeedLength {
+        seed: seed.to_vec(),
+    })
+}
+
+/// Number of seeds kept by `SeedHistory`, older ones are dropped.
+pub const MAX_SEED_HISTORY: usize = 16;
+
+/// Why a seed doesn't look random. Bench and some cloned ECUs send such
+/// seeds when their immobilizer is in a special state, flashing them may
+/// behave differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedDefect {
+    /// Every byte is 0xFF.
+    AllOnes,
+    /// The seed was received `times` times in the history.
+    Repeated { times: usize },
+}
+
+impl Display for SeedDefect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AllOnes => write!(f, "all bytes 0xff"),
+            Self::Repeated { times } => write!(f, "received {times} times"),
+        }
+    }
+}
+
+/// Seeds received from the ECU, newest last. Can be saved and restored to
+/// check seeds across sessions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedHistory {
+    pub seeds: VecDeque<[u8; 4]>,
+    /// Number of times a seed has to be received for it to count as
+    /// repeated.
+    pub repeat_threshold: usize,
+}
+
+impl Default for SeedHistory {
+    fn default() -> Self {
+        Self {
+            seeds: VecDeque::new(),
+            repeat_threshold: 2,
+        }
+    }
+}
+
+impl SeedHistory {
+    /// Adds `seed` and returns what is wrong with it, if anything.
+    pub fn record(&mut self, seed: [u8; 4]) -> Option<SeedDefect> {
+        if self.seeds.len() >= MAX_SEED_HISTORY {
+            self.seeds.pop_front();
+        }
+        self.seeds.push_back(seed);
+        if seed == [0xFF; 4] {
+            return Some(SeedDefect::AllOnes);
+        }
+        let times = self.seeds.iter().filter(|s| **s == seed).count();
+        (times >= self.repeat_threshold.max(2)).then_some(SeedDefect::Repeated { times })
+    }
+}
+
+/// How the ECU answered the key of a security access exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityAccessOutcome {
+    Granted,
+    Rejected(ServiceError),
+}
+
+/// Seed received and key sent in the last attempt of a security access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityAccessReport {
+    pub level: SecurityLevel,
+    pub seed: Vec<u8>,
+    pub key_sent: Vec<u8>,
+    /// Number of seed requests made, including retries after lockouts
+    pub attempts: u8,
+    /// Why the seed doesn't look random, see `Client::seed_history`.
+    pub seed_defect: Option<SeedDefect>,
+    pub outcome: SecurityAccessOutcome,
+    /// Leave the key out of the `Display` output.
+    pub redact_key: bool,
+}
+
+impl Display for SecurityAccessReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "level {:?}, seed {:02x?}, key ", self.level, self.seed)?;
+        if self.redact_key {
+            write!(f, "<redacted>")?;
+        } else {
+            write!(f, "{:02x?}", self.key_sent)?;
+        }
+        write!(f, ", {} attempts, {:?}", self.attempts, self.outcome)?;
+        if let Some(defect) = self.seed_defect {
+            write!(f, ", degenerate seed ({defect})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_of_the_wrong_length_is_rejected() {
+        for (seed, status_byte) in [(&[][..], false), (&[1, 2, 3], false), (&[1, 2, 3, 4], true)] {
+            assert!(matches!(
+                seed_bytes(seed, status_byte),
+                Err(Error::InvalidSeedLength { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::HashMap, fmt::Display, time::Duration};
+
+use super::constants::ServiceId;
+
+/// Upper bounds of the latency histogram buckets, the last bucket holds
+/// everything slower.
+pub const LATENCY_BUCKETS: [Duration; 7] = [
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_millis(1000),
+    Duration::from_millis(2500),
+];
+
+/// Time between sending a request and receiving its final response.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    pub count: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+    /// Number of ResponsePending answers received before final responses
+    pub pending: u32,
+    /// Number of responses per bucket of `LATENCY_BUCKETS`
+    pub buckets: [u32; LATENCY_BUCKETS.len() + 1],
+}
+
+impl LatencyStats {
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count
+        }
+    }
+
+    fn record(&mut self, latency: Duration, pending: u32) {
+        if self.count == 0 || latency < self.min {
+            self.min = latency;
+        }
+        self.max = self.max.max(latency);
+        self.count += 1;
+        self.total += latency;
+        self.pending += pending;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|b| latency <= *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Counters of noteworthy events over the lifetime of a client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientStats {
+    /// Number of times the dynamically defined identifier used by the `dd_*`
+    /// helpers had to be defined again because the ECU lost the definition.
+    pub dd_redefinitions: u32,
+    /// Number of `TesterPresent` requests a log sent to keep the session
+    /// alive between samples.
+    pub keep_alives: u32,
+    /// Number of requests delayed by the profile's rate limits.
+    pub rate_limit_delays: u32,
+    /// Total time requests were delayed by the profile's rate limits.
+    pub rate_limit_delay_total: Duration,
+    /// Number of times the session was initialized again after the ECU
+    /// dropped it.
+    pub session_recoveries: u32,
+    /// Number of times the interface was closed and opened again because
+    /// initializing failed, see `Interface::reset_hard`.
+    pub hard_resets: u32,
+    /// Number of frames received, including echoes.
+    pub frames_received: u32,
+    /// Number of echoes of sent requests received.
+    pub echoes: u32,
+    /// Number of frames dropped because of a bad checksum.
+    pub invalid_checksums: u32,
+    latencies: HashMap<ServiceId, LatencyStats>,
+}
+
+impl ClientStats {
+    pub fn latency(&self, service: ServiceId) -> Option<&LatencyStats> {
+        self.latencies.get(&service)
+    }
+
+    pub(crate) fn record_latency(&mut self, service: ServiceId, latency: Duration, pending: u32) {
+        self.latencies
+            .entry(service)
+            .or_default()
+            .record(latency, pending);
+    }
+}
+
+impl Display for ClientStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "dd redefinitions: {}", self.dd_redefinitions)?;
+        writeln!(f, "keep-alives: {}", self.keep_alives)?;
+        writeln!(
+            f,
+            "rate limited: {} requests, {:?} total",
+            self.rate_limit_delays, self.rate_limit_delay_total
+        )?;
+        writeln!(f, "session recoveries: {}", self.session_recoveries)?;
+        writeln!(f, "hard resets: {}", self.hard_resets)?;
+        writeln!(
+            f,
+            "frames received: {}, {} echoes, {} bad checksums",
+            self.frames_received, self.echoes, self.invalid_checksums
+        )?;
+        let mut services: Vec<_> = self.latencies.iter().collect();
+        services.sort_by_key(|(s, _)| **s as u8);
+        for (service, l) in services {
+            writeln!(
+                f,
+                "{:?}: {} requests, min {:?}, mean {:?}, max {:?}, {} pending",
+                service,
+                l.count,
+                l.min,
+                l.mean(),
+                l.max,
+                l.pending
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Undoing what a session changed on the ECU before it ends, so the next
+//! tool finds the ECU as this one did.
+
+use crate::Error;
+
+use super::{
+    client::Client,
+    constants::{IoControlParameter, LocalIdentifier, ReadMode},
+    io_control::IoOutput,
+    message::Message,
+    response::Response,
+    timing::TimingState,
+    warning::Warning,
+};
+
+/// Change to the ECU's state made by a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EcuChange {
+    PeriodicStarted(LocalIdentifier),
+    PeriodicStopped(LocalIdentifier),
+    IdentifierDefined(LocalIdentifier),
+    IdentifierCleared(LocalIdentifier),
+    TimingChanged,
+    TimingRestored,
+    IoControlled(IoOutput),
+    IoReleased(IoOutput),
+}
+
+/// Change made by an `InputOutputControl` request with `control`.
+fn io_change(output: IoOutput, control: IoControlParameter) -> Option<EcuChange> {
+    match control {
+        IoControlParameter::ReturnControlToEcu => Some(EcuChange::IoReleased(output)),
+        IoControlParameter::ShortTermAdjustment
+        | IoControlParameter::FreezeCurrentState
+        | IoControlParameter::ExecuteControlOption => Some(EcuChange::IoControlled(output)),
+        _ => None,
+    }
+}
+
+impl EcuChange {
+    pub(crate) fn of(message: &Message) -> Option<Self> {
+        Some(match *message {
+            Message::ReadLocalIdentifier(id, ReadMode::Stop, _) => Self::PeriodicStopped(id),
+            Message::ReadLocalIdentifier(
+                id,
+                ReadMode::Slow | ReadMode::Medium | ReadMode::Fast,
+                _,
+            ) => Self::PeriodicStarted(id),
+            Message::DefineLocalIdentifierAddress(id, ..) => Self::IdentifierDefined(id),
+            Message::ClearLocalIdentifier(id) => Self::IdentifierCleared(id),
+            Message::ChangeTimingParameters { .. } => Self::TimingChanged,
+            Message::GetDefaultTiming => Self::TimingRestored,
+            Message::IoControlByLocalIdentifier {
+                identifier,
+                control,
+                ..
+            } => io_change(IoOutput::Local(identifier), control)?,
+            Message::IoControlByCommonIdentifier {
+                identifier,
+                control,
+                ..
+            } => io_change(IoOutput::Common(identifier), control)?,
+            _ => return None,
+        })
+    }
+}
+
+/// ECU side state the client changed and hasn't undone yet, from the
+/// requests it sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EcuChanges {
+    /// Identifiers with a periodic transmission running
+    pub periodic: Vec<LocalIdentifier>,
+    /// Dynamically defined identifiers
+    pub defined_identifiers: Vec<LocalIdentifier>,
+    /// The timing parameters were changed from their defaults.
+    pub timing_changed: bool,
+    /// Outputs under the tester's control
+    pub io_controlled: Vec<IoOutput>,
+}
+
+impl EcuChanges {
+    pub(crate) fn apply(&mut self, change: EcuChange) {
+        fn add<T: PartialEq>(ids: &mut Vec<T>, id: T) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        match change {
+            EcuChange::PeriodicStarted(id) => add(&mut self.periodic, id),
+            EcuChange::PeriodicStopped(id) => self.periodic.retain(|i| *i != id),
+            EcuChange::IdentifierDefined(id) => add(&mut self.defined_identifiers, id),
+            EcuChange::IdentifierCleared(id) => self.defined_identifiers.retain(|i| *i != id),
+            EcuChange::TimingChanged => self.timing_changed = true,
+            EcuChange::TimingRestored => self.timing_changed = false,
+            EcuChange::IoControlled(id) => add(&mut self.io_controlled, id),
+            EcuChange::IoReleased(id) => self.io_controlled.retain(|i| *i != id),
+        }
+    }
+
+    /// Whether the ECU is as the client found it, as far as it knows.
+    pub fn is_empty(&self) -> bool {
+        self.periodic.is_empty()
+            && self.defined_identifiers.is_empty()
+            && !self.timing_changed
+            && self.io_controlled.is_empty()
+    }
+}
+
+impl Client {
+    /// Undoes the changes in `ecu_changes`: stops periodic transmissions,
+    /// clears dynamically defined identifiers, returns controlled outputs
+    /// to the ECU and restores the default timing. Every step is tried, failures are recorded as
+    /// `Warning::TeardownStepFailed`.
+    pub fn teardown(&mut self) {
+        for id in self.ecu_changes().periodic.clone() {
+            let result = self
+                .send(Message::ReadLocalIdentifier(id, ReadMode::Stop, 0))
+                // samples may still be on their way, whatever arrives ends it
+                .and_then(|()| self.next_response().map(|_| ()));
+            self.teardown_step("stop periodic transmission", result);
+        }
+        for id in self.ecu_changes().defined_identifiers.clone() {
+            let result = self.send(Message::ClearLocalIdentifier(id)).and_then(|()| {
+                match self.next_response()? {
+                    Response::LocalIdentifierDefined(i) if i == id => Ok(()),
+                    r => Err(Error::UnexpectedResponse(r)),
+                }
+            });
+            self.teardown_step("clear dynamic identifier", result);
+        }
+        for output in self.ecu_changes().io_controlled.clone() {
+            let result = self.release_output(output);
+            self.teardown_step("return output control", result);
+        }
+        if self.ecu_changes().timing_changed {
+            let result =
+                self.send(Message::GetDefaultTiming)
+                    .and_then(|()| match self.next_response()? {
+                        Response::TimingRestoredToDefault => {
+                            self.timing = TimingState::default();
+                            Ok(())
+                        }
+                        r => Err(Error::UnexpectedResponse(r)),
+                    });
+            self.teardown_step("restore default timing", result);
+        }
+    }
+    fn teardown_step(&mut self, step: &'static str, result: Result<(), Error>) {
+        if let Err(e) = result {
+            self.warn(Warning::TeardownStepFailed {
+                step,
+                error: e.to_string(),
+            });
+            // the request is not going to be answered
+            self.reset_session_state();
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::time::Duration;
+
+use crate::Error;
+
+use super::{
+    client::Client, constants::TimingParameter, message::Message, raw_message::HeaderFormat,
+    response::Response,
+};
+
+/// Bits sent per byte on the K-line: start bit, eight data bits, stop bit.
+const BITS_PER_BYTE: u64 = 10;
+
+/// Timing parameters currently in effect for the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingState {
+    /// Minimum time between tester request and ECU response
+    pub p2min: Duration,
+    /// Maximum time between tester request and ECU response
+    pub p2max: Duration,
+    /// Minimum time between ECU response and next tester request
+    pub p3min: Duration,
+    /// Maximum time between ECU response and next tester request, the ECU
+    /// ends the session if nothing is received in this window.
+    pub p3max: Duration,
+    /// Minimum interbyte time of tester requests
+    pub p4min: Duration,
+}
+
+impl TimingState {
+    /// Maximum interbyte time of ECU responses. The key bytes only select
+    /// between the normal and extended timing sets, both use this P1.
+    pub const P1MAX: Duration = Duration::from_millis(20);
+    /// Maximum time between a `ResponsePending` and the next response
+    /// (P2*max) unless the profile sets another, see
+    /// `EcuProfile::p2max_extended`.
+    pub const P2MAX_EXTENDED: Duration = Duration::from_millis(5000);
+
+    /// Duration of one step of the P2min, P3min and P4min bytes.
+    pub const MIN_RESOLUTION: Duration = Duration::from_micros(500);
+    /// Duration of one step of the P2max byte.
+    pub const P2MAX_RESOLUTION: Duration = Duration::from_millis(25);
+    /// Duration of one step of the P3max byte.
+    pub const P3MAX_RESOLUTION: Duration = Duration::from_millis(250);
+
+    /// Largest P2min, P3min and P4min, a 0xFF byte.
+    pub const MIN_CEILING: Duration = Duration::from_micros(500 * 255);
+    /// Smallest P2max, one step. A zero P2max would time out every
+    /// request before the ECU could answer.
+    pub const P2MAX_FLOOR: Duration = Self::P2MAX_RESOLUTION;
+    /// Largest P2max, a 0xFF byte.
+    pub const P2MAX_CEILING: Duration = Duration::from_millis(25 * 255);
+    /// Smallest P3max, one step. With a zero P3max keep-alives would be
+    /// sent back to back.
+    pub const P3MAX_FLOOR: Duration = Self::P3MAX_RESOLUTION;
+    /// Largest P3max, a 0xFF byte.
+    pub const P3MAX_CEILING: Duration = Duration::from_millis(250 * 255);
+
+    /// Decodes timing parameter bytes as sent in `AccessTimingParameter`
+    /// messages, see `Message::ChangeTimingParameters` for the resolutions.
+    /// Zero P2max and P3max bytes are raised to their floors.
+    pub fn from_bytes(p2min: u8, p2max: u8, p3min: u8, p3max: u8, p4min: u8) -> Self {
+        Self {
+            p2min: Self::MIN_RESOLUTION * p2min as u32,
+            p2max: Self::P2MAX_RESOLUTION * p2max as u32,
+            p3min: Self::MIN_RESOLUTION * p3min as u32,
+            p3max: Self::P3MAX_RESOLUTION * p3max as u32,
+            p4min: Self::MIN_RESOLUTION * p4min as u32,
+        }
+        .clamped()
+    }
+
+    /// The parameters limited to what their bytes can express, with P2max
+    /// and P3max at least their floors and P2min at most P2max. The fields
+    /// are public, the methods below use this so a value set by hand can't
+    /// make them time out at once or send frames back to back.
+    pub fn clamped(&self) -> Self {
+        let p2max = self.p2max.clamp(Self::P2MAX_FLOOR, Self::P2MAX_CEILING);
+        Self {
+            p2min: self.p2min.min(Self::MIN_CEILING).min(p2max),
+            p2max,
+            p3min: self.p3min.min(Self::MIN_CEILING),
+            p3max: self.p3max.clamp(Self::P3MAX_FLOOR, Self::P3MAX_CEILING),
+            p4min: self.p4min.min(Self::MIN_CEILING),
+        }
+    }
+
+    /// Time to wait for the first response to a request, P2max. See
+    /// `Client::response_timeout` for the wait after a `ResponsePending`.
+    pub fn response_timeout(&self) -> Duration {
+        self.clamped().p2max
+    }
+
+    /// Time without a frame after which the ECU ends the session, P3max.
+    pub fn session_timeout(&self) -> Duration {
+        self.clamped().p3max
+    }
+
+    /// `fraction` of P3max. NaN counts as 1, other values are clamped to
+    /// 0..=1.
+    pub fn session_fraction(&self, fraction: f32) -> Duration {
+        let fraction = if fraction.is_nan() {
+            1.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        self.session_timeout().mul_f32(fraction)
+    }
+
+    /// Time after the last frame at which a keep-alive goes out, `margin`
+    /// before P3max runs out but no earlier than half of it.
+    pub fn keep_alive_interval(&self, margin: Duration) -> Duration {
+        let p3max = self.session_timeout();
+        p3max.saturating_sub(margin).max(p3max / 2)
+    }
+
+    /// Time between the requests of a periodic schedule asking for
+    /// `period`: at least P3min, and at least one P3min step so a zero
+    /// period doesn't busy loop.
+    pub fn request_period(&self, period: Duration) -> Duration {
+        period.max(self.clamped().p3min).max(Self::MIN_RESOLUTION)
+    }
+
+    /// Encodes the parameters as `[p2min, p2max, p3min, p3max, p4min]`
+    /// bytes, rounding each to the nearest step of its resolution and
+    /// saturating at the largest value a byte can hold. Values decoded with
+    /// `from_bytes` encode to the same bytes, apart from zero P2max and
+    /// P3max bytes.
+    pub fn to_bytes(&self) -> [u8; 5] {
+        fn steps(value: Duration, resolution: Duration) -> u8 {
+            let resolution = resolution.as_nanos();
+            let steps = (value.as_nanos() + resolution / 2) / resolution;
+            steps.min(u8::MAX as u128) as u8
+        }
+        [
+            steps(self.p2min, Self::MIN_RESOLUTION),
+            steps(self.p2max, Self::P2MAX_RESOLUTION),
+            steps(self.p3min, Self::MIN_RESOLUTION),
+            steps(self.p3max, Self::P3MAX_RESOLUTION),
+            steps(self.p4min, Self::MIN_RESOLUTION),
+        ]
+    }
+}
+
+impl Default for TimingState {
+    /// KWP2000 default timing
+    fn default() -> Self {
+        Self {
+            p2min: Duration::from_millis(25),
+            p2max: Duration::from_millis(50),
+            p3min: Duration::from_millis(55),
+            p3max: Duration::from_millis(5000),
+            p4min: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Timing parameter sets of the session, see `Client::timing_report`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingReport {
+    /// Parameters currently in effect as reported by the ECU
+    pub current: TimingState,
+    /// KWP2000 default parameters, restored by `Message::GetDefaultTiming`.
+    /// Not read from the ECU as asking for them resets the current ones.
+    pub default: TimingState,
+    /// Fastest parameters the ECU accepts
+    pub limits: TimingState,
+    /// Maximum interbyte time of ECU responses
+    pub p1max: Duration,
+    /// Baud rate of the link
+    pub baud_rate: u32,
+    /// Framing of the messages
+    pub header_format: HeaderFormat,
+}
+
+/// Time it takes to transmit `bytes` bytes at `baud_rate`.
+pub fn transmit_time(bytes: usize, baud_rate: u32) -> Duration {
+    Duration::from_micros(
+        (bytes as u64)
+            .saturating_mul(BITS_PER_BYTE * 1_000_000)
+            .div_ceil(baud_rate.max(1) as u64),
+    )
+}
+
+impl TimingReport {
+    /// Time it takes to transmit `bytes` bytes at the link's baud rate.
+    pub fn transmit_time(&self, bytes: usize) -> Duration {
+        transmit_time(bytes, self.baud_rate)
+    }
+
+    /// Shortest possible time from the start of one request to the start of
+    /// the next with the current timing, for requests of `request` and
+    /// responses of `response` payload bytes.
+    pub fn min_exchange_time(&self, request: usize, response: usize) -> Duration {
+        let request = self.header_format.frame_len(request);
+        let response = self.header_format.frame_len(response);
+        let current = self.current.clamped();
+        self.transmit_time(request)
+            .saturating_add(
+                current
+                    .p4min
+                    .saturating_mul(request.saturating_sub(1) as u32),
+            )
+            .saturating_add(current.p2min)
+            .saturating_add(self.transmit_time(response))
+            .saturating_add(current.p3min)
+    }
+
+    /// Maximum number of request/response exchanges per second, see
+    /// `min_exchange_time`.
+    pub fn max_rate(&self, request: usize, response: usize) -> f64 {
+        1.0 / self.min_exchange_time(request, response).as_secs_f64()
+    }
+}
+
+impl Client {
+    /// Time to wait for a response: P2max, or after a `ResponsePending` the
+    /// profile's P2*max, which is never shorter.
+    pub fn response_timeout(&self, pending: bool) -> Duration {
+        let p2max = self.timing.response_timeout();
+        if pending {
+            self.profile.p2max_extended.max(p2max)
+        } else {
+            p2max
+        }
+    }
+    /// Reads a timing parameter set from the ECU.
+    pub(crate) fn read_timing(
+        &mut self,
+        message: Message,
+        kind: TimingParameter,
+    ) -> Result<TimingState, Error> {
+        self.send(message)?;
+        let response = self.next_response()?;
+        self.timing_supported = Some(!matches!(response, Response::Error(_)));
+        match response {
+            Response::TimingParameters {
+                kind: k,
+                p2min,
+                p2max,
+                p3min,
+                p3max,
+                p4min,
+            } if k == kind => Ok(TimingState::from_bytes(p2min, p2max, p3min, p3max, p4min)),
+            r => Err(Error::UnexpectedResponse(r)),
+        }
+    }
+    /// Reads the current and limit timing parameters from the ECU and
+    /// updates `Client::timing` with the current ones.
+    pub fn timing_report(&mut self) -> Result<TimingReport, Error> {
+        let current = self.read_timing(Message::GetCurrentTiming, TimingParameter::Read)?;
+        let limits = self.read_timing(Message::GetTimingLimits, TimingParameter::Limits)?;
+        self.timing = current;
+        Ok(TimingReport {
+            current,
+            default: TimingState::default(),
+            limits,
+            p1max: TimingState::P1MAX,
+            baud_rate: self.baud_rate,
+            header_format: self.header_format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_round_trip() {
+        // P2max and P3max at their largest so no field limits another
+        let base = [0x00, 0xFF, 0x00, 0xFF, 0x00];
+        for field in 0..base.len() {
+            for value in 0..=u8::MAX {
+                let mut bytes = base;
+                bytes[field] = value;
+                let [p2min, p2max, p3min, p3max, p4min] = bytes;
+                let mut expected = bytes;
+                // zero P2max and P3max are raised to their floors
+                if matches!(field, 1 | 3) && value == 0 {
+                    expected[field] = 1;
+                }
+                assert_eq!(
+                    TimingState::from_bytes(p2min, p2max, p3min, p3max, p4min).to_bytes(),
+                    expected,
+                    "field {field}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn p2min_is_limited_to_p2max() {
+        // 100ms P2min, 25ms P2max
+        let timing = TimingState::from_bytes(200, 1, 0, 1, 0);
+        assert_eq!(timing.p2min, timing.p2max);
+        assert_eq!(timing.to_bytes(), [50, 1, 0, 1, 0]);
+    }
+}
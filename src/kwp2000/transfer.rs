@@ -0,0 +1,273 @@
+//! Validated construction of `RequestUpload` and `RequestDownload` messages.
+
+use crate::Error;
+
+#[cfg(feature = "flash")]
+use super::codec::DataCodec;
+use super::{
+    client::Client,
+    constants::{CompressionFormat, EncryptionFormat},
+    message::{Message, TransferType},
+};
+
+/// Largest address or size that fits the 3 bytes a transfer request has
+/// for each.
+pub const MAX_TRANSFER_VALUE: u32 = 0xFF_FFFF;
+
+/// Reason a transfer request was rejected before it was sent.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TransferRejection {
+    #[error("transfer of 0 bytes")]
+    EmptyTransfer,
+    #[error("address 0x{0:x} does not fit in 3 bytes")]
+    AddressTooLarge(u32),
+    #[error("size 0x{0:x} does not fit in 3 bytes")]
+    SizeTooLarge(u32),
+    #[error("0x{address:06x} + 0x{size:x} runs past the end of the address space")]
+    RangeOverflow { address: u32, size: u32 },
+    #[error(
+        "{transfer_type:?} with {compression:?} compression and {encryption:?} encryption is not supported"
+    )]
+    UnsupportedFormat {
+        transfer_type: TransferType,
+        compression: CompressionFormat,
+        encryption: EncryptionFormat,
+    },
+    #[error("0x{address:06x}..0x{end:06x} is outside the profile's memory")]
+    OutsideMemory { address: u32, end: u64 },
+}
+
+/// Builder for the message starting an upload or download, checked against
+/// the client's profile and codecs by `build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferRequest {
+    pub transfer_type: TransferType,
+    pub address: u32,
+    pub size: u32,
+    pub compression: CompressionFormat,
+    pub encryption: EncryptionFormat,
+    /// The formats are those of a codec the caller has, registered or not.
+    codec_provided: bool,
+}
+
+impl TransferRequest {
+    fn new(transfer_type: TransferType, address: u32, size: u32) -> Self {
+        Self {
+            transfer_type,
+            address,
+            size,
+            compression: CompressionFormat::Uncompressed,
+            encryption: EncryptionFormat::Unencrypted,
+            codec_provided: false,
+        }
+    }
+    pub fn upload(address: u32, size: u32) -> Self {
+        Self::new(TransferType::Upload, address, size)
+    }
+    pub fn download(address: u32, size: u32) -> Self {
+        Self::new(TransferType::Download, address, size)
+    }
+    pub fn compressed(mut self, compression: CompressionFormat) -> Self {
+        self.compression = compression;
+        self.codec_provided = false;
+        self
+    }
+    pub fn encrypted(mut self, encryption: EncryptionFormat) -> Self {
+        self.encryption = encryption;
+        self.codec_provided = false;
+        self
+    }
+    /// Uses the formats of `codec`, which the data will be encoded with.
+    #[cfg(feature = "flash")]
+    pub fn encoded_with(mut self, codec: &dyn DataCodec) -> Self {
+        self.compression = codec.compression();
+        self.encryption = codec.encryption();
+        self.codec_provided = true;
+        self
+    }
+
+    /// Checks that the address and size fit the request, uploads are
+    /// uncompressed and unencrypted (received data isn't decoded) and
+    /// downloads stay inside the profile's memory layout and use formats a
+    /// codec is registered for.
+    pub fn validate(&self, client: &Client) -> Result<(), TransferRejection> {
+        if self.size == 0 {
+            return Err(TransferRejection::EmptyTransfer);
+        }
+        if self.address > MAX_TRANSFER_VALUE {
+            return Err(TransferRejection::AddressTooLarge(self.address));
+        }
+        if self.size > MAX_TRANSFER_VALUE {
+            return Err(TransferRejection::SizeTooLarge(self.size));
+        }
+        let end = self.address as u64 + self.size as u64;
+        if end > MAX_TRANSFER_VALUE as u64 + 1 {
+            return Err(TransferRejection::RangeOverflow {
+                address: self.address,
+                size: self.size,
+            });
+        }
+        let supported = match self.transfer_type {
+            TransferType::Upload => {
+                self.compression == CompressionFormat::Uncompressed
+                    && self.encryption == EncryptionFormat::Unencrypted
+            }
+            #[cfg(feature = "flash")]
+            TransferType::Download => {
+                self.codec_provided || client.codec(self.compression, self.encryption).is_some()
+            }
+            #[cfg(not(feature = "flash"))]
+            TransferType::Download => false,
+        };
+        if !supported {
+            return Err(TransferRejection::UnsupportedFormat {
+                transfer_type: self.transfer_type,
+                compression: self.compression,
+                encryption: self.encryption,
+            });
+        }
+        // the layout only describes the flash, uploads may read RAM too
+        let layout = &client.profile.memory_layout;
+        if self.transfer_type == TransferType::Download
+            && (self.address < layout.base_address
+                || end > layout.base_address as u64 + layout.size as u64)
+        {
+            return Err(TransferRejection::OutsideMemory {
+                address: self.address,
+                end,
+            });
+        }
+        Ok(())
+    }
+
+    /// The request message, fails with `Error::TransferRejected` if
+    /// `validate` does.
+    pub fn build(self, client: &Client) -> Result<Message, Error> {
+        self.validate(client).map_err(Error::TransferRejected)?;
+        Ok(Message::RequestDataTransfer {
+            transfer_type: self.transfer_type,
+            address: self.address,
+            size: self.size,
+            encryption: self.encryption,
+            compression: self.compression,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::mock;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Client whose ECU only counts the requests it gets.
+    fn client() -> (Client, Arc<AtomicUsize>) {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        let client = mock::client(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            vec![]
+        });
+        (client, sent)
+    }
+
+    #[test]
+    fn every_rejection_reason() {
+        let (client, _) = client();
+        let base = client.profile.memory_layout.base_address;
+        let rejected = |request: TransferRequest| request.validate(&client).unwrap_err();
+        assert_eq!(
+            rejected(TransferRequest::upload(0x1000, 0)),
+            TransferRejection::EmptyTransfer
+        );
+        assert_eq!(
+            rejected(TransferRequest::upload(0x100_0000, 1)),
+            TransferRejection::AddressTooLarge(0x100_0000)
+        );
+        assert_eq!(
+            rejected(TransferRequest::upload(0, 0x100_0000)),
+            TransferRejection::SizeTooLarge(0x100_0000)
+        );
+        assert_eq!(
+            rejected(TransferRequest::upload(0xFF_FFF0, 0x11)),
+            TransferRejection::RangeOverflow {
+                address: 0xFF_FFF0,
+                size: 0x11
+            }
+        );
+        assert_eq!(
+            rejected(TransferRequest::upload(base, 16).compressed(CompressionFormat::Bosch)),
+            TransferRejection::UnsupportedFormat {
+                transfer_type: TransferType::Upload,
+                compression: CompressionFormat::Bosch,
+                encryption: EncryptionFormat::Unencrypted,
+            }
+        );
+        assert_eq!(
+            rejected(TransferRequest::download(base, 16).encrypted(EncryptionFormat::Hitachi)),
+            TransferRejection::UnsupportedFormat {
+                transfer_type: TransferType::Download,
+                compression: CompressionFormat::Uncompressed,
+                encryption: EncryptionFormat::Hitachi,
+            }
+        );
+        #[cfg(feature = "flash")]
+        assert_eq!(
+            rejected(
+                TransferRequest::download(base - 16, 32)
+                    .compressed(CompressionFormat::Bosch)
+                    .encrypted(EncryptionFormat::Bosch)
+            ),
+            TransferRejection::OutsideMemory {
+                address: base - 16,
+                end: base as u64 + 16
+            }
+        );
+    }
+
+    #[test]
+    fn valid_requests_are_built() {
+        let (client, _) = client();
+        // the last byte of the address space can be uploaded
+        assert!(
+            TransferRequest::upload(0xFF_FFFF, 1)
+                .validate(&client)
+                .is_ok()
+        );
+        assert!(matches!(
+            TransferRequest::upload(0x380000, 0x100).build(&client),
+            Ok(Message::RequestDataTransfer {
+                transfer_type: TransferType::Upload,
+                address: 0x380000,
+                size: 0x100,
+                encryption: EncryptionFormat::Unencrypted,
+                compression: CompressionFormat::Uncompressed,
+            })
+        ));
+        #[cfg(feature = "flash")]
+        assert!(
+            TransferRequest::download(client.profile.memory_layout.base_address, 16)
+                .compressed(CompressionFormat::Bosch)
+                .encrypted(EncryptionFormat::Bosch)
+                .validate(&client)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejected_reads_send_nothing() {
+        let (mut client, sent) = client();
+        for (address, size) in [(0x380000, 0), (0x100_0000, 16), (0xFF_FFF0, 0x20)] {
+            let mut dump = Vec::new();
+            assert!(matches!(
+                client.read_data(address, size, &mut dump),
+                Err(Error::TransferRejected(_))
+            ));
+            assert!(dump.is_empty());
+        }
+        assert_eq!(sent.load(Ordering::SeqCst), 0);
+    }
+}
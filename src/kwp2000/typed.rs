@@ -0,0 +1,235 @@
+//! Decoding the bytes of live variables into integers and scaled physical
+//! values, so callers don't each convert `dd_read_address` results.
+
+use strum::EnumString;
+
+use crate::Error;
+
+use super::client::Client;
+
+/// Value that can be decoded from the bytes the ECU stores it in.
+pub trait FromEcuBytes: Sized {
+    /// Number of bytes the value takes.
+    const SIZE: usize;
+    /// Decodes the value from the first `SIZE` bytes of `bytes`, fails with
+    /// `Error::NotEnoughData` if there are fewer.
+    fn from_ecu_bytes(bytes: &[u8]) -> Result<Self, Error>;
+
+    /// Decodes the value at `offset` in a buffer holding several, like the
+    /// data of a periodic sample.
+    fn from_buffer(buffer: &[u8], offset: usize) -> Result<Self, Error> {
+        Self::from_ecu_bytes(buffer.get(offset..).ok_or(Error::NotEnoughData)?)
+    }
+}
+
+/// Value stored big endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Be<T>(pub T);
+
+/// Value stored little endian, like the C167 does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Le<T>(pub T);
+
+/// First `N` bytes of `bytes`.
+fn take<const N: usize>(bytes: &[u8]) -> Result<[u8; N], Error> {
+    bytes
+        .get(..N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or(Error::NotEnoughData)
+}
+
+impl FromEcuBytes for u8 {
+    const SIZE: usize = 1;
+    fn from_ecu_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(take::<1>(bytes)?[0])
+    }
+}
+
+impl FromEcuBytes for i8 {
+    const SIZE: usize = 1;
+    fn from_ecu_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(i8::from_ne_bytes(take(bytes)?))
+    }
+}
+
+macro_rules! from_ecu_bytes {
+    ($($t:ty),*) => {$(
+        impl FromEcuBytes for Be<$t> {
+            const SIZE: usize = size_of::<$t>();
+            fn from_ecu_bytes(bytes: &[u8]) -> Result<Self, Error> {
+                Ok(Be(<$t>::from_be_bytes(take(bytes)?)))
+            }
+        }
+        impl FromEcuBytes for Le<$t> {
+            const SIZE: usize = size_of::<$t>();
+            fn from_ecu_bytes(bytes: &[u8]) -> Result<Self, Error> {
+                Ok(Le(<$t>::from_le_bytes(take(bytes)?)))
+            }
+        }
+    )*};
+}
+
+from_ecu_bytes!(u16, i16, u32, i32);
+
+/// Integer type of a raw value, chosen at runtime, e.g. from a definition
+/// file (`u16le`, `i32be`, ...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum RawType {
+    U8,
+    I8,
+    U16Be,
+    U16Le,
+    I16Be,
+    I16Le,
+    U32Be,
+    U32Le,
+    I32Be,
+    I32Le,
+}
+
+impl RawType {
+    /// Number of bytes the value takes.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U8 | Self::I8 => 1,
+            Self::U16Be | Self::U16Le | Self::I16Be | Self::I16Le => 2,
+            Self::U32Be | Self::U32Le | Self::I32Be | Self::I32Le => 4,
+        }
+    }
+
+    /// Decodes the value from the first `size` bytes of `bytes`.
+    pub fn decode(&self, bytes: &[u8]) -> Result<i64, Error> {
+        Ok(match self {
+            Self::U8 => u8::from_ecu_bytes(bytes)?.into(),
+            Self::I8 => i8::from_ecu_bytes(bytes)?.into(),
+            Self::U16Be => Be::<u16>::from_ecu_bytes(bytes)?.0.into(),
+            Self::U16Le => Le::<u16>::from_ecu_bytes(bytes)?.0.into(),
+            Self::I16Be => Be::<i16>::from_ecu_bytes(bytes)?.0.into(),
+            Self::I16Le => Le::<i16>::from_ecu_bytes(bytes)?.0.into(),
+            Self::U32Be => Be::<u32>::from_ecu_bytes(bytes)?.0.into(),
+            Self::U32Le => Le::<u32>::from_ecu_bytes(bytes)?.0.into(),
+            Self::I32Be => Be::<i32>::from_ecu_bytes(bytes)?.0.into(),
+            Self::I32Le => Le::<i32>::from_ecu_bytes(bytes)?.0.into(),
+        })
+    }
+}
+
+/// Physical value stored as `raw_type`, `raw * factor + offset`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scaled {
+    pub raw_type: RawType,
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Scaled {
+    /// Decodes the value from the first bytes of `bytes`.
+    pub fn decode(&self, bytes: &[u8]) -> Result<f64, Error> {
+        Ok(self.raw_type.decode(bytes)? as f64 * self.factor + self.offset)
+    }
+
+    /// Decodes the value at `offset` in a buffer holding several.
+    pub fn from_buffer(&self, buffer: &[u8], offset: usize) -> Result<f64, Error> {
+        self.decode(buffer.get(offset..).ok_or(Error::NotEnoughData)?)
+    }
+}
+
+impl Client {
+    /// Reads a `T` from `address` with `dd_read_address`.
+    pub fn read_typed<T: FromEcuBytes>(&mut self, address: u32) -> Result<T, Error> {
+        T::from_ecu_bytes(&self.dd_read_address(address, T::SIZE as u8)?)
+    }
+    /// Reads the value `scaled` describes from `address` with
+    /// `dd_read_address`.
+    pub fn read_scaled(&mut self, address: u32, scaled: &Scaled) -> Result<f64, Error> {
+        scaled.decode(&self.dd_read_address(address, scaled.raw_type.size() as u8)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::kwp2000::mock;
+
+    #[test]
+    fn raw_types_decode_both_byte_orders() {
+        let cases: [(&str, &[u8], i64); 14] = [
+            ("u8", &[0xFE], 0xFE),
+            ("i8", &[0xFE], -2),
+            ("i8", &[0x7F], 127),
+            ("u16be", &[0x12, 0x34], 0x1234),
+            ("u16le", &[0x12, 0x34], 0x3412),
+            ("i16be", &[0xFF, 0xFE], -2),
+            ("i16le", &[0xFE, 0xFF], -2),
+            ("i16be", &[0x80, 0x00], i16::MIN as i64),
+            ("i16le", &[0xFF, 0x7F], i16::MAX as i64),
+            ("u32be", &[0xDE, 0xAD, 0xBE, 0xEF], 0xDEADBEEF),
+            ("u32le", &[0xDE, 0xAD, 0xBE, 0xEF], 0xEFBEADDE),
+            ("i32be", &[0xFF, 0xFF, 0xFF, 0xFE], -2),
+            ("i32le", &[0x00, 0x00, 0x00, 0x80], i32::MIN as i64),
+            // bytes after the value are ignored
+            ("I16LE", &[0x01, 0x00, 0xFF], 1),
+        ];
+        for (name, bytes, expected) in cases {
+            let raw_type = RawType::from_str(name).unwrap();
+            assert_eq!(raw_type.decode(bytes).unwrap(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn short_data_is_an_error() {
+        for raw_type in [RawType::U8, RawType::I16Be, RawType::U32Le] {
+            let bytes = vec![0; raw_type.size() - 1];
+            assert!(matches!(raw_type.decode(&bytes), Err(Error::NotEnoughData)));
+        }
+        assert!(matches!(
+            Le::<u16>::from_buffer(&[0x01, 0x02], 1),
+            Err(Error::NotEnoughData)
+        ));
+        assert!(matches!(
+            u8::from_buffer(&[0x01], 2),
+            Err(Error::NotEnoughData)
+        ));
+        assert_eq!(
+            Be::<u16>::from_buffer(&[0, 0x01, 0x02], 1).unwrap(),
+            Be(0x0102)
+        );
+    }
+
+    #[test]
+    fn scaled_values() {
+        // coolant temperature, 0.75 °C per bit from -48 °C
+        let temperature = Scaled {
+            raw_type: RawType::U8,
+            factor: 0.75,
+            offset: -48.0,
+        };
+        assert_eq!(temperature.decode(&[0x00]).unwrap(), -48.0);
+        assert_eq!(temperature.decode(&[0xA0]).unwrap(), 72.0);
+        // signed raw values keep their sign through the factor
+        let correction = Scaled {
+            raw_type: RawType::I16Le,
+            factor: 0.5,
+            offset: 1.0,
+        };
+        assert_eq!(correction.decode(&[0xFC, 0xFF]).unwrap(), -1.0);
+        assert_eq!(correction.from_buffer(&[0xAA, 0x10, 0x00], 1).unwrap(), 9.0);
+    }
+
+    #[test]
+    fn client_reads_typed_values() {
+        // the dd ECU answers with the low byte of each address
+        let mut client = mock::client(mock::dd_ecu(0..0));
+        assert_eq!(client.read_typed::<Be<u16>>(0x380010).unwrap(), Be(0x1011));
+        assert_eq!(client.read_typed::<Le<u16>>(0x380010).unwrap(), Le(0x1110));
+        let scaled = Scaled {
+            raw_type: RawType::I8,
+            factor: 2.0,
+            offset: 0.5,
+        };
+        assert_eq!(client.read_scaled(0x3800FF, &scaled).unwrap(), -1.5);
+    }
+}
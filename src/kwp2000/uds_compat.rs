@@ -0,0 +1,721 @@
+//! Translation between this crate's requests and responses and the ISO
+//! 14229 (UDS) names of the services KWP2000 shares with it, so code
+//! talking to both kinds of ECUs can use one vocabulary. Only the
+//! application layer is translated, this is not a UDS implementation.
+//!
+//! Concepts without an equivalent, like local identifiers or the timing
+//! service, fail with `Error::NotTranslatable`.
+
+use crate::Error;
+
+use super::{
+    constants::{
+        CommonIdentifier, CompressionFormat, DiagnosticMode, EncryptionFormat, ResetMode,
+        SecurityLevel, ServiceError, ServiceId, data_format_byte,
+    },
+    message::{Message, TransferType},
+    response::{ProcessError, Response},
+};
+
+/// UDS session and the KWP2000 diagnostic mode used for it.
+const SESSIONS: [(u8, DiagnosticMode); 3] = [
+    // default session
+    (0x01, DiagnosticMode::OBD),
+    (0x02, DiagnosticMode::Programming),
+    // extended diagnostic session
+    (0x03, DiagnosticMode::Diagnostics),
+];
+
+/// UDS service ids of the services both protocols have.
+const SERVICES: [(ServiceId, u8); 9] = [
+    (ServiceId::StartDiagnosticSession, 0x10),
+    (ServiceId::ECUReset, 0x11),
+    (ServiceId::SecurityAccess, 0x27),
+    (ServiceId::ReadDataByCommonIdentifier, 0x22),
+    (ServiceId::RequestDownload, 0x34),
+    (ServiceId::RequestUpload, 0x35),
+    (ServiceId::TransferData, 0x36),
+    (ServiceId::RequestTransferExit, 0x37),
+    (ServiceId::TesterPresent, 0x3E),
+];
+
+/// UDS negative response codes of the KWP2000 errors with the same
+/// meaning. The download and upload rejections all become
+/// uploadDownloadNotAccepted, translating back gives `DownloadNotAccepted`.
+const NEGATIVE_RESPONSE_CODES: [(ServiceError, u8); 18] = [
+    (ServiceError::GeneralReject, 0x10),
+    (ServiceError::ServiceNotSupported, 0x11),
+    (ServiceError::FunctionNotSupportedOrInvalidFormat, 0x12),
+    (ServiceError::BusyRepeatRequest, 0x21),
+    (ServiceError::ConditionsNotCorrect, 0x22),
+    (ServiceError::RequestOutOfRange, 0x31),
+    (ServiceError::SecurityAccessRequired, 0x33),
+    (ServiceError::InvalidKey, 0x35),
+    (ServiceError::TooManyAttempts, 0x36),
+    (ServiceError::RequestingTooFast, 0x37),
+    (ServiceError::DownloadNotAccepted, 0x70),
+    (ServiceError::ImproperDownloadType, 0x70),
+    (ServiceError::CannotDownloadToAddress, 0x70),
+    (ServiceError::BadDownloadSize, 0x70),
+    (ServiceError::UploadNotAccepted, 0x70),
+    (ServiceError::TransferSuspended, 0x71),
+    (ServiceError::ResponsePending, 0x78),
+    (ServiceError::ServiceNotSupportedInActiveMode, 0x7F),
+];
+
+/// UDS hard reset, the only reset type with a KWP2000 equivalent.
+const HARD_RESET: u8 = 0x01;
+
+/// UDS request of a service KWP2000 also has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UdsRequest {
+    DiagnosticSessionControl {
+        session: u8,
+    },
+    EcuReset {
+        reset_type: u8,
+    },
+    /// `data` is empty for seed requests (odd sub functions) and the key
+    /// otherwise.
+    SecurityAccess {
+        sub_function: u8,
+        data: Vec<u8>,
+    },
+    /// Translated to `ReadDataByCommonIdentifier`
+    ReadDataByIdentifier {
+        identifier: u16,
+    },
+    /// `data_format` has the compression in the high and the encryption in
+    /// the low nibble, in both protocols.
+    RequestDownload {
+        data_format: u8,
+        address: u32,
+        size: u32,
+    },
+    RequestUpload {
+        data_format: u8,
+        address: u32,
+        size: u32,
+    },
+    /// The block sequence counter is the first byte of `data` if the ECU
+    /// uses one (see `BlockCounter`), as in `Message::SendData`.
+    TransferData {
+        data: Vec<u8>,
+    },
+    RequestTransferExit,
+    TesterPresent {
+        suppress_response: bool,
+    },
+}
+
+/// UDS positive or negative response of a service KWP2000 also has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UdsResponse {
+    DiagnosticSessionControl {
+        session: u8,
+    },
+    EcuReset,
+    /// Seed for odd sub functions, empty after the key was accepted.
+    SecurityAccess {
+        sub_function: u8,
+        seed: Vec<u8>,
+    },
+    ReadDataByIdentifier {
+        identifier: u16,
+        data: Vec<u8>,
+    },
+    RequestDownload {
+        max_block_length: u16,
+    },
+    RequestUpload {
+        max_block_length: u16,
+    },
+    TransferData {
+        data: Vec<u8>,
+    },
+    RequestTransferExit,
+    TesterPresent,
+    NegativeResponse {
+        service: u8,
+        code: u8,
+    },
+}
+
+fn uds_session(mode: DiagnosticMode) -> Result<u8, Error> {
+    SESSIONS
+        .iter()
+        .find(|(_, m)| *m == mode)
+        .map(|(s, _)| *s)
+        .ok_or(Error::NotTranslatable("diagnostic mode"))
+}
+
+fn kwp_mode(session: u8) -> Result<DiagnosticMode, Error> {
+    SESSIONS
+        .iter()
+        .find(|(s, _)| *s == session)
+        .map(|(_, m)| *m)
+        .ok_or(Error::NotTranslatable("diagnostic session"))
+}
+
+fn parse_data_format(byte: u8) -> Result<(CompressionFormat, EncryptionFormat), Error> {
+    CompressionFormat::from_repr(byte & 0xF0)
+        .zip(EncryptionFormat::from_repr(byte & 0x0F))
+        .ok_or(Error::NotTranslatable("data format"))
+}
+
+/// Translates `message` to the UDS request for the same service.
+pub fn to_uds_request(message: Message) -> Result<UdsRequest, Error> {
+    Ok(match message {
+        Message::StartDiagnosticSession(mode, None) => UdsRequest::DiagnosticSessionControl {
+            session: uds_session(mode)?,
+        },
+        Message::StartDiagnosticSession(_, Some(_)) => {
+            return Err(Error::NotTranslatable("baud rate change"));
+        }
+        Message::ECUReset(ResetMode::PowerOn) => UdsRequest::EcuReset {
+            reset_type: HARD_RESET,
+        },
+        Message::ECUReset(_) => return Err(Error::NotTranslatable("reset mode")),
+        Message::RequestSecuritySeed => UdsRequest::SecurityAccess {
+            sub_function: 0x01,
+            data: Vec::new(),
+        },
+        Message::SendSecurityKey(key) => UdsRequest::SecurityAccess {
+            sub_function: 0x02,
+            data: key.to_be_bytes().to_vec(),
+        },
+        Message::ReadCommonIdentifier(id) => UdsRequest::ReadDataByIdentifier { identifier: id.0 },
+        Message::RequestDataTransfer {
+            transfer_type,
+            address,
+            size,
+            encryption,
+            compression,
+        } => {
+            let data_format = data_format_byte(compression, encryption);
+            match transfer_type {
+                TransferType::Download => UdsRequest::RequestDownload {
+                    data_format,
+                    address,
+                    size,
+                },
+                TransferType::Upload => UdsRequest::RequestUpload {
+                    data_format,
+                    address,
+                    size,
+                },
+            }
+        }
+        Message::SendData(data) => UdsRequest::TransferData { data },
+        Message::RequestData => UdsRequest::TransferData { data: Vec::new() },
+        Message::RequestTransferExit => UdsRequest::RequestTransferExit,
+        Message::TesterPresent(respond) => UdsRequest::TesterPresent {
+            suppress_response: !respond,
+        },
+        Message::ClearLocalIdentifier(_)
+        | Message::ReadLocalIdentifier(..)
+        | Message::WriteLocalIdentifier(..)
+        | Message::DefineLocalIdentifierAddress(..) => {
+            return Err(Error::NotTranslatable("local identifier"));
+        }
+        Message::GetCurrentTiming
+        | Message::GetDefaultTiming
+        | Message::GetTimingLimits
+        | Message::ChangeTimingParameters { .. } => {
+            return Err(Error::NotTranslatable("timing parameters"));
+        }
+        Message::StopCommunication
+        | Message::ReadDiagnosticTroubleCodes { .. }
+        | Message::ReadDtcByStatus { .. }
+        | Message::ReadStatusOfDtc(_)
+        | Message::ReadFreezeFrame { .. }
+        | Message::ReadCommonIdentifiers(_)
+        | Message::WriteCommonIdentifier(..)
+        | Message::ClearDiagnosticInformation { .. }
+        | Message::StopDiagnosticSession
+        | Message::ReadMemoryByAddress { .. }
+        | Message::WriteMemoryByAddress { .. }
+        | Message::SetDataRates { .. }
+        | Message::ReadDataRates
+        | Message::ReadECUIdentification(_)
+        | Message::StartRoutine(..)
+        | Message::StopRoutine(..)
+        | Message::StartRoutineByAddress { .. }
+        | Message::IoControlByLocalIdentifier { .. }
+        | Message::IoControlByCommonIdentifier { .. }
+        | Message::StopRoutineByAddress { .. }
+        | Message::RequestRoutineResultsByAddress { .. }
+        | Message::RequestRoutineResults(_) => {
+            return Err(Error::NotTranslatable("service"));
+        }
+    })
+}
+
+/// Translates `request` to the KWP2000 message for the same service.
+pub fn from_uds_request(request: UdsRequest) -> Result<Message, Error> {
+    Ok(match request {
+        UdsRequest::DiagnosticSessionControl { session } => {
+            Message::StartDiagnosticSession(kwp_mode(session)?, None)
+        }
+        UdsRequest::EcuReset {
+            reset_type: HARD_RESET,
+        } => Message::ECUReset(ResetMode::PowerOn),
+        UdsRequest::EcuReset { .. } => return Err(Error::NotTranslatable("reset type")),
+        // only the first level is supported by `Message`
+        UdsRequest::SecurityAccess {
+            sub_function: 0x01,
+            data,
+        } if data.is_empty() => Message::RequestSecuritySeed,
+        UdsRequest::SecurityAccess {
+            sub_function: 0x02,
+            data,
+        } => Message::SendSecurityKey(u32::from_be_bytes(
+            data.try_into()
+                .map_err(|_| Error::NotTranslatable("security key length"))?,
+        )),
+        UdsRequest::SecurityAccess { .. } => {
+            return Err(Error::NotTranslatable("security access level"));
+        }
+        UdsRequest::ReadDataByIdentifier { identifier } => {
+            Message::ReadCommonIdentifier(CommonIdentifier(identifier))
+        }
+        UdsRequest::RequestDownload {
+            data_format,
+            address,
+            size,
+        }
+        | UdsRequest::RequestUpload {
+            data_format,
+            address,
+            size,
+        } => {
+            let (compression, encryption) = parse_data_format(data_format)?;
+            Message::RequestDataTransfer {
+                transfer_type: if matches!(request, UdsRequest::RequestDownload { .. }) {
+                    TransferType::Download
+                } else {
+                    TransferType::Upload
+                },
+                address,
+                size,
+                encryption,
+                compression,
+            }
+        }
+        UdsRequest::TransferData { data } if data.is_empty() => Message::RequestData,
+        UdsRequest::TransferData { data } => Message::SendData(data),
+        UdsRequest::RequestTransferExit => Message::RequestTransferExit,
+        UdsRequest::TesterPresent { suppress_response } => {
+            Message::TesterPresent(!suppress_response)
+        }
+    })
+}
+
+/// Translates `response` to the UDS response of the same service.
+pub fn to_uds_response(response: Response) -> Result<UdsResponse, Error> {
+    Ok(match response {
+        Response::StartedDiagnosticMode(mode, _) => UdsResponse::DiagnosticSessionControl {
+            session: uds_session(mode)?,
+        },
+        Response::EcuReset => UdsResponse::EcuReset,
+        Response::SecurityAccessSeed(level, seed) => UdsResponse::SecurityAccess {
+            sub_function: level as u8,
+            seed,
+        },
+        Response::SecurityAccessGranted(level) => UdsResponse::SecurityAccess {
+            sub_function: level as u8,
+            seed: Vec::new(),
+        },
+        Response::CommonIdentifierRead(id, data) => UdsResponse::ReadDataByIdentifier {
+            identifier: id.0,
+            data,
+        },
+        Response::DownloadConfirmation(max_block_length) => {
+            UdsResponse::RequestDownload { max_block_length }
+        }
+        Response::UploadConfirmation(max_block_length) => {
+            UdsResponse::RequestUpload { max_block_length }
+        }
+        Response::DataTransfer(data) => UdsResponse::TransferData { data },
+        Response::ReadyForMoreData => UdsResponse::TransferData { data: Vec::new() },
+        Response::TransferExited => UdsResponse::RequestTransferExit,
+        Response::TesterPresent => UdsResponse::TesterPresent,
+        Response::Error(ProcessError { error, service, .. }) => UdsResponse::NegativeResponse {
+            service: SERVICES
+                .iter()
+                .find(|(s, _)| *s == service)
+                .map(|(_, uds)| *uds)
+                .ok_or(Error::NotTranslatable("service"))?,
+            code: NEGATIVE_RESPONSE_CODES
+                .iter()
+                .find(|(e, _)| *e == error)
+                .map(|(_, code)| *code)
+                .ok_or(Error::NotTranslatable("negative response code"))?,
+        },
+        Response::StillProcessing(_) => {
+            return Err(Error::NotTranslatable("pending response"));
+        }
+        Response::LocalIdentifierDefined(_)
+        | Response::LocalIdentifierRead(..)
+        | Response::LocalIdentifierWritten(_) => {
+            return Err(Error::NotTranslatable("local identifier"));
+        }
+        Response::TimingParameters { .. }
+        | Response::TimingRestoredToDefault
+        | Response::TimingSet => return Err(Error::NotTranslatable("timing parameters")),
+        Response::MemoryAddressRead(..)
+        | Response::MemoryAddressWritten(_)
+        | Response::DataRates(_)
+        | Response::DataRatesSet
+        | Response::DiagnosticSessionStopped
+        | Response::CommunicationStopped
+        | Response::Echo(_)
+        | Response::EcuIdentification(..)
+        | Response::ObdData(..)
+        | Response::DiagnosticTroubleCodes(..)
+        | Response::DtcsByStatus(..)
+        | Response::DtcStatus { .. }
+        | Response::FreezeFrame(..)
+        | Response::DiagnosticInformationCleared
+        | Response::RoutineStarted(..)
+        | Response::RoutineStopped(..)
+        | Response::RoutineByAddressStarted(..)
+        | Response::IoControlled(..)
+        | Response::IoControlledCommon(..)
+        | Response::CommonIdentifierWritten(_)
+        | Response::RoutineByAddressStopped(..)
+        | Response::RoutineByAddressResults(..)
+        | Response::RoutineResults(..) => return Err(Error::NotTranslatable("service")),
+    })
+}
+
+/// Translates `response` to the KWP2000 response of the same service.
+pub fn from_uds_response(response: UdsResponse) -> Result<Response, Error> {
+    Ok(match response {
+        UdsResponse::DiagnosticSessionControl { session } => {
+            Response::StartedDiagnosticMode(kwp_mode(session)?, None)
+        }
+        UdsResponse::EcuReset => Response::EcuReset,
+        UdsResponse::SecurityAccess { sub_function, seed } => {
+            let level = SecurityLevel::from_repr(sub_function)
+                .ok_or(Error::NotTranslatable("security access level"))?;
+            if seed.is_empty() {
+                Response::SecurityAccessGranted(level)
+            } else {
+                Response::SecurityAccessSeed(level, seed)
+            }
+        }
+        UdsResponse::ReadDataByIdentifier { identifier, data } => {
+            Response::CommonIdentifierRead(CommonIdentifier(identifier), data)
+        }
+        UdsResponse::RequestDownload { max_block_length } => {
+            Response::DownloadConfirmation(max_block_length)
+        }
+        UdsResponse::RequestUpload { max_block_length } => {
+            Response::UploadConfirmation(max_block_length)
+        }
+        UdsResponse::TransferData { data } if data.is_empty() => Response::ReadyForMoreData,
+        UdsResponse::TransferData { data } => Response::DataTransfer(data),
+        UdsResponse::RequestTransferExit => Response::TransferExited,
+        UdsResponse::TesterPresent => Response::TesterPresent,
+        UdsResponse::NegativeResponse { service, code } => {
+            let service = SERVICES
+                .iter()
+                .find(|(_, uds)| *uds == service)
+                .map(|(s, _)| *s)
+                .ok_or(Error::NotTranslatable("service"))?;
+            let error = NEGATIVE_RESPONSE_CODES
+                .iter()
+                .find(|(_, c)| *c == code)
+                .map(|(e, _)| *e)
+                .ok_or(Error::NotTranslatable("negative response code"))?;
+            if error == ServiceError::ResponsePending {
+                Response::StillProcessing(service)
+            } else {
+                Response::Error(ProcessError {
+                    error,
+                    service,
+                    extra: Vec::new(),
+                })
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kwp2000::constants::{LocalIdentifier, ReadMode};
+
+    /// Service byte and data of `message`, `Message` can't be compared.
+    fn bytes(message: Message) -> Vec<u8> {
+        let raw = message.raw().unwrap();
+        [vec![raw.service.into()], raw.data].concat()
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = [
+            (
+                Message::StartDiagnosticSession(DiagnosticMode::Programming, None),
+                UdsRequest::DiagnosticSessionControl { session: 0x02 },
+            ),
+            (
+                Message::ECUReset(ResetMode::PowerOn),
+                UdsRequest::EcuReset { reset_type: 0x01 },
+            ),
+            (
+                Message::RequestSecuritySeed,
+                UdsRequest::SecurityAccess {
+                    sub_function: 0x01,
+                    data: vec![],
+                },
+            ),
+            (
+                Message::SendSecurityKey(0x12345678),
+                UdsRequest::SecurityAccess {
+                    sub_function: 0x02,
+                    data: vec![0x12, 0x34, 0x56, 0x78],
+                },
+            ),
+            (
+                Message::ReadCommonIdentifier(CommonIdentifier(0xF190)),
+                UdsRequest::ReadDataByIdentifier { identifier: 0xF190 },
+            ),
+            (
+                Message::RequestDataTransfer {
+                    transfer_type: TransferType::Download,
+                    address: 0x800000,
+                    size: 0x4000,
+                    encryption: EncryptionFormat::Bosch,
+                    compression: CompressionFormat::Bosch,
+                },
+                UdsRequest::RequestDownload {
+                    data_format: 0x11,
+                    address: 0x800000,
+                    size: 0x4000,
+                },
+            ),
+            (
+                Message::RequestDataTransfer {
+                    transfer_type: TransferType::Upload,
+                    address: 0x380000,
+                    size: 0x100,
+                    encryption: EncryptionFormat::Unencrypted,
+                    compression: CompressionFormat::Uncompressed,
+                },
+                UdsRequest::RequestUpload {
+                    data_format: 0x00,
+                    address: 0x380000,
+                    size: 0x100,
+                },
+            ),
+            (
+                Message::SendData(vec![0x01, 0xAA]),
+                UdsRequest::TransferData {
+                    data: vec![0x01, 0xAA],
+                },
+            ),
+            (
+                Message::RequestData,
+                UdsRequest::TransferData { data: vec![] },
+            ),
+            (
+                Message::RequestTransferExit,
+                UdsRequest::RequestTransferExit,
+            ),
+            (
+                Message::TesterPresent(false),
+                UdsRequest::TesterPresent {
+                    suppress_response: true,
+                },
+            ),
+        ];
+        for (message, request) in requests {
+            let expected = bytes(message.clone());
+            assert_eq!(to_uds_request(message).unwrap(), request);
+            assert_eq!(bytes(from_uds_request(request).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn untranslatable_requests() {
+        for (message, concept) in [
+            (
+                Message::ReadLocalIdentifier(LocalIdentifier(0xF0), ReadMode::Single, 1),
+                "local identifier",
+            ),
+            (Message::GetCurrentTiming, "timing parameters"),
+            (
+                Message::StartDiagnosticSession(DiagnosticMode::Programming, Some(0x14)),
+                "baud rate change",
+            ),
+            (
+                Message::StartDiagnosticSession(DiagnosticMode::EndOfLineVW, None),
+                "diagnostic mode",
+            ),
+            (
+                Message::ECUReset(ResetMode::NonvolatileMemory),
+                "reset mode",
+            ),
+            (Message::StopCommunication, "service"),
+        ] {
+            assert!(
+                matches!(to_uds_request(message), Err(Error::NotTranslatable(c)) if c == concept),
+                "{concept}"
+            );
+        }
+        for (request, concept) in [
+            (
+                UdsRequest::DiagnosticSessionControl { session: 0x04 },
+                "diagnostic session",
+            ),
+            (UdsRequest::EcuReset { reset_type: 0x03 }, "reset type"),
+            (
+                UdsRequest::SecurityAccess {
+                    sub_function: 0x03,
+                    data: vec![],
+                },
+                "security access level",
+            ),
+            (
+                UdsRequest::SecurityAccess {
+                    sub_function: 0x02,
+                    data: vec![0x12, 0x34],
+                },
+                "security key length",
+            ),
+            (
+                UdsRequest::RequestDownload {
+                    data_format: 0x55,
+                    address: 0,
+                    size: 1,
+                },
+                "data format",
+            ),
+        ] {
+            assert!(
+                matches!(from_uds_request(request), Err(Error::NotTranslatable(c)) if c == concept),
+                "{concept}"
+            );
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let seed = to_uds_response(Response::SecurityAccessSeed(
+            SecurityLevel::Seed1,
+            vec![0xAB, 0xCD],
+        ))
+        .unwrap();
+        assert_eq!(
+            seed,
+            UdsResponse::SecurityAccess {
+                sub_function: 0x01,
+                seed: vec![0xAB, 0xCD]
+            }
+        );
+        assert!(matches!(
+            from_uds_response(seed).unwrap(),
+            Response::SecurityAccessSeed(SecurityLevel::Seed1, s) if s == [0xAB, 0xCD]
+        ));
+        let granted =
+            to_uds_response(Response::SecurityAccessGranted(SecurityLevel::Key1)).unwrap();
+        assert!(matches!(
+            from_uds_response(granted).unwrap(),
+            Response::SecurityAccessGranted(SecurityLevel::Key1)
+        ));
+        let read = to_uds_response(Response::CommonIdentifierRead(
+            CommonIdentifier(0xF190),
+            vec![0x57],
+        ))
+        .unwrap();
+        assert!(matches!(
+            from_uds_response(read).unwrap(),
+            Response::CommonIdentifierRead(CommonIdentifier(0xF190), d) if d == [0x57]
+        ));
+        assert_eq!(
+            to_uds_response(Response::DownloadConfirmation(0x80)).unwrap(),
+            UdsResponse::RequestDownload {
+                max_block_length: 0x80
+            }
+        );
+        assert!(matches!(
+            from_uds_response(UdsResponse::TransferData { data: vec![] }).unwrap(),
+            Response::ReadyForMoreData
+        ));
+        assert!(matches!(
+            to_uds_response(Response::TimingSet),
+            Err(Error::NotTranslatable("timing parameters"))
+        ));
+    }
+
+    #[test]
+    fn negative_responses_are_mapped() {
+        let refused = |error| {
+            to_uds_response(Response::Error(ProcessError {
+                error,
+                service: ServiceId::RequestDownload,
+                extra: vec![],
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            refused(ServiceError::InvalidKey),
+            UdsResponse::NegativeResponse {
+                service: 0x34,
+                code: 0x35
+            }
+        );
+        // every download rejection is uploadDownloadNotAccepted
+        let rejected = refused(ServiceError::CannotDownloadToAddress);
+        assert_eq!(
+            rejected,
+            UdsResponse::NegativeResponse {
+                service: 0x34,
+                code: 0x70
+            }
+        );
+        assert!(matches!(
+            from_uds_response(rejected).unwrap(),
+            Response::Error(ProcessError {
+                error: ServiceError::DownloadNotAccepted,
+                service: ServiceId::RequestDownload,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_uds_response(UdsResponse::NegativeResponse {
+                service: 0x36,
+                code: 0x78
+            })
+            .unwrap(),
+            Response::StillProcessing(ServiceId::TransferData)
+        ));
+        assert!(matches!(
+            from_uds_response(UdsResponse::NegativeResponse {
+                service: 0x31,
+                code: 0x10
+            }),
+            Err(Error::NotTranslatable("service"))
+        ));
+    }
+
+    #[test]
+    fn tables_have_no_duplicates() {
+        for (i, (mode, session)) in SESSIONS.iter().map(|(s, m)| (m, s)).enumerate() {
+            assert!(
+                SESSIONS[i + 1..]
+                    .iter()
+                    .all(|(s, m)| s != session && m != mode)
+            );
+        }
+        for (i, (service, uds)) in SERVICES.iter().enumerate() {
+            assert!(
+                SERVICES[i + 1..]
+                    .iter()
+                    .all(|(s, u)| s != service && u != uds)
+            );
+        }
+    }
+}
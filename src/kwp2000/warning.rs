@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+#[cfg(feature = "flash")]
+use super::programming::ProgrammingStatus;
+use super::{
+    best_effort::ReadStrategy,
+    client::{BlockCounter, UploadBlockFormat},
+    constants::{ResetMode, ServiceId},
+    security::SeedDefect,
+};
+
+/// Maximum number of warnings kept by the client, older ones are dropped.
+pub const MAX_WARNINGS: usize = 64;
+
+/// Something the client worked around without failing, kept so it can be
+/// reviewed after the fact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The ECU lost the definition of the `dd_*` identifier, it was defined
+    /// again for the access at `address`.
+    IdentifierRedefined { address: u32 },
+    /// No upload block format was configured, `format` was detected from
+    /// the first block of the upload from `address`.
+    UploadFormatDetected {
+        address: u32,
+        format: UploadBlockFormat,
+    },
+    /// The profile's block counter was set to `BlockCounter::Detect`,
+    /// `counter` was detected from the first block of the upload from
+    /// `address`.
+    BlockCounterDetected { address: u32, counter: BlockCounter },
+    /// The block with sequence counter `counter` was transferred again, the
+    /// repeat was dropped (upload) or asked for by the ECU (download).
+    BlockRepeated { counter: u8 },
+    /// `read_best_effort` had to fall back to `strategy` for `address`
+    /// because the preferred strategies were refused.
+    ReadStrategyFallback {
+        address: u32,
+        strategy: ReadStrategy,
+    },
+    /// The ECU reported a failed previous programming attempt before a
+    /// flash write was started.
+    #[cfg(feature = "flash")]
+    HalfProgrammed { status: ProgrammingStatus },
+    /// The group of a flash write starting at `address` failed verification
+    /// and was sent again.
+    #[cfg(feature = "flash")]
+    VerifyRetry { address: u32 },
+    /// The profile doesn't know where the ECU keeps the tester fingerprint,
+    /// it was not written.
+    #[cfg(feature = "flash")]
+    FingerprintSkipped,
+    /// A request of `service` was held back for `delay` to respect the
+    /// profile's rate limits.
+    RateLimited { service: ServiceId, delay: Duration },
+    /// The ECU dropped the session, most likely because it reset itself, and
+    /// was initialized again. Initializing took `recovery`.
+    SessionInterrupted { recovery: Duration },
+    /// Initializing the ECU again failed with `error`, the interface was
+    /// closed and opened again.
+    InterfaceReset { error: String },
+    /// Nothing was sent or received for `idle`, close to the session's
+    /// `p3max`, see `Client::poll_watchdog`.
+    SessionExpiryImminent { idle: Duration, p3max: Duration },
+    /// The ECU did not answer the check after a baud rate switch at
+    /// `baud_rate`.
+    BaudProbeFailed { baud_rate: u32 },
+    /// `Client::attach` got no answer from the session assumed at
+    /// `baud_rate` and initialized the ECU again.
+    AttachFailed { baud_rate: u32 },
+    /// The ECU rejected a longer dynamic identifier definition, `dd_*`
+    /// accesses are split in pieces of at most `length` bytes.
+    DdLengthLimited { length: u8 },
+    /// `Client::detect_tester_present_inversion` found the ECU's
+    /// `TesterPresent` sub functions `inverted`, unlike the profile says.
+    TesterPresentInverted { inverted: bool },
+    /// The ECU refused `TesterPresent` in the active mode, the profile's
+    /// heartbeat keeps the session open instead.
+    TesterPresentUnsupported,
+    /// Undoing the session's changes to the ECU failed at `step`.
+    TeardownStepFailed { step: &'static str, error: String },
+    /// The security access `seed` doesn't look random, the ECU may be a
+    /// bench or unlocked unit.
+    DegenerateSeed { seed: Vec<u8>, defect: SeedDefect },
+    /// The ECU didn't answer a reset request, it was assumed to have reset
+    /// before it could.
+    ResetUnconfirmed { mode: ResetMode },
+}
@@ -0,0 +1,63 @@
+//! Safety net for applications that drive the session themselves and may
+//! stall long enough for the ECU to drop it.
+
+use std::time::Instant;
+
+use crate::Error;
+
+use super::{bus::BusActivity, client::Client, warning::Warning};
+
+/// Settings of `Client::poll_watchdog`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchdog {
+    /// Fraction of P3max without a frame after which the session is about
+    /// to expire.
+    pub threshold: f32,
+    /// Send a `TesterPresent` once the threshold has passed.
+    pub keep_alive: bool,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            keep_alive: true,
+        }
+    }
+}
+
+impl Client {
+    /// Checks the time since the last frame against `watchdog`, meant to be
+    /// called from a timer. Once the threshold has passed a
+    /// `Warning::SessionExpiryImminent` is recorded (once per quiet period)
+    /// and, if enabled, a `TesterPresent` is sent. Nothing is sent while the
+    /// bus is busy (see `Client::bus_activity`): the block transfer or the
+    /// ECU's `ResponsePending` and `RoutineNotComplete` answers keep the
+    /// session open. Returns whether the threshold had passed.
+    pub fn poll_watchdog(&mut self) -> Result<bool, Error> {
+        let (Some(watchdog), Some(last_frame)) = (self.watchdog, self.last_frame()) else {
+            return Ok(false);
+        };
+        let idle = last_frame.elapsed();
+        if idle < self.timing.session_fraction(watchdog.threshold) {
+            return Ok(false);
+        }
+        if self.watchdog_warned != Some(last_frame) {
+            self.watchdog_warned = Some(last_frame);
+            self.warn(Warning::SessionExpiryImminent {
+                idle,
+                p3max: self.timing.session_timeout(),
+            });
+        }
+        if watchdog.keep_alive && self.bus_activity() == BusActivity::Idle {
+            self.stats.keep_alives += 1;
+            self.tester_present()?;
+        }
+        Ok(true)
+    }
+    /// Time at which the session expires if nothing is sent, `None` without
+    /// a session.
+    pub fn session_expiry(&self) -> Option<Instant> {
+        Some(self.last_frame()? + self.timing.session_timeout())
+    }
+}
@@ -0,0 +1,404 @@
+use kwp2000::{
+    constants::ServiceId, policy::DangerousAction, response::Response,
+    security::SecurityAccessReport, transfer::TransferRejection,
+};
+#[cfg(feature = "flash")]
+use kwp2000::{
+    constants::{CompressionFormat, EncryptionFormat},
+    programming::ProgrammingStatus,
+};
+
+pub mod analysis;
+#[cfg(feature = "flash")]
+pub mod bcb;
+pub mod k_line;
+#[cfg(feature = "async")]
+pub mod k_line_async;
+pub mod kwp2000;
+pub mod port_lock;
+pub mod profile;
+#[cfg(feature = "transport-serialport")]
+pub mod repl;
+pub mod util;
+
+pub const KEY: &[u8; 6] = b"GEHEIM";
+
+pub const INIT_ADDRESS: u8 = 0x01;
+
+/// Engine, transmission, ABS, airbag, instrument cluster and immobilizer.
+pub const SCAN_ADDRESSES: [u8; 6] = [0x01, 0x02, 0x03, 0x15, 0x17, 0x25];
+
+#[derive(Debug, Clone)]
+pub struct MemoryLayout {
+    pub base_address: u32,
+    pub size: u32,
+    pub sectors: Vec<u32>,
+}
+
+impl MemoryLayout {
+    /// Number of sectors touched by the `size` bytes starting at `address`.
+    pub fn sectors_in_range(&self, address: u32, size: u32) -> usize {
+        let end = address as u64 + size as u64;
+        let mut start = self.base_address as u64;
+        let mut count = 0;
+        for sector in &self.sectors {
+            let sector_end = start + *sector as u64;
+            if size > 0 && (address as u64) < sector_end && end > start {
+                count += 1;
+            }
+            start = sector_end;
+        }
+        count
+    }
+
+    /// Index of the sector holding `address`, `None` outside the flash.
+    pub fn sector_index(&self, address: u32) -> Option<usize> {
+        let mut end = self.base_address as u64;
+        if (address as u64) < end {
+            return None;
+        }
+        self.sectors.iter().position(|sector| {
+            end += *sector as u64;
+            (address as u64) < end
+        })
+    }
+
+    /// Checks that the `size` bytes starting at `address` lie inside the
+    /// flash and, unless `partial_sectors` is set, start on a sector
+    /// boundary.
+    pub fn check_range(
+        &self,
+        address: u32,
+        size: u32,
+        partial_sectors: bool,
+    ) -> Result<(), LayoutViolation> {
+        let sectors: u64 = self.sectors.iter().map(|s| *s as u64).sum();
+        if sectors != self.size as u64 {
+            return Err(LayoutViolation::InconsistentLayout {
+                sectors,
+                size: self.size,
+            });
+        }
+        let base = self.base_address as u64;
+        let flash_end = base + self.size as u64;
+        let end = address as u64 + size as u64;
+        if (address as u64) < base || end > flash_end {
+            return Err(LayoutViolation::OutsideFlash {
+                address,
+                end,
+                base: self.base_address,
+                flash_end,
+            });
+        }
+        if !partial_sectors {
+            let mut sector_start = base;
+            for sector in &self.sectors {
+                if sector_start + *sector as u64 > address as u64 {
+                    break;
+                }
+                sector_start += *sector as u64;
+            }
+            if sector_start != address as u64 {
+                return Err(LayoutViolation::NotSectorAligned {
+                    address,
+                    sector_start,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason a flash write was rejected before anything was sent.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LayoutViolation {
+    #[error("profile layout sectors add up to {sectors} bytes but its size is {size}")]
+    InconsistentLayout { sectors: u64, size: u32 },
+    #[error(
+        "0x{address:06x}..0x{end:06x} is not inside the flash at 0x{base:06x}..0x{flash_end:06x}"
+    )]
+    OutsideFlash {
+        address: u32,
+        end: u64,
+        base: u32,
+        flash_end: u64,
+    },
+    #[error(
+        "0x{address:06x} is not on a sector boundary, the sector starts at 0x{sector_start:06x}"
+    )]
+    NotSectorAligned { address: u32, sector_start: u64 },
+    #[error("flash device id {got:02x?} does not match the profile's {expected:02x?}")]
+    FlashDeviceMismatch { expected: Vec<u8>, got: Vec<u8> },
+}
+
+pub mod memory_layout {
+    pub const BASE_ADDRESS: u32 = 8388608;
+    pub const SIZE: u32 = 1048576;
+
+    pub const SECTORS: [u32; 19] = [
+        16384, 8192, 8192, 32768, 65536, 65536, 65536, 65536, 65536, 65536, 65536, 65536, 65536,
+        65536, 65536, 65536, 65536, 65536, 65536,
+    ];
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("new diagnostic mode not expected")]
+    UnexpectedMode,
+    #[error("response not expected for given command")]
+    UnexpectedResponse(Response),
+    #[error("command being processed does not match last command sent")]
+    UnexpectedPending,
+    #[error("response repeats {field} 0x{got:x}, the request had 0x{expected:x}")]
+    ParameterEchoMismatch {
+        field: &'static str,
+        expected: u32,
+        got: u32,
+    },
+    #[error("{service:?} response has {got} data bytes, needs at least {need}")]
+    ResponseTooShort {
+        service: kwp2000::constants::ServiceResponse,
+        got: usize,
+        need: usize,
+    },
+    #[error("ECU answered ResponsePending {count} times to {service:?}, giving up")]
+    PendingLimitExceeded { service: ServiceId, count: u32 },
+    #[error("unexpected value")]
+    UnexpectedValue,
+    #[error("service not implemented client side")]
+    NotImplemented,
+    #[error("specified length is bigger than received data")]
+    NotEnoughData,
+    #[error("message data is {length} bytes long, at most {max} fit")]
+    MessageTooLong { length: usize, max: usize },
+    #[error("given checksum 0x{received:02x} does not match message contents (0x{computed:02x})")]
+    InvalidChecksum { received: u8, computed: u8 },
+    #[error("unknown service given")]
+    InvalidService,
+    #[error("unknown service error type")]
+    InvalidServiceError,
+    #[error("upload block for address 0x{got:06x} received, expected 0x{expected:06x}")]
+    UploadAddressMismatch { expected: u32, got: u32 },
+    #[error("block {expected} missing, received block {got}")]
+    BlockSequenceGap { expected: u8, got: u8 },
+    #[error("dynamic identifier definition lost too many times in a row")]
+    DefinitionUnstable,
+    #[error("operation deadline exceeded after {completed} bytes")]
+    DeadlineExceeded { completed: usize },
+    #[error("operation denied by danger policy")]
+    DeniedByPolicy(DangerousAction),
+    #[error(
+        "security algorithm does not match profile vectors (seed {seed:02x?}, expected key 0x{expected:08x}, got 0x{got:08x}), wrong ECU family?"
+    )]
+    SecurityAlgorithmMismatch {
+        seed: [u8; 4],
+        expected: u32,
+        got: u32,
+    },
+    #[cfg(feature = "flash")]
+    #[error("previous programming attempt failed ({0:?}), flash write needs to be forced")]
+    HalfProgrammed(ProgrammingStatus),
+    #[error("security access denied ({0})")]
+    SecurityAccessDenied(Box<SecurityAccessReport>),
+    #[cfg(feature = "flash")]
+    #[error("no codec registered for {compression:?} compression with {encryption:?} encryption")]
+    UnsupportedDataFormat {
+        compression: CompressionFormat,
+        encryption: EncryptionFormat,
+    },
+    #[cfg(feature = "flash")]
+    #[error("flash write rejected: {0}")]
+    FlashRangeRejected(LayoutViolation),
+    #[error("batch entries write different values to 0x{address:06x}")]
+    WriteConflict { address: u32 },
+    #[cfg(feature = "flash")]
+    #[error("flash at 0x{address:06x} failed verification, {verified} bytes verified")]
+    VerifyFailed { address: u32, verified: usize },
+    #[cfg(feature = "diag")]
+    #[error("ECU does not expose its {0}")]
+    NotExposed(&'static str),
+    #[cfg(feature = "diag")]
+    #[error("malformed {what} received: {data:02x?}")]
+    MalformedIdentification { what: &'static str, data: Vec<u8> },
+    #[error("read back {got:02x?} from 0x{address:06x} after writing {expected:02x?}")]
+    VerifyMismatch {
+        address: u32,
+        expected: Vec<u8>,
+        got: Vec<u8>,
+    },
+    #[cfg(feature = "uds_compat")]
+    #[error("{0} can't be translated between KWP2000 and UDS")]
+    NotTranslatable(&'static str),
+    #[error("ECU does not know DTC {0:04X}")]
+    UnknownDtc(u16),
+    #[error("security timeout in effect")]
+    SecurityTimout,
+    #[error("bus did not go quiet before init, {observed} bytes received")]
+    BusBusy { observed: usize, sample: Vec<u8> },
+    #[error("port is in use by process {holder_pid}, pass --force to use it anyway")]
+    PortBusy { holder_pid: u32 },
+    #[error("read stopped at 0x{at_address:06x}")]
+    PartialRead {
+        /// Data read before stopping, empty for reads that streamed it to
+        /// a writer already
+        data: Vec<u8>,
+        at_address: u32,
+        source: Box<Error>,
+    },
+    #[error("transfer request rejected: {0}")]
+    TransferRejected(TransferRejection),
+    #[error("definition file line {line}: {reason}")]
+    DefinitionParse { line: usize, reason: String },
+    #[error("capture line {line}: {reason}")]
+    CaptureParse { line: usize, reason: String },
+    #[error("security seed {seed:02x?} is not 4 bytes long")]
+    InvalidSeedLength { seed: Vec<u8> },
+    #[error("cannot write {name}: {reason}")]
+    LiveValueRejected { name: String, reason: String },
+    #[error("ECU did not switch to {requested} baud, still at {current}")]
+    BaudSwitchRejected { requested: u32, current: u32 },
+    #[error("only {remaining} security access attempts left, not sending a key")]
+    SecurityAttemptsLow { remaining: u8 },
+    #[error("identification 0x{option:02x} is {got:02x?}, expected {expected:02x?}")]
+    WrongEcu {
+        option: u8,
+        expected: Vec<u8>,
+        got: Vec<u8>,
+    },
+    #[error("identification 0x{option:02x} stopped after {} of {expected} bytes", data.len())]
+    IncompleteIdentification {
+        option: u8,
+        expected: usize,
+        data: Vec<u8>,
+        source: Box<Error>,
+    },
+    #[error("the ECU rejected a dynamic identifier of {length} bytes")]
+    DdLengthRejected { length: u8 },
+    #[error("a block transfer is in progress")]
+    BusBusyWithTransfer,
+    #[error("keep-alive not sent, the bus is busy ({0:?})")]
+    KeepAliveSkipped(kwp2000::bus::BusActivity),
+    #[error("cannot resume dump: {0}")]
+    ResumeRejected(String),
+    #[error("dump failed its integrity check: {0}")]
+    DumpCorrupted(String),
+    #[error("{0:?} requires security access")]
+    SecurityAccessRequired(ServiceId),
+    #[error("ECU does not support setting data rates")]
+    DataRatesNotSupported,
+    #[error("no ECU connected")]
+    NotConnected,
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "transport-serialport")]
+    #[error("connect timed out in phase {phase:?}")]
+    ConnectTimedOut {
+        phase: kwp2000::quick_connect::ConnectPhase,
+    },
+    #[cfg(feature = "transport-serialport")]
+    #[error("serialport error")]
+    SerialPort(#[from] serialport::Error),
+}
+
+impl Error {
+    /// Wraps `source` in `Error::PartialRead` if `data`, read starting at
+    /// `address`, is not empty.
+    pub(crate) fn partial(data: Vec<u8>, address: u32, source: Error) -> Error {
+        if data.is_empty() {
+            source
+        } else {
+            Error::PartialRead {
+                at_address: address.wrapping_add(data.len() as u32),
+                data,
+                source: Box::new(source),
+            }
+        }
+    }
+}
+
+/// Settings of the serial port at `path` before the initialization.
+#[cfg(feature = "transport-serialport")]
+pub fn port_builder(path: &str) -> serialport::SerialPortBuilder {
+    serialport::new(path, kwp2000::INIT_BAUD_RATE)
+        .timeout(std::time::Duration::from_millis(4000)) // ecu P3 default is 5000, but I want a bit of leeway so I can close the session cleanly
+        .flow_control(serialport::FlowControl::None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> MemoryLayout {
+        MemoryLayout {
+            base_address: memory_layout::BASE_ADDRESS,
+            size: memory_layout::SIZE,
+            sectors: memory_layout::SECTORS.to_vec(),
+        }
+    }
+
+    #[test]
+    fn range_must_be_inside_the_flash() {
+        let layout = layout();
+        let base = layout.base_address;
+        let flash_end = base as u64 + layout.size as u64;
+        assert_eq!(layout.check_range(base, layout.size, false), Ok(()));
+        assert_eq!(
+            layout.check_range(base - 1, 16, true),
+            Err(LayoutViolation::OutsideFlash {
+                address: base - 1,
+                end: base as u64 + 15,
+                base,
+                flash_end,
+            })
+        );
+        // the last sector up to the last byte, and one byte past it
+        let last = (flash_end - 0x10000) as u32;
+        assert_eq!(layout.check_range(last, 0x10000, false), Ok(()));
+        assert_eq!(
+            layout.check_range(last, 0x10001, false),
+            Err(LayoutViolation::OutsideFlash {
+                address: last,
+                end: flash_end + 1,
+                base,
+                flash_end,
+            })
+        );
+        assert!(layout.check_range(u32::MAX, 16, true).is_err());
+    }
+
+    #[test]
+    fn range_must_start_on_a_sector() {
+        let layout = layout();
+        let second = layout.base_address + layout.sectors[0];
+        assert_eq!(layout.check_range(second, 16, false), Ok(()));
+        assert_eq!(
+            layout.check_range(second + 1, 16, false),
+            Err(LayoutViolation::NotSectorAligned {
+                address: second + 1,
+                sector_start: second as u64,
+            })
+        );
+        assert_eq!(
+            layout.check_range(second - 1, 16, false),
+            Err(LayoutViolation::NotSectorAligned {
+                address: second - 1,
+                sector_start: layout.base_address as u64,
+            })
+        );
+        assert_eq!(layout.check_range(second + 1, 16, true), Ok(()));
+    }
+
+    #[test]
+    fn sectors_must_add_up_to_the_size() {
+        let mut layout = layout();
+        layout.sectors.pop();
+        assert_eq!(
+            layout.check_range(layout.base_address, 16, true),
+            Err(LayoutViolation::InconsistentLayout {
+                sectors: memory_layout::SIZE as u64 - 0x10000,
+                size: memory_layout::SIZE,
+            })
+        );
+    }
+}
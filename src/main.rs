@@ -1,86 +1,315 @@
-#![feature(iter_map_windows)]
+#![cfg_attr(feature = "transport-serialport", feature(iter_map_windows))]
 
-use std::{fs::OpenOptions, io::Read, io::Write, time::Duration};
-
-use k_line::KLine;
-use kwp2000::{
-    client::Client,
-    constants::{ServiceError, ServiceId},
-    raw_message::RawMessage,
-    response::{ProcessError, Response},
+#[cfg(feature = "transport-serialport")]
+use {
+    ecu_flasher::{
+        Error, INIT_ADDRESS, SCAN_ADDRESSES, analysis,
+        analysis::capture::Direction,
+        k_line::{InitTiming, KLine, SerialInterface},
+        kwp2000::{
+            attach::{ConnectOptions, SessionState},
+            client::Client,
+            diagnostics::{self, Hint},
+            dump::DumpManifest,
+            image,
+            progress::Progress,
+            response,
+            stats::ClientStats,
+        },
+        memory_layout, port_builder,
+        port_lock::PortLock,
+        repl, util,
+    },
+    std::{
+        fs::{File, OpenOptions},
+        io::Read,
+        io::Write,
+        path::Path,
+        path::PathBuf,
+    },
 };
 
-pub mod bcb;
-pub mod k_line;
-pub mod kwp2000;
+#[cfg(not(feature = "transport-serialport"))]
+fn main() {
+    eprintln!("built without a transport, enable the transport-serialport feature");
+}
 
-pub const KEY: &[u8; 6] = b"GEHEIM";
+/// Value following `name` on the command line.
+#[cfg(feature = "transport-serialport")]
+fn option_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|a| a != name).nth(1)
+}
 
-pub const INIT_ADDRESS: u8 = 0x01;
+/// Command line arguments after the subcommand that aren't options.
+#[cfg(feature = "transport-serialport")]
+fn positional_args() -> Vec<String> {
+    let mut args = Vec::new();
+    let mut iter = std::env::args().skip(2);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--force" | "--attach" | "--sidecar" | "--ignore-sidecar" => {}
+            "--status-file" | "--resume" | "--from" | "--transcript" => {
+                iter.next();
+            }
+            _ => args.push(arg),
+        }
+    }
+    args
+}
 
-#[derive(Debug, Clone)]
-pub struct MemoryLayout {
-    pub base_address: u32,
-    pub size: u32,
-    pub sectors: Vec<u32>,
+/// Replaces `path` with `progress` as JSON. Written to a temporary file
+/// that is renamed over it so readers never see a partial document.
+#[cfg(feature = "transport-serialport")]
+fn write_status_file(path: &str, progress: &Progress) -> std::io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, progress.to_json())?;
+    std::fs::rename(tmp, path)
 }
 
-pub mod memory_layout {
-    pub const BASE_ADDRESS: u32 = 8388608;
-    pub const SIZE: u32 = 1048576;
+#[cfg(feature = "transport-serialport")]
+fn print_hints(hints: &[Hint]) {
+    for hint in hints {
+        eprintln!("hint: {hint}");
+    }
+}
 
-    pub const SECTORS: [u32; 19] = [
-        16384, 8192, 8192, 32768, 65536, 65536, 65536, 65536, 65536, 65536, 65536, 65536, 65536,
-        65536, 65536, 65536, 65536, 65536, 65536,
-    ];
+/// Start of dumped data shown on the terminal.
+#[cfg(feature = "transport-serialport")]
+fn preview(data: &[u8]) -> &[u8] {
+    &data[..data.len().min(256)]
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("new diagnostic mode not expected")]
-    UnexpectedMode,
-    #[error("response not expected for given command")]
-    UnexpectedResponse(Response),
-    #[error("command being processed does not match last command sent")]
-    UnexpectedPending,
-    #[error("unexpected value")]
-    UnexpectedValue,
-    #[error("service not implemented client side")]
-    NotImplemented,
-    #[error("specified length is bigger than received data")]
-    NotEnoughData,
-    #[error("given checksum does not match message contents")]
-    InvalidChecksum,
-    #[error("unknown service given")]
-    InvalidService,
-    #[error("unknown service error type")]
-    InvalidServiceError,
-    #[error("security timeout in effect")]
-    SecurityTimout,
-    #[error("io error")]
-    Io(#[from] std::io::Error),
-    #[cfg(feature = "serialport")]
-    #[error("serialport error")]
-    SerialPort(#[from] serialport::Error),
+/// Prints the regions of a dump named by a definition file.
+#[cfg(feature = "transport-serialport")]
+fn inspect() -> Result<(), Error> {
+    let (Some(defs), Some(dump)) = (option_value("--defs"), option_value("--dump")) else {
+        println!(
+            "usage: inspect --defs <file> --dump <file> [--base <address>] [--ignore-sidecar]"
+        );
+        return Ok(());
+    };
+    let base = match option_value("--base") {
+        Some(base) => u32::from_str_radix(base.trim_start_matches("0x"), 16)
+            .map_err(|_| Error::UnexpectedValue)?,
+        None => memory_layout::BASE_ADDRESS,
+    };
+    let definitions = analysis::definitions::load(Path::new(&defs))?;
+    let force = std::env::args().any(|a| a == "--ignore-sidecar");
+    let data = image::read_dump(Path::new(&dump), force)?;
+    for region in analysis::annotate(&data, base, &definitions) {
+        let definition = region.definition;
+        print!(
+            "{} at 0x{:06x}, {} bytes",
+            definition.name, definition.address, definition.size
+        );
+        if let Some((columns, rows)) = definition.axes {
+            print!(", {columns}x{rows}");
+        }
+        println!();
+        match region.data {
+            Some(bytes) => print!("{}", util::hexdump_to_string(definition.address, bytes)),
+            None => println!("missing from the dump"),
+        }
+    }
+    Ok(())
 }
 
+/// Checks a dump against the sidecar written next to it.
+#[cfg(feature = "transport-serialport")]
+fn verify_file() -> Result<(), Error> {
+    let args = positional_args();
+    let [path] = args.as_slice() else {
+        println!("usage: verify-file <file>");
+        return Ok(());
+    };
+    let sidecar = image::verify_sidecar(Path::new(path))?;
+    println!(
+        "{path}: {} bytes from 0x{:06x}, {} chunks ok",
+        sidecar.size,
+        sidecar.base_address,
+        sidecar.chunk_crcs.len()
+    );
+    Ok(())
+}
+
+/// Prints the frames of a log recorded by another tool, decoded.
+#[cfg(feature = "transport-serialport")]
+fn convert() -> Result<(), Error> {
+    let args = positional_args();
+    let (Some("nefmoto"), [path]) = (option_value("--from").as_deref(), args.as_slice()) else {
+        println!("usage: convert --from nefmoto <file>");
+        return Ok(());
+    };
+    for frame in analysis::capture::load_nefmoto(Path::new(path))? {
+        match frame.at {
+            Some(at) => {
+                let secs = at.as_secs();
+                print!(
+                    "{:02}:{:02}:{:02}.{:03} ",
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60,
+                    at.subsec_millis()
+                )
+            }
+            None => print!("{:>12} ", "-"),
+        }
+        let direction = match frame.direction {
+            Direction::ToEcu => "tester",
+            Direction::FromEcu => "ecu",
+        };
+        print!(
+            "{direction:<6}{} ",
+            if frame.direction_inferred { "?" } else { " " }
+        );
+        match frame.direction {
+            Direction::ToEcu => println!("{:?} {:02x?}", frame.message.service, frame.message.data),
+            Direction::FromEcu => match response::from_raw(frame.message.clone()) {
+                Ok(response) => println!("{response:?}"),
+                Err(e) => println!(
+                    "{:?} {:02x?} ({e})",
+                    frame.message.service, frame.message.data
+                ),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "transport-serialport")]
 fn main() -> Result<(), Error> {
-    let mut port = serialport::new("/dev/ttyUSB0", 10400)
-        .timeout(Duration::from_millis(4000)) // ecu P3 default is 5000, but I want a bit of leeway so I can close the session cleanly
-        .flow_control(serialport::FlowControl::None)
-        .open_native()
-        .unwrap();
+    if std::env::args().nth(1).as_deref() == Some("inspect") {
+        return inspect();
+    }
+    if std::env::args().nth(1).as_deref() == Some("convert") {
+        return convert();
+    }
+    if std::env::args().nth(1).as_deref() == Some("verify-file") {
+        return verify_file();
+    }
 
-    port.init_kwp2000(INIT_ADDRESS)?;
+    let port_path = "/dev/ttyUSB0";
+    let force = std::env::args().any(|a| a == "--force");
+    let _lock = PortLock::acquire(port_path, force)?;
 
-    println!("init done");
+    if std::env::args().nth(1).as_deref() == Some("repl") {
+        let transcript = option_value("--transcript").map(File::create).transpose()?;
+        return repl::Repl::new(port_path, transcript).run_lines(std::io::stdin().lock());
+    }
+
+    let mut port = SerialInterface::open(port_builder(port_path)).unwrap();
+
+    if std::env::args().nth(1).as_deref() == Some("scan") {
+        println!("address  key bytes  kwp2000");
+        for result in port.scan_addresses(&SCAN_ADDRESSES) {
+            match result.key_bytes {
+                Some([kb1, kb2]) => println!(
+                    "0x{:02x}     {:02x} {:02x}      {}",
+                    result.address,
+                    kb1,
+                    kb2,
+                    result.is_kwp2000()
+                ),
+                None => println!("0x{:02x}     -", result.address),
+            }
+        }
+        return Ok(());
+    }
+
+    // take over a session left open by a previous run instead of
+    // initializing again, which would drop it
+    let mut client = if std::env::args().any(|a| a == "--attach") {
+        Client::attach(
+            Box::new(port),
+            SessionState::default(),
+            Some(ConnectOptions::default()),
+        )?
+    } else {
+        if let Err(e) = port.init_kwp2000(INIT_ADDRESS, &InitTiming::default()) {
+            print_hints(&diagnostics::explain(&e, &ClientStats::default(), &[]));
+            return Err(e);
+        }
+        Client::new(Box::new(port))
+    };
 
-    let mut client = Client::new(Box::new(port));
+    println!("init done");
 
     client.diagnostic_mode().unwrap();
 
+    if let Err(e) = client.detect_tester_present_inversion() {
+        eprintln!("could not check the TesterPresent sub functions: {e}");
+    }
+
     println!("diagmode");
 
+    #[cfg(feature = "diag")]
+    if std::env::args().nth(1).as_deref() == Some("info") {
+        println!("profile: {}", client.profile.id);
+        println!("capabilities: {}", client.capabilities());
+        match client.read_vin_kwp() {
+            Ok(vin) => println!("VIN: {vin}"),
+            Err(e) => println!("VIN: {e}"),
+        }
+        match client.read_immobilizer_id() {
+            Ok(id) => println!("immobilizer id: {id}"),
+            Err(e) => println!("immobilizer id: {e}"),
+        }
+        client.disconnect()?;
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("dump") {
+        if let Some(path) = option_value("--status-file") {
+            client.on_progress.0 = Some(Box::new(move |progress| {
+                if let Err(e) = write_status_file(&path, progress) {
+                    eprintln!("failed to write status file: {e}");
+                }
+            }));
+        }
+        client.dump_sidecar = std::env::args().any(|a| a == "--sidecar");
+        let args = positional_args();
+        let parse_hex = |s: &String| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+        let (path, address, result) = match (
+            option_value("--resume"),
+            args.first().and_then(parse_hex),
+            args.get(1).and_then(parse_hex),
+            args.get(2),
+        ) {
+            (Some(path), ..) => {
+                let path = PathBuf::from(path);
+                let manifest = std::fs::read_to_string(DumpManifest::path(&path))?;
+                let address = DumpManifest::parse(&manifest)?.address;
+                let result = client.resume_dump(&path);
+                (path, address, result)
+            }
+            (None, Some(address), Some(size), Some(path)) => {
+                let path = PathBuf::from(path);
+                let result = client.dump_resumable(address, size, &path);
+                (path, address, result)
+            }
+            _ => {
+                println!("usage: dump <address> <size> <file> [--status-file <path>] [--sidecar]");
+                println!("       dump --resume <file> [--status-file <path>] [--sidecar]");
+                return Ok(());
+            }
+        };
+        let data = std::fs::read(&path)?;
+        print!("{}", util::hexdump_to_string(address, preview(&data)));
+        if let Err(e) = result {
+            let partial = PathBuf::from(format!("{}.partial", path.display()));
+            std::fs::write(&partial, &data)?;
+            println!(
+                "dump stopped at 0x{:06x}, read data saved to {}",
+                address.wrapping_add(data.len() as u32),
+                partial.display()
+            );
+            println!("continue it with dump --resume: {e}");
+            print_hints(&client.explain(&e));
+        }
+        client.disconnect()?;
+        return Ok(());
+    }
+
     let mut file = OpenOptions::new().create(true).write(true).open("mem")?;
 
     for i in 0..(0x380000 / 0x50) {
@@ -0,0 +1,76 @@
+//! Advisory lock keeping two instances from talking on the same port.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::Error;
+
+/// Lock on a serial port held by this process, released when dropped.
+#[derive(Debug)]
+pub struct PortLock {
+    path: PathBuf,
+}
+
+/// Whether the process `pid` is still running. Assumed to be true where it
+/// can't be checked.
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+impl PortLock {
+    /// Locks `port` using a lock file in the system's temporary directory.
+    pub fn acquire(port: &str, force: bool) -> Result<Self, Error> {
+        Self::acquire_in(&std::env::temp_dir(), port, force)
+    }
+
+    /// Locks `port` using a lock file in `dir`. The lock file is named after
+    /// the canonical path of the port so that different names for the same
+    /// device share a lock. A lock left behind by a process that no longer
+    /// runs is taken over, `force` also takes over a lock that is held.
+    pub fn acquire_in(dir: &Path, port: &str, force: bool) -> Result<Self, Error> {
+        let canonical = fs::canonicalize(port).unwrap_or_else(|_| PathBuf::from(port));
+        let name: String = canonical
+            .to_string_lossy()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("ecu_flasher{name}.lock"));
+
+        // one retry after removing a stale lock
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let holder_pid = fs::read_to_string(&path)?.trim().parse().ok();
+                    match holder_pid {
+                        Some(pid) if !force && process_alive(pid) => {
+                            return Err(Error::PortBusy { holder_pid: pid });
+                        }
+                        _ => fs::remove_file(&path)?,
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // another process took the lock between removing and creating it
+        Err(Error::PortBusy {
+            holder_pid: fs::read_to_string(&path)?.trim().parse().unwrap_or(0),
+        })
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,281 @@
+//! ECU family specific parameters that the protocol code should not have to
+//! hardcode.
+
+use std::time::Duration;
+
+#[cfg(feature = "flash")]
+use crate::kwp2000::{
+    constants::{LocalIdentifier, RoutineIdentifier, me7},
+    programming::ProgrammingStatusDecoder,
+};
+
+use crate::{
+    INIT_ADDRESS, MemoryLayout,
+    k_line::InitTiming,
+    kwp2000::{
+        client::BlockCounter, constants::ServiceId, security_key_from_seed, timing::TimingState,
+    },
+    memory_layout,
+};
+
+/// Computes the key to send for a security access seed.
+pub type SeedKeyAlgorithm = fn([u8; 4]) -> u32;
+
+/// Minimum time between requests the ECU tolerates for a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub service: ServiceId,
+    /// First data byte of the requests the limit applies to, all requests of
+    /// the service if `None`.
+    pub sub_function: Option<u8>,
+    pub interval: Duration,
+}
+
+/// Request keeping the session open in modes where the ECU refuses
+/// `TesterPresent`, see `Client::tester_present`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heartbeat {
+    /// Read an identification option
+    Identification(u8),
+    /// Read the current timing parameters
+    TimingRead,
+}
+
+/// Operations a profile, or a profile on a live session, supports. Used by
+/// front ends to offer only what will work.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Writing the flash, needs the `flash` feature, a sector layout and
+    /// the programming session.
+    pub flash: bool,
+    /// Checking written flash with the checksum routine
+    pub flash_verify: bool,
+    /// Reading the result of the last programming attempt
+    pub programming_status: bool,
+    pub fingerprint: bool,
+    /// Checking the flash device id before writes
+    pub flash_device_check: bool,
+    /// Known seed and key pairs confirm the security algorithm
+    pub security_verified: bool,
+    pub vin: bool,
+    pub immobilizer_id: bool,
+    pub programming_session: bool,
+    /// Reading and changing the timing parameters
+    pub timing_parameters: bool,
+    /// Longest dynamic identifier definition the ECU accepts, if it is
+    /// shorter than what the framing allows.
+    pub dd_length_limit: Option<u8>,
+}
+
+impl Capabilities {
+    /// Name and value of every capability.
+    pub fn list(&self) -> [(&'static str, bool); 10] {
+        [
+            ("flash", self.flash),
+            ("flash_verify", self.flash_verify),
+            ("programming_status", self.programming_status),
+            ("fingerprint", self.fingerprint),
+            ("flash_device_check", self.flash_device_check),
+            ("security_verified", self.security_verified),
+            ("vin", self.vin),
+            ("immobilizer_id", self.immobilizer_id),
+            ("programming_session", self.programming_session),
+            ("timing_parameters", self.timing_parameters),
+        ]
+    }
+
+    /// Encodes the capabilities as a JSON object of booleans and the
+    /// `dd_length_limit` number (`null` if there is none).
+    pub fn to_json(&self) -> String {
+        let mut fields: Vec<String> = self
+            .list()
+            .iter()
+            .map(|(name, value)| format!("\"{name}\":{value}"))
+            .collect();
+        let limit = self
+            .dd_length_limit
+            .map_or_else(|| "null".to_string(), |l| l.to_string());
+        fields.push(format!("\"dd_length_limit\":{limit}"));
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl std::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let supported: Vec<&str> = self
+            .list()
+            .iter()
+            .filter(|(_, value)| *value)
+            .map(|(name, _)| *name)
+            .collect();
+        write!(f, "{}", supported.join(", "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EcuProfile {
+    /// Stable identifier of the profile, for front ends and status files.
+    pub id: &'static str,
+    /// Bytes put in front of the compressed data of the first `TransferData`
+    /// block of a download before it is encrypted. Empty if the ECU does not
+    /// expect one.
+    #[cfg(feature = "flash")]
+    pub bcb_first_block_header: Vec<u8>,
+    /// Address the ECU is initialized at.
+    pub init_address: u8,
+    /// Timing of the 5-baud initialization.
+    pub init_timing: InitTiming,
+    /// Time the ECU keeps listening at a negotiated baud rate after the
+    /// session ended before it falls back to the initialization rate.
+    pub baud_fallback_window: Duration,
+    /// Longest dynamic identifier definition the ECU accepts, `None` if
+    /// only the framing limits it. Learned at runtime if the ECU rejects a
+    /// definition, see `Client::dd_length_limit`.
+    pub dd_length_limit: Option<u8>,
+    /// Whether `TransferData` blocks carry a sequence counter.
+    pub block_counter: BlockCounter,
+    /// Layout of the flash memory.
+    pub memory_layout: MemoryLayout,
+    pub security_algorithm: SeedKeyAlgorithm,
+    /// The seed is followed by a status byte that isn't part of it.
+    pub seed_status_byte: bool,
+    /// The ECU takes the `TesterPresent` sub function for no response
+    /// (0x02) as asking for one and the other way around, see
+    /// `Client::detect_tester_present_inversion`.
+    pub tester_present_inverted: bool,
+    /// Sent instead of `TesterPresent` once the ECU refused it in the
+    /// active mode.
+    pub heartbeat: Heartbeat,
+    /// Time the ECU may take to answer after each `ResponsePending`
+    /// (P2*max), instead of P2max.
+    pub p2max_extended: Duration,
+    /// Most `ResponsePending` answers, or `RoutineNotComplete` answers
+    /// while polling a routine, accepted for one request before giving up
+    /// with `Error::PendingLimitExceeded`.
+    pub max_pending: u32,
+    /// Negative responses to a wrong key hold the number of attempts left.
+    pub reports_remaining_attempts: bool,
+    /// Time waited before trying security access again after the ECU
+    /// refused it for too many attempts or requests coming too fast.
+    pub security_lockout: Duration,
+    /// Most times security access is tried again after such a refusal.
+    pub security_retries: u8,
+    /// Known good seed and key pairs, checked against `security_algorithm`
+    /// before the first key is sent so a wrong algorithm doesn't use up
+    /// security access attempts.
+    pub security_vectors: Vec<([u8; 4], u32)>,
+    /// Identification option holding the result of the last programming
+    /// attempt and how to decode it, `None` if the ECU doesn't report it.
+    #[cfg(feature = "flash")]
+    pub programming_status: Option<(u8, ProgrammingStatusDecoder)>,
+    /// Routine that checks the flash over a range against an expected
+    /// `additive_checksum`.
+    #[cfg(feature = "flash")]
+    pub checksum_routine: Option<RoutineIdentifier>,
+    /// Identification option the tester fingerprint is read from and local
+    /// identifier it is written to.
+    #[cfg(feature = "flash")]
+    pub fingerprint: Option<(u8, LocalIdentifier)>,
+    /// Routine returning the id of the flash device and the id expected,
+    /// checked before flash writes as the layout only fits that device.
+    #[cfg(feature = "flash")]
+    pub flash_device: Option<(RoutineIdentifier, Vec<u8>)>,
+    /// Requests matching one of these are delayed until its interval has
+    /// passed since the last matching request.
+    pub rate_limits: Vec<RateLimit>,
+    /// Identification options the ECU splits over several responses when
+    /// they don't fit one. The first data byte gives the length of the
+    /// rest, see `Client::read_ecu_identification`.
+    pub split_identifications: Vec<u8>,
+    /// Identification option holding the VIN.
+    pub vin_identification: Option<u8>,
+    /// Identification option holding the immobilizer identifier.
+    pub immobilizer_identification: Option<u8>,
+}
+
+impl EcuProfile {
+    /// Bosch ME7
+    pub fn me7() -> Self {
+        Self {
+            id: "bosch-me7.1-1mb",
+            #[cfg(feature = "flash")]
+            bcb_first_block_header: vec![0x1A, 0x01],
+            init_address: INIT_ADDRESS,
+            init_timing: InitTiming::me7(),
+            // not measured, the ECU drops the session once P3max (5s) passed
+            baud_fallback_window: Duration::from_secs(5),
+            dd_length_limit: None,
+            block_counter: BlockCounter::Off,
+            memory_layout: MemoryLayout {
+                base_address: memory_layout::BASE_ADDRESS,
+                size: memory_layout::SIZE,
+                sectors: memory_layout::SECTORS.to_vec(),
+            },
+            security_algorithm: security_key_from_seed,
+            seed_status_byte: false,
+            reports_remaining_attempts: false,
+            // the delay the standard requires after a refused key
+            security_lockout: Duration::from_secs(10),
+            security_retries: 2,
+            tester_present_inverted: false,
+            // the ME7 bootloader refuses TesterPresent in programming mode
+            heartbeat: Heartbeat::Identification(0x9B),
+            p2max_extended: TimingState::P2MAX_EXTENDED,
+            // not measured, ample for a sector erase
+            max_pending: 100,
+            security_vectors: Vec::new(),
+            #[cfg(feature = "flash")]
+            programming_status: None,
+            #[cfg(feature = "flash")]
+            checksum_routine: Some(me7::CHECKSUM_ROUTINE),
+            // not known for ME7 yet
+            #[cfg(feature = "flash")]
+            fingerprint: None,
+            // not known for ME7 yet
+            #[cfg(feature = "flash")]
+            flash_device: None,
+            // seed requests, ME7 locks security access if they come too fast
+            rate_limits: vec![RateLimit {
+                service: ServiceId::SecurityAccess,
+                sub_function: Some(0x01),
+                interval: Duration::from_secs(10),
+            }],
+            // not needed for ME7
+            split_identifications: Vec::new(),
+            vin_identification: Some(0x90),
+            // not known for ME7 yet
+            immobilizer_identification: None,
+        }
+    }
+
+    /// What the profile's populated fields allow, without knowing how the
+    /// ECU behaves. See `Client::capabilities` for a live session.
+    pub fn capabilities(&self) -> Capabilities {
+        #[cfg(feature = "flash")]
+        let flash = Capabilities {
+            flash: !self.memory_layout.sectors.is_empty(),
+            flash_verify: self.checksum_routine.is_some(),
+            programming_status: self.programming_status.is_some(),
+            fingerprint: self.fingerprint.is_some(),
+            flash_device_check: self.flash_device.is_some(),
+            ..Capabilities::default()
+        };
+        #[cfg(not(feature = "flash"))]
+        let flash = Capabilities::default();
+        Capabilities {
+            security_verified: !self.security_vectors.is_empty(),
+            vin: self.vin_identification.is_some(),
+            immobilizer_id: self.immobilizer_identification.is_some(),
+            programming_session: true,
+            timing_parameters: true,
+            dd_length_limit: self.dd_length_limit,
+            ..flash
+        }
+    }
+}
+
+impl Default for EcuProfile {
+    fn default() -> Self {
+        Self::me7()
+    }
+}
@@ -0,0 +1,390 @@
+//! Interactive shell for exploring an ECU: each line is parsed into a
+//! `Command` first, then run against the client, so the protocol side
+//! doesn't depend on how lines are read.
+
+use std::{
+    fs::File,
+    io::{BufRead, Write},
+};
+
+use crate::{
+    Error, INIT_ADDRESS,
+    k_line::{InitTiming, KLine, SerialInterface},
+    kwp2000::{
+        client::Client,
+        constants::{DiagnosticMode, ServiceId},
+        raw_message::RawMessage,
+    },
+    util,
+};
+
+/// Names of the commands, for `help` and completing a prefix.
+pub const COMMANDS: [&str; 13] = [
+    "connect",
+    "disconnect",
+    "session",
+    "sec",
+    "id",
+    "read",
+    "raw",
+    "timing",
+    "trace",
+    "frames",
+    "history",
+    "help",
+    "quit",
+];
+
+/// Line of the shell, parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Connect,
+    Disconnect,
+    Session(DiagnosticMode),
+    Security,
+    Identification(u8),
+    Read {
+        address: u32,
+        length: u8,
+    },
+    /// Service id and data bytes, sent as they are
+    Raw {
+        service: u8,
+        data: Vec<u8>,
+    },
+    Timing,
+    /// Print the frames received by every command
+    Trace(bool),
+    /// Print the last frames received
+    Frames,
+    History,
+    Help,
+    Quit,
+}
+
+fn parse_number(token: &str) -> Result<u32, String> {
+    match token.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => token.parse(),
+    }
+    .map_err(|_| format!("not a number: {token}"))
+}
+
+fn parse_byte(token: &str) -> Result<u8, String> {
+    u8::from_str_radix(token.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("not a hex byte: {token}"))
+}
+
+fn parse_mode(token: &str) -> Result<DiagnosticMode, String> {
+    Ok(match token {
+        "obd" => DiagnosticMode::OBD,
+        "eol-vw" => DiagnosticMode::EndOfLineVW,
+        "eol-bosch" => DiagnosticMode::EndOfLineBosch,
+        "prog" => DiagnosticMode::Programming,
+        "dev" => DiagnosticMode::Developer,
+        "diag" => DiagnosticMode::Diagnostics,
+        _ => {
+            return Err(format!(
+                "unknown session {token}, one of obd, eol-vw, eol-bosch, prog, dev, diag"
+            ));
+        }
+    })
+}
+
+/// Expands `name` to the command it is an unambiguous prefix of.
+pub fn complete(name: &str) -> Vec<&'static str> {
+    COMMANDS
+        .iter()
+        .copied()
+        .filter(|c| c.starts_with(name))
+        .collect()
+}
+
+impl Command {
+    /// Parses a line, `None` if it is empty. Numbers are decimal unless
+    /// prefixed with `0x`, bytes are always hex.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let mut tokens = line.split_whitespace();
+        let Some(name) = tokens.next() else {
+            return Ok(None);
+        };
+        let name = match complete(name).as_slice() {
+            [name] => *name,
+            [] => return Err(format!("unknown command {name}, see help")),
+            names if names.contains(&name) => name,
+            names => return Err(format!("ambiguous command {name}: {}", names.join(", "))),
+        };
+        let args: Vec<&str> = tokens.collect();
+        let command = match (name, args.as_slice()) {
+            ("connect", []) => Self::Connect,
+            ("disconnect", []) => Self::Disconnect,
+            ("session", [mode]) => Self::Session(parse_mode(mode)?),
+            ("sec", []) => Self::Security,
+            ("id", [option]) => Self::Identification(parse_byte(option)?),
+            ("read", [address, length]) => Self::Read {
+                address: parse_number(address)?,
+                length: parse_number(length)?
+                    .try_into()
+                    .map_err(|_| format!("length too long: {length}"))?,
+            },
+            ("raw", [service, data @ ..]) => Self::Raw {
+                service: parse_byte(service)?,
+                data: data
+                    .iter()
+                    .map(|b| parse_byte(b))
+                    .collect::<Result<_, _>>()?,
+            },
+            ("timing", []) => Self::Timing,
+            ("trace", ["on"]) => Self::Trace(true),
+            ("trace", ["off"]) => Self::Trace(false),
+            ("frames", []) => Self::Frames,
+            ("history", []) => Self::History,
+            ("help", []) => Self::Help,
+            ("quit", []) => Self::Quit,
+            (name, _) => return Err(format!("wrong arguments for {name}, see help")),
+        };
+        Ok(Some(command))
+    }
+}
+
+const HELP: &str = "\
+connect                   initialize the ECU and enter the diagnostic session
+disconnect                end the session
+session <mode>            switch to obd, eol-vw, eol-bosch, prog, dev or diag
+sec                       get security access
+id <option>               read an identification option, e.g. id 9b
+read <address> <length>   read memory, e.g. read 0x380000 16
+raw <service> [bytes]     send a request as is, e.g. raw 21 f0
+timing                    read the timing parameters
+trace on|off              print the frames received by every command
+frames                    print the last frames received
+history                   print the lines entered
+help                      print this
+quit                      disconnect and leave
+commands can be shortened to an unambiguous prefix";
+
+/// State of the shell.
+pub struct Repl {
+    port_path: String,
+    client: Option<Client>,
+    trace: bool,
+    history: Vec<String>,
+    /// Everything entered and printed is copied here
+    transcript: Option<File>,
+}
+
+impl Repl {
+    pub fn new(port_path: &str, transcript: Option<File>) -> Self {
+        Self {
+            port_path: port_path.to_string(),
+            client: None,
+            trace: false,
+            history: Vec::new(),
+            transcript,
+        }
+    }
+
+    fn say(&mut self, text: &str) {
+        println!("{text}");
+        if let Some(file) = &mut self.transcript
+            && let Err(e) = writeln!(file, "{text}")
+        {
+            eprintln!("failed to write transcript: {e}");
+            self.transcript = None;
+        }
+    }
+
+    fn client(&mut self) -> Result<&mut Client, Error> {
+        self.client.as_mut().ok_or(Error::NotConnected)
+    }
+
+    /// Runs `command`, returns the text to print.
+    fn run(&mut self, command: Command) -> Result<String, Error> {
+        Ok(match command {
+            Command::Connect => {
+                let mut port = SerialInterface::open(crate::port_builder(&self.port_path))?;
+                port.init_kwp2000(INIT_ADDRESS, &InitTiming::default())?;
+                let mut client = Client::new(Box::new(port));
+                client.diagnostic_mode()?;
+                self.client = Some(client);
+                "connected".to_string()
+            }
+            Command::Disconnect => {
+                if let Some(client) = self.client.take() {
+                    client.disconnect()?;
+                }
+                "disconnected".to_string()
+            }
+            Command::Session(mode) => {
+                self.client()?.switch_mode(mode, None)?;
+                format!("{mode:?}")
+            }
+            Command::Security => {
+                self.client()?.get_security_access()?;
+                "security access granted".to_string()
+            }
+            Command::Identification(option) => {
+                let data = self.client()?.read_ecu_identification(option)?;
+                format!(
+                    "{:02x?} {:?}",
+                    data,
+                    String::from_utf8_lossy(&data).trim_end()
+                )
+            }
+            Command::Read { address, length } => {
+                let data = self.client()?.dd_read_address(address, length)?;
+                util::hexdump_to_string(address, &data)
+            }
+            Command::Raw { service, data } => {
+                let service = ServiceId::from_repr(service).ok_or(Error::InvalidService)?;
+                let client = self.client()?;
+                client.send_untracked(RawMessage::new_query(service, data)?)?;
+                format!("{:?}", client.next_response()?)
+            }
+            Command::Timing => format!("{:?}", self.client()?.timing_report()?),
+            Command::Trace(on) => {
+                self.trace = on;
+                format!("trace {}", if on { "on" } else { "off" })
+            }
+            Command::Frames => self
+                .client()?
+                .recent_frames()
+                .iter()
+                .map(|f| format!("{f:02x?}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Command::History => self.history.join("\n"),
+            Command::Help => HELP.to_string(),
+            Command::Quit => unreachable!("handled by the loop"),
+        })
+    }
+
+    /// Reads commands from `input` until `quit` or the end of input.
+    pub fn run_lines<R: BufRead>(&mut self, input: R) -> Result<(), Error> {
+        print!("> ");
+        std::io::stdout().flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if let Some(file) = &mut self.transcript {
+                writeln!(file, "> {line}")?;
+            }
+            match Command::parse(&line) {
+                Ok(None) => {}
+                Ok(Some(Command::Quit)) => break,
+                Ok(Some(command)) => {
+                    self.history.push(line.clone());
+                    let received = self.client.as_ref().map(|c| c.stats.frames_received);
+                    let result = self.run(command);
+                    if self.trace
+                        && let Some(client) = &self.client
+                    {
+                        let new = client.stats.frames_received - received.unwrap_or(0);
+                        let frames = client.recent_frames();
+                        let trace: Vec<String> = frames
+                            [frames.len().saturating_sub(new as usize)..]
+                            .iter()
+                            .map(|f| format!("< {f:02x?}"))
+                            .collect();
+                        if !trace.is_empty() {
+                            self.say(&trace.join("\n"));
+                        }
+                    }
+                    match result {
+                        Ok(text) => self.say(&text),
+                        Err(e) => self.say(&format!("error: {e}")),
+                    }
+                }
+                Err(message) => self.say(&message),
+            }
+            print!("> ");
+            std::io::stdout().flush()?;
+        }
+        if let Some(client) = self.client.take() {
+            client.disconnect()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Command {
+        Command::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(
+            parse("read 0x380000 16"),
+            Command::Read {
+                address: 0x380000,
+                length: 16
+            }
+        );
+        assert_eq!(parse("id 9b"), Command::Identification(0x9B));
+        assert_eq!(parse("id 0x9b"), Command::Identification(0x9B));
+        assert_eq!(
+            parse("raw 21 F0"),
+            Command::Raw {
+                service: 0x21,
+                data: vec![0xF0]
+            }
+        );
+        assert_eq!(
+            parse("raw 3e"),
+            Command::Raw {
+                service: 0x3E,
+                data: vec![]
+            }
+        );
+        assert_eq!(
+            parse("session prog"),
+            Command::Session(DiagnosticMode::Programming)
+        );
+        assert_eq!(parse("  trace   on "), Command::Trace(true));
+        assert_eq!(parse("trace off"), Command::Trace(false));
+        assert_eq!(parse("sec"), Command::Security);
+        assert_eq!(Command::parse("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn prefixes_are_completed() {
+        assert_eq!(parse("con"), Command::Connect);
+        assert_eq!(parse("ti"), Command::Timing);
+        assert_eq!(parse("q"), Command::Quit);
+        assert_eq!(complete("r"), ["read", "raw"]);
+        assert_eq!(complete("x"), [] as [&str; 0]);
+        assert!(
+            Command::parse("t")
+                .unwrap_err()
+                .starts_with("ambiguous command t: timing, trace")
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_errors() {
+        for line in [
+            "bogus",
+            "read 0x380000",
+            "read 0x380000 256",
+            "read zz 1",
+            "id 100",
+            "raw",
+            "raw 21 xyz",
+            "session fast",
+            "trace maybe",
+            "quit now",
+        ] {
+            assert!(Command::parse(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn every_command_has_help() {
+        for name in COMMANDS {
+            assert!(HELP.lines().any(|l| l.starts_with(name)), "{name}");
+        }
+    }
+}
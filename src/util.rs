@@ -0,0 +1,151 @@
+//! Formatting helpers shared by Display impls and the CLI.
+
+use std::fmt::{self, Write};
+
+/// Layout of a hex dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexdumpOptions {
+    /// Number of bytes per line
+    pub width: usize,
+    /// Print runs of lines identical to the line before them as a single
+    /// `*` line, like `hexdump -C`.
+    pub squeeze: bool,
+}
+
+impl Default for HexdumpOptions {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            squeeze: true,
+        }
+    }
+}
+
+/// Writes `data` as lines of an address, hex bytes and their ASCII
+/// characters, addresses starting at `base_address`. See `hexdump_with`.
+pub fn hexdump<W: Write>(base_address: u32, data: &[u8], f: &mut W) -> fmt::Result {
+    hexdump_with(HexdumpOptions::default(), base_address, data, f)
+}
+
+/// Writes `data` in the format of `hexdump -C`. Bytes that aren't printable
+/// ASCII are shown as `.` in the sidebar and a final line holds the address
+/// after the end of the data.
+pub fn hexdump_with<W: Write>(
+    options: HexdumpOptions,
+    base_address: u32,
+    data: &[u8],
+    f: &mut W,
+) -> fmt::Result {
+    let width = options.width.max(1);
+    // addresses are computed wider than the base so a dump reaching the end
+    // of the address space doesn't wrap
+    let address = |offset: usize| base_address as u64 + offset as u64;
+    let mut previous: Option<&[u8]> = None;
+    let mut squeezing = false;
+    for (n, line) in data.chunks(width).enumerate() {
+        if options.squeeze && previous == Some(line) {
+            if !squeezing {
+                writeln!(f, "*")?;
+                squeezing = true;
+            }
+            continue;
+        }
+        previous = Some(line);
+        squeezing = false;
+
+        write!(f, "{:08x} ", address(n * width))?;
+        for i in 0..width {
+            if i % 8 == 0 {
+                f.write_char(' ')?;
+            }
+            match line.get(i) {
+                Some(b) => write!(f, "{b:02x} ")?,
+                None => f.write_str("   ")?,
+            }
+        }
+        f.write_str(" |")?;
+        for b in line {
+            let c = if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            };
+            f.write_char(c)?;
+        }
+        writeln!(f, "|")?;
+    }
+    writeln!(f, "{:08x}", address(data.len()))
+}
+
+/// `hexdump` into a new string.
+pub fn hexdump_to_string(base_address: u32, data: &[u8]) -> String {
+    let mut s = String::new();
+    // writing to a string does not fail
+    let _ = hexdump(base_address, data, &mut s);
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_last_line_keeps_the_sidebar_aligned() {
+        let data: Vec<u8> = (0x41..0x41 + 20).collect();
+        assert_eq!(
+            hexdump_to_string(0x800000, &data),
+            "00800000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|\n\
+             00800010  51 52 53 54                                       |QRST|\n\
+             00800014\n"
+        );
+    }
+
+    #[test]
+    fn non_printable_bytes_are_dots() {
+        assert_eq!(
+            hexdump_to_string(0, &[0x00, b' ', 0x7F, 0x80, 0xFF, b'~', b'\n']),
+            "00000000  00 20 7f 80 ff 7e 0a                              |. ...~.|\n\
+             00000007\n"
+        );
+    }
+
+    #[test]
+    fn repeated_lines_are_squeezed() {
+        let mut data = vec![0xFF; 0x40];
+        data.extend_from_slice(&[0x00; 8]);
+        assert_eq!(
+            hexdump_to_string(0, &data),
+            "00000000  ff ff ff ff ff ff ff ff  ff ff ff ff ff ff ff ff  |................|\n\
+             *\n\
+             00000040  00 00 00 00 00 00 00 00                           |........|\n\
+             00000048\n"
+        );
+        // one repeated byte all the way, the end address still follows
+        assert_eq!(
+            hexdump_to_string(0x1000, &[0x55; 0x100]),
+            "00001000  55 55 55 55 55 55 55 55  55 55 55 55 55 55 55 55  |UUUUUUUUUUUUUUUU|\n\
+             *\n\
+             00001100\n"
+        );
+        let mut s = String::new();
+        let options = HexdumpOptions {
+            width: 4,
+            squeeze: false,
+        };
+        hexdump_with(options, 0, &[0xAA; 8], &mut s).unwrap();
+        assert_eq!(
+            s,
+            "00000000  aa aa aa aa  |....|\n\
+             00000004  aa aa aa aa  |....|\n\
+             00000008\n"
+        );
+    }
+
+    #[test]
+    fn addresses_do_not_wrap() {
+        let data: Vec<u8> = (0..0x20).collect();
+        let s = hexdump_to_string(u32::MAX - 0x0F, &data);
+        let addresses: Vec<_> = s.lines().map(|l| l.split(' ').next().unwrap()).collect();
+        assert_eq!(addresses, ["fffffff0", "100000000", "100000010"]);
+    }
+}
@@ -0,0 +1,243 @@
+//! Replays the captures in `tests/fixtures` through `Client` and checks the
+//! outcomes recorded next to them, so a capture of a session with an ECU
+//! can be added as a regression test without writing Rust.
+//!
+//! A capture is a NefMoto log `<name>.log` (see `analysis::capture`) of one
+//! session with the ECU, starting after its initialization. The
+//! `StartCommunication` exchange may be left in, it is skipped as the
+//! interface sends it rather than the client. `<name>.expect` lists the
+//! operations done in the session, one per line as
+//! `<operation> [arguments] => <outcome>`, blank lines and lines starting
+//! with `#` are skipped. Arguments are hex numbers, the operations are:
+//!
+//! - `diagnostic_mode`
+//! - `identification <option>`
+//! - `security`
+//! - `read_memory <address> <size>`
+//! - `dd_read <address> <size> <chunk size>`
+//! - `fastest_timing`
+//! - `tester_present`
+//! - `disconnect`, ends the session
+//!
+//! The outcome is `ok`, the bytes read as hex, the identification as text
+//! in double quotes, or `refused <code>` for a negative response with that
+//! code in hex. Every request the client sends must be the next one in the
+//! capture and all frames of the capture must be used up.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::ErrorKind,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ecu_flasher::{
+    Error,
+    analysis::capture::{CapturedFrame, Direction, load_nefmoto},
+    kwp2000::{
+        Interface,
+        client::Client,
+        constants::{Service, ServiceId, ServiceResponse},
+        raw_message::RawMessage,
+        response::Response,
+        security::SecurityAccessOutcome,
+    },
+};
+
+/// Interface answering every request with the frames the ECU sent after
+/// it in a capture. The request must be the next one the tester sent.
+#[derive(Debug)]
+struct ReplayInterface {
+    name: String,
+    /// Frames not replayed yet, shared with the test to check they are all
+    /// used up.
+    frames: Arc<Mutex<VecDeque<CapturedFrame>>>,
+    received: VecDeque<RawMessage>,
+}
+
+/// Service byte followed by the data of `message`.
+fn request_bytes(message: &RawMessage) -> Vec<u8> {
+    let mut bytes = vec![message.service.into()];
+    bytes.extend_from_slice(&message.data);
+    bytes
+}
+
+impl Interface for ReplayInterface {
+    fn switch_baud(&mut self, _baud_rate: u32) -> Result<(), Error> {
+        Ok(())
+    }
+    fn send_raw(&mut self, message: RawMessage) -> Result<(), Error> {
+        let sent = request_bytes(&message);
+        let mut frames = self.frames.lock().unwrap();
+        let Some(expected) = frames.pop_front() else {
+            panic!(
+                "{}: sent {sent:02X?} after the end of the capture",
+                self.name
+            );
+        };
+        assert!(
+            expected.direction == Direction::ToEcu && request_bytes(&expected.message) == sent,
+            "{}:{}: sent {sent:02X?}, the capture has {:?} {:02X?}",
+            self.name,
+            expected.line,
+            expected.direction,
+            request_bytes(&expected.message),
+        );
+        while frames
+            .front()
+            .is_some_and(|f| f.direction == Direction::FromEcu)
+        {
+            self.received.push_back(frames.pop_front().unwrap().message);
+        }
+        Ok(())
+    }
+    fn next_raw_message(&mut self) -> Result<RawMessage, Error> {
+        self.received
+            .pop_front()
+            .ok_or_else(|| Error::Io(ErrorKind::TimedOut.into()))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Text(String),
+    Bytes(Vec<u8>),
+    Refused(u8),
+    /// Any other error, never expected
+    Failed(String),
+}
+
+fn hex(token: &str) -> u32 {
+    u32::from_str_radix(token, 16).unwrap_or_else(|_| panic!("{token:?} is not a hex number"))
+}
+
+fn parse_outcome(text: &str) -> Outcome {
+    if let Some(text) = text.strip_prefix('"') {
+        let text = text
+            .strip_suffix('"')
+            .expect("text outcome without closing quote");
+        return Outcome::Text(text.to_string());
+    }
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        ["ok"] => Outcome::Ok,
+        ["refused", code] => Outcome::Refused(hex(code) as u8),
+        ref bytes => Outcome::Bytes(bytes.iter().map(|b| hex(b) as u8).collect()),
+    }
+}
+
+fn outcome<T>(result: Result<T, Error>, value: impl FnOnce(T) -> Outcome) -> Outcome {
+    match result {
+        Ok(t) => value(t),
+        Err(Error::UnexpectedResponse(Response::Error(e))) => Outcome::Refused(e.error as u8),
+        Err(Error::SecurityAccessDenied(report)) => match report.outcome {
+            SecurityAccessOutcome::Rejected(e) => Outcome::Refused(e as u8),
+            SecurityAccessOutcome::Granted => unreachable!(),
+        },
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// Does `operation` of an expect file, the client is gone after
+/// `disconnect`.
+fn run(client: &mut Option<Client>, operation: &str) -> Outcome {
+    let ok = |()| Outcome::Ok;
+    let mut words = operation.split_whitespace();
+    let name = words.next().expect("empty operation");
+    let args: Vec<u32> = words.map(hex).collect();
+    if name == "disconnect" {
+        let client = client.take().expect("disconnect after the session ended");
+        return outcome(client.disconnect(), ok);
+    }
+    let client = client.as_mut().expect("operation after the session ended");
+    match (name, &args[..]) {
+        ("diagnostic_mode", []) => outcome(client.diagnostic_mode(), ok),
+        ("identification", &[option]) => {
+            outcome(client.read_ecu_identification(option as u8), |data| {
+                Outcome::Text(String::from_utf8_lossy(&data).into_owned())
+            })
+        }
+        ("security", []) => outcome(client.get_security_access(), ok),
+        ("read_memory", &[address, size]) => outcome(
+            client.read_memory_by_address(address, size as u8),
+            Outcome::Bytes,
+        ),
+        ("dd_read", &[address, size, chunk_size]) => outcome(
+            client.dd_read_range(address, size, chunk_size as u8),
+            |read| match &read.unreadable[..] {
+                [] => Outcome::Bytes(read.data),
+                unreadable => Outcome::Failed(format!("unreadable chunks {unreadable:X?}")),
+            },
+        ),
+        ("fastest_timing", []) => outcome(client.use_fastest_timing(), ok),
+        ("tester_present", []) => outcome(client.tester_present(), ok),
+        _ => panic!("unknown operation {operation:?}"),
+    }
+}
+
+/// Replays the capture at `path`, returns how its outcomes differ from the
+/// expected ones.
+fn replay(path: &Path) -> Vec<String> {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let frames = load_nefmoto(path).unwrap_or_else(|e| panic!("{name}: {e}"));
+    let frames: VecDeque<_> = frames
+        .into_iter()
+        .filter(|f| {
+            !matches!(
+                f.message.service,
+                Service::Query(ServiceId::StartCommunication)
+                    | Service::Response(ServiceResponse::StartCommunication)
+            )
+        })
+        .collect();
+    let frames = Arc::new(Mutex::new(frames));
+    let interface = ReplayInterface {
+        name: name.clone(),
+        frames: frames.clone(),
+        received: VecDeque::new(),
+    };
+    let mut client = Some(Client::new(Box::new(interface)));
+
+    let expect_name = name.replace(".log", ".expect");
+    let expect = fs::read_to_string(path.with_extension("expect"))
+        .unwrap_or_else(|e| panic!("{expect_name}: {e}"));
+    let mut failures = Vec::new();
+    for (i, line) in expect.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (operation, expected) = line
+            .split_once("=>")
+            .unwrap_or_else(|| panic!("{}:{}: no `=>`", expect_name, i + 1));
+        let got = run(&mut client, operation);
+        let expected = parse_outcome(expected.trim());
+        if got != expected {
+            failures.push(format!(
+                "{}:{}: {} gave {got:X?}, expected {expected:X?}",
+                expect_name,
+                i + 1,
+                operation.trim(),
+            ));
+        }
+    }
+    if let Some(frame) = frames.lock().unwrap().front() {
+        failures.push(format!("{name}:{}: frames left over", frame.line));
+    }
+    failures
+}
+
+#[test]
+fn captures_give_their_outcomes() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut captures: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "log"))
+        .collect();
+    captures.sort();
+    assert!(!captures.is_empty(), "no captures in {}", dir.display());
+    let failures: Vec<_> = captures.iter().flat_map(|path| replay(path)).collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}